@@ -0,0 +1,42 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use reef_benches::{parse, run_in_quanta};
+use reef_interpreter::transform::StripOptions;
+use reef_interpreter::types::value::WasmValue;
+
+/// A loop whose exit and rarely taken `if` are hinted
+const LOOP: &str = r#"
+(module
+    (func (export "sum") (param i32) (result i32) (local i32)
+        (block $b
+            (loop $l
+                (i32.eqz (local.get 0))
+                (@metadata.code.branch_hint "\00")
+                br_if $b
+                (i32.eqz (i32.and (local.get 0) (i32.const 1023)))
+                (@metadata.code.branch_hint "\00")
+                if
+                    (local.set 1 (i32.sub (local.get 1) (i32.const 1)))
+                end
+                (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                (i32.gt_u (local.get 0) (i32.const 0))
+                (@metadata.code.branch_hint "\01")
+                br_if $l))
+        (local.get 1)))
+"#;
+
+/// Conditional branches of the same module with and without their hints
+fn branch_hints(c: &mut Criterion) {
+    let hinted = parse(LOOP);
+    let mut unhinted = hinted.clone();
+    unhinted.strip(&StripOptions::default());
+    let params = [WasmValue::I32(100_000)];
+
+    let mut group = c.benchmark_group("branch_hints");
+    group.bench_function("hinted", |b| b.iter(|| run_in_quanta(&hinted, "sum", &params, usize::MAX, false)));
+    group.bench_function("unhinted", |b| b.iter(|| run_in_quanta(&unhinted, "sum", &params, usize::MAX, false)));
+    group.finish();
+}
+
+criterion_group!(benches, branch_hints);
+criterion_main!(benches);
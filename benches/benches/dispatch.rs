@@ -0,0 +1,39 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use reef_benches::{parse, FIB};
+use reef_interpreter::exec::CallResult;
+use reef_interpreter::imports::Imports;
+use reef_interpreter::types::value::WasmValue;
+use reef_interpreter::{Instance, Module};
+
+/// A loop of arithmetic on locals, where dispatch is most of the work
+const ARITH: &str = r#"
+(module
+    (func (export "arith") (param i32) (result i32) (local i32)
+        (loop $l
+            (local.set 1 (i32.xor (i32.mul (local.get 1) (i32.const 31)) (local.get 0)))
+            (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+            (br_if $l (local.get 0)))
+        (local.get 1)))
+"#;
+
+/// Run `name` to completion, returning the number of instructions executed
+fn run(module: &Module, name: &str, params: &[WasmValue]) -> u64 {
+    let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+    let mut exec = instance.exported_func_untyped(name).unwrap().call(params.to_vec(), None).unwrap();
+    assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(_))));
+    exec.stats().instructions
+}
+
+/// Instructions executed per second without instrumentation, the baseline for changes to the dispatch loop
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    for (name, wat, params) in [("fib", FIB, WasmValue::I32(20)), ("arith", ARITH, WasmValue::I32(100_000))] {
+        let module = parse(wat);
+        group.throughput(Throughput::Elements(run(&module, name, &[params])));
+        group.bench_function(name, |b| b.iter(|| run(&module, name, &[params])));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use reef_benches::{parse, run_in_quanta, FIB};
+use reef_interpreter::types::value::WasmValue;
+
+const QUANTA: [usize; 5] = [100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// Overhead of pausing (and optionally snapshotting) every `max_cycles` compared to an uninterrupted run
+fn quantum(c: &mut Criterion) {
+    let module = parse(FIB);
+    let params = [WasmValue::I32(20)];
+
+    let mut group = c.benchmark_group("quantum");
+    group.bench_function("uninterrupted", |b| b.iter(|| run_in_quanta(&module, "fib", &params, usize::MAX, false)));
+    for quantum in QUANTA {
+        group.bench_with_input(BenchmarkId::new("pause", quantum), &quantum, |b, &quantum| {
+            b.iter(|| run_in_quanta(&module, "fib", &params, quantum, false))
+        });
+        group.bench_with_input(BenchmarkId::new("snapshot", quantum), &quantum, |b, &quantum| {
+            b.iter(|| run_in_quanta(&module, "fib", &params, quantum, true))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, quantum);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use reef_benches::{paused, spinner};
+use reef_interpreter::exec::CallResult;
+use reef_interpreter::imports::Imports;
+use reef_interpreter::Instance;
+use rkyv::AlignedVec;
+
+const PAGES: [usize; 4] = [1, 16, 64, 256];
+
+/// Snapshot throughput depending on the size of memory
+fn serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+    for pages in PAGES {
+        let mut exec = paused(spinner(pages), "spin", Vec::new());
+        let len = exec.serialize(AlignedVec::new()).unwrap().len();
+        group.throughput(Throughput::Bytes(len as u64));
+
+        let mut buf = Some(AlignedVec::with_capacity(len));
+        group.bench_with_input(BenchmarkId::from_parameter(pages), &pages, |b, _| {
+            b.iter(|| {
+                let mut state = buf.take().unwrap();
+                state.clear();
+                buf = Some(exec.serialize(state).unwrap());
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Time from a snapshot to a resumed call that has run its first instruction
+fn resume(c: &mut Criterion) {
+    let mut group = c.benchmark_group("resume");
+    for pages in PAGES {
+        let module = spinner(pages);
+        let state = paused(module.clone(), "spin", Vec::new()).serialize(AlignedVec::new()).unwrap();
+        group.throughput(Throughput::Bytes(state.len() as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(pages), &pages, |b, _| {
+            b.iter(|| {
+                let (instance, stack) =
+                    Instance::instantiate_with_state(module.clone(), Imports::new(), &state).unwrap();
+                let mut exec = instance.exported_func_untyped("spin").unwrap().call(Vec::new(), Some(stack)).unwrap();
+                assert!(matches!(exec.run(1), Ok(CallResult::Incomplete)));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialize, resume);
+criterion_main!(benches);
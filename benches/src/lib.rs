@@ -0,0 +1,66 @@
+//! Modules and helpers shared by the benchmarks
+
+use reef_interpreter::exec::{CallResult, ExecHandle};
+use reef_interpreter::imports::Imports;
+use reef_interpreter::types::value::WasmValue;
+use reef_interpreter::{Instance, Module};
+use rkyv::AlignedVec;
+
+/// Recursive fibonacci, exported as `fib`
+pub const FIB: &str = r#"
+(module
+    (memory (export "memory") 1)
+    (func $fib (export "fib") (param i32) (result i32)
+        (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+            (then (local.get 0))
+            (else (i32.add
+                (call $fib (i32.sub (local.get 0) (i32.const 1)))
+                (call $fib (i32.sub (local.get 0) (i32.const 2))))))))
+"#;
+
+/// A module with `pages` pages of memory and a `spin` function that never returns
+pub fn spinner(pages: usize) -> Module {
+    let wat = format!(r#"(module (memory (export "memory") {pages}) (func (export "spin") (loop $l (br $l))))"#);
+    reef_interpreter::parse_bytes(&wat::parse_str(wat).unwrap()).unwrap()
+}
+
+/// Parse the module in text format `wat`
+pub fn parse(wat: &str) -> Module {
+    reef_interpreter::parse_bytes(&wat::parse_str(wat).unwrap()).unwrap()
+}
+
+/// Start `name` and run it for a few cycles, so there is a paused call to serialize
+///
+/// Every page of memory gets a few non-zero bytes, so the snapshot can't skip any of them.
+pub fn paused(module: Module, name: &str, params: Vec<WasmValue>) -> ExecHandle {
+    let mut instance = Instance::instantiate(module, Imports::new()).unwrap();
+    let mut memory = instance.exported_memory_mut("memory").unwrap();
+    for page in 0..memory.page_count() {
+        memory.store(page * reef_interpreter::PAGE_SIZE, 8, &(page as u64 + 1).to_le_bytes()).unwrap();
+    }
+
+    let mut exec = instance.exported_func_untyped(name).unwrap().call(params, None).unwrap();
+    assert!(matches!(exec.run(16), Ok(CallResult::Incomplete)));
+    exec
+}
+
+/// Run `name` to completion in quanta of `max_cycles`, optionally serializing and restoring between quanta
+pub fn run_in_quanta(module: &Module, name: &str, params: &[WasmValue], max_cycles: usize, snapshot: bool) {
+    let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+    let mut exec = instance.exported_func_untyped(name).unwrap().call(params.to_vec(), None).unwrap();
+    let mut buf = AlignedVec::new();
+
+    loop {
+        match exec.run(max_cycles).unwrap() {
+            CallResult::Done(_) => break,
+            CallResult::Incomplete if snapshot => {
+                buf.clear();
+                buf = exec.serialize(buf).unwrap();
+                let (instance, stack) = Instance::instantiate_with_state(module.clone(), Imports::new(), &buf).unwrap();
+                exec = instance.exported_func_untyped(name).unwrap().call(params.to_vec(), Some(stack)).unwrap();
+            }
+            CallResult::Incomplete => {}
+            CallResult::Breakpoint(_) | CallResult::Exit(_) => unreachable!(),
+        }
+    }
+}
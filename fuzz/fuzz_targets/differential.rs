@@ -0,0 +1,160 @@
+//! Runs modules generated by `wasm-smith` in both reef_interpreter and wasmi and compares the results
+//!
+//! Every exported function is called with all parameters set to zero, in export order and on the same instance,
+//! so later calls see the effects of earlier ones. Results, whether a call trapped and the final contents of
+//! exported memories have to match. Comparison stops once either side runs out of fuel.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use reef_interpreter::exec::CallResult;
+use reef_interpreter::imports::Imports;
+use reef_interpreter::types::value::{ValType, WasmValue};
+use reef_interpreter::types::ExternType;
+use reef_interpreter::{Instance, PAGE_SIZE};
+
+const FUEL: u64 = 100_000;
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let Ok(wasm) = generate(&mut u) else { return };
+    compare(&wasm);
+});
+
+fn generate(u: &mut Unstructured<'_>) -> Result<Vec<u8>> {
+    let mut config = wasm_smith::Config::arbitrary(u)?;
+
+    // Restrict modules to what reef_interpreter supports
+    config.max_imports = 0;
+    config.max_memories = 1;
+    config.max_memory32_pages = 16;
+    config.memory64_enabled = false;
+    config.simd_enabled = false;
+    config.relaxed_simd_enabled = false;
+    config.threads_enabled = false;
+    config.exceptions_enabled = false;
+    config.tail_call_enabled = false;
+
+    // NaN bit patterns are allowed to differ between engines
+    config.canonicalize_nans = true;
+    config.export_everything = true;
+
+    Ok(wasm_smith::Module::new(config, u)?.to_bytes())
+}
+
+fn compare(wasm: &[u8]) {
+    let Ok(module) = reef_interpreter::parse_bytes(wasm) else { return };
+    let exports: Vec<(String, Vec<ValType>)> = module
+        .exports()
+        .filter_map(|export| match export.ty {
+            ExternType::Func(ty) => Some((export.name.to_string(), ty.params.to_vec())),
+            _ => None,
+        })
+        .collect();
+    let memories: Vec<String> = module
+        .exports()
+        .filter(|export| matches!(export.ty, ExternType::Memory(_)))
+        .map(|export| export.name.to_string())
+        .collect();
+
+    let mut config = wasmi::Config::default();
+    config.consume_fuel(true);
+    let engine = wasmi::Engine::new(&config);
+    let mut store = wasmi::Store::new(&engine, ());
+    store.set_fuel(FUEL).unwrap();
+    let wasmi_module = wasmi::Module::new(&engine, wasm).expect("wasmi rejected a module reef_interpreter accepted");
+    let linker = wasmi::Linker::<()>::new(&engine);
+
+    let reef = Instance::instantiate_start(module, Imports::new(), FUEL as usize);
+    let wasmi = linker.instantiate(&mut store, &wasmi_module).and_then(|pre| pre.start(&mut store));
+    let (mut reef, wasmi) = match (reef, wasmi) {
+        (Ok(reef), Ok(wasmi)) => (reef, wasmi),
+        (Err(_), Err(_)) => return,
+        (reef, wasmi) => {
+            // Running out of fuel in the start function isn't a mismatch
+            if store.get_fuel().unwrap() == 0 || reef.as_ref().is_err_and(|err| err.trap().is_none()) {
+                return;
+            }
+            panic!("instantiation differs: reef {:?}, wasmi {:?}", reef.err(), wasmi.err());
+        }
+    };
+
+    for (name, params) in exports {
+        let func = wasmi.get_func(&store, &name).unwrap();
+        let mut wasmi_results: Vec<wasmi::Val> =
+            func.ty(&store).results().iter().map(|ty| wasmi::Val::default(*ty)).collect();
+        let wasmi_params: Vec<wasmi::Val> = params.iter().map(|ty| wasmi::Val::default(wasmi_type(*ty))).collect();
+        let wasmi_res = func.call(&mut store, &wasmi_params, &mut wasmi_results);
+
+        let reef_params = params.iter().map(|ty| ty.default_value()).collect();
+        let mut exec = reef.exported_func_untyped(&name).unwrap().call(reef_params, None).unwrap();
+        let reef_res = exec.run(FUEL as usize);
+        reef = Instance::from(exec);
+
+        if matches!(reef_res, Ok(CallResult::Incomplete)) || store.get_fuel().unwrap() == 0 {
+            return;
+        }
+
+        match (reef_res, wasmi_res) {
+            (Ok(CallResult::Done(reef_values)), Ok(())) => {
+                let wasmi_values: Vec<WasmValue> = wasmi_results.iter().map(from_wasmi).collect();
+                assert_eq!(
+                    reef_values.iter().map(bits).collect::<Vec<_>>(),
+                    wasmi_values.iter().map(bits).collect::<Vec<_>>(),
+                    "results of `{}` differ: reef {:?}, wasmi {:?}",
+                    name,
+                    reef_values,
+                    wasmi_values
+                );
+            }
+            (Err(reef_err), Err(_)) if reef_err.trap().is_some() => {}
+            (reef_res, wasmi_res) => panic!("`{}` differs: reef {:?}, wasmi {:?}", name, reef_res, wasmi_res),
+        }
+    }
+
+    for name in memories {
+        let wasmi_data = wasmi.get_memory(&store, &name).unwrap().data(&store).to_vec();
+        let mut memory = reef.exported_memory_mut(&name).unwrap();
+        let len = memory.page_count() * PAGE_SIZE;
+        assert!(memory.load(0, len).unwrap() == &wasmi_data[..], "memory `{}` differs", name);
+    }
+}
+
+fn wasmi_type(ty: ValType) -> wasmi::core::ValType {
+    match ty {
+        ValType::I32 => wasmi::core::ValType::I32,
+        ValType::I64 => wasmi::core::ValType::I64,
+        ValType::F32 => wasmi::core::ValType::F32,
+        ValType::F64 => wasmi::core::ValType::F64,
+        ValType::RefFunc => wasmi::core::ValType::FuncRef,
+        ValType::RefExtern => wasmi::core::ValType::ExternRef,
+    }
+}
+
+fn from_wasmi(val: &wasmi::Val) -> WasmValue {
+    match val {
+        wasmi::Val::I32(v) => WasmValue::I32(*v),
+        wasmi::Val::I64(v) => WasmValue::I64(*v),
+        wasmi::Val::F32(v) => WasmValue::F32(v.to_float()),
+        wasmi::Val::F64(v) => WasmValue::F64(v.to_float()),
+        wasmi::Val::FuncRef(f) if f.is_null() => WasmValue::RefNull(ValType::RefFunc),
+        wasmi::Val::ExternRef(r) if r.is_null() => WasmValue::RefNull(ValType::RefExtern),
+        // Non-null references can't be compared across engines
+        wasmi::Val::FuncRef(_) => WasmValue::RefFunc(0),
+        wasmi::Val::ExternRef(_) => WasmValue::RefExtern(0),
+    }
+}
+
+/// Compare floats by their bits and references only by whether they are null
+fn bits(value: &WasmValue) -> (u8, u64) {
+    match value {
+        WasmValue::I32(v) => (0, *v as u32 as u64),
+        WasmValue::I64(v) => (1, *v as u64),
+        WasmValue::F32(v) => (2, v.to_bits() as u64),
+        WasmValue::F64(v) => (3, v.to_bits()),
+        WasmValue::RefNull(_) => (4, 0),
+        WasmValue::RefFunc(_) => (5, 0),
+        WasmValue::RefExtern(_) => (6, 0),
+    }
+}
@@ -0,0 +1,9 @@
+//! Feeds arbitrary bytes to the parser, which has to reject invalid modules without panicking
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = reef_interpreter::parse_bytes(data);
+});
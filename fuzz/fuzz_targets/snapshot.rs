@@ -0,0 +1,127 @@
+//! Pauses execution of modules generated by `wasm-smith` at arbitrary fuel budgets, serializes the state,
+//! restores it into a fresh instance and continues. The final result and memory have to match an uninterrupted run.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use reef_interpreter::exec::CallResult;
+use reef_interpreter::imports::Imports;
+use reef_interpreter::types::value::WasmValue;
+use reef_interpreter::types::ExternType;
+use reef_interpreter::{Instance, PAGE_SIZE};
+use rkyv::AlignedVec;
+
+const FUEL: usize = 100_000;
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let _ = roundtrip(&mut u);
+});
+
+fn generate(u: &mut Unstructured<'_>) -> Result<Vec<u8>> {
+    let mut config = wasm_smith::Config::arbitrary(u)?;
+
+    config.max_imports = 0;
+    config.export_everything = true;
+
+    // Snapshots only cover the first memory and the globals, so keep everything else immutable
+    config.min_memories = 1;
+    config.max_memories = 1;
+    config.max_memory32_pages = 16;
+    config.memory64_enabled = false;
+    config.bulk_memory_enabled = false;
+    config.reference_types_enabled = false;
+
+    config.simd_enabled = false;
+    config.relaxed_simd_enabled = false;
+    config.threads_enabled = false;
+    config.exceptions_enabled = false;
+    config.tail_call_enabled = false;
+
+    Ok(wasm_smith::Module::new(config, u)?.to_bytes())
+}
+
+fn roundtrip(u: &mut Unstructured<'_>) -> Result<()> {
+    let wasm = generate(u)?;
+    let Ok(module) = reef_interpreter::parse_bytes(&wasm) else { return Ok(()) };
+
+    let funcs: Vec<(String, Vec<WasmValue>)> = module
+        .exports()
+        .filter_map(|export| match export.ty {
+            ExternType::Func(ty) => {
+                Some((export.name.to_string(), ty.params.iter().map(|ty| ty.default_value()).collect()))
+            }
+            _ => None,
+        })
+        .collect();
+    if funcs.is_empty() {
+        return Ok(());
+    }
+    let (name, params) = u.choose(&funcs)?;
+
+    let Ok(instance) = Instance::instantiate(module.clone(), Imports::new()) else { return Ok(()) };
+    let mut exec = instance.exported_func_untyped(name).unwrap().call(params.clone(), None).unwrap();
+    let expected = match exec.run(FUEL) {
+        Ok(CallResult::Done(res)) => Ok(res),
+        // Only traps are deterministic, other errors (like running out of fuel) can't be compared
+        Err(err) if err.trap().is_some() => Err(err.to_string()),
+        _ => return Ok(()),
+    };
+    let expected_memory = memory(Instance::from(exec));
+
+    let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+    let mut exec = instance.exported_func_untyped(name).unwrap().call(params.clone(), None).unwrap();
+    let mut fuel = FUEL;
+    let res = loop {
+        let budget = u.int_in_range(1..=fuel.min(1000))?;
+        fuel = fuel.saturating_sub(budget).max(1);
+
+        match exec.run(budget) {
+            Ok(CallResult::Incomplete) => {
+                let state = exec.serialize(AlignedVec::new()).unwrap();
+                let (instance, stack) =
+                    Instance::instantiate_with_state(module.clone(), Imports::new(), &state).unwrap();
+                exec = instance.exported_func_untyped(name).unwrap().call(params.clone(), Some(stack)).unwrap();
+            }
+            Ok(CallResult::Done(res)) => break Ok(res),
+            Ok(CallResult::Breakpoint(_)) => unreachable!("no breakpoints were set"),
+            Err(err) => break Err(err.to_string()),
+        }
+    };
+    let memory = memory(Instance::from(exec));
+
+    assert_eq!(
+        res.as_ref().map(|values| values.iter().map(bits).collect::<Vec<_>>()),
+        expected.as_ref().map(|values| values.iter().map(bits).collect::<Vec<_>>()),
+        "result of `{}` changed after restoring",
+        name
+    );
+    assert!(memory == expected_memory, "memory changed after restoring");
+    Ok(())
+}
+
+fn memory(mut instance: Instance) -> Vec<u8> {
+    let name = instance
+        .module()
+        .exports()
+        .find(|export| matches!(export.ty, ExternType::Memory(_)))
+        .map(|export| export.name.to_string());
+    let Some(name) = name else { return Vec::new() };
+    let mut memory = instance.exported_memory_mut(&name).unwrap();
+    let len = memory.page_count() * PAGE_SIZE;
+    memory.load(0, len).unwrap().to_vec()
+}
+
+/// Compare floats by their bits, so NaNs compare equal to themselves
+fn bits(value: &WasmValue) -> (u8, u64) {
+    match value {
+        WasmValue::I32(v) => (0, *v as u32 as u64),
+        WasmValue::I64(v) => (1, *v as u64),
+        WasmValue::F32(v) => (2, v.to_bits() as u64),
+        WasmValue::F64(v) => (3, v.to_bits()),
+        WasmValue::RefNull(_) => (4, 0),
+        WasmValue::RefFunc(addr) => (5, *addr as u64),
+        WasmValue::RefExtern(addr) => (6, *addr as u64),
+    }
+}
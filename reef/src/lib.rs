@@ -0,0 +1,41 @@
+#![no_std]
+#![warn(missing_docs, missing_debug_implementations, rust_2018_idioms, unreachable_pub)]
+
+//! The stable API of the Reef WebAssembly runtime
+//!
+//! Reef components should depend on this crate instead of [`reef_interpreter`]. Everything exported here follows
+//! semantic versioning, while the module layout of the interpreter may change with any release.
+//!
+//! A job is run by parsing its module, linking it with [`Imports`] and calling an export of the [`Instance`].
+//! The returned [`ExecHandle`] runs the call for a limited number of cycles at a time and can be serialized into a
+//! [`Snapshot`] in between, to continue the job later with [`Instance::instantiate_with_state`].
+//!
+//! ## Features
+//!- **`std`**\
+//!  Enables the use of `std` in the interpreter. This is enabled by default.
+//!- **`parallel`**, **`tracing`**, **`mmap`**, **`serde`**\
+//!  Enable the interpreter features of the same name.
+
+pub use reef_interpreter::error::{Error, LinkingError, ParseError, Result, SnapshotError, Trap, TrapKind};
+pub use reef_interpreter::exec::{
+    AlignmentCheck, CallResult, CallResultTyped, ExecConfig, ExecHandle, ExecHandleTyped, ExecStats, MemoryAccess,
+    SafePoint, Stack, StackLimits,
+};
+pub use reef_interpreter::func::{
+    FromWasmValueTuple, FuncHandle, FuncHandleTyped, IntoWasmValueTuple, ValTypesFromTuple,
+};
+pub use reef_interpreter::imports::{Extern, FuncContext, ImportQuota, ImportUsage, Imports, StubBehavior};
+pub use reef_interpreter::mem;
+#[cfg(feature = "std")]
+pub use reef_interpreter::output::OutputCapture;
+pub use reef_interpreter::reference::{
+    InstanceView, MemoryRef, MemoryRefLoad, MemoryRefMut, MemoryStringExt, MemoryView,
+};
+pub use reef_interpreter::types::value::{ValType, WasmValue};
+pub use reef_interpreter::types::{FeaturePolicy, ProducerVersion, ProducersField, TargetFeature};
+pub use reef_interpreter::{
+    parse_bytes, Fingerprint, Instance, Module, ParserLimits, ParserSession, MAX_PAGES, PAGE_SIZE,
+};
+
+/// The serialized state of a paused execution, see [`ExecHandle::serialize`]
+pub type Snapshot = rkyv::AlignedVec;
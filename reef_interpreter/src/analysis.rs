@@ -0,0 +1,229 @@
+//! Static analysis of parsed modules
+//!
+//! [`Module::analyze`] looks at the call graph of a module without running it, e.g. to reject modules that could
+//! call an import the host doesn't want to allow, even if they only do so through other functions.
+//!
+//! Indirect calls are resolved conservatively: a function that uses `call_indirect` is assumed to call every function
+//! in an element segment, as tables can't be tracked statically.
+
+use alloc::{vec, vec::Vec};
+
+use crate::types::{
+    instructions::{ConstInstruction, Instruction},
+    ElementItem, ExternalKind, FuncAddr, ImportKind, ImportType, Module,
+};
+
+/// The result of [`Module::analyze`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis<'m> {
+    /// Imported functions that can be called from an export or the start function, in the order they're imported
+    pub reachable_imports: Vec<ImportType<'m>>,
+    /// The largest number of functions defined in the module that can be on the call stack at once
+    ///
+    /// `None` if a recursive function can be reached, as the depth then depends on the input.
+    pub max_call_depth: Option<u32>,
+    /// The number of instructions of every function, indexed by function address
+    ///
+    /// Counts refer to the interpreter's bytecode, imported functions have none.
+    pub instructions: Vec<usize>,
+}
+
+impl Module {
+    /// Analyze the call graph of the module, starting at its exported functions and its start function
+    ///
+    /// See the [module documentation](crate::analysis) for how indirect calls are handled.
+    pub fn analyze(&self) -> Analysis<'_> {
+        let imported = self.imported_func_count() as usize;
+        let callees = self.callees();
+
+        let mut roots: Vec<FuncAddr> = self.start_func.into_iter().collect();
+        roots.extend(self.exports.iter().filter(|e| e.kind == ExternalKind::Func).map(|e| e.index));
+
+        roots.sort_unstable();
+        roots.dedup();
+        let reachable = self.reachable(&callees, &roots);
+
+        let reachable_imports = self
+            .imports()
+            .zip(&self.imports)
+            .filter(|(_, import)| matches!(import.kind, ImportKind::Function(_)))
+            .zip(&reachable)
+            .filter(|(_, reachable)| **reachable)
+            .map(|((ty, _), _)| ty)
+            .collect();
+
+        let mut instructions = vec![0; imported];
+        instructions.extend(self.funcs.iter().map(|func| func.instructions.len()));
+
+        Analysis { reachable_imports, max_call_depth: max_call_depth(&callees, imported, &roots), instructions }
+    }
+
+    /// Find a loop in the start function or a function it calls that may never end
+    ///
+    /// Only loops that call an imported function in their body are accepted, like the ones instrumented for
+    /// gas metering, as the host can stop those. Returns the function and instruction index of the first other loop.
+    pub(crate) fn find_unbounded_start_loop(&self) -> Option<(FuncAddr, usize)> {
+        let imported = self.imported_func_count();
+        let callees = self.callees();
+        let reachable = self.reachable(&callees, &[self.start_func?]);
+
+        for (idx, func) in self.funcs.iter().enumerate().filter(|(idx, _)| reachable[imported as usize + idx]) {
+            for (instr_ptr, instr) in func.instructions.iter().enumerate() {
+                let Instruction::Loop(_, end) = instr else { continue };
+                let body = &func.instructions[instr_ptr + 1..instr_ptr + *end as usize];
+                if !body.iter().any(|instr| matches!(instr, Instruction::Call(func) if *func < imported)) {
+                    return Some((imported + idx as FuncAddr, instr_ptr));
+                }
+            }
+        }
+        None
+    }
+
+    /// Which functions can be called from `roots`, indexed by function address
+    fn reachable(&self, callees: &[Vec<FuncAddr>], roots: &[FuncAddr]) -> Vec<bool> {
+        let imported = self.imported_func_count() as usize;
+
+        // functions are marked as reachable once they're pushed, so each is visited only once
+        let mut reachable = vec![false; imported + self.funcs.len()];
+        let mut stack = roots.to_vec();
+        stack.iter().for_each(|func| reachable[*func as usize] = true);
+        while let Some(func) = stack.pop() {
+            for callee in callees.get((func as usize).wrapping_sub(imported)).into_iter().flatten() {
+                if !core::mem::replace(&mut reachable[*callee as usize], true) {
+                    stack.push(*callee);
+                }
+            }
+        }
+        reachable
+    }
+
+    /// The functions every defined function may call, indexed by the function's position in `funcs`
+    fn callees(&self) -> Vec<Vec<FuncAddr>> {
+        let mut indirect: Vec<FuncAddr> = self
+            .elements
+            .iter()
+            .flat_map(|element| element.items.iter())
+            .filter_map(|item| match item {
+                ElementItem::Func(func) | ElementItem::Expr(ConstInstruction::RefFunc(func)) => Some(*func),
+                ElementItem::Expr(_) => None,
+            })
+            .collect();
+        indirect.sort_unstable();
+        indirect.dedup();
+
+        self.funcs
+            .iter()
+            .map(|func| {
+                let mut callees = Vec::new();
+                for instr in func.instructions.iter() {
+                    match instr {
+                        Instruction::Call(callee) => callees.push(*callee),
+                        Instruction::CallIndirect(..) => callees.extend_from_slice(&indirect),
+                        _ => {}
+                    }
+                }
+                callees.sort_unstable();
+                callees.dedup();
+                callees
+            })
+            .collect()
+    }
+}
+
+/// The longest chain of calls from any of the roots, or `None` if a cycle is reachable
+fn max_call_depth(callees: &[Vec<FuncAddr>], imported: usize, roots: &[FuncAddr]) -> Option<u32> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        New,
+        Visiting,
+        Done(u32),
+    }
+
+    // imported functions don't add to the depth, as they run on the host
+    let mut states = vec![State::Done(0); imported];
+    states.resize(imported + callees.len(), State::New);
+
+    let mut max = 0;
+    for root in roots {
+        // depth first search without recursion, as call graphs can be arbitrarily deep
+        let mut stack = vec![(*root, 0)];
+        while let Some((func, next)) = stack.pop() {
+            if next == 0 {
+                match states[func as usize] {
+                    State::Done(_) => continue,
+                    State::Visiting => return None,
+                    State::New => states[func as usize] = State::Visiting,
+                }
+            }
+
+            let func_callees = &callees[func as usize - imported];
+            if let Some(callee) = func_callees.get(next) {
+                stack.push((func, next + 1));
+                match states[*callee as usize] {
+                    State::Visiting => return None,
+                    State::New => stack.push((*callee, 0)),
+                    State::Done(_) => {}
+                }
+                continue;
+            }
+
+            let depth = func_callees.iter().map(|callee| match states[*callee as usize] {
+                State::Done(depth) => depth,
+                _ => unreachable!("callees are finished before their caller"),
+            });
+            states[func as usize] = State::Done(depth.max().unwrap_or(0) + 1);
+        }
+
+        if let State::Done(depth) = states[*root as usize] {
+            max = max.max(depth);
+        }
+    }
+    Some(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::parse_bytes;
+
+    #[test]
+    fn test_analyze() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "log" (func $log (param i32)))
+                (import "env" "exec" (func $exec (param i32)))
+                (import "env" "time" (func $time (result i32)))
+                (type $t (func (param i32)))
+                (table 1 funcref)
+                (elem (i32.const 0) $leaf)
+                (func $unused (call $exec (i32.const 0)))
+                (func $leaf (param i32) (call $log (local.get 0)))
+                (func $middle (param i32) (call_indirect (type $t) (local.get 0) (i32.const 0)))
+                (func (export "main") (call $middle (call $time)) (call $leaf (i32.const 1))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+
+        let analysis = module.analyze();
+        let imports: Vec<_> = analysis.reachable_imports.iter().map(|i| i.name).collect();
+        assert_eq!(imports, ["log", "time"]);
+        assert_eq!(analysis.max_call_depth, Some(3));
+        assert_eq!(analysis.instructions.len(), 7);
+        assert_eq!(analysis.instructions[..3], [0, 0, 0]);
+        assert_eq!(analysis.instructions[3], module.funcs[0].instructions.len());
+
+        let recursive = wat::parse_str(
+            r#"
+            (module
+                (func $a (call $b))
+                (func $b (call $a))
+                (func (export "main") (call $a)))
+            "#,
+        )
+        .unwrap();
+        assert_eq!(parse_bytes(&recursive).unwrap().analyze().max_call_depth, None);
+    }
+}
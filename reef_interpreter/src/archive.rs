@@ -0,0 +1,176 @@
+use alloc::format;
+use core::fmt::{self, Display};
+
+use rkyv::{
+    ser::{
+        serializers::{AlignedSerializer, AllocScratch, CompositeSerializer, SharedSerializeMap},
+        Serializer,
+    },
+    AlignedVec, Deserialize,
+};
+
+use crate::error::{Error, Result};
+use crate::types::{ArchivedModule, Module};
+
+impl Module {
+    /// Serialize the parsed module into an archive, so it can be loaded again without parsing and validating it
+    ///
+    /// The archive is a plain rkyv archive of the [`Module`], with its root object at the end of the buffer.
+    /// It can be written to disk as-is and loaded with [`Module::from_archive_bytes`] or [`Module::view_archive`].
+    pub fn to_archive(&self) -> Result<AlignedVec> {
+        let mut serializer = CompositeSerializer::new(
+            AlignedSerializer::new(AlignedVec::new()),
+            AllocScratch::new(),
+            SharedSerializeMap::new(),
+        );
+        serializer.serialize_value(self).map_err(|e| Error::Other(format!("Failed to archive module: {:?}", e)))?;
+        Ok(serializer.into_serializer().into_inner())
+    }
+
+    /// Validate an archive created by [`Module::to_archive`] and access it in place, without copying it
+    ///
+    /// This is meant for memory-mapped module caches shared between workers: only the validation pass touches the
+    /// bytes, everything else is read lazily from the archive itself. The view can be inspected (exports, imports,
+    /// memory types) without loading the module, but instances can't run on it: instantiating needs an owned module
+    /// from [`Module::from_archive_bytes`].
+    /// The bytes must be aligned to 16 bytes, which is always the case for the start of a memory mapping
+    /// or an [`AlignedVec`]. Misaligned or corrupted archives are rejected.
+    pub fn view_archive(bytes: &[u8]) -> Result<&ArchivedModule> {
+        rkyv::check_archived_root::<Module>(bytes).map_err(|e| Error::Other(format!("Invalid module archive: {}", e)))
+    }
+
+    /// Validate an archive created by [`Module::to_archive`] and load it into an owned module
+    ///
+    /// This copies the module out of the archive, which is still much cheaper than parsing and validating it again.
+    /// See [`Module::view_archive`] for the alignment requirements.
+    pub fn from_archive_bytes(bytes: &[u8]) -> Result<Self> {
+        let archived = Self::view_archive(bytes)?;
+        Ok(archived.deserialize(&mut rkyv::Infallible).unwrap_or_else(|e| match e {}))
+    }
+
+    /// A hash of the module's contents, to tell whether two modules behave the same
+    ///
+    /// Everything that only comes from custom sections (like function names) or depends on the layout of the
+    /// binary is left out, so rebuilds that only change those have the same fingerprint.
+    ///
+    /// The fingerprint hashes the [archive](Module::to_archive) of the parsed module, so it covers the exact bytecode
+    /// the interpreter runs. Archives use the native byte order, which makes the fingerprint stable between runs and
+    /// between machines of the same endianness only. It may also change between versions of this crate.
+    pub fn fingerprint(&self) -> Result<Fingerprint> {
+        let mut module = self.clone();
+        module.func_names = Default::default();
+        module.producers = Default::default();
+        module.target_features = Default::default();
+        module.clear_branch_hints();
+        module.funcs.iter_mut().for_each(|func| func.offsets = Default::default());
+        module.data.iter_mut().for_each(|data| data.range = 0..0);
+        module.elements.iter_mut().for_each(|element| element.range = 0..0);
+        Ok(Fingerprint::of(&module.to_archive()?))
+    }
+}
+
+/// A content hash of a [`Module`], see [`Module::fingerprint`]
+///
+/// Displayed as 32 hexadecimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fingerprint(pub u128);
+
+impl Fingerprint {
+    /// 128 bit FNV-1a
+    fn of(bytes: &[u8]) -> Self {
+        const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+        const PRIME: u128 = 0x0000000001000000000000000000013b;
+        Self(bytes.iter().fold(OFFSET_BASIS, |hash, byte| (hash ^ u128::from(*byte)).wrapping_mul(PRIME)))
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_bytes;
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (data (i32.const 16) "hello")
+                (func (export "add") (param i32 i32) (result i32)
+                    (i32.add (local.get 0) (local.get 1))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let archive = module.to_archive().unwrap();
+
+        let view = Module::view_archive(&archive).unwrap();
+        assert_eq!(view.exports.len(), 2);
+        assert_eq!(view.funcs[0].instructions.len(), module.funcs[0].instructions.len());
+        assert_eq!(Module::from_archive_bytes(&archive).unwrap(), module);
+
+        assert!(Module::view_archive(&archive[..archive.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_archive_large_module() {
+        // a long function body and many functions both need more scratch space than a fixed buffer would have
+        let body = "(drop (i32.const 1))".repeat(5000);
+        let funcs = "(func (drop (i32.add (i32.const 1) (i32.const 2))))".repeat(1000);
+        let wasm = wat::parse_str(alloc::format!("(module (func {}) {})", body, funcs)).unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        assert!(module.funcs[0].instructions.len() > 5000);
+
+        let archive = module.to_archive().unwrap();
+        assert_eq!(Module::from_archive_bytes(&archive).unwrap(), module);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprint = |wat: &str| parse_bytes(&wat::parse_str(wat).unwrap()).unwrap().fingerprint().unwrap();
+        let module = fingerprint(
+            r#"
+            (module
+                (memory 1)
+                (data (i32.const 16) "hello")
+                (func $add (export "add") (param i32 i32) (result i32)
+                    (i32.add (local.get 0) (local.get 1))))
+            "#,
+        );
+
+        // names and custom sections shift everything around, but don't change what the module does
+        let rebuilt = fingerprint(
+            r#"
+            (module
+                (@custom "producers" (before first) "rebuilt")
+                (memory 1)
+                (data (i32.const 16) "hello")
+                (func $sum (export "add") (param $a i32) (param $b i32) (result i32)
+                    (i32.add (local.get $a) (local.get $b))))
+            "#,
+        );
+        assert_eq!(module, rebuilt);
+
+        let changed = fingerprint(
+            r#"
+            (module
+                (memory 1)
+                (data (i32.const 16) "hellO")
+                (func $add (export "add") (param i32 i32) (result i32)
+                    (i32.add (local.get 0) (local.get 1))))
+            "#,
+        );
+        assert_ne!(module, changed);
+        assert_eq!(alloc::format!("{}", module).len(), 32);
+
+        let funcs = "(func (drop (i32.add (i32.const 1) (i32.const 2))))".repeat(1000);
+        let large = alloc::format!("(module {})", funcs);
+        assert_eq!(fingerprint(&large), fingerprint(&large));
+    }
+}
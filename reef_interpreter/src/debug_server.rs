@@ -0,0 +1,505 @@
+//! A Debug Adapter Protocol server for debugging guests from an IDE
+//!
+//! The server speaks the [Debug Adapter Protocol](https://microsoft.github.io/debug-adapter-protocol/)
+//! over a single connection and drives an [`ExecHandle`] through its breakpoints and steps.
+//! There is no mapping back to the guest's source code, so breakpoints are set with instruction breakpoints,
+//! which reference interpreter instructions as `"<func>:<instr>"`.
+//!
+//! Supported requests are `initialize`, `launch`, `attach`, `setInstructionBreakpoints`, `configurationDone`,
+//! `threads`, `stackTrace`, `scopes`, `variables`, `readMemory`, `continue`, `next`, `stepIn`, `stepOut`,
+//! `pause` and `disconnect`.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use serde_json::{json, Value};
+
+use crate::error::{Error, Result};
+use crate::exec::{CallResult, ExecHandle, FrameState, StepResult};
+
+/// Number of instructions executed between checks for the end of execution while continuing
+const CONTINUE_CYCLES: usize = 1 << 20;
+
+/// How far a step request runs
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    /// A single instruction, entering calls (`stepIn`)
+    In,
+    /// Until the next instruction of the current function, running calls to completion (`next`)
+    Over,
+    /// Until the current function returned (`stepOut`)
+    Out,
+}
+
+/// A Debug Adapter Protocol server for a single client
+#[derive(Debug)]
+pub struct DebugServer<S: Read + Write> {
+    reader: BufReader<S>,
+    seq: i64,
+}
+
+impl DebugServer<TcpStream> {
+    /// Listen on the given address and wait for a debugger to connect
+    pub fn accept(addr: impl ToSocketAddrs) -> Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: Read + Write> DebugServer<S> {
+    /// Create a server talking to a client over an established connection
+    pub fn new(stream: S) -> Self {
+        Self { reader: BufReader::new(stream), seq: 1 }
+    }
+
+    /// Handle requests until execution finishes or the client disconnects
+    ///
+    /// Returns [`CallResult::Incomplete`] if the client disconnected before the function returned,
+    /// in which case the handle can be used to continue execution without the debugger.
+    pub fn run(&mut self, exec: &mut ExecHandle) -> Result<CallResult> {
+        while let Some(request) = self.read_message()? {
+            let command = request["command"].as_str().unwrap_or_default().to_string();
+            let args = &request["arguments"];
+
+            let result = match command.as_str() {
+                "initialize" => {
+                    self.respond(&request, Ok(capabilities()))?;
+                    self.event("initialized", json!({}))?;
+                    continue;
+                }
+                "launch" | "attach" | "pause" => Ok(json!({})),
+                "setInstructionBreakpoints" => set_breakpoints(exec, args),
+                "configurationDone" => {
+                    self.respond(&request, Ok(json!({})))?;
+                    self.stopped("entry")?;
+                    continue;
+                }
+                "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "main" }] })),
+                "stackTrace" => stack_trace(exec),
+                "scopes" => scopes(args),
+                "variables" => variables(exec, args),
+                "readMemory" => read_memory(exec, args),
+                "continue" | "next" | "stepIn" | "stepOut" => {
+                    self.respond(&request, Ok(json!({ "allThreadsContinued": true })))?;
+                    let finished = match command.as_str() {
+                        "continue" => self.resume(exec)?,
+                        "next" => self.step(exec, Step::Over)?,
+                        "stepIn" => self.step(exec, Step::In)?,
+                        _ => self.step(exec, Step::Out)?,
+                    };
+                    if let Some(res) = finished {
+                        let code = match res {
+                            CallResult::Exit(code) => code,
+                            _ => 0,
+                        };
+                        self.event("exited", json!({ "exitCode": code }))?;
+                        self.event("terminated", json!({}))?;
+                        return Ok(res);
+                    }
+                    continue;
+                }
+                "disconnect" => {
+                    self.respond(&request, Ok(json!({})))?;
+                    break;
+                }
+                command => Err(format!("Unsupported request: {}", command)),
+            };
+
+            self.respond(&request, result)?;
+        }
+
+        Ok(CallResult::Incomplete)
+    }
+
+    /// Run until the next breakpoint, returning the result if the function finished or exited
+    fn resume(&mut self, exec: &mut ExecHandle) -> Result<Option<CallResult>> {
+        loop {
+            match exec.run(CONTINUE_CYCLES)? {
+                res @ (CallResult::Done(_) | CallResult::Exit(_)) => return Ok(Some(res)),
+                CallResult::Incomplete => continue,
+                CallResult::Breakpoint(_) => {
+                    self.stopped("breakpoint")?;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Step until the call depth is at most the one `step` stops at, or a breakpoint is reached
+    fn step(&mut self, exec: &mut ExecHandle, step: Step) -> Result<Option<CallResult>> {
+        let depth = exec.stack.call_stack.len();
+        let max_depth = match step {
+            Step::In => usize::MAX,
+            Step::Over => depth,
+            Step::Out => depth.saturating_sub(1),
+        };
+
+        loop {
+            match exec.step(1)? {
+                StepResult::Done(res) => return Ok(Some(CallResult::Done(res))),
+                StepResult::Exit(code) => return Ok(Some(CallResult::Exit(code))),
+                StepResult::Paused(frame) if frame.at_breakpoint => {
+                    self.stopped("breakpoint")?;
+                    return Ok(None);
+                }
+                StepResult::Paused(frame) if frame.depth <= max_depth => {
+                    self.stopped("step")?;
+                    return Ok(None);
+                }
+                StepResult::Paused(_) => {}
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) -> Result<()> {
+        self.event("stopped", json!({ "reason": reason, "threadId": 1, "allThreadsStopped": true }))
+    }
+
+    fn respond(&mut self, request: &Value, result: core::result::Result<Value, String>) -> Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        message["seq"] = self.seq.into();
+        self.seq += 1;
+
+        let body = message.to_string();
+        let stream = self.reader.get_mut();
+        write!(stream, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Read the next message, returning `None` once the client closed the connection
+    fn read_message(&mut self) -> Result<Option<Value>> {
+        let mut content_length = None;
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(len) = line.strip_prefix("Content-Length:") {
+                content_length = len.trim().parse::<usize>().ok();
+            }
+        }
+
+        let len = content_length.ok_or_else(|| Error::Other("Missing Content-Length header".to_string()))?;
+        let mut body = alloc::vec![0; len];
+        self.reader.read_exact(&mut body)?;
+        serde_json::from_slice(&body).map(Some).map_err(|e| Error::Other(format!("Invalid DAP message: {}", e)))
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsReadMemoryRequest": true,
+        "supportsSteppingGranularity": true,
+    })
+}
+
+fn parse_instruction_reference(reference: &str, offset: i64) -> Option<(u32, usize)> {
+    let (func, instr) = reference.split_once(':')?;
+    let instr = instr.parse::<i64>().ok()?.checked_add(offset)?;
+    Some((func.parse().ok()?, instr.try_into().ok()?))
+}
+
+fn set_breakpoints(exec: &mut ExecHandle, args: &Value) -> core::result::Result<Value, String> {
+    exec.clear_breakpoints();
+    let breakpoints = args["breakpoints"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let breakpoints = breakpoints
+        .iter()
+        .map(|bp| {
+            let reference = bp["instructionReference"].as_str().unwrap_or_default();
+            match parse_instruction_reference(reference, bp["offset"].as_i64().unwrap_or(0)) {
+                Some((func, instr)) => {
+                    exec.add_breakpoint(func, instr);
+                    json!({ "verified": true, "instructionReference": format!("{}:{}", func, instr) })
+                }
+                None => json!({ "verified": false, "message": "Invalid instruction reference" }),
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({ "breakpoints": breakpoints }))
+}
+
+fn frame_name(exec: &ExecHandle, frame: &FrameState) -> String {
+    match exec.func_handle.instance.module.func_name(frame.func) {
+        Some(name) => name.to_string(),
+        None => format!("func[{}]", frame.func),
+    }
+}
+
+fn stack_trace(exec: &ExecHandle) -> core::result::Result<Value, String> {
+    let frames = exec.backtrace().map_err(|e| e.to_string())?;
+    let frames = frames
+        .iter()
+        .map(|frame| {
+            json!({
+                "id": frame.depth,
+                "name": frame_name(exec, frame),
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("{}:{}", frame.func, frame.instr_ptr),
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+}
+
+fn scopes(args: &Value) -> core::result::Result<Value, String> {
+    // frame ids are the depth of the frame, so they can be used as variable references directly
+    let frame = args["frameId"].as_u64().ok_or("Missing frameId")?;
+    Ok(json!({ "scopes": [{ "name": "Locals", "variablesReference": frame, "expensive": false }] }))
+}
+
+fn variables(exec: &ExecHandle, args: &Value) -> core::result::Result<Value, String> {
+    let depth = args["variablesReference"].as_u64().ok_or("Missing variablesReference")? as usize;
+    let frames = exec.backtrace().map_err(|e| e.to_string())?;
+    let frame = frames.iter().find(|f| f.depth == depth).ok_or("Unknown frame")?;
+
+    let variables = frame
+        .locals
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| {
+            json!({
+                "name": format!("local{}", idx),
+                "value": format!("{:?}", value),
+                "type": format!("{:?}", value.val_type()),
+                "variablesReference": 0,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({ "variables": variables }))
+}
+
+fn read_memory(exec: &ExecHandle, args: &Value) -> core::result::Result<Value, String> {
+    let mem =
+        args["memoryReference"].as_str().and_then(|r| r.parse::<usize>().ok()).ok_or("Invalid memoryReference")?;
+    let mem = exec.func_handle.instance.memories.get(mem).ok_or("Unknown memory")?;
+
+    let offset = args["offset"].as_u64().unwrap_or(0) as usize;
+    let start = offset.min(mem.data.len());
+    let end = offset.saturating_add(args["count"].as_u64().unwrap_or(0) as usize).min(mem.data.len());
+    let data = mem.load(start, end - start).map_err(|e| e.to_string())?;
+
+    Ok(json!({
+        "address": format!("0x{:x}", offset),
+        "unreadableBytes": args["count"].as_u64().unwrap_or(0) as usize - data.len(),
+        "data": base64(data),
+    }))
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::Imports;
+    use crate::types::value::WasmValue;
+    use crate::{parse_bytes, Instance};
+
+    /// A connection that replays scripted requests and collects the responses
+    struct Script {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn script(requests: &[Value]) -> Script {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = (seq + 1).into();
+            request["type"] = "request".into();
+            let body = request.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        Script { input: std::io::Cursor::new(input), output: Vec::new() }
+    }
+
+    fn messages(output: &[u8]) -> Vec<Value> {
+        let mut reader = DebugServer::new(Script { input: std::io::Cursor::new(output.to_vec()), output: Vec::new() });
+        core::iter::from_fn(|| reader.read_message().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_debug_session() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory 1)
+                (data (i32.const 0) "hi")
+                (func $double (param i32) (result i32)
+                    (i32.add (local.get 0) (local.get 0)))
+                (func (export "main") (result i32)
+                    (call $double (i32.const 21))))
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+
+        let mut server = DebugServer::new(script(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "setInstructionBreakpoints", "arguments": { "breakpoints": [{ "instructionReference": "0:0" }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+            json!({ "command": "readMemory", "arguments": { "memoryReference": "0", "count": 2 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        ]));
+
+        let res = server.run(&mut exec).unwrap();
+        assert!(matches!(res, CallResult::Done(res) if res == [WasmValue::I32(42)]));
+
+        let messages = messages(&server.reader.get_ref().output);
+        let response = |command: &str| {
+            messages.iter().find(|m| m["type"] == "response" && m["command"] == command).unwrap()["body"].clone()
+        };
+
+        assert_eq!(response("setInstructionBreakpoints")["breakpoints"][0]["verified"], true);
+        let frames = response("stackTrace")["stackFrames"].clone();
+        assert_eq!(frames[0]["name"], "double");
+        assert_eq!(frames[0]["instructionPointerReference"], "0:0");
+        assert_eq!(frames[1]["id"], 1);
+        assert_eq!(response("variables")["variables"][0]["type"], "I32");
+        assert_eq!(response("readMemory")["data"], "aGk=");
+
+        let stops: Vec<_> = messages.iter().filter(|m| m["event"] == "stopped").map(|m| &m["body"]["reason"]).collect();
+        assert_eq!(stops, ["entry", "breakpoint"]);
+        assert_eq!(messages.last().unwrap()["event"], "terminated");
+    }
+
+    /// Run a session calling `$double` three times from `main` and collect the stack depth after every stop
+    fn stack_depths(requests: &[Value]) -> Vec<usize> {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func $double (param i32) (result i32)
+                    (i32.add (local.get 0) (local.get 0)))
+                (func (export "main") (result i32)
+                    (drop (call $double (i32.const 1)))
+                    (drop (call $double (i32.const 2)))
+                    (call $double (i32.const 21))))
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+
+        let mut server = DebugServer::new(script(requests));
+        let res = server.run(&mut exec).unwrap();
+        assert!(matches!(res, CallResult::Done(res) if res == [WasmValue::I32(42)]));
+
+        let messages = messages(&server.reader.get_ref().output);
+        assert_eq!(messages.last().unwrap()["event"], "terminated");
+        messages
+            .iter()
+            .filter(|m| m["type"] == "response" && m["command"] == "stackTrace")
+            .map(|m| m["body"]["stackFrames"].as_array().unwrap().len())
+            .collect()
+    }
+
+    fn steps(command: &str) -> Vec<Value> {
+        let mut requests =
+            alloc::vec![json!({ "command": "initialize", "arguments": {} }), json!({ "command": "configurationDone" })];
+        for _ in 0..32 {
+            requests.push(json!({ "command": command, "arguments": { "threadId": 1 } }));
+            requests.push(json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }));
+        }
+        requests
+    }
+
+    #[test]
+    fn test_step_over_and_in() {
+        let depths = stack_depths(&steps("next"));
+        assert!(!depths.is_empty());
+        assert!(depths.iter().all(|&depth| depth == 1), "{depths:?}");
+
+        let depths = stack_depths(&steps("stepIn"));
+        assert!(depths.contains(&2), "{depths:?}");
+    }
+
+    #[test]
+    fn test_step_out() {
+        let depths = stack_depths(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "setInstructionBreakpoints", "arguments": { "breakpoints": [{ "instructionReference": "0:0" }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "setInstructionBreakpoints", "arguments": { "breakpoints": [] } }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+        ]);
+        // stepping out of `$double` stops in `main`, stepping out of `main` finishes the call
+        assert_eq!(depths, [2, 1]);
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+}
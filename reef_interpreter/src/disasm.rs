@@ -0,0 +1,372 @@
+//! Disassembly of the interpreter's bytecode
+//!
+//! [`Module::disassemble`] lists the instructions of a function as the parser produced them, which can differ from
+//! the original module: blocks store the positions of their `else` and `end`, `br_table` labels follow it as separate
+//! `br_label` instructions, and common sequences are fused into a single instruction.
+//!
+//! Instructions are formatted like the text format where one exists. Everything that is only known to the
+//! interpreter is added as a `;;` comment, with positions of other instructions written as `@` and their index.
+//! Fused instructions get a name made up from the instructions they replace, e.g. `i32.add_locals 0 1`.
+
+use core::fmt::{self, Display, Formatter};
+
+use crate::types::{
+    instructions::{BlockArgs, BranchHint, Instruction},
+    value::ValType,
+    FuncAddr, MemAddr, Module,
+};
+
+/// An instruction formatted like the text format, see the [module documentation](crate::disasm)
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionDisplay<'m> {
+    module: &'m Module,
+    instr_ptr: usize,
+    instr: &'m Instruction,
+}
+
+impl<'m> InstructionDisplay<'m> {
+    /// The instruction itself
+    pub fn instruction(&self) -> &'m Instruction {
+        self.instr
+    }
+
+    /// Position of an instruction `offset` instructions after this one
+    fn target(&self, offset: u32) -> usize {
+        self.instr_ptr + offset as usize
+    }
+}
+
+impl Module {
+    /// List the instructions of the function at `func_idx` with their index in the interpreter's bytecode
+    ///
+    /// The index is the one used by breakpoints and [`TraceEvent::Instruction`](crate::exec::TraceEvent::Instruction),
+    /// [`Module::resolve_location`] maps it to the original module binary. Imported functions have no instructions.
+    pub fn disassemble(&self, func_idx: FuncAddr) -> impl Iterator<Item = (usize, InstructionDisplay<'_>)> + '_ {
+        let func = func_idx.checked_sub(self.imported_func_count()).and_then(|idx| self.funcs.get(idx as usize));
+        let instrs = func.map(|func| &*func.instructions).unwrap_or_default();
+        instrs
+            .iter()
+            .enumerate()
+            .map(|(instr_ptr, instr)| (instr_ptr, InstructionDisplay { module: self, instr_ptr, instr }))
+    }
+}
+
+impl Display for InstructionDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self.instr {
+            BrLabel(label) => write!(f, "br_label {}", label),
+            I32LocalGetConstAdd(local, value) => write!(f, "i32.local_get_const_add {} {}", local, value),
+            I32StoreLocal { local, const_i32, offset, mem_addr, align } => {
+                write!(f, "i32.store_local {} {}", local, const_i32)?;
+                memarg(f, MemAddr::from(mem_addr), offset, align, 2)
+            }
+            I64XorConstRotl(value) => write!(f, "i64.xor_const_rotl {}", value),
+            LocalTeeGet(a, b) => write!(f, "local.tee_get {} {}", a, b),
+            LocalGet2(a, b) => write!(f, "local.get2 {} {}", a, b),
+            LocalGet3(a, b, c) => write!(f, "local.get3 {} {} {}", a, b, c),
+            LocalGetSet(a, b) => write!(f, "local.get_set {} {}", a, b),
+            I32AddLocals(a, b) => write!(f, "i32.add_locals {} {}", a, b),
+            I32AddConst(value) => write!(f, "i32.add_const {}", value),
+            I32EqzBrIf(label, hint) => {
+                write!(f, "i32.eqz_br_if {}", label)?;
+                branch_hint(f, hint)
+            }
+            I32CmpBrIf(cmp, label, hint) => {
+                write!(f, "{}_br_if {}", numeric_name(&cmp.instruction()).unwrap_or("i32.cmp"), label)?;
+                branch_hint(f, hint)
+            }
+            FloatDenied => write!(f, "float_denied"),
+
+            Unreachable => write!(f, "unreachable"),
+            Nop => write!(f, "nop"),
+            Block(args, end) => {
+                write!(f, "block{}", Args(args))?;
+                write!(f, " ;; end @{}", self.target(end))
+            }
+            Loop(args, end) => {
+                write!(f, "loop{}", Args(args))?;
+                write!(f, " ;; end @{}", self.target(end))
+            }
+            If(args, else_offset, end, hint) => {
+                write!(f, "if{}", Args(args.into()))?;
+                match else_offset {
+                    0 => write!(f, " ;; end @{}", self.target(end))?,
+                    _ => write!(f, " ;; else @{}, end @{}", self.target(else_offset), self.target(end))?,
+                }
+                match hint {
+                    BranchHint::Unknown => Ok(()),
+                    BranchHint::Likely => write!(f, ", likely"),
+                    BranchHint::Unlikely => write!(f, ", unlikely"),
+                }
+            }
+            Else(end) => write!(f, "else ;; end @{}", self.target(end)),
+            EndBlockFrame => write!(f, "end"),
+            Br(label) => write!(f, "br {}", label),
+            BrIf(label, hint) => {
+                write!(f, "br_if {}", label)?;
+                branch_hint(f, hint)
+            }
+            BrTable(default, len) => write!(f, "br_table {} ;; default, {} br_label follow", default, len),
+            Return => write!(f, "return"),
+            Call(func) => {
+                write!(f, "call {}", func)?;
+                match self.module.func_name(func) {
+                    Some(name) => write!(f, " ;; {}", name),
+                    None => Ok(()),
+                }
+            }
+            CallIndirect(ty, table) => write!(f, "call_indirect {} (type {})", table, ty),
+
+            Drop => write!(f, "drop"),
+            Select(None) => write!(f, "select"),
+            Select(Some(ty)) => write!(f, "select (result {})", ty),
+
+            LocalGet(idx) => write!(f, "local.get {}", idx),
+            LocalSet(idx) => write!(f, "local.set {}", idx),
+            LocalTee(idx) => write!(f, "local.tee {}", idx),
+            GlobalGet(idx) => write!(f, "global.get {}", idx),
+            GlobalSet(idx) => write!(f, "global.set {}", idx),
+
+            I32Load { offset, mem_addr, align } => load_store(f, "i32.load", mem_addr, offset, align, 2),
+            I64Load { offset, mem_addr, align } => load_store(f, "i64.load", mem_addr, offset, align, 3),
+            F32Load { offset, mem_addr, align } => load_store(f, "f32.load", mem_addr, offset, align, 2),
+            F64Load { offset, mem_addr, align } => load_store(f, "f64.load", mem_addr, offset, align, 3),
+            I32Load8S { offset, mem_addr, align } => load_store(f, "i32.load8_s", mem_addr, offset, align, 0),
+            I32Load8U { offset, mem_addr, align } => load_store(f, "i32.load8_u", mem_addr, offset, align, 0),
+            I32Load16S { offset, mem_addr, align } => load_store(f, "i32.load16_s", mem_addr, offset, align, 1),
+            I32Load16U { offset, mem_addr, align } => load_store(f, "i32.load16_u", mem_addr, offset, align, 1),
+            I64Load8S { offset, mem_addr, align } => load_store(f, "i64.load8_s", mem_addr, offset, align, 0),
+            I64Load8U { offset, mem_addr, align } => load_store(f, "i64.load8_u", mem_addr, offset, align, 0),
+            I64Load16S { offset, mem_addr, align } => load_store(f, "i64.load16_s", mem_addr, offset, align, 1),
+            I64Load16U { offset, mem_addr, align } => load_store(f, "i64.load16_u", mem_addr, offset, align, 1),
+            I64Load32S { offset, mem_addr, align } => load_store(f, "i64.load32_s", mem_addr, offset, align, 2),
+            I64Load32U { offset, mem_addr, align } => load_store(f, "i64.load32_u", mem_addr, offset, align, 2),
+            I32Store { offset, mem_addr, align } => load_store(f, "i32.store", mem_addr, offset, align, 2),
+            I64Store { offset, mem_addr, align } => load_store(f, "i64.store", mem_addr, offset, align, 3),
+            F32Store { offset, mem_addr, align } => load_store(f, "f32.store", mem_addr, offset, align, 2),
+            F64Store { offset, mem_addr, align } => load_store(f, "f64.store", mem_addr, offset, align, 3),
+            I32Store8 { offset, mem_addr, align } => load_store(f, "i32.store8", mem_addr, offset, align, 0),
+            I32Store16 { offset, mem_addr, align } => load_store(f, "i32.store16", mem_addr, offset, align, 1),
+            I64Store8 { offset, mem_addr, align } => load_store(f, "i64.store8", mem_addr, offset, align, 0),
+            I64Store16 { offset, mem_addr, align } => load_store(f, "i64.store16", mem_addr, offset, align, 1),
+            I64Store32 { offset, mem_addr, align } => load_store(f, "i64.store32", mem_addr, offset, align, 2),
+            MemorySize(mem, _) => with_index(f, "memory.size", mem),
+            MemoryGrow(mem, _) => with_index(f, "memory.grow", mem),
+
+            I32Const(value) => write!(f, "i32.const {}", value),
+            I64Const(value) => write!(f, "i64.const {}", value),
+            F32Const(value) => write!(f, "f32.const {}", Float(value.into(), value.to_bits().into(), 23)),
+            F64Const(value) => write!(f, "f64.const {}", Float(value, value.to_bits(), 52)),
+
+            RefNull(ValType::RefExtern) => write!(f, "ref.null extern"),
+            RefNull(_) => write!(f, "ref.null func"),
+            RefFunc(func) => write!(f, "ref.func {}", func),
+            RefIsNull => write!(f, "ref.is_null"),
+
+            // The parser stores the operands of these in the order they appear in the binary format
+            TableInit(elem, table) => write!(f, "table.init {} {}", table, elem),
+            TableGet(table) => write!(f, "table.get {}", table),
+            TableSet(table) => write!(f, "table.set {}", table),
+            TableCopy { from, to } => write!(f, "table.copy {} {}", to, from),
+            TableGrow(table) => write!(f, "table.grow {}", table),
+            TableSize(table) => write!(f, "table.size {}", table),
+            TableFill(table) => write!(f, "table.fill {}", table),
+            MemoryInit(data, mem) => write!(f, "memory.init {} {}", mem, data),
+            MemoryCopy(dst, src) => write!(f, "memory.copy {} {}", dst, src),
+            MemoryFill(mem) => with_index(f, "memory.fill", mem),
+            DataDrop(data) => write!(f, "data.drop {}", data),
+
+            ref numeric => f.write_str(numeric_name(numeric).unwrap_or("unknown")),
+        }
+    }
+}
+
+/// The type of a block, like `(result i32)`, with a leading space unless it's empty
+struct Args(BlockArgs);
+
+impl Display for Args {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            BlockArgs::Empty => Ok(()),
+            BlockArgs::Type(ty) => write!(f, " (result {})", ty),
+            BlockArgs::FuncType(ty) => write!(f, " (type {})", ty),
+        }
+    }
+}
+
+/// A float constant, with the payload of NaNs written out as the text format does
+struct Float(f64, u64, u32);
+
+impl Display for Float {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Self(value, bits, mantissa_bits) = *self;
+        if !value.is_nan() {
+            return write!(f, "{}", value);
+        }
+        let sign = if value.is_sign_negative() { "-" } else { "" };
+        write!(f, "{}nan:0x{:x}", sign, bits & ((1 << mantissa_bits) - 1))
+    }
+}
+
+fn branch_hint(f: &mut Formatter<'_>, hint: BranchHint) -> fmt::Result {
+    match hint {
+        BranchHint::Unknown => Ok(()),
+        BranchHint::Likely => write!(f, " ;; likely"),
+        BranchHint::Unlikely => write!(f, " ;; unlikely"),
+    }
+}
+
+/// An instruction with a memory index, which is left out for the first memory
+fn with_index(f: &mut Formatter<'_>, name: &str, mem: MemAddr) -> fmt::Result {
+    match mem {
+        0 => f.write_str(name),
+        _ => write!(f, "{} {}", name, mem),
+    }
+}
+
+fn load_store(f: &mut Formatter<'_>, name: &str, mem: MemAddr, offset: u32, align: u8, natural: u8) -> fmt::Result {
+    f.write_str(name)?;
+    memarg(f, mem, offset, align, natural)
+}
+
+/// The immediates of a load or store, leaving out the defaults like the text format
+fn memarg(f: &mut Formatter<'_>, mem: MemAddr, offset: u32, align: u8, natural: u8) -> fmt::Result {
+    if mem != 0 {
+        write!(f, " {}", mem)?;
+    }
+    if offset != 0 {
+        write!(f, " offset={}", offset)?;
+    }
+    if align != natural {
+        write!(f, " align={}", 1u32 << align)?;
+    }
+    Ok(())
+}
+
+/// Names of the instructions without immediates
+#[rustfmt::skip]
+fn numeric_name(instr: &Instruction) -> Option<&'static str> {
+    use Instruction::*;
+    let name = match instr {
+        I32Eqz => "i32.eqz", I32Eq => "i32.eq", I32Ne => "i32.ne", I32LtS => "i32.lt_s", I32LtU => "i32.lt_u",
+        I32GtS => "i32.gt_s", I32GtU => "i32.gt_u", I32LeS => "i32.le_s", I32LeU => "i32.le_u", I32GeS => "i32.ge_s",
+        I32GeU => "i32.ge_u",
+        I64Eqz => "i64.eqz", I64Eq => "i64.eq", I64Ne => "i64.ne", I64LtS => "i64.lt_s", I64LtU => "i64.lt_u",
+        I64GtS => "i64.gt_s", I64GtU => "i64.gt_u", I64LeS => "i64.le_s", I64LeU => "i64.le_u", I64GeS => "i64.ge_s",
+        I64GeU => "i64.ge_u",
+        F32Eq => "f32.eq", F32Ne => "f32.ne", F32Lt => "f32.lt", F32Gt => "f32.gt", F32Le => "f32.le", F32Ge => "f32.ge",
+        F64Eq => "f64.eq", F64Ne => "f64.ne", F64Lt => "f64.lt", F64Gt => "f64.gt", F64Le => "f64.le", F64Ge => "f64.ge",
+        I32Clz => "i32.clz", I32Ctz => "i32.ctz", I32Popcnt => "i32.popcnt", I32Add => "i32.add", I32Sub => "i32.sub",
+        I32Mul => "i32.mul", I32DivS => "i32.div_s", I32DivU => "i32.div_u", I32RemS => "i32.rem_s",
+        I32RemU => "i32.rem_u", I32And => "i32.and", I32Or => "i32.or", I32Xor => "i32.xor", I32Shl => "i32.shl",
+        I32ShrS => "i32.shr_s", I32ShrU => "i32.shr_u", I32Rotl => "i32.rotl", I32Rotr => "i32.rotr",
+        I64Clz => "i64.clz", I64Ctz => "i64.ctz", I64Popcnt => "i64.popcnt", I64Add => "i64.add", I64Sub => "i64.sub",
+        I64Mul => "i64.mul", I64DivS => "i64.div_s", I64DivU => "i64.div_u", I64RemS => "i64.rem_s",
+        I64RemU => "i64.rem_u", I64And => "i64.and", I64Or => "i64.or", I64Xor => "i64.xor", I64Shl => "i64.shl",
+        I64ShrS => "i64.shr_s", I64ShrU => "i64.shr_u", I64Rotl => "i64.rotl", I64Rotr => "i64.rotr",
+        F32Abs => "f32.abs", F32Neg => "f32.neg", F32Ceil => "f32.ceil", F32Floor => "f32.floor",
+        F32Trunc => "f32.trunc", F32Nearest => "f32.nearest", F32Sqrt => "f32.sqrt", F32Add => "f32.add",
+        F32Sub => "f32.sub", F32Mul => "f32.mul", F32Div => "f32.div", F32Min => "f32.min", F32Max => "f32.max",
+        F32Copysign => "f32.copysign",
+        F64Abs => "f64.abs", F64Neg => "f64.neg", F64Ceil => "f64.ceil", F64Floor => "f64.floor",
+        F64Trunc => "f64.trunc", F64Nearest => "f64.nearest", F64Sqrt => "f64.sqrt", F64Add => "f64.add",
+        F64Sub => "f64.sub", F64Mul => "f64.mul", F64Div => "f64.div", F64Min => "f64.min", F64Max => "f64.max",
+        F64Copysign => "f64.copysign",
+        I32WrapI64 => "i32.wrap_i64", I32TruncF32S => "i32.trunc_f32_s", I32TruncF32U => "i32.trunc_f32_u",
+        I32TruncF64S => "i32.trunc_f64_s", I32TruncF64U => "i32.trunc_f64_u",
+        I64ExtendI32S => "i64.extend_i32_s", I64ExtendI32U => "i64.extend_i32_u", I64TruncF32S => "i64.trunc_f32_s",
+        I64TruncF32U => "i64.trunc_f32_u", I64TruncF64S => "i64.trunc_f64_s", I64TruncF64U => "i64.trunc_f64_u",
+        F32ConvertI32S => "f32.convert_i32_s", F32ConvertI32U => "f32.convert_i32_u",
+        F32ConvertI64S => "f32.convert_i64_s", F32ConvertI64U => "f32.convert_i64_u", F32DemoteF64 => "f32.demote_f64",
+        F64ConvertI32S => "f64.convert_i32_s", F64ConvertI32U => "f64.convert_i32_u",
+        F64ConvertI64S => "f64.convert_i64_s", F64ConvertI64U => "f64.convert_i64_u", F64PromoteF32 => "f64.promote_f32",
+        I32ReinterpretF32 => "i32.reinterpret_f32", I64ReinterpretF64 => "i64.reinterpret_f64",
+        F32ReinterpretI32 => "f32.reinterpret_i32", F64ReinterpretI64 => "f64.reinterpret_i64",
+        I32Extend8S => "i32.extend8_s", I32Extend16S => "i32.extend16_s", I64Extend8S => "i64.extend8_s",
+        I64Extend16S => "i64.extend16_s", I64Extend32S => "i64.extend32_s",
+        I32TruncSatF32S => "i32.trunc_sat_f32_s", I32TruncSatF32U => "i32.trunc_sat_f32_u",
+        I32TruncSatF64S => "i32.trunc_sat_f64_s", I32TruncSatF64U => "i32.trunc_sat_f64_u",
+        I64TruncSatF32S => "i64.trunc_sat_f32_s", I64TruncSatF32U => "i64.trunc_sat_f32_u",
+        I64TruncSatF64S => "i64.trunc_sat_f64_s", I64TruncSatF64U => "i64.trunc_sat_f64_u",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::String, vec::Vec};
+
+    use crate::parse_bytes;
+
+    fn disassemble(wat: &str, func: u32) -> Vec<String> {
+        let module = parse_bytes(&wat::parse_str(wat).unwrap()).unwrap();
+        module.disassemble(func).map(|(idx, instr)| format!("{}: {}", idx, instr)).collect()
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let wat = r#"
+            (module
+                (import "env" "host" (func $host (param i32)))
+                (memory 1)
+                (func $main (param i32) (result i32)
+                    (block $b (result i32)
+                        (i32.load offset=4 align=2 (local.get 0))
+                        (br_if $b (i32.eqz (local.get 0)))
+                        (call $host)
+                        (if (local.get 0) (then (nop)) (else (drop (f64.const nan:0x4))))
+                        (i64.store8 (i32.const 0) (i64.const 1))
+                        (i32.const 7)))
+                (func (param i32)
+                    (block (block (br_table 0 1 0 (local.get 0))))
+                    (i32.store (local.get 0) (i32.const 5))
+                    (br_if 0 (i32.lt_u (local.get 0) (i32.const 3)))))
+        "#;
+        assert_eq!(disassemble(wat, 0), Vec::<String>::new());
+        assert_eq!(
+            disassemble(wat, 1),
+            [
+                "0: block (result i32) ;; end @16",
+                "1: local.get 0",
+                "2: i32.load offset=4 align=2",
+                "3: local.get 0",
+                "4: i32.eqz_br_if 0",
+                "5: call 0 ;; host",
+                "6: local.get 0",
+                "7: if ;; else @8, end @11",
+                "8: else ;; end @11",
+                "9: f64.const nan:0x4",
+                "10: drop",
+                "11: end",
+                "12: i32.const 0",
+                "13: i64.const 1",
+                "14: i64.store8",
+                "15: i32.const 7",
+                "16: end",
+                "17: return",
+            ]
+        );
+        assert_eq!(
+            disassemble(wat, 2),
+            [
+                "0: block ;; end @7",
+                "1: block ;; end @6",
+                "2: local.get 0",
+                "3: br_table 0 ;; default, 2 br_label follow",
+                "4: br_label 0",
+                "5: br_label 1",
+                "6: end",
+                "7: end",
+                "8: i32.store_local 0 5",
+                "9: local.get 0",
+                "10: i32.const 3",
+                "11: i32.lt_u_br_if 0",
+                "12: return",
+            ]
+        );
+        assert_eq!(disassemble(wat, 3), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,975 @@
+//! Encoder that writes a [`Module`] back into the WebAssembly binary format.
+//!
+//! Fused instructions are split back into the instructions they were created from, so the output
+//! can be parsed again by any WebAssembly runtime. Information that isn't kept in a [`Module`]
+//! (custom sections other than function names, branch hints, producers and target features, the original
+//! order of identical types) is not restored.
+
+use alloc::{format, vec::Vec};
+
+use crate::error::{Error, Result};
+use crate::types::{
+    instructions::{BlockArgs, BranchHint, ConstInstruction, Instruction},
+    value::ValType,
+    DataKind, ElementItem, ElementKind, ExternalKind, FuncType, GlobalType, ImportKind, MemoryType, Module, TableType,
+};
+
+const MAGIC: [u8; 4] = *b"\0asm";
+const VERSION: [u8; 4] = [1, 0, 0, 0];
+
+impl Module {
+    /// Encode the module into the WebAssembly binary format
+    ///
+    /// The result is a valid module that behaves the same as this one.
+    /// Of the custom sections, only function names, branch hints, producers and target features are kept in a
+    /// [`Module`], so these are the only ones written.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(1024);
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&VERSION);
+
+        if !self.func_types.is_empty() {
+            section(&mut out, 1, |s| {
+                leb_u32(s, self.func_types.len() as u32);
+                self.func_types.iter().for_each(|ty| func_type(s, ty));
+                Ok(())
+            })?;
+        }
+
+        if !self.imports.is_empty() {
+            section(&mut out, 2, |s| {
+                leb_u32(s, self.imports.len() as u32);
+                for import in self.imports.iter() {
+                    name(s, &import.module);
+                    name(s, &import.name);
+                    match &import.kind {
+                        ImportKind::Function(ty) => {
+                            s.push(0x00);
+                            leb_u32(s, *ty);
+                        }
+                        ImportKind::Table(ty) => {
+                            s.push(0x01);
+                            table_type(s, ty);
+                        }
+                        ImportKind::Memory(ty) => {
+                            s.push(0x02);
+                            memory_type(s, ty);
+                        }
+                        ImportKind::Global(ty) => {
+                            s.push(0x03);
+                            global_type(s, ty);
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        if !self.funcs.is_empty() {
+            section(&mut out, 3, |s| {
+                leb_u32(s, self.funcs.len() as u32);
+                for func in self.funcs.iter() {
+                    leb_u32(s, self.type_index(&func.ty)?);
+                }
+                Ok(())
+            })?;
+        }
+
+        if !self.table_types.is_empty() {
+            section(&mut out, 4, |s| {
+                leb_u32(s, self.table_types.len() as u32);
+                self.table_types.iter().for_each(|ty| table_type(s, ty));
+                Ok(())
+            })?;
+        }
+
+        if !self.memory_types.is_empty() {
+            section(&mut out, 5, |s| {
+                leb_u32(s, self.memory_types.len() as u32);
+                self.memory_types.iter().for_each(|ty| memory_type(s, ty));
+                Ok(())
+            })?;
+        }
+
+        if !self.globals.is_empty() {
+            section(&mut out, 6, |s| {
+                leb_u32(s, self.globals.len() as u32);
+                for global in self.globals.iter() {
+                    global_type(s, &global.ty);
+                    const_expr(s, &global.init);
+                }
+                Ok(())
+            })?;
+        }
+
+        if !self.exports.is_empty() {
+            section(&mut out, 7, |s| {
+                leb_u32(s, self.exports.len() as u32);
+                for export in self.exports.iter() {
+                    name(s, &export.name);
+                    s.push(match export.kind {
+                        ExternalKind::Func => 0x00,
+                        ExternalKind::Table => 0x01,
+                        ExternalKind::Memory => 0x02,
+                        ExternalKind::Global => 0x03,
+                    });
+                    leb_u32(s, export.index);
+                }
+                Ok(())
+            })?;
+        }
+
+        if let Some(start) = self.start_func {
+            section(&mut out, 8, |s| {
+                leb_u32(s, start);
+                Ok(())
+            })?;
+        }
+
+        if !self.elements.is_empty() {
+            section(&mut out, 9, |s| {
+                leb_u32(s, self.elements.len() as u32);
+                for element in self.elements.iter() {
+                    let exprs = element.items.iter().any(|item| matches!(item, ElementItem::Expr(_)));
+
+                    // Use the encodings with an explicit table index and element type for everything
+                    let flags = match element.kind {
+                        ElementKind::Passive => 0b001,
+                        ElementKind::Active { .. } => 0b010,
+                        ElementKind::Declared => 0b011,
+                    };
+                    s.push(flags | if exprs { 0b100 } else { 0 });
+
+                    if let ElementKind::Active { table, offset } = &element.kind {
+                        leb_u32(s, *table);
+                        const_expr(s, offset);
+                    }
+
+                    match exprs {
+                        true => s.push(element.ty.to_byte()),
+                        false => s.push(0x00),
+                    }
+
+                    leb_u32(s, element.items.len() as u32);
+                    for item in element.items.iter() {
+                        match (item, exprs) {
+                            (ElementItem::Func(func), false) => leb_u32(s, *func),
+                            (ElementItem::Func(func), true) => const_expr(s, &ConstInstruction::RefFunc(*func)),
+                            (ElementItem::Expr(expr), _) => const_expr(s, expr),
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        if !self.data.is_empty() {
+            section(&mut out, 12, |s| {
+                leb_u32(s, self.data.len() as u32);
+                Ok(())
+            })?;
+        }
+
+        // branch hints refer to offsets in the function bodies, but have to come before the code section
+        let mut bodies = Vec::with_capacity(self.funcs.len());
+        let mut hints = Vec::new();
+        for (idx, func) in self.funcs.iter().enumerate() {
+            let mut body = Vec::with_capacity(func.instructions.len() * 2);
+            let mut func_hints = Vec::new();
+            locals(&mut body, &func.locals);
+            instructions(&mut body, &func.instructions, &mut func_hints)
+                .map_err(|err| Error::Other(format!("Failed to encode function {}: {}", idx, err)))?;
+            if !func_hints.is_empty() {
+                hints.push((self.imported_func_count() + idx as u32, func_hints));
+            }
+            bodies.push(body);
+        }
+
+        if !hints.is_empty() {
+            section(&mut out, 0, |s| {
+                name(s, "metadata.code.branch_hint");
+                leb_u32(s, hints.len() as u32);
+                for (func, func_hints) in hints.iter() {
+                    leb_u32(s, *func);
+                    leb_u32(s, func_hints.len() as u32);
+                    for (offset, hint) in func_hints.iter() {
+                        leb_u32(s, *offset);
+                        s.push(1);
+                        s.push((*hint == BranchHint::Likely) as u8);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        if !bodies.is_empty() {
+            section(&mut out, 10, |s| {
+                leb_u32(s, bodies.len() as u32);
+                for body in bodies.iter() {
+                    leb_u32(s, body.len() as u32);
+                    s.extend_from_slice(body);
+                }
+                Ok(())
+            })?;
+        }
+
+        if !self.data.is_empty() {
+            section(&mut out, 11, |s| {
+                leb_u32(s, self.data.len() as u32);
+                for data in self.data.iter() {
+                    match &data.kind {
+                        DataKind::Active { mem: 0, offset } => {
+                            s.push(0x00);
+                            const_expr(s, offset);
+                        }
+                        DataKind::Active { mem, offset } => {
+                            s.push(0x02);
+                            leb_u32(s, *mem);
+                            const_expr(s, offset);
+                        }
+                        DataKind::Passive => s.push(0x01),
+                    }
+                    leb_u32(s, data.data.len() as u32);
+                    s.extend_from_slice(&data.data);
+                }
+                Ok(())
+            })?;
+        }
+
+        if !self.func_names.is_empty() {
+            section(&mut out, 0, |s| {
+                name(s, "name");
+                let mut names = Vec::new();
+                leb_u32(&mut names, self.func_names.len() as u32);
+                for (func, func_name) in self.func_names.iter() {
+                    leb_u32(&mut names, *func);
+                    name(&mut names, func_name);
+                }
+
+                // function names subsection
+                s.push(1);
+                leb_u32(s, names.len() as u32);
+                s.extend_from_slice(&names);
+                Ok(())
+            })?;
+        }
+
+        if !self.producers.is_empty() {
+            section(&mut out, 0, |s| {
+                name(s, "producers");
+                leb_u32(s, self.producers.len() as u32);
+                for field in self.producers.iter() {
+                    name(s, &field.name);
+                    leb_u32(s, field.values.len() as u32);
+                    for value in field.values.iter() {
+                        name(s, &value.name);
+                        name(s, &value.version);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        if !self.target_features.is_empty() {
+            section(&mut out, 0, |s| {
+                name(s, "target_features");
+                leb_u32(s, self.target_features.len() as u32);
+                for feature in self.target_features.iter() {
+                    s.push(feature.policy.prefix());
+                    name(s, &feature.name);
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(out)
+    }
+
+    fn type_index(&self, ty: &FuncType) -> Result<u32> {
+        self.func_types
+            .iter()
+            .position(|t| t == ty)
+            .map(|idx| idx as u32)
+            .ok_or_else(|| Error::Other(format!("Function type {:?} is not part of the type section", ty)))
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, f: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> Result<()> {
+    let mut contents = Vec::new();
+    f(&mut contents)?;
+    out.push(id);
+    leb_u32(out, contents.len() as u32);
+    out.extend_from_slice(&contents);
+    Ok(())
+}
+
+fn leb_u32(out: &mut Vec<u8>, value: u32) {
+    leb_u64(out, value.into())
+}
+
+fn leb_u64(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn leb_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    leb_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn func_type(out: &mut Vec<u8>, ty: &FuncType) {
+    out.push(0x60);
+    leb_u32(out, ty.params.len() as u32);
+    out.extend(ty.params.iter().map(|t| t.to_byte()));
+    leb_u32(out, ty.results.len() as u32);
+    out.extend(ty.results.iter().map(|t| t.to_byte()));
+}
+
+fn limits(out: &mut Vec<u8>, min: u64, max: Option<u64>) {
+    match max {
+        Some(max) => {
+            out.push(0x01);
+            leb_u64(out, min);
+            leb_u64(out, max);
+        }
+        None => {
+            out.push(0x00);
+            leb_u64(out, min);
+        }
+    }
+}
+
+fn table_type(out: &mut Vec<u8>, ty: &TableType) {
+    out.push(ty.element_type.to_byte());
+    limits(out, ty.size_initial.into(), ty.size_max.map(Into::into));
+}
+
+fn memory_type(out: &mut Vec<u8>, ty: &MemoryType) {
+    limits(out, ty.page_count_initial, ty.page_count_max);
+}
+
+fn global_type(out: &mut Vec<u8>, ty: &GlobalType) {
+    out.push(ty.ty.to_byte());
+    out.push(ty.mutable as u8);
+}
+
+fn const_expr(out: &mut Vec<u8>, instr: &ConstInstruction) {
+    match instr {
+        ConstInstruction::I32Const(v) => {
+            out.push(0x41);
+            leb_i64(out, (*v).into());
+        }
+        ConstInstruction::I64Const(v) => {
+            out.push(0x42);
+            leb_i64(out, *v);
+        }
+        ConstInstruction::F32Const(v) => {
+            out.push(0x43);
+            out.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        ConstInstruction::F64Const(v) => {
+            out.push(0x44);
+            out.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        ConstInstruction::GlobalGet(idx) => {
+            out.push(0x23);
+            leb_u32(out, *idx);
+        }
+        ConstInstruction::RefNull(ty) => {
+            out.push(0xD0);
+            out.push(ty.to_byte());
+        }
+        ConstInstruction::RefFunc(idx) => {
+            out.push(0xD2);
+            leb_u32(out, *idx);
+        }
+    }
+    out.push(0x0B);
+}
+
+fn locals(out: &mut Vec<u8>, locals: &[ValType]) {
+    let mut groups: Vec<(u32, ValType)> = Vec::new();
+    for local in locals {
+        match groups.last_mut() {
+            Some((count, ty)) if ty == local => *count += 1,
+            _ => groups.push((1, *local)),
+        }
+    }
+
+    leb_u32(out, groups.len() as u32);
+    for (count, ty) in groups {
+        leb_u32(out, count);
+        out.push(ty.to_byte());
+    }
+}
+
+fn block_type(out: &mut Vec<u8>, args: BlockArgs) {
+    match args {
+        BlockArgs::Empty => out.push(0x40),
+        BlockArgs::Type(ty) => out.push(ty.to_byte()),
+        BlockArgs::FuncType(idx) => leb_i64(out, idx.into()),
+    }
+}
+
+fn memarg(out: &mut Vec<u8>, offset: u32, mem_addr: u32, align: u8) {
+    // the memory index follows the alignment if bit 6 of it is set
+    match mem_addr {
+        0 => leb_u32(out, align.into()),
+        _ => {
+            leb_u32(out, u32::from(align) | 0x40);
+            leb_u32(out, mem_addr);
+        }
+    }
+    leb_u32(out, offset);
+}
+
+fn prefixed(out: &mut Vec<u8>, op: u32) {
+    out.push(0xFC);
+    leb_u32(out, op);
+}
+
+/// Encode a function body, collecting the offsets of hinted branches relative to the start of `out`
+fn instructions(
+    out: &mut Vec<u8>,
+    instrs: &[Instruction],
+    hints: &mut Vec<(u32, BranchHint)>,
+) -> core::result::Result<(), &'static str> {
+    use Instruction::*;
+
+    let mut hint = |out: &Vec<u8>, hint: BranchHint| {
+        if hint != BranchHint::Unknown {
+            hints.push((out.len() as u32, hint));
+        }
+    };
+
+    let mut iter = instrs.iter().enumerate();
+    while let Some((idx, instr)) = iter.next() {
+        match instr {
+            // The last instruction of a function is always the `Return` created from the final `end`
+            Return if idx == instrs.len() - 1 => out.push(0x0B),
+            Return => out.push(0x0F),
+
+            BrLabel(_) => return Err("unexpected br_label outside of a br_table"),
+            I32LocalGetConstAdd(local, value) => {
+                out.push(0x20);
+                leb_u32(out, *local);
+                out.push(0x41);
+                leb_i64(out, (*value).into());
+                out.push(0x6A);
+            }
+            I32StoreLocal { local, const_i32, offset, mem_addr, align } => {
+                out.push(0x20);
+                leb_u32(out, *local);
+                out.push(0x41);
+                leb_i64(out, (*const_i32).into());
+                out.push(0x36);
+                memarg(out, *offset, (*mem_addr).into(), *align);
+            }
+            I64XorConstRotl(value) => {
+                out.push(0x85);
+                out.push(0x42);
+                leb_i64(out, *value);
+                out.push(0x89);
+            }
+            LocalTeeGet(a, b) => {
+                out.push(0x22);
+                leb_u32(out, *a);
+                out.push(0x20);
+                leb_u32(out, *b);
+            }
+            LocalGet2(a, b) => {
+                for local in [a, b] {
+                    out.push(0x20);
+                    leb_u32(out, *local);
+                }
+            }
+            LocalGet3(a, b, c) => {
+                for local in [a, b, c] {
+                    out.push(0x20);
+                    leb_u32(out, *local);
+                }
+            }
+            LocalGetSet(a, b) => {
+                out.push(0x20);
+                leb_u32(out, *a);
+                out.push(0x21);
+                leb_u32(out, *b);
+            }
+            I32AddLocals(a, b) => {
+                for local in [a, b] {
+                    out.push(0x20);
+                    leb_u32(out, *local);
+                }
+                out.push(0x6A);
+            }
+            I32AddConst(value) => {
+                out.push(0x41);
+                leb_i64(out, (*value).into());
+                out.push(0x6A);
+            }
+            I32EqzBrIf(label, branch_hint) => {
+                out.push(0x45);
+                hint(out, *branch_hint);
+                out.push(0x0D);
+                leb_u32(out, *label);
+            }
+            I32CmpBrIf(cmp, label, branch_hint) => {
+                instructions(out, &[cmp.instruction()], &mut Vec::new())?;
+                hint(out, *branch_hint);
+                out.push(0x0D);
+                leb_u32(out, *label);
+            }
+
+            // trapping is all a denied float instruction does
+            Unreachable | FloatDenied => out.push(0x00),
+            Nop => out.push(0x01),
+            Block(args, _) => {
+                out.push(0x02);
+                block_type(out, *args);
+            }
+            Loop(args, _) => {
+                out.push(0x03);
+                block_type(out, *args);
+            }
+            If(args, _, _, branch_hint) => {
+                hint(out, *branch_hint);
+                out.push(0x04);
+                block_type(out, (*args).into());
+            }
+            Else(_) => out.push(0x05),
+            EndBlockFrame => out.push(0x0B),
+            Br(label) => {
+                out.push(0x0C);
+                leb_u32(out, *label);
+            }
+            BrIf(label, branch_hint) => {
+                hint(out, *branch_hint);
+                out.push(0x0D);
+                leb_u32(out, *label);
+            }
+            BrTable(default, len) => {
+                out.push(0x0E);
+                leb_u32(out, *len);
+                for _ in 0..*len {
+                    match iter.next() {
+                        Some((_, BrLabel(label))) => leb_u32(out, *label),
+                        _ => return Err("br_table is missing br_label targets"),
+                    }
+                }
+                leb_u32(out, *default);
+            }
+            Call(func) => {
+                out.push(0x10);
+                leb_u32(out, *func);
+            }
+            CallIndirect(ty, table) => {
+                out.push(0x11);
+                leb_u32(out, *ty);
+                leb_u32(out, *table);
+            }
+
+            Drop => out.push(0x1A),
+            Select(None) => out.push(0x1B),
+            Select(Some(ty)) => {
+                out.push(0x1C);
+                leb_u32(out, 1);
+                out.push(ty.to_byte());
+            }
+
+            LocalGet(idx) => {
+                out.push(0x20);
+                leb_u32(out, *idx);
+            }
+            LocalSet(idx) => {
+                out.push(0x21);
+                leb_u32(out, *idx);
+            }
+            LocalTee(idx) => {
+                out.push(0x22);
+                leb_u32(out, *idx);
+            }
+            GlobalGet(idx) => {
+                out.push(0x23);
+                leb_u32(out, *idx);
+            }
+            GlobalSet(idx) => {
+                out.push(0x24);
+                leb_u32(out, *idx);
+            }
+
+            I32Load { offset, mem_addr, align }
+            | I64Load { offset, mem_addr, align }
+            | F32Load { offset, mem_addr, align }
+            | F64Load { offset, mem_addr, align }
+            | I32Load8S { offset, mem_addr, align }
+            | I32Load8U { offset, mem_addr, align }
+            | I32Load16S { offset, mem_addr, align }
+            | I32Load16U { offset, mem_addr, align }
+            | I64Load8S { offset, mem_addr, align }
+            | I64Load8U { offset, mem_addr, align }
+            | I64Load16S { offset, mem_addr, align }
+            | I64Load16U { offset, mem_addr, align }
+            | I64Load32S { offset, mem_addr, align }
+            | I64Load32U { offset, mem_addr, align }
+            | I32Store { offset, mem_addr, align }
+            | I64Store { offset, mem_addr, align }
+            | F32Store { offset, mem_addr, align }
+            | F64Store { offset, mem_addr, align }
+            | I32Store8 { offset, mem_addr, align }
+            | I32Store16 { offset, mem_addr, align }
+            | I64Store8 { offset, mem_addr, align }
+            | I64Store16 { offset, mem_addr, align }
+            | I64Store32 { offset, mem_addr, align } => {
+                out.push(memory_opcode(instr).ok_or("unknown memory instruction")?);
+                memarg(out, *offset, *mem_addr, *align);
+            }
+            MemorySize(mem, _) => {
+                out.push(0x3F);
+                leb_u32(out, *mem);
+            }
+            MemoryGrow(mem, _) => {
+                out.push(0x40);
+                leb_u32(out, *mem);
+            }
+
+            I32Const(value) => {
+                out.push(0x41);
+                leb_i64(out, (*value).into());
+            }
+            I64Const(value) => {
+                out.push(0x42);
+                leb_i64(out, *value);
+            }
+            F32Const(value) => {
+                out.push(0x43);
+                out.extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+            F64Const(value) => {
+                out.push(0x44);
+                out.extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+
+            RefNull(ty) => {
+                out.push(0xD0);
+                out.push(ty.to_byte());
+            }
+            RefIsNull => out.push(0xD1),
+            RefFunc(idx) => {
+                out.push(0xD2);
+                leb_u32(out, *idx);
+            }
+
+            I32TruncSatF32S => prefixed(out, 0),
+            I32TruncSatF32U => prefixed(out, 1),
+            I32TruncSatF64S => prefixed(out, 2),
+            I32TruncSatF64U => prefixed(out, 3),
+            I64TruncSatF32S => prefixed(out, 4),
+            I64TruncSatF32U => prefixed(out, 5),
+            I64TruncSatF64S => prefixed(out, 6),
+            I64TruncSatF64U => prefixed(out, 7),
+
+            // The parser stores the operands of these in the order they appear in the binary format
+            MemoryInit(data, mem) => {
+                prefixed(out, 8);
+                leb_u32(out, *data);
+                leb_u32(out, *mem);
+            }
+            DataDrop(data) => {
+                prefixed(out, 9);
+                leb_u32(out, *data);
+            }
+            MemoryCopy(dst, src) => {
+                prefixed(out, 10);
+                leb_u32(out, *dst);
+                leb_u32(out, *src);
+            }
+            MemoryFill(mem) => {
+                prefixed(out, 11);
+                leb_u32(out, *mem);
+            }
+            TableInit(elem, table) => {
+                prefixed(out, 12);
+                leb_u32(out, *elem);
+                leb_u32(out, *table);
+            }
+            TableCopy { from, to } => {
+                prefixed(out, 14);
+                leb_u32(out, *to);
+                leb_u32(out, *from);
+            }
+            TableGrow(table) => {
+                prefixed(out, 15);
+                leb_u32(out, *table);
+            }
+            TableSize(table) => {
+                prefixed(out, 16);
+                leb_u32(out, *table);
+            }
+            TableFill(table) => {
+                prefixed(out, 17);
+                leb_u32(out, *table);
+            }
+            TableGet(table) => {
+                out.push(0x25);
+                leb_u32(out, *table);
+            }
+            TableSet(table) => {
+                out.push(0x26);
+                leb_u32(out, *table);
+            }
+
+            numeric => out.push(numeric_opcode(numeric).ok_or("unknown instruction")?),
+        }
+    }
+
+    Ok(())
+}
+
+/// Opcodes of the loads and stores, in the order of the binary format
+fn memory_opcode(instr: &Instruction) -> Option<u8> {
+    use Instruction::*;
+    let op = match instr {
+        I32Load { .. } => 0x28,
+        I64Load { .. } => 0x29,
+        F32Load { .. } => 0x2A,
+        F64Load { .. } => 0x2B,
+        I32Load8S { .. } => 0x2C,
+        I32Load8U { .. } => 0x2D,
+        I32Load16S { .. } => 0x2E,
+        I32Load16U { .. } => 0x2F,
+        I64Load8S { .. } => 0x30,
+        I64Load8U { .. } => 0x31,
+        I64Load16S { .. } => 0x32,
+        I64Load16U { .. } => 0x33,
+        I64Load32S { .. } => 0x34,
+        I64Load32U { .. } => 0x35,
+        I32Store { .. } => 0x36,
+        I64Store { .. } => 0x37,
+        F32Store { .. } => 0x38,
+        F64Store { .. } => 0x39,
+        I32Store8 { .. } => 0x3A,
+        I32Store16 { .. } => 0x3B,
+        I64Store8 { .. } => 0x3C,
+        I64Store16 { .. } => 0x3D,
+        I64Store32 { .. } => 0x3E,
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// Opcodes of the instructions without immediates, in the order of the binary format
+#[rustfmt::skip]
+fn numeric_opcode(instr: &Instruction) -> Option<u8> {
+    use Instruction::*;
+    let op = match instr {
+        I32Eqz => 0x45, I32Eq => 0x46, I32Ne => 0x47, I32LtS => 0x48, I32LtU => 0x49, I32GtS => 0x4A,
+        I32GtU => 0x4B, I32LeS => 0x4C, I32LeU => 0x4D, I32GeS => 0x4E, I32GeU => 0x4F,
+        I64Eqz => 0x50, I64Eq => 0x51, I64Ne => 0x52, I64LtS => 0x53, I64LtU => 0x54, I64GtS => 0x55,
+        I64GtU => 0x56, I64LeS => 0x57, I64LeU => 0x58, I64GeS => 0x59, I64GeU => 0x5A,
+        F32Eq => 0x5B, F32Ne => 0x5C, F32Lt => 0x5D, F32Gt => 0x5E, F32Le => 0x5F, F32Ge => 0x60,
+        F64Eq => 0x61, F64Ne => 0x62, F64Lt => 0x63, F64Gt => 0x64, F64Le => 0x65, F64Ge => 0x66,
+        I32Clz => 0x67, I32Ctz => 0x68, I32Popcnt => 0x69, I32Add => 0x6A, I32Sub => 0x6B, I32Mul => 0x6C,
+        I32DivS => 0x6D, I32DivU => 0x6E, I32RemS => 0x6F, I32RemU => 0x70, I32And => 0x71, I32Or => 0x72,
+        I32Xor => 0x73, I32Shl => 0x74, I32ShrS => 0x75, I32ShrU => 0x76, I32Rotl => 0x77, I32Rotr => 0x78,
+        I64Clz => 0x79, I64Ctz => 0x7A, I64Popcnt => 0x7B, I64Add => 0x7C, I64Sub => 0x7D, I64Mul => 0x7E,
+        I64DivS => 0x7F, I64DivU => 0x80, I64RemS => 0x81, I64RemU => 0x82, I64And => 0x83, I64Or => 0x84,
+        I64Xor => 0x85, I64Shl => 0x86, I64ShrS => 0x87, I64ShrU => 0x88, I64Rotl => 0x89, I64Rotr => 0x8A,
+        F32Abs => 0x8B, F32Neg => 0x8C, F32Ceil => 0x8D, F32Floor => 0x8E, F32Trunc => 0x8F, F32Nearest => 0x90,
+        F32Sqrt => 0x91, F32Add => 0x92, F32Sub => 0x93, F32Mul => 0x94, F32Div => 0x95, F32Min => 0x96,
+        F32Max => 0x97, F32Copysign => 0x98,
+        F64Abs => 0x99, F64Neg => 0x9A, F64Ceil => 0x9B, F64Floor => 0x9C, F64Trunc => 0x9D, F64Nearest => 0x9E,
+        F64Sqrt => 0x9F, F64Add => 0xA0, F64Sub => 0xA1, F64Mul => 0xA2, F64Div => 0xA3, F64Min => 0xA4,
+        F64Max => 0xA5, F64Copysign => 0xA6,
+        I32WrapI64 => 0xA7, I32TruncF32S => 0xA8, I32TruncF32U => 0xA9, I32TruncF64S => 0xAA, I32TruncF64U => 0xAB,
+        I64ExtendI32S => 0xAC, I64ExtendI32U => 0xAD, I64TruncF32S => 0xAE, I64TruncF32U => 0xAF,
+        I64TruncF64S => 0xB0, I64TruncF64U => 0xB1,
+        F32ConvertI32S => 0xB2, F32ConvertI32U => 0xB3, F32ConvertI64S => 0xB4, F32ConvertI64U => 0xB5,
+        F32DemoteF64 => 0xB6,
+        F64ConvertI32S => 0xB7, F64ConvertI32U => 0xB8, F64ConvertI64S => 0xB9, F64ConvertI64U => 0xBA,
+        F64PromoteF32 => 0xBB,
+        I32ReinterpretF32 => 0xBC, I64ReinterpretF64 => 0xBD, F32ReinterpretI32 => 0xBE, F64ReinterpretI64 => 0xBF,
+        I32Extend8S => 0xC0, I32Extend16S => 0xC1, I64Extend8S => 0xC2, I64Extend16S => 0xC3, I64Extend32S => 0xC4,
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::instructions::{BranchHint, Instruction};
+    use crate::{parse_bytes, types::Module};
+
+    fn roundtrip(wat: &str) -> (Module, Module) {
+        let wasm = wat::parse_str(wat).unwrap();
+        let mut module = parse_bytes(&wasm).unwrap();
+        let mut reencoded = parse_bytes(&module.encode().unwrap()).unwrap();
+
+        // byte ranges refer to the original binary
+        for module in [&mut module, &mut reencoded] {
+            module.data.iter_mut().for_each(|d| d.range = 0..0);
+            module.elements.iter_mut().for_each(|e| e.range = 0..0);
+            module.funcs.iter_mut().for_each(|f| f.offsets = Default::default());
+        }
+        (module, reencoded)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (module, reencoded) = roundtrip(
+            r#"
+            (module
+                (type $t (func (param i32) (result i32)))
+                (import "env" "log" (func $log (param i32)))
+                (import "env" "g" (global $g (mut i64)))
+                (memory (export "memory") 1 2)
+                (table 2 funcref)
+                (global $counter (mut i32) (i32.const -7))
+                (elem (i32.const 0) $add $add)
+                (data (i32.const 16) "hello")
+                (data "passive")
+                (func $add (export "add") (type $t) (local i64 i64 f32)
+                    local.get 0
+                    i32.const 42
+                    i32.add
+                    local.tee 0
+                    local.get 0
+                    i32.store offset=8
+                    (block $b (loop $l (br_table $b $l $b (local.get 0))))
+                    (if (result i32) (local.get 0) (then (i32.const 1)) (else (i32.const 2)))
+                    i64.const 5
+                    i64.const 6
+                    i64.xor
+                    drop
+                    (call_indirect (type $t) (i32.const 3) (i32.const 1))
+                    global.get $counter
+                    i32.add
+                    (memory.init 1 (i32.const 0) (i32.const 0) (i32.const 1))
+                    call $log
+                    f32.const 1.5
+                    drop
+                    memory.size
+                    drop
+                    return)
+                (start $start)
+                (func $start i64.const 1 i64.const 2 i64.xor i64.const 3 i64.rotl global.set $g))
+            "#,
+        );
+        assert_eq!(module, reencoded);
+    }
+
+    #[test]
+    fn test_memory_roundtrip() {
+        let loads = ["i32.load", "i64.load", "f32.load", "f64.load", "i32.load8_s", "i32.load8_u", "i32.load16_s"]
+            .into_iter()
+            .chain(["i32.load16_u", "i64.load8_s", "i64.load8_u", "i64.load16_s", "i64.load16_u", "i64.load32_s"])
+            .chain(["i64.load32_u"]);
+        let stores = [("i32.store", "i32"), ("i64.store", "i64"), ("f32.store", "f32"), ("f64.store", "f64")]
+            .into_iter()
+            .chain([("i32.store8", "i32"), ("i32.store16", "i32"), ("i64.store8", "i64"), ("i64.store16", "i64")])
+            .chain([("i64.store32", "i64")]);
+
+        let mut body = alloc::string::String::new();
+        for load in loads {
+            body += &alloc::format!("(drop ({} offset=4 (i32.const 0)))", load);
+        }
+        for (store, ty) in stores {
+            body += &alloc::format!("({} offset=8 (i32.const 0) ({}.const 1))", store, ty);
+        }
+
+        let (module, reencoded) = roundtrip(&alloc::format!("(module (memory 1) (func {}))", body));
+        assert_eq!(module, reencoded);
+    }
+
+    #[test]
+    fn test_fused_roundtrip() {
+        use crate::types::instructions::I32Cmp;
+
+        let (module, reencoded) = roundtrip(
+            r#"
+            (module
+                (func (param i32 i32) (result i32)
+                    (block $b
+                        (loop $l
+                            (br_if $b (i32.eqz (local.get 0)))
+                            (local.set 0 (i32.add (i32.shr_u (local.get 0) (i32.const 1)) (i32.const -1)))
+                            (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                            (br_if $l (i32.lt_u (local.get 1) (i32.const 100)))))
+                    (local.get 1)))
+            "#,
+        );
+        assert_eq!(module, reencoded);
+
+        let instrs = &module.funcs[0].instructions;
+        assert!(instrs.contains(&Instruction::I32EqzBrIf(1, BranchHint::Unknown)));
+        assert!(instrs.contains(&Instruction::I32AddConst(-1)));
+        assert!(instrs.contains(&Instruction::I32AddLocals(1, 0)));
+        assert!(instrs.contains(&Instruction::I32CmpBrIf(I32Cmp::LtU, 0, BranchHint::Unknown)));
+    }
+
+    #[test]
+    fn test_branch_hints() {
+        use crate::exec::CallResult;
+        use crate::types::{instructions::I32Cmp, value::WasmValue};
+
+        let wat = r#"
+            (module
+                (import "env" "log" (func $log (param i32)))
+                (func (export "sum") (param i32) (result i32) (local i32)
+                    (block $b
+                        (loop $l
+                            (i32.eqz (local.get 0))
+                            (@metadata.code.branch_hint "\00")
+                            br_if $b
+                            (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                            (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                            (i32.gt_u (local.get 0) (i32.const 0))
+                            (@metadata.code.branch_hint "\01")
+                            br_if $l))
+                    (i32.gt_u (local.get 1) (i32.const 1000))
+                    (@metadata.code.branch_hint "\00")
+                    if
+                        (call $log (local.get 1))
+                    end
+                    (local.get 1)))
+            "#;
+        let (module, reencoded) = roundtrip(wat);
+        assert_eq!(module, reencoded);
+
+        let instrs = &module.funcs[0].instructions;
+        assert!(instrs.contains(&Instruction::I32EqzBrIf(1, BranchHint::Unlikely)));
+        assert!(instrs.contains(&Instruction::I32CmpBrIf(I32Cmp::GtU, 0, BranchHint::Likely)));
+        assert!(instrs.iter().any(|i| matches!(i, Instruction::If(_, _, _, BranchHint::Unlikely))));
+
+        let mut unhinted = module.clone();
+        unhinted.clear_branch_hints();
+        assert_ne!(unhinted, module);
+        assert_eq!(unhinted.fingerprint().unwrap(), module.fingerprint().unwrap());
+
+        let mut imports = crate::imports::Imports::new();
+        imports.define("env", "log", crate::imports::Extern::typed_func(|_, _: i32| Ok(()))).unwrap();
+        let instance = crate::Instance::instantiate(module, imports).unwrap();
+        let mut exec =
+            instance.exported_func_untyped("sum").unwrap().call(alloc::vec![WasmValue::I32(10)], None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(55)]));
+    }
+}
@@ -1,12 +1,19 @@
 //! Errors for this crate
 
-use alloc::string::{String, ToString};
-use core::fmt::Display;
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{any::Any, fmt::Display};
 
-use crate::parser::error::ParseError;
-use crate::types::{FuncType, Import};
+pub use crate::parser::error::{ErrorLocation, ParseError, SectionKind};
+use crate::types::{value::ValType, ExternalKind, FuncType, Import};
+use crate::Fingerprint;
 
 /// Errors that can occur for this crates operations
+///
+/// Every variant has a stable numeric code, see [`Error::code`].
 #[derive(Debug)]
 pub enum Error {
     /// A WebAssembly trap occurred
@@ -39,25 +46,139 @@ pub enum Error {
     /// The store is not the one that the module instance was instantiated in
     InvalidStore,
 
+    /// The internal bytecode of a function is malformed
+    ///
+    /// Modules produced by the parser never cause this, but archived or transformed modules might.
+    InvalidBytecode(String),
+
+    /// No export with the given name and kind exists
+    ExportNotFound {
+        /// The name of the export
+        name: String,
+        /// The kind of export that was requested
+        kind: ExternalKind,
+    },
+
+    /// A function was called with the wrong number of parameters
+    ParamCountMismatch {
+        /// The number of parameters the function takes
+        expected: usize,
+        /// The number of parameters passed
+        got: usize,
+    },
+
+    /// A function was called with a parameter of the wrong type
+    ParamTypeMismatch {
+        /// The type of the parameter
+        expected: ValType,
+        /// The type of the value passed
+        got: ValType,
+        /// The index of the parameter
+        index: usize,
+    },
+
+    /// The module has a start function, but [`StartPolicy::Deny`](crate::StartPolicy::Deny) was used
+    StartDenied,
+
+    /// The start function didn't finish within its budget, see [`StartPolicy::Run`](crate::StartPolicy::Run)
+    StartBudgetExceeded {
+        /// The budget of the start function
+        max_cycles: usize,
+    },
+
+    /// An auxiliary call didn't finish within its budget, see
+    /// [`ExecHandle::call_auxiliary`](crate::exec::ExecHandle::call_auxiliary)
+    AuxiliaryBudgetExceeded {
+        /// The budget of the call
+        max_cycles: usize,
+    },
+
+    /// A call of the guest's allocator didn't finish within its budget, see
+    /// [`GuestAllocator::max_cycles`](crate::GuestAllocator::max_cycles)
+    AllocatorBudgetExceeded {
+        /// The budget of the call
+        max_cycles: usize,
+    },
+
+    /// An import required by the module was not provided
+    ImportUnresolved {
+        /// The module name
+        module: String,
+        /// The import name
+        name: String,
+    },
+
+    /// A host function's signature doesn't match the type the module imports it with
+    ImportSignatureMismatch {
+        /// The module name
+        module: String,
+        /// The import name
+        name: String,
+        /// The type declared by the module
+        expected: FuncType,
+        /// The type of the provided host function
+        got: FuncType,
+    },
+
+    /// Several imports failed to resolve
+    ///
+    /// Contains one [`Error::ImportUnresolved`], [`Error::ImportSignatureMismatch`] or [`Error::Linker`]
+    /// for every failed import, in the order the module declares them.
+    Imports(Vec<Error>),
+
     #[cfg(feature = "std")]
     /// An I/O error occurred
-    Io(crate::std::io::Error),
+    Io(std::io::Error),
 
     /// A parsing error occurred
     ParseError(ParseError),
+
+    /// A snapshot couldn't be restored
+    Snapshot(SnapshotError),
+}
+
+/// Reasons a snapshot can't be restored, see [`Instance::instantiate_with_state`](crate::Instance::instantiate_with_state)
+///
+/// Offsets are byte offsets in the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The snapshot is shorter than its header says, e.g. because it wasn't completely written or transferred
+    ///
+    /// Reading it again might succeed.
+    Truncated {
+        /// The length of the snapshot according to its header
+        expected: usize,
+        /// The number of bytes that were passed
+        actual: usize,
+    },
+    /// The snapshot was taken by a version of this crate that uses a different format
+    VersionSkew {
+        /// The format version of the snapshot
+        found: u32,
+        /// The format version this crate reads
+        expected: u32,
+    },
+    /// The snapshot was taken from an instance of a different module
+    ///
+    /// Restoring it with the module the snapshot was taken from might succeed.
+    ModuleMismatch {
+        /// The fingerprint of the module the snapshot is restored with
+        expected: Fingerprint,
+        /// The fingerprint of the module the snapshot was taken from
+        found: Fingerprint,
+    },
+    /// The snapshot is damaged or not a snapshot at all
+    Corrupt {
+        /// Where the damaged part starts
+        offset: usize,
+        /// What is wrong with it
+        message: String,
+    },
 }
 
 #[derive(Debug)]
 /// Errors that can occur when linking a WebAssembly module
 pub enum LinkingError {
-    /// An unknown import was encountered
-    UnknownImport {
-        /// The module name
-        module: String,
-        /// The import name
-        name: String,
-    },
-
     /// A mismatched import type was encountered
     IncompatibleImportType {
         /// The module name
@@ -71,10 +192,6 @@ impl LinkingError {
     pub(crate) fn incompatible_import_type(import: &Import) -> Self {
         Self::IncompatibleImportType { module: import.module.to_string(), name: import.name.to_string() }
     }
-
-    pub(crate) fn unknown_import(import: &Import) -> Self {
-        Self::UnknownImport { module: import.module.to_string(), name: import.name.to_string() }
-    }
 }
 
 #[derive(Debug)]
@@ -87,21 +204,21 @@ pub enum Trap {
 
     /// An out-of-bounds memory access occurred
     MemoryOutOfBounds {
-        /// The offset of the access
+        /// The address of the access, or its static offset if the address overflowed
         offset: usize,
-        /// The size of the access
+        /// The size of the access in bytes
         len: usize,
-        /// The maximum size of the memory
+        /// The size of the memory in bytes
         max: usize,
     },
 
     /// An out-of-bounds table access occurred
     TableOutOfBounds {
-        /// The offset of the access
+        /// The index of the access
         offset: usize,
-        /// The size of the access
+        /// The number of elements accessed
         len: usize,
-        /// The maximum size of the memory
+        /// The size of the table
         max: usize,
     },
 
@@ -114,8 +231,13 @@ pub enum Trap {
     /// Integer Overflow
     IntegerOverflow,
 
-    /// Call stack overflow
-    CallStackOverflow,
+    /// One of the [`StackLimits`](crate::exec::StackLimits) was exceeded
+    StackExhausted {
+        /// The name of the limit
+        limit: &'static str,
+        /// The configured maximum
+        max: usize,
+    },
 
     /// An undefined element was encountered
     UndefinedElement {
@@ -136,9 +258,138 @@ pub enum Trap {
         /// The actual type
         actual: FuncType,
     },
+
+    /// A host function aborted the execution, see [`Error::host_trap`]
+    HostError {
+        /// The reason given by the host function
+        message: String,
+        /// Typed context attached by the host function, see [`Trap::downcast_ref`]
+        payload: Option<Box<dyn Any + Send + Sync>>,
+    },
+
+    /// A floating point instruction was executed in a module parsed with [`DenyFloats::Trap`](crate::DenyFloats::Trap)
+    FloatDenied,
+
+    /// A memory was written during an auxiliary call that may only read it, see
+    /// [`MemoryAccess::ReadOnly`](crate::exec::MemoryAccess::ReadOnly)
+    ReadOnlyMemory {
+        /// The address of the write
+        offset: usize,
+        /// The size of the write in bytes
+        len: usize,
+    },
+
+    /// A memory access wasn't aligned to its alignment hint, see
+    /// [`AlignmentCheck::Trap`](crate::exec::AlignmentCheck::Trap)
+    MisalignedAccess {
+        /// The address of the access
+        offset: usize,
+        /// The alignment hint of the access in bytes
+        align: usize,
+    },
+
+    /// The guest exceeded the quota of a function import, see
+    /// [`Imports::set_quota`](crate::imports::Imports::set_quota)
+    ImportQuotaExceeded {
+        /// The module of the import
+        module: String,
+        /// The name of the import
+        name: String,
+    },
+
+    /// The guest or a host function ended the execution with an exit status, see [`Error::exit`]
+    ///
+    /// [`ExecHandle::run`](crate::exec::ExecHandle::run) reports this as
+    /// [`CallResult::Exit`](crate::exec::CallResult::Exit) instead of an error.
+    Exit(i32),
+}
+
+/// The category of a [`Trap`], without its details
+///
+/// Useful to decide how to handle a failed execution without matching on messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrapKind {
+    /// See [`Trap::Unreachable`]
+    Unreachable,
+    /// See [`Trap::MemoryOutOfBounds`]
+    MemoryOutOfBounds,
+    /// See [`Trap::TableOutOfBounds`]
+    TableOutOfBounds,
+    /// See [`Trap::DivisionByZero`]
+    DivisionByZero,
+    /// See [`Trap::InvalidConversionToInt`]
+    InvalidConversionToInt,
+    /// See [`Trap::IntegerOverflow`]
+    IntegerOverflow,
+    /// See [`Trap::StackExhausted`]
+    StackExhausted,
+    /// See [`Trap::UndefinedElement`]
+    UndefinedElement,
+    /// See [`Trap::UninitializedElement`]
+    UninitializedElement,
+    /// See [`Trap::IndirectCallTypeMismatch`]
+    IndirectCallTypeMismatch,
+    /// See [`Trap::HostError`]
+    HostError,
+    /// See [`Trap::FloatDenied`]
+    FloatDenied,
+    /// See [`Trap::Exit`]
+    Exit,
+    /// See [`Trap::ReadOnlyMemory`]
+    ReadOnlyMemory,
+    /// See [`Trap::MisalignedAccess`]
+    MisalignedAccess,
+    /// See [`Trap::ImportQuotaExceeded`]
+    ImportQuotaExceeded,
+}
+
+impl TrapKind {
+    /// Get the stable numeric code of the trap, see [`Error::code`]
+    pub fn code(self) -> u32 {
+        100 + match self {
+            Self::Unreachable => 0,
+            Self::MemoryOutOfBounds => 1,
+            Self::TableOutOfBounds => 2,
+            Self::DivisionByZero => 3,
+            Self::InvalidConversionToInt => 4,
+            Self::IntegerOverflow => 5,
+            Self::StackExhausted => 6,
+            Self::UndefinedElement => 7,
+            Self::UninitializedElement => 8,
+            Self::IndirectCallTypeMismatch => 9,
+            Self::HostError => 10,
+            Self::FloatDenied => 11,
+            Self::Exit => 12,
+            Self::ReadOnlyMemory => 13,
+            Self::MisalignedAccess => 14,
+            Self::ImportQuotaExceeded => 15,
+        }
+    }
 }
 
 impl Trap {
+    /// Get the category of the trap
+    pub fn kind(&self) -> TrapKind {
+        match self {
+            Self::Unreachable => TrapKind::Unreachable,
+            Self::MemoryOutOfBounds { .. } => TrapKind::MemoryOutOfBounds,
+            Self::TableOutOfBounds { .. } => TrapKind::TableOutOfBounds,
+            Self::DivisionByZero => TrapKind::DivisionByZero,
+            Self::InvalidConversionToInt => TrapKind::InvalidConversionToInt,
+            Self::IntegerOverflow => TrapKind::IntegerOverflow,
+            Self::StackExhausted { .. } => TrapKind::StackExhausted,
+            Self::UndefinedElement { .. } => TrapKind::UndefinedElement,
+            Self::UninitializedElement { .. } => TrapKind::UninitializedElement,
+            Self::IndirectCallTypeMismatch { .. } => TrapKind::IndirectCallTypeMismatch,
+            Self::HostError { .. } => TrapKind::HostError,
+            Self::FloatDenied => TrapKind::FloatDenied,
+            Self::Exit(_) => TrapKind::Exit,
+            Self::ReadOnlyMemory { .. } => TrapKind::ReadOnlyMemory,
+            Self::MisalignedAccess { .. } => TrapKind::MisalignedAccess,
+            Self::ImportQuotaExceeded { .. } => TrapKind::ImportQuotaExceeded,
+        }
+    }
+
     /// Get the message of the trap
     pub fn message(&self) -> &'static str {
         match self {
@@ -148,10 +399,36 @@ impl Trap {
             Self::DivisionByZero => "integer divide by zero",
             Self::InvalidConversionToInt => "invalid conversion to integer",
             Self::IntegerOverflow => "integer overflow",
-            Self::CallStackOverflow => "call stack exhausted",
+            Self::StackExhausted { .. } => "call stack exhausted",
             Self::UndefinedElement { .. } => "undefined element",
             Self::UninitializedElement { .. } => "uninitialized element",
             Self::IndirectCallTypeMismatch { .. } => "indirect call type mismatch",
+            Self::HostError { .. } => "host error",
+            Self::FloatDenied => "floating point instruction denied",
+            Self::Exit(_) => "exit",
+            Self::ReadOnlyMemory { .. } => "write to read-only memory",
+            Self::MisalignedAccess { .. } => "misaligned memory access",
+            Self::ImportQuotaExceeded { .. } => "import quota exceeded",
+        }
+    }
+
+    /// Get the payload of a [`Trap::HostError`] if it has the type `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match self {
+            Self::HostError { payload: Some(payload), .. } => payload.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Take the payload of a [`Trap::HostError`] if it has the type `T`
+    ///
+    /// Returns `None` and leaves the payload in place if it has a different type.
+    pub fn downcast<T: Any>(&mut self) -> Option<T> {
+        match self {
+            Self::HostError { payload, .. } if payload.as_ref().is_some_and(|p| p.is::<T>()) => {
+                payload.take()?.downcast().ok().map(|payload| *payload)
+            }
+            _ => None,
         }
     }
 }
@@ -160,12 +437,125 @@ impl LinkingError {
     /// Get the message of the linking error
     pub fn message(&self) -> &'static str {
         match self {
-            Self::UnknownImport { .. } => "unknown import",
             Self::IncompatibleImportType { .. } => "incompatible import type",
         }
     }
 }
 
+impl Error {
+    pub(crate) fn export_not_found(name: &str, kind: ExternalKind) -> Self {
+        Self::ExportNotFound { name: name.to_string(), kind }
+    }
+
+    pub(crate) fn import_unresolved(import: &Import) -> Self {
+        Self::ImportUnresolved { module: import.module.to_string(), name: import.name.to_string() }
+    }
+
+    pub(crate) fn import_signature_mismatch(import: &Import, expected: &FuncType, got: &FuncType) -> Self {
+        Self::ImportSignatureMismatch {
+            module: import.module.to_string(),
+            name: import.name.to_string(),
+            expected: expected.clone(),
+            got: got.clone(),
+        }
+    }
+
+    /// Create an error a host function can return to abort the execution with [`Trap::HostError`]
+    ///
+    /// The trap is returned from the outer [`ExecHandle::run`](crate::exec::ExecHandle::run).
+    pub fn host_trap(message: impl Into<String>) -> Self {
+        Self::Trap(Trap::HostError { message: message.into(), payload: None })
+    }
+
+    /// Create an error a host function can return to end the execution with an exit status, like WASI's `proc_exit`
+    ///
+    /// Unlike other traps, this isn't reported as a failure: [`ExecHandle::run`](crate::exec::ExecHandle::run)
+    /// returns [`CallResult::Exit`](crate::exec::CallResult::Exit).
+    pub fn exit(code: i32) -> Self {
+        Self::Trap(Trap::Exit(code))
+    }
+
+    /// Like [`Error::host_trap`], but with typed context the caller can get back with [`Trap::downcast_ref`]
+    pub fn host_trap_with<T: Any + Send + Sync>(message: impl Into<String>, payload: T) -> Self {
+        Self::Trap(Trap::HostError { message: message.into(), payload: Some(Box::new(payload)) })
+    }
+
+    /// Get a stable numeric code identifying the kind of error
+    ///
+    /// Codes never change between releases, so they can be stored or sent across process boundaries.
+    /// Codes below 100 identify the variant of [`Error`], traps are assigned `100 + n` where `n`
+    /// is the position of their [`TrapKind`]:
+    ///
+    /// | Code | Error |
+    /// |------|-------|
+    /// | 1 | [`Error::Other`] |
+    /// | 2 | [`Error::UnsupportedFeature`] |
+    /// | 3 | [`Error::FuncDidNotReturn`] |
+    /// | 4 | [`Error::ValueStackUnderflow`] |
+    /// | 5 | [`Error::BlockStackUnderflow`] |
+    /// | 6 | [`Error::CallStackUnderflow`] |
+    /// | 7 | [`Error::InvalidLabelType`] |
+    /// | 8 | [`Error::InvalidStore`] |
+    /// | 9 | [`Error::InvalidBytecode`] |
+    /// | 10 | [`Error::ExportNotFound`] |
+    /// | 11 | [`Error::ParamTypeMismatch`] |
+    /// | 12 | [`Error::ParamCountMismatch`] |
+    /// | 13 | [`Error::StartDenied`] |
+    /// | 14 | [`Error::StartBudgetExceeded`] |
+    /// | 15 | [`Error::AuxiliaryBudgetExceeded`] |
+    /// | 16 | [`Error::AllocatorBudgetExceeded`] |
+    /// | 20 | [`Error::ImportUnresolved`] |
+    /// | 21 | [`LinkingError::IncompatibleImportType`] |
+    /// | 22 | [`Error::ImportSignatureMismatch`] |
+    /// | 23 | [`Error::Imports`] |
+    /// | 30 | [`Error::ParseError`] |
+    /// | 40 | `Error::Io` |
+    /// | 50 | [`Error::Snapshot`] |
+    /// | 100.. | [`Error::Trap`], see [`TrapKind::code`] |
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Other(_) => 1,
+            Self::UnsupportedFeature(_) => 2,
+            Self::FuncDidNotReturn => 3,
+            Self::ValueStackUnderflow => 4,
+            Self::BlockStackUnderflow => 5,
+            Self::CallStackUnderflow => 6,
+            Self::InvalidLabelType => 7,
+            Self::InvalidStore => 8,
+            Self::InvalidBytecode(_) => 9,
+            Self::ExportNotFound { .. } => 10,
+            Self::ParamTypeMismatch { .. } => 11,
+            Self::ParamCountMismatch { .. } => 12,
+            Self::StartDenied => 13,
+            Self::StartBudgetExceeded { .. } => 14,
+            Self::AuxiliaryBudgetExceeded { .. } => 15,
+            Self::AllocatorBudgetExceeded { .. } => 16,
+            Self::ImportUnresolved { .. } => 20,
+            Self::Linker(LinkingError::IncompatibleImportType { .. }) => 21,
+            Self::ImportSignatureMismatch { .. } => 22,
+            Self::Imports(_) => 23,
+            Self::ParseError(_) => 30,
+            #[cfg(feature = "std")]
+            Self::Io(_) => 40,
+            Self::Snapshot(_) => 50,
+            Self::Trap(trap) => trap.kind().code(),
+        }
+    }
+
+    /// Get the trap that caused this error, if any
+    pub fn trap(&self) -> Option<&Trap> {
+        match self {
+            Self::Trap(trap) => Some(trap),
+            _ => None,
+        }
+    }
+
+    /// Get the category of the trap that caused this error, if any
+    pub fn trap_kind(&self) -> Option<TrapKind> {
+        self.trap().map(Trap::kind)
+    }
+}
+
 impl From<Trap> for Error {
     fn from(value: Trap) -> Self {
         Self::Trap(value)
@@ -195,6 +585,7 @@ impl Display for Error {
 
             Self::Trap(trap) => write!(f, "trap: {}", trap),
             Self::Linker(err) => write!(f, "linking error: {}", err),
+            Self::Snapshot(err) => write!(f, "invalid snapshot: {}", err),
             Self::CallStackUnderflow => write!(f, "call stack empty"),
             Self::InvalidLabelType => write!(f, "invalid label type"),
             Self::Other(message) => write!(f, "unknown error: {}", message),
@@ -203,6 +594,49 @@ impl Display for Error {
             Self::BlockStackUnderflow => write!(f, "label stack underflow"),
             Self::ValueStackUnderflow => write!(f, "value stack underflow"),
             Self::InvalidStore => write!(f, "invalid store"),
+            Self::InvalidBytecode(message) => write!(f, "invalid bytecode: {}", message),
+            Self::ExportNotFound { name, kind } => write!(f, "export not found: {} ({:?})", name, kind),
+            Self::ParamCountMismatch { expected, got } => {
+                write!(f, "param count mismatch: expected {}, got {}", expected, got)
+            }
+            Self::ParamTypeMismatch { expected, got, index } => {
+                write!(f, "param type mismatch: expected {:?}, got {:?} at index {}", expected, got, index)
+            }
+            Self::StartDenied => write!(f, "module has a start function"),
+            Self::StartBudgetExceeded { max_cycles } => {
+                write!(f, "start function did not finish within {} cycles", max_cycles)
+            }
+            Self::AuxiliaryBudgetExceeded { max_cycles } => {
+                write!(f, "auxiliary call did not finish within {} cycles", max_cycles)
+            }
+            Self::AllocatorBudgetExceeded { max_cycles } => {
+                write!(f, "guest allocator did not finish within {} cycles", max_cycles)
+            }
+            Self::ImportUnresolved { module, name } => write!(f, "unresolved import: {}.{}", module, name),
+            Self::ImportSignatureMismatch { module, name, expected, got } => {
+                write!(f, "import signature mismatch for {}.{}: expected {:?}, got {:?}", module, name, expected, got)
+            }
+            Self::Imports(errors) => {
+                write!(f, "{} imports failed to resolve", errors.len())?;
+                errors.iter().try_for_each(|err| write!(f, "\n  {}", err))
+            }
+        }
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Truncated { expected, actual } => {
+                write!(f, "truncated: expected {} bytes, got {}", expected, actual)
+            }
+            Self::VersionSkew { found, expected } => {
+                write!(f, "format version {} is not supported, expected {}", found, expected)
+            }
+            Self::ModuleMismatch { expected, found } => {
+                write!(f, "taken from module {}, expected module {}", found, expected)
+            }
+            Self::Corrupt { offset, message } => write!(f, "corrupt at offset {}: {}", offset, message),
         }
     }
 }
@@ -210,7 +644,6 @@ impl Display for Error {
 impl Display for LinkingError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            Self::UnknownImport { module, name } => write!(f, "unknown import: {}.{}", module, name),
             Self::IncompatibleImportType { module, name } => {
                 write!(f, "incompatible import type: {}.{}", module, name)
             }
@@ -231,7 +664,9 @@ impl Display for Trap {
             Self::DivisionByZero => write!(f, "integer divide by zero"),
             Self::InvalidConversionToInt => write!(f, "invalid conversion to integer"),
             Self::IntegerOverflow => write!(f, "integer overflow"),
-            Self::CallStackOverflow => write!(f, "call stack exhausted"),
+            Self::StackExhausted { limit, max } => {
+                write!(f, "call stack exhausted: limit of {} {} reached", max, limit)
+            }
             Self::UndefinedElement { index } => write!(f, "undefined element: index={}", index),
             Self::UninitializedElement { index } => {
                 write!(f, "uninitialized element: index={}", index)
@@ -239,12 +674,45 @@ impl Display for Trap {
             Self::IndirectCallTypeMismatch { expected, actual } => {
                 write!(f, "indirect call type mismatch: expected={:?}, actual={:?}", expected, actual)
             }
+            Self::HostError { message, .. } => write!(f, "host error: {}", message),
+            Self::FloatDenied => write!(f, "floating point instruction denied"),
+            Self::Exit(code) => write!(f, "exit with status {}", code),
+            Self::ReadOnlyMemory { offset, len } => {
+                write!(f, "write to read-only memory: offset={}, len={}", offset, len)
+            }
+            Self::MisalignedAccess { offset, align } => {
+                write!(f, "misaligned memory access: offset={}, align={}", offset, align)
+            }
+            Self::ImportQuotaExceeded { module, name } => write!(f, "import quota exceeded: {}.{}", module, name),
         }
     }
 }
 
-#[cfg(feature = "std")]
-impl crate::std::error::Error for Error {}
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Trap(trap) => Some(trap),
+            Self::Linker(err) => Some(err),
+            Self::ParseError(err) => Some(err),
+            Self::Snapshot(err) => Some(err),
+            #[cfg(feature = "std")]
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl core::error::Error for Trap {}
+
+impl core::error::Error for LinkingError {}
+
+impl core::error::Error for SnapshotError {}
+
+impl From<SnapshotError> for Error {
+    fn from(value: SnapshotError) -> Self {
+        Self::Snapshot(value)
+    }
+}
 
 impl From<ParseError> for Error {
     fn from(value: ParseError) -> Self {
@@ -253,4 +721,134 @@ impl From<ParseError> for Error {
 }
 
 /// A wrapper around [`core::result::Result`] for this crates operations
-pub type Result<T, E = Error> = crate::std::result::Result<T, E>;
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::Imports;
+    use crate::types::value::WasmValue;
+    use crate::{parse_bytes, Instance};
+
+    fn run(func: &str, args: &[WasmValue]) -> Error {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory 1)
+                (func (export "div") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1)))
+                (func (export "load") (param i32) (result i64) (i64.load (local.get 0))))
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped(func).unwrap().call(args.to_vec(), None).unwrap();
+        exec.run(usize::MAX).unwrap_err()
+    }
+
+    #[test]
+    fn test_trap_kinds() {
+        let err = run("div", &[WasmValue::I32(1), WasmValue::I32(0)]);
+        assert_eq!(err.trap_kind(), Some(TrapKind::DivisionByZero));
+
+        let err = run("div", &[WasmValue::I32(i32::MIN), WasmValue::I32(-1)]);
+        assert_eq!(err.trap_kind(), Some(TrapKind::IntegerOverflow));
+
+        let err = run("load", &[WasmValue::I32(65534)]);
+        assert_eq!(err.trap_kind(), Some(TrapKind::MemoryOutOfBounds));
+        assert!(matches!(err.trap(), Some(Trap::MemoryOutOfBounds { offset: 65534, len: 8, max: 65536 })));
+        assert_eq!(err.code(), 101);
+        assert!(core::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn test_host_trap() {
+        #[derive(Debug, PartialEq)]
+        struct RangeViolation {
+            progress: u32,
+        }
+
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "reef" "progress" (func $progress (param i32)))
+                (func (export "run") (call $progress (i32.const 150))))
+            "#,
+        )
+        .unwrap();
+        let mut imports = Imports::new();
+        let progress = crate::imports::Extern::typed_func(|_, progress: i32| -> Result<()> {
+            Err(Error::host_trap_with("progress out of range", RangeViolation { progress: progress as u32 }))
+        });
+        imports.define("reef", "progress", progress).unwrap();
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), imports).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(alloc::vec::Vec::new(), None).unwrap();
+
+        let Error::Trap(mut trap) = exec.run(usize::MAX).unwrap_err() else { panic!("expected a trap") };
+        assert_eq!(trap.kind(), TrapKind::HostError);
+        assert_eq!(trap.kind().code(), 110);
+        assert_eq!(trap.to_string(), "host error: progress out of range");
+        assert_eq!(trap.downcast_ref::<RangeViolation>(), Some(&RangeViolation { progress: 150 }));
+        assert_eq!(trap.downcast::<u32>(), None);
+        assert_eq!(trap.downcast::<RangeViolation>(), Some(RangeViolation { progress: 150 }));
+        assert_eq!(trap.downcast_ref::<RangeViolation>(), None);
+    }
+
+    #[test]
+    fn test_all_imports_reported() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "a" (func))
+                (import "env" "b" (func (param i32)))
+                (import "env" "c" (global i32))
+                (import "env" "d" (func)))
+            "#,
+        )
+        .unwrap();
+        let mut imports = Imports::new();
+        imports.define("env", "a", crate::imports::Extern::typed_func(|_, ()| Ok(()))).unwrap();
+        imports.define("env", "b", crate::imports::Extern::typed_func(|_, ()| Ok(()))).unwrap();
+
+        let err = Instance::instantiate(parse_bytes(&wasm).unwrap(), imports).unwrap_err();
+        assert_eq!(err.code(), 23);
+        let Error::Imports(errors) = &err else { panic!("expected all failed imports") };
+        assert!(matches!(&errors[..], [
+            Error::ImportSignatureMismatch { name: b, .. },
+            Error::ImportUnresolved { name: c, .. },
+            Error::ImportUnresolved { name: d, .. },
+        ] if b == "b" && c == "c" && d == "d"));
+        assert_eq!(err.to_string().lines().count(), 4);
+    }
+
+    #[test]
+    fn test_error_variants() {
+        let wasm = wat::parse_str(r#"(module (import "env" "f" (func)) (func (export "g") (param i32 i64)))"#).unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+
+        let err = Instance::instantiate(module.clone(), Imports::new()).unwrap_err();
+        assert!(matches!(&err, Error::ImportUnresolved { module, name } if module == "env" && name == "f"));
+        assert_eq!(err.code(), 20);
+
+        let mut imports = Imports::new();
+        imports.define("env", "f", crate::imports::Extern::typed_func(|_, x: i32| Ok(x))).unwrap();
+        let err = Instance::instantiate(module.clone(), imports).unwrap_err();
+        let Error::ImportSignatureMismatch { expected, got, .. } = &err else {
+            panic!("expected a signature mismatch")
+        };
+        assert_eq!(expected, &FuncType::default());
+        assert_eq!(got, &FuncType { params: [ValType::I32].into(), results: [ValType::I32].into() });
+        assert_eq!(err.code(), 22);
+
+        let mut imports = Imports::new();
+        imports.define("env", "f", crate::imports::Extern::typed_func(|_, ()| Ok(()))).unwrap();
+        let instance = Instance::instantiate(module, imports).unwrap();
+
+        let err = instance.exported_memory("g").unwrap_err();
+        assert!(matches!(&err, Error::ExportNotFound { name, kind: ExternalKind::Memory } if name == "g"));
+        assert_eq!(err.code(), 10);
+
+        let func = instance.exported_func_untyped("g").unwrap();
+        let err = func.call(alloc::vec![WasmValue::I32(0), WasmValue::I32(0)], None).unwrap_err();
+        assert!(matches!(err, Error::ParamTypeMismatch { expected: ValType::I64, got: ValType::I32, index: 1 }));
+    }
+}
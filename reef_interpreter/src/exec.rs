@@ -1,20 +1,216 @@
 //! Modules for types related to controlling the execution of Wasm
 
-use alloc::vec::Vec;
+use alloc::{boxed::Box, rc::Rc, string::ToString, vec::Vec};
+use core::cell::RefCell;
 use core::mem::take;
 
-use rkyv::{
-    ser::{
-        serializers::{AlignedSerializer, CompositeSerializer, HeapScratch, SharedSerializeMap},
-        Serializer,
-    },
-    AlignedVec,
-};
+use rkyv::AlignedVec;
+
+use crate::error::{Error, Result, Trap};
+use crate::func::{FromWasmValueTuple, FuncHandle, FuncId};
+use crate::imports::Function;
+use crate::instance::Instance;
+use crate::profile::{Coverage, Profile, Profiler};
+use crate::replay::{HostCalls, ReplayLog};
+use crate::runtime::{CallFrame, RawWasmValue, MIN_VALUE_STACK_SIZE};
+use crate::store::memory::SparseMemory;
+use crate::types::{instructions::Instruction, value::WasmValue, FuncAddr, FuncType, MemAddr};
+use crate::{unlikely, VecExt, CALL_STACK_SIZE};
+
+pub use crate::runtime::{Stack, StackLimits};
+
+/// Configuration for executions
+///
+/// A config is attached to an instance with [`Instance::instantiate_with_config`] and used for every call on it, or
+/// passed to a single call with [`FuncHandle::call_with_config`]. Build one from the defaults with the `with_` methods:
+///
+/// ```
+/// # use reef_interpreter::exec::ExecConfig;
+/// let config = ExecConfig::new().with_fuel_check_interval(1000).with_max_memory_pages(Some(16));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecConfig {
+    /// Number of values the value stack is allocated for up front
+    pub value_stack_capacity: usize,
+    /// Number of call frames the call stack is allocated for up front
+    pub call_stack_capacity: usize,
+    /// Limits on the size of the stack
+    pub stack_limits: StackLimits,
+    /// Number of instructions executed between checks of the cycle budget passed to [`ExecHandle::run`]
+    ///
+    /// Larger intervals take the budget check out of the hot loop, the budget is still never exceeded.
+    /// An interval of `1` checks before every instruction. [`ExecHandle::step`] always does that.
+    pub fuel_check_interval: usize,
+    /// Maximum number of pages a memory can grow to with `memory.grow`, on top of the module's own limits
+    ///
+    /// Growing beyond it fails like growing beyond the memory's maximum. This is taken from the instance's config,
+    /// the one passed to a single call is ignored.
+    pub max_memory_pages: Option<usize>,
+    /// Only pause at a [`SafePoint`] when the cycle budget passed to [`ExecHandle::run`] runs out
+    ///
+    /// Execution continues past the budget until it reaches the next one. Returning isn't a safe point, so this can
+    /// run the rest of the current function and of every caller it returns into, up to the next call or loop in
+    /// each: the overrun is bounded by the total length of the function bodies on the call stack, not by a single
+    /// one. [`ExecHandle::pause_point`] reports where it paused. Breakpoints and [`ExecHandle::step`] still
+    /// pause anywhere. Like the stack limits, this is saved with the stack.
+    pub safe_points_only: bool,
+    /// Whether memory accesses are checked against their alignment hint, see [`AlignmentCheck`]
+    ///
+    /// Like the stack limits, this is saved with the stack.
+    pub alignment_check: AlignmentCheck,
+}
+
+impl Default for ExecConfig {
+    fn default() -> Self {
+        Self {
+            value_stack_capacity: MIN_VALUE_STACK_SIZE,
+            call_stack_capacity: CALL_STACK_SIZE,
+            stack_limits: StackLimits::default(),
+            fuel_check_interval: 1,
+            max_memory_pages: None,
+            safe_points_only: false,
+            alignment_check: AlignmentCheck::Off,
+        }
+    }
+}
+
+impl ExecConfig {
+    /// The default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-use crate::error::Result;
-use crate::func::{FromWasmValueTuple, FuncHandle};
-use crate::runtime::{RawWasmValue, Stack};
-use crate::types::value::WasmValue;
+    /// Set [`ExecConfig::value_stack_capacity`]
+    pub fn with_value_stack_capacity(mut self, capacity: usize) -> Self {
+        self.value_stack_capacity = capacity;
+        self
+    }
+
+    /// Set [`ExecConfig::call_stack_capacity`]
+    pub fn with_call_stack_capacity(mut self, capacity: usize) -> Self {
+        self.call_stack_capacity = capacity;
+        self
+    }
+
+    /// Set [`ExecConfig::stack_limits`]
+    pub fn with_stack_limits(mut self, limits: StackLimits) -> Self {
+        self.stack_limits = limits;
+        self
+    }
+
+    /// Set [`ExecConfig::fuel_check_interval`]
+    pub fn with_fuel_check_interval(mut self, interval: usize) -> Self {
+        self.fuel_check_interval = interval;
+        self
+    }
+
+    /// Set [`ExecConfig::max_memory_pages`]
+    pub fn with_max_memory_pages(mut self, max_pages: Option<usize>) -> Self {
+        self.max_memory_pages = max_pages;
+        self
+    }
+
+    /// Set [`ExecConfig::safe_points_only`]
+    pub fn with_safe_points_only(mut self, safe_points_only: bool) -> Self {
+        self.safe_points_only = safe_points_only;
+        self
+    }
+
+    /// Set [`ExecConfig::alignment_check`]
+    pub fn with_alignment_check(mut self, check: AlignmentCheck) -> Self {
+        self.alignment_check = check;
+        self
+    }
+}
+
+/// A place where execution can pause without depending on the exact instructions in between, see
+/// [`ExecConfig::safe_points_only`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub enum SafePoint {
+    /// Before the first instruction of a function
+    FunctionEntry,
+    /// Before a `call` or `call_indirect` instruction
+    Call,
+    /// At the start of a loop body, after branching back to it
+    LoopBackEdge,
+}
+
+/// How memory accesses that aren't aligned to their alignment hint are handled, see [`ExecConfig::alignment_check`]
+///
+/// Wasm allows misaligned accesses, the alignment of a load or store is only a hint. Checking it helps to find guests
+/// that rely on the host tolerating them, which is slower for some accesses. Only the address of the access is checked,
+/// the alignment of the memory's host allocation isn't taken into account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub enum AlignmentCheck {
+    /// Misaligned accesses are executed like any other access
+    #[default]
+    Off,
+    /// Misaligned accesses are reported to the trace hook with [`TraceEvent::MisalignedAccess`] before executing them
+    Trace,
+    /// Misaligned accesses trap with [`Trap::MisalignedAccess`]
+    Trap,
+}
+
+/// Whether an auxiliary call may write to memory, see [`ExecHandle::call_auxiliary`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryAccess {
+    /// The call can read and write all memories
+    #[default]
+    ReadWrite,
+    /// Writes trap with [`Trap::ReadOnlyMemory`] and `memory.grow` fails, unless it grows by zero pages
+    ///
+    /// Host functions called by the guest can't write either. Globals and tables can still be changed.
+    ReadOnly,
+}
+
+/// A pool of stacks whose allocations are reused between calls
+///
+/// Start executions with [`FuncHandle::call_pooled`] and hand finished ones back with [`StackPool::recycle`].
+/// Hosts running many short calls save allocating a new value and call stack for every one of them.
+#[derive(Debug, Default)]
+pub struct StackPool {
+    config: ExecConfig,
+    stacks: Vec<Stack>,
+}
+
+impl StackPool {
+    /// Create an empty pool, new stacks are created with the given configuration
+    pub fn new(config: ExecConfig) -> Self {
+        Self { config, stacks: Vec::new() }
+    }
+
+    /// The number of stacks ready for reuse
+    pub fn len(&self) -> usize {
+        self.stacks.len()
+    }
+
+    /// Whether no stacks are ready for reuse
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    /// Return the stack of an execution to the pool, getting back the function handle to call again
+    ///
+    /// The execution doesn't have to be finished, any remaining state is discarded. This includes the parameters of
+    /// an unfinished typed call, use [`ExecHandle::free_params`] first to free them.
+    pub fn recycle(&mut self, exec: impl Into<ExecHandle>) -> FuncHandle {
+        let exec = exec.into();
+        self.stacks.push(exec.stack);
+        exec.func_handle
+    }
+
+    pub(crate) fn take(&mut self, call_frame: CallFrame) -> Stack {
+        match self.stacks.pop() {
+            Some(mut stack) => {
+                stack.reset(call_frame, &self.config);
+                stack
+            }
+            None => Stack::new(call_frame, &self.config),
+        }
+    }
+}
 
 /// Retuened by [`run`](ExecHandle::run) to indicate if the function finsihed execution with the given max_cycles
 #[derive(Debug)]
@@ -23,6 +219,80 @@ pub enum CallResult {
     Done(Vec<WasmValue>),
     /// Execution has not finished and `run` has to be called again
     Incomplete,
+    /// Execution stopped at a breakpoint, see [`ExecHandle::add_breakpoint`]
+    ///
+    /// Calling `run` again continues from the breakpoint.
+    Breakpoint(FrameState),
+    /// The guest or a host function ended the execution with an exit status, see [`Error::exit`]
+    ///
+    /// The execution can't be continued, calling `run` again returns the same status.
+    Exit(i32),
+}
+
+/// What happened during the last [`run`](ExecHandle::run) or [`step`](ExecHandle::step), see [`ExecHandle::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecStats {
+    /// Number of instructions executed
+    pub instructions: u64,
+    /// Number of cycles of the budget passed to `run` that were used up
+    ///
+    /// Every instruction takes one cycle, so this is the same as `instructions`. It can be larger than the budget
+    /// with [`ExecConfig::safe_points_only`].
+    pub fuel: u64,
+    /// Number of calls to Wasm or host functions, including calls of host functions back into the guest
+    pub calls: u64,
+    /// The largest number of Wasm call frames at once, including the ones that existed before
+    pub max_call_depth: usize,
+    /// Number of pages all memories grew by
+    pub memory_grown: u64,
+}
+
+impl ExecStats {
+    /// Add the stats of a following run
+    #[cfg(feature = "std")]
+    fn add(&mut self, next: &ExecStats) {
+        self.instructions += next.instructions;
+        self.fuel += next.fuel;
+        self.calls += next.calls;
+        self.max_call_depth = self.max_call_depth.max(next.max_call_depth);
+        self.memory_grown += next.memory_grown;
+    }
+}
+
+/// Number of cycles [`ExecHandle::run_for`] runs between checks of the time
+#[cfg(feature = "std")]
+pub const RUN_FOR_SLICE: usize = 10_000;
+
+/// Returned by [`step`](ExecHandle::step)
+#[derive(Debug)]
+pub enum StepResult {
+    /// Execution finished and the resulting function return is included
+    Done(Vec<WasmValue>),
+    /// See [`CallResult::Exit`]
+    Exit(i32),
+    /// Execution was paused, either because all steps were taken or a breakpoint was reached
+    Paused(FrameState),
+}
+
+/// The state of the innermost call frame of a paused execution
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameState {
+    /// Address of the function that is executing
+    pub func: FuncAddr,
+    /// Index of the next instruction in the function body
+    pub instr_ptr: usize,
+    /// Number of call frames on the call stack, including this one
+    pub depth: usize,
+    /// Values of the parameters and locals of the function
+    pub locals: Vec<WasmValue>,
+    /// Whether execution stopped because of a breakpoint at this instruction
+    pub at_breakpoint: bool,
+}
+
+enum ExecState {
+    Done(Vec<WasmValue>),
+    Exit(i32),
+    Paused { at_breakpoint: bool },
 }
 
 /// Handle to a running execution context of a Wasm function
@@ -30,48 +300,429 @@ pub enum CallResult {
 pub struct ExecHandle {
     pub(crate) func_handle: FuncHandle,
     pub(crate) stack: Stack,
+    pub(crate) trace_hook: Option<TraceHook>,
+    pub(crate) breakpoints: Vec<(FuncAddr, usize)>,
+    pub(crate) at_breakpoint: bool,
+    pub(crate) profiler: Option<Profiler>,
+    pub(crate) coverage: Option<Coverage>,
+    pub(crate) host_calls: Option<HostCalls>,
+    /// The status the execution ended with, see [`CallResult::Exit`]
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) stats: ExecStats,
 }
 
 impl ExecHandle {
     /// Make progress on the execution of the started Wasm function. `max_cycles` instructions will be executed.
+    ///
+    /// Each call is a new quantum for the [`ImportQuota`](crate::imports::ImportQuota)s of the instance.
     pub fn run(&mut self, max_cycles: usize) -> Result<CallResult> {
-        let runtime = crate::runtime::interpreter::Interpreter {};
-        if !runtime.exec(&mut self.func_handle.instance, &mut self.stack, max_cycles)? {
-            return Ok(CallResult::Incomplete);
+        self.func_handle.instance.quotas.start_quantum();
+        self.run_slice(max_cycles)
+    }
+
+    fn run_slice(&mut self, max_cycles: usize) -> Result<CallResult> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("run", max_cycles).entered();
+
+        Ok(match self.exec(max_cycles, self.stack.fuel_check_interval, self.stack.safe_points_only)? {
+            ExecState::Done(res) => {
+                self.free_params()?;
+                CallResult::Done(res)
+            }
+            ExecState::Exit(code) => CallResult::Exit(code),
+            ExecState::Paused { at_breakpoint: false } => CallResult::Incomplete,
+            ExecState::Paused { at_breakpoint: true } => CallResult::Breakpoint(self.frame_state(true)?),
+        })
+    }
+
+    /// Run until the function returns or `duration` has passed, returning [`CallResult::Incomplete`] in that case
+    ///
+    /// Execution runs in slices of [`RUN_FOR_SLICE`] cycles, and the time is checked after each one, so it can
+    /// take a bit longer than `duration`. At least one slice is run, so even a zero duration makes progress.
+    /// Host functions aren't interrupted. [`ExecHandle::stats`] covers all slices, and they are a single quantum for
+    /// [`ImportQuota`](crate::imports::ImportQuota)s.
+    #[cfg(feature = "std")]
+    pub fn run_for(&mut self, duration: std::time::Duration) -> Result<CallResult> {
+        let start = std::time::Instant::now();
+        self.run_slices(|| start.elapsed() >= duration)
+    }
+
+    /// Run slices of [`RUN_FOR_SLICE`] cycles until the function returns or `out_of_time` returns true after a slice
+    #[cfg(feature = "std")]
+    fn run_slices(&mut self, mut out_of_time: impl FnMut() -> bool) -> Result<CallResult> {
+        let mut stats = ExecStats::default();
+        self.func_handle.instance.quotas.start_quantum();
+        loop {
+            let result = self.run_slice(RUN_FOR_SLICE);
+            stats.add(&self.stats);
+            self.stats = stats;
+
+            match result? {
+                CallResult::Incomplete if !out_of_time() => {}
+                result => return Ok(result),
+            }
         }
+    }
+
+    /// Execute at most `n` instructions and return the state of the current frame
+    ///
+    /// Like [`run`](Self::run), this stops early at breakpoints.
+    pub fn step(&mut self, n: usize) -> Result<StepResult> {
+        if n == 0 {
+            return Ok(StepResult::Paused(self.frame_state(false)?));
+        }
+
+        Ok(match self.exec(n - 1, 1, false)? {
+            ExecState::Done(res) => {
+                self.free_params()?;
+                StepResult::Done(res)
+            }
+            ExecState::Exit(code) => StepResult::Exit(code),
+            ExecState::Paused { at_breakpoint } => StepResult::Paused(self.frame_state(at_breakpoint)?),
+        })
+    }
+
+    /// Free the guest memory holding the parameters of a [typed call](crate::func::FuncHandleTyped::call)
+    ///
+    /// This happens when the call is done, and when an [`ExecHandleTyped`] is turned back into its instance. An
+    /// execution that is given up before that, e.g. with [`ExecHandle::into_instance`], should call this first.
+    /// The regions are part of the execution's state, so they are also freed after resuming a serialized execution.
+    pub fn free_params(&mut self) -> Result<()> {
+        let instance = &mut self.func_handle.instance;
+        self.stack.param_regions.drain(..).try_for_each(|region| instance.free_in_guest(region))
+    }
 
+    /// Give up the execution and get back the instance it ran on
+    ///
+    /// The execution doesn't have to be finished, any remaining state is discarded. This includes the parameters of
+    /// an unfinished typed call, use [`ExecHandle::free_params`] first to free them.
+    pub fn into_instance(self) -> Instance {
+        self.func_handle.instance
+    }
+
+    /// The instance the execution runs on, e.g. to [`freeze`](Instance::freeze) it while execution is paused
+    pub fn instance(&self) -> &Instance {
+        &self.func_handle.instance
+    }
+
+    /// Statistics of the last call to [`run`](Self::run) or [`step`](Self::step), also if it failed
+    ///
+    /// Schedulers can use these to size the budget of the next run. They aren't part of the serialized state.
+    pub fn stats(&self) -> ExecStats {
+        self.stats
+    }
+
+    /// The limits on the size of the stack
+    pub fn stack_limits(&self) -> StackLimits {
+        self.stack.limits
+    }
+
+    /// Set the limits on the size of the stack
+    ///
+    /// The limits are saved with the stack, so they also apply when execution is resumed from serialized state.
+    pub fn set_stack_limits(&mut self, limits: StackLimits) {
+        self.stack.limits = limits;
+    }
+
+    /// The number of instructions between checks of the cycle budget, see [`ExecConfig::fuel_check_interval`]
+    pub fn fuel_check_interval(&self) -> usize {
+        self.stack.fuel_check_interval
+    }
+
+    /// Set the number of instructions between checks of the cycle budget
+    ///
+    /// Like the stack limits, this is saved with the stack. An interval of `0` is treated as `1`.
+    pub fn set_fuel_check_interval(&mut self, interval: usize) {
+        self.stack.fuel_check_interval = interval;
+    }
+
+    /// Set whether running out of budget only pauses at safe points, see [`ExecConfig::safe_points_only`]
+    pub fn set_safe_points_only(&mut self, safe_points_only: bool) {
+        self.stack.safe_points_only = safe_points_only;
+    }
+
+    /// Set how misaligned memory accesses are handled, see [`ExecConfig::alignment_check`]
+    pub fn set_alignment_check(&mut self, check: AlignmentCheck) {
+        self.stack.alignment_check = check;
+    }
+
+    /// The safe point execution paused at when it last ran out of budget
+    ///
+    /// `None` if [`ExecConfig::safe_points_only`] isn't set, or execution didn't pause because of the budget. This is
+    /// saved with the stack, so it's also known after resuming from a snapshot.
+    pub fn pause_point(&self) -> Option<SafePoint> {
+        self.stack.pause_point
+    }
+
+    /// Stop execution before the instruction at `instr_ptr` in the function at `func` is executed
+    ///
+    /// Instruction indices refer to the interpreter's bytecode, as reported by [`FrameState::instr_ptr`]
+    /// and [`TraceEvent::Instruction`]. Execution that resumes at a breakpoint doesn't stop there again immediately.
+    pub fn add_breakpoint(&mut self, func: FuncAddr, instr_ptr: usize) {
+        if let Err(idx) = self.breakpoints.binary_search(&(func, instr_ptr)) {
+            self.breakpoints.insert(idx, (func, instr_ptr));
+        }
+    }
+
+    /// Remove all breakpoints
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Remove a breakpoint, returning whether it existed
+    pub fn remove_breakpoint(&mut self, func: FuncAddr, instr_ptr: usize) -> bool {
+        match self.breakpoints.binary_search(&(func, instr_ptr)) {
+            Ok(idx) => {
+                self.breakpoints.remove(idx);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Start collecting a [`Profile`] of the execution, resetting any previous profile
+    ///
+    /// Functions that are already running are counted as called once.
+    pub fn enable_profiling(&mut self) {
+        let func_count = self.func_handle.instance.funcs.len();
+        let call_stack = self.stack.call_stack.frames.iter().map(|frame| frame.func_instance);
+        self.profiler = Some(Profiler::new(func_count, call_stack));
+    }
+
+    /// Stop collecting a profile
+    pub fn disable_profiling(&mut self) {
+        self.profiler = None;
+    }
+
+    /// Get the profile collected so far
+    ///
+    /// The profile is empty if profiling wasn't enabled with [`ExecHandle::enable_profiling`].
+    pub fn profile(&self) -> Profile {
+        self.profiler.as_ref().map(Profiler::profile).unwrap_or_default()
+    }
+
+    /// Start counting how often each instruction is executed, resetting any previous counts
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(&self.func_handle.instance.funcs));
+    }
+
+    /// Stop counting instruction executions
+    pub fn disable_coverage(&mut self) {
+        self.coverage = None;
+    }
+
+    /// Get the instruction counts collected so far
+    ///
+    /// The coverage is empty if it wasn't enabled with [`ExecHandle::enable_coverage`].
+    pub fn coverage(&self) -> Coverage {
+        self.coverage.clone().unwrap_or_default()
+    }
+
+    /// Start recording the results of all host function calls into a [`ReplayLog`]
+    ///
+    /// This replaces a previous recording or replay.
+    pub fn start_recording(&mut self) {
+        self.host_calls = Some(HostCalls::Record(ReplayLog::default()));
+    }
+
+    /// Stop recording host function calls and return the log
+    ///
+    /// Returns `None` if [`ExecHandle::start_recording`] wasn't called before.
+    pub fn take_recording(&mut self) -> Option<ReplayLog> {
+        match self.host_calls.take() {
+            Some(HostCalls::Record(log)) => Some(log),
+            host_calls => {
+                self.host_calls = host_calls;
+                None
+            }
+        }
+    }
+
+    /// Answer host function calls from a recorded log instead of calling the imports
+    ///
+    /// Execution fails with an error if it diverges from the recording.
+    pub fn replay(&mut self, log: ReplayLog) {
+        self.host_calls = Some(HostCalls::Replay { log, next: 0 });
+    }
+
+    /// Get the state of every frame on the call stack, starting with the innermost one
+    ///
+    /// The instruction index of a calling frame points to the instruction after the call.
+    pub fn backtrace(&self) -> Result<Vec<FrameState>> {
+        let frames = &self.stack.call_stack.frames;
+        frames.iter().enumerate().rev().map(|(idx, frame)| self.describe_frame(frame, idx + 1, false)).collect()
+    }
+
+    fn frame_state(&self, at_breakpoint: bool) -> Result<FrameState> {
+        let frame = self.stack.call_stack.frames.last().ok_or(Error::CallStackUnderflow)?;
+        self.describe_frame(frame, self.stack.call_stack.frames.len(), at_breakpoint)
+    }
+
+    fn describe_frame(&self, frame: &CallFrame, depth: usize, at_breakpoint: bool) -> Result<FrameState> {
+        let Function::Wasm(func) = self.func_handle.instance.funcs.get_or_instance(frame.func_instance, "function")?
+        else {
+            return Err(Error::Other("Current frame is not a Wasm function".to_string()));
+        };
+
+        let types = func.ty.params.iter().chain(func.locals.iter());
+        Ok(FrameState {
+            func: frame.func_instance,
+            instr_ptr: frame.instr_ptr,
+            depth,
+            locals: frame.locals(&self.stack.values).iter().zip(types).map(|(v, ty)| v.attach_type(*ty)).collect(),
+            at_breakpoint,
+        })
+    }
+
+    fn exec(&mut self, max_cycles: usize, fuel_check_interval: usize, safe_points_only: bool) -> Result<ExecState> {
+        if let Some(code) = self.exit_code {
+            self.stats = ExecStats::default();
+            return Ok(ExecState::Exit(code));
+        }
+
+        match self.exec_until_return(max_cycles, fuel_check_interval, safe_points_only) {
+            Ok(true) => Ok(ExecState::Done(self.results()?.collect())),
+            Ok(false) => Ok(ExecState::Paused { at_breakpoint: self.at_breakpoint }),
+            Err(Error::Trap(Trap::Exit(code))) => {
+                self.exit_code = Some(code);
+                Ok(ExecState::Exit(code))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Run for at most `max_cycles` instructions, returning whether the function returned
+    ///
+    /// With `safe_points_only`, execution continues past `max_cycles` until it reaches a [`SafePoint`].
+    fn exec_until_return(
+        &mut self,
+        max_cycles: usize,
+        fuel_check_interval: usize,
+        safe_points_only: bool,
+    ) -> Result<bool> {
+        let mut runtime = crate::runtime::interpreter::Interpreter {
+            fuel_check_interval,
+            safe_points_only,
+            alignment_check: self.stack.alignment_check,
+            trace: self.trace_hook.as_mut(),
+            profiler: self.profiler.as_mut(),
+            coverage: self.coverage.as_mut(),
+            host_calls: self.host_calls.as_mut(),
+            breakpoints: &self.breakpoints,
+            resume_from_breakpoint: self.at_breakpoint,
+            hit_breakpoint: false,
+            ..Default::default()
+        };
+        let pages = |instance: &Instance| instance.memories.iter().map(|m| m.page_count as u64).sum::<u64>();
+        let pages_before = pages(&self.func_handle.instance);
+        let done = runtime.exec(&mut self.func_handle.instance, &mut self.stack, max_cycles);
+        self.stats = ExecStats { memory_grown: pages(&self.func_handle.instance) - pages_before, ..runtime.stats };
+        #[cfg(feature = "tracing")]
+        match &done {
+            Err(Error::Trap(Trap::Exit(code))) => tracing::debug!(code, "execution exited"),
+            Err(Error::Trap(trap)) => tracing::warn!(?trap, "execution trapped"),
+            _ => {}
+        }
+        let done = done?;
+        self.at_breakpoint = runtime.hit_breakpoint;
+        Ok(done)
+    }
+
+    /// The results of the function once it returned
+    fn results(&self) -> Result<impl Iterator<Item = WasmValue> + '_> {
         // Once the function returns:
         let result_m = self.func_handle.ty.results.len();
 
-        // 1. Assert: m values are on the top of the stack (Ensured by validation)
-        assert!(self.stack.values.len() >= result_m);
-
-        // 2. Pop m values from the stack
+        // Pop m values from the stack, which are there after validation
         let res = self.stack.values.last_n(result_m)?;
 
         // The values are returned as the results of the invocation.
-        Ok(CallResult::Done(
-            res.iter().zip(self.func_handle.ty.results.iter()).map(|(v, ty)| v.attach_type(*ty)).collect(),
-        ))
+        Ok(res.iter().zip(self.func_handle.ty.results.iter()).map(|(v, ty)| v.attach_type(*ty)))
+    }
+
+    /// Set a hook that is called for every [`TraceEvent`] during execution, replacing the previous one
+    ///
+    /// This makes it possible to build profilers and debuggers on top of the interpreter.
+    /// The hook is not part of the serialized state and has to be set again after resuming execution.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(TraceEvent) + 'static) {
+        self.trace_hook = Some(TraceHook(Box::new(hook)));
+    }
+
+    /// Remove the trace hook
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
     }
 
     /// Take the current execution state and serialize it
+    ///
+    /// The contents of `buf` are replaced, pass the previous snapshot to reuse its allocation. The snapshot starts
+    /// with a header that identifies the module and format version, so restoring it with
+    /// [`Instance::instantiate_with_state`] fails with a [`SnapshotError`](crate::error::SnapshotError)
+    /// instead of resuming with the wrong code.
+    ///
+    /// Only the values, blocks and frames currently on the stack are included, not the space the stack reserved
+    /// while it was deeper, so the size of a snapshot doesn't depend on how deep the execution has been before.
     pub fn serialize(&mut self, buf: AlignedVec) -> Result<AlignedVec> {
-        let memory = &mut self.func_handle.instance.memories[0];
-        let globals = self.func_handle.instance.globals.iter().map(|g| g.value).collect();
-        let data = SerializationState { stack: take(&mut self.stack), memory: take(&mut memory.data), globals };
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "serialize",
+            memory_bytes = self.func_handle.instance.memories.first().map_or(0, |mem| mem.data.len())
+        )
+        .entered();
 
-        let mut serializer = CompositeSerializer::new(
-            AlignedSerializer::new(buf),
-            HeapScratch::<0x1000>::new(),
-            SharedSerializeMap::new(),
-        );
-        serializer.serialize_value(&data).expect("Failed to serialize state");
+        let instance = &self.func_handle.instance;
+        let fingerprint = instance.fingerprint()?;
+        // modules without a memory are snapshotted with an empty one
+        let memory = instance.memories.first().map(|mem| mem.snapshot()).transpose()?.unwrap_or_default();
+        let globals = instance.globals.iter().map(|g| g.value).collect();
+        let data = SerializationState { stack: take(&mut self.stack), memory, globals };
 
-        memory.data = data.memory;
+        let res = crate::snapshot::write(&data, fingerprint, buf);
         self.stack = data.stack;
+        res
+    }
+
+    /// Call the exported function `name` on the instance of this execution and run it to completion
+    ///
+    /// The call gets a stack of its own, created from the instance's [`ExecConfig`], and none of the breakpoints,
+    /// hooks or recordings of this execution. So a paused execution can be continued afterwards as if the call never
+    /// happened, apart from the changes the call made to the instance, which `access` can restrict. This is meant for
+    /// short calls like asking the guest for its status, the call fails with [`Error::AuxiliaryBudgetExceeded`] if it
+    /// doesn't return within `max_cycles` instructions.
+    pub fn call_auxiliary(
+        &mut self,
+        name: &str,
+        params: &[WasmValue],
+        max_cycles: usize,
+        access: MemoryAccess,
+    ) -> Result<Vec<WasmValue>> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("call_auxiliary", name, max_cycles, ?access).entered();
+
+        let instance = &mut self.func_handle.instance;
+        let FuncId { addr, ty, .. } = instance.exported_func_id(name)?;
+        crate::func::check_params(&ty, params)?;
+        let Function::Wasm(func) = instance.funcs.get_or_instance(addr, "function")? else {
+            return Err(Error::Other("Can't call Host function directly".to_string()));
+        };
+        let mut stack = Stack::new(CallFrame::new(addr, func, 0, 0), &instance.exec_config);
+        stack.values.extend_from_typed(params);
+        stack.values.enter_frame(func)?;
+
+        let mut runtime = crate::runtime::interpreter::Interpreter {
+            fuel_check_interval: instance.exec_config.fuel_check_interval,
+            alignment_check: instance.exec_config.alignment_check,
+            ..Default::default()
+        };
+        instance.memories.iter_mut().for_each(|mem| mem.read_only = access == MemoryAccess::ReadOnly);
+        let done = runtime.exec(instance, &mut stack, max_cycles);
+        instance.memories.iter_mut().for_each(|mem| mem.read_only = false);
 
-        Ok(serializer.into_serializer().into_inner())
+        if !done? {
+            return Err(Error::AuxiliaryBudgetExceeded { max_cycles });
+        }
+        let results = stack.values.last_n(ty.results.len())?;
+        Ok(results.iter().zip(ty.results.iter()).map(|(v, ty)| v.attach_type(*ty)).collect())
     }
 }
 
@@ -82,6 +733,111 @@ pub enum CallResultTyped<R: FromWasmValueTuple> {
     Done(R),
     /// See [`CallResult::Incomplete`]
     Incomplete,
+    /// See [`CallResult::Breakpoint`]
+    Breakpoint(FrameState),
+    /// See [`CallResult::Exit`]
+    Exit(i32),
+}
+
+/// A function that is called many times, see [`FuncHandle::prepare`]
+///
+/// The stack and the results are kept between calls, so once they have grown large enough a call doesn't allocate.
+/// Parameters are passed as a slice, which the host can reuse as well.
+#[derive(Debug)]
+pub struct PreparedCall {
+    exec: ExecHandle,
+    results: Vec<WasmValue>,
+    /// Whether a call was started and hasn't returned yet
+    running: bool,
+}
+
+impl PreparedCall {
+    pub(crate) fn new(func_handle: FuncHandle, config: &ExecConfig) -> Result<Self> {
+        let Function::Wasm(wasm_func) = func_handle.instance.funcs.get_or_instance(func_handle.addr, "function")?
+        else {
+            return Err(Error::Other("Can't call Host function directly".to_string()));
+        };
+        let stack = Stack::new(CallFrame::new(func_handle.addr, wasm_func, 0, 0), config);
+        let results = Vec::with_capacity(func_handle.ty.results.len());
+        Ok(Self { exec: func_handle.into_exec(stack), results, running: false })
+    }
+
+    /// The type of the function
+    pub fn ty(&self) -> &FuncType {
+        &self.exec.func_handle.ty
+    }
+
+    /// The instance the function is called on, e.g. to write input to memory between calls
+    pub fn instance(&self) -> &Instance {
+        &self.exec.func_handle.instance
+    }
+
+    /// The instance the function is called on, e.g. to write input to memory between calls
+    pub fn instance_mut(&mut self) -> &mut Instance {
+        &mut self.exec.func_handle.instance
+    }
+
+    /// Start a new call with `params`, abandoning the current one if it didn't return yet
+    pub fn start(&mut self, params: &[WasmValue]) -> Result<()> {
+        let func_handle = &self.exec.func_handle;
+        let ty = &func_handle.ty;
+        if unlikely(ty.params.len() != params.len()) {
+            return Err(Error::ParamCountMismatch { expected: ty.params.len(), got: params.len() });
+        }
+        for (index, (ty, param)) in ty.params.iter().zip(params).enumerate() {
+            if unlikely(*ty != param.val_type()) {
+                return Err(Error::ParamTypeMismatch { expected: *ty, got: param.val_type(), index });
+            }
+        }
+
+        // prepared calls are only created for Wasm functions
+        let Function::Wasm(wasm_func) = func_handle.instance.funcs.get_or_instance(func_handle.addr, "function")?
+        else {
+            return Err(Error::Other("Can't call Host function directly".to_string()));
+        };
+        let stack = &mut self.exec.stack;
+        stack.restart(CallFrame::new(func_handle.addr, wasm_func, 0, 0));
+        stack.values.extend_from_typed(params);
+        stack.values.enter_frame(wasm_func)?;
+        self.exec.at_breakpoint = false;
+        self.running = true;
+        Ok(())
+    }
+
+    /// Run the current call for at most `max_cycles` instructions, returning its results once it returned
+    ///
+    /// Like [`ExecHandle::run`], each call is a new quantum for import quotas.
+    pub fn run(&mut self, max_cycles: usize) -> Result<Option<&[WasmValue]>> {
+        if unlikely(!self.running) {
+            return Err(Error::Other("No call was started".to_string()));
+        }
+        self.exec.func_handle.instance.quotas.start_quantum();
+        let stack = &self.exec.stack;
+        let done = self.exec.exec_until_return(max_cycles, stack.fuel_check_interval, stack.safe_points_only);
+        if !done.inspect_err(|_| self.running = false)? {
+            return Ok(None);
+        }
+
+        self.running = false;
+        self.results.clear();
+        self.results.extend(self.exec.results()?);
+        Ok(Some(&self.results))
+    }
+
+    /// Call the function with `params` and run it until it returns
+    pub fn call(&mut self, params: &[WasmValue]) -> Result<&[WasmValue]> {
+        self.start(params)?;
+        match self.run(usize::MAX)? {
+            Some(_) => Ok(&self.results),
+            // there are no breakpoints and the cycle limit is never reached
+            None => Err(Error::FuncDidNotReturn),
+        }
+    }
+
+    /// Give up the prepared call and get back the instance
+    pub fn into_instance(self) -> Instance {
+        self.exec.into_instance()
+    }
 }
 
 /// [`ExecHandle`] but typed
@@ -91,28 +847,1396 @@ pub struct ExecHandleTyped<R: FromWasmValueTuple> {
     pub(crate) _marker: core::marker::PhantomData<R>,
 }
 
+impl<R: FromWasmValueTuple> From<ExecHandleTyped<R>> for ExecHandle {
+    fn from(value: ExecHandleTyped<R>) -> Self {
+        value.exec_handle
+    }
+}
+
 impl<R: FromWasmValueTuple> ExecHandleTyped<R> {
     /// See [`ExecHandle::run`]
     pub fn run(&mut self, max_cycles: usize) -> Result<CallResultTyped<R>> {
         // Call the underlying WASM function
         let result = self.exec_handle.run(max_cycles)?;
+        self.typed(result)
+    }
 
+    fn typed(&mut self, result: CallResult) -> Result<CallResultTyped<R>> {
         Ok(match result {
             CallResult::Done(values) => CallResultTyped::Done(R::from_wasm_value_tuple(&values)?),
             CallResult::Incomplete => CallResultTyped::Incomplete,
+            CallResult::Breakpoint(frame) => CallResultTyped::Breakpoint(frame),
+            CallResult::Exit(code) => CallResultTyped::Exit(code),
         })
     }
 
+    /// See [`ExecHandle::run_for`]
+    #[cfg(feature = "std")]
+    pub fn run_for(&mut self, duration: std::time::Duration) -> Result<CallResultTyped<R>> {
+        let result = self.exec_handle.run_for(duration)?;
+        self.typed(result)
+    }
+
+    /// See [`ExecHandle::step`]
+    pub fn step(&mut self, n: usize) -> Result<StepResult> {
+        self.exec_handle.step(n)
+    }
+
+    /// See [`ExecHandle::free_params`]
+    pub fn free_params(&mut self) -> Result<()> {
+        self.exec_handle.free_params()
+    }
+
+    /// See [`ExecHandle::into_instance`]
+    ///
+    /// Parameters that are still allocated in guest memory are freed.
+    pub fn into_instance(mut self) -> Result<Instance> {
+        self.exec_handle.free_params()?;
+        Ok(self.exec_handle.into_instance())
+    }
+
+    /// See [`ExecHandle::instance`]
+    pub fn instance(&self) -> &Instance {
+        self.exec_handle.instance()
+    }
+
+    /// See [`ExecHandle::stats`]
+    pub fn stats(&self) -> ExecStats {
+        self.exec_handle.stats()
+    }
+
+    /// See [`ExecHandle::enable_profiling`]
+    pub fn enable_profiling(&mut self) {
+        self.exec_handle.enable_profiling()
+    }
+
+    /// See [`ExecHandle::disable_profiling`]
+    pub fn disable_profiling(&mut self) {
+        self.exec_handle.disable_profiling()
+    }
+
+    /// See [`ExecHandle::profile`]
+    pub fn profile(&self) -> Profile {
+        self.exec_handle.profile()
+    }
+
+    /// See [`ExecHandle::enable_coverage`]
+    pub fn enable_coverage(&mut self) {
+        self.exec_handle.enable_coverage()
+    }
+
+    /// See [`ExecHandle::disable_coverage`]
+    pub fn disable_coverage(&mut self) {
+        self.exec_handle.disable_coverage()
+    }
+
+    /// See [`ExecHandle::coverage`]
+    pub fn coverage(&self) -> Coverage {
+        self.exec_handle.coverage()
+    }
+
+    /// See [`ExecHandle::start_recording`]
+    pub fn start_recording(&mut self) {
+        self.exec_handle.start_recording()
+    }
+
+    /// See [`ExecHandle::take_recording`]
+    pub fn take_recording(&mut self) -> Option<ReplayLog> {
+        self.exec_handle.take_recording()
+    }
+
+    /// See [`ExecHandle::replay`]
+    pub fn replay(&mut self, log: ReplayLog) {
+        self.exec_handle.replay(log)
+    }
+
+    /// See [`ExecHandle::stack_limits`]
+    pub fn stack_limits(&self) -> StackLimits {
+        self.exec_handle.stack_limits()
+    }
+
+    /// See [`ExecHandle::set_stack_limits`]
+    pub fn set_stack_limits(&mut self, limits: StackLimits) {
+        self.exec_handle.set_stack_limits(limits)
+    }
+
+    /// See [`ExecHandle::pause_point`]
+    pub fn pause_point(&self) -> Option<SafePoint> {
+        self.exec_handle.pause_point()
+    }
+
+    /// See [`ExecHandle::add_breakpoint`]
+    pub fn add_breakpoint(&mut self, func: FuncAddr, instr_ptr: usize) {
+        self.exec_handle.add_breakpoint(func, instr_ptr)
+    }
+
+    /// See [`ExecHandle::remove_breakpoint`]
+    pub fn remove_breakpoint(&mut self, func: FuncAddr, instr_ptr: usize) -> bool {
+        self.exec_handle.remove_breakpoint(func, instr_ptr)
+    }
+
+    /// See [`ExecHandle::set_trace_hook`]
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(TraceEvent) + 'static) {
+        self.exec_handle.set_trace_hook(hook)
+    }
+
+    /// See [`ExecHandle::clear_trace_hook`]
+    pub fn clear_trace_hook(&mut self) {
+        self.exec_handle.clear_trace_hook()
+    }
+
     /// See [`ExecHandle::serialize`]
     pub fn serialize(&mut self, buf: AlignedVec) -> Result<AlignedVec> {
         self.exec_handle.serialize(buf)
     }
+
+    /// See [`ExecHandle::call_auxiliary`]
+    pub fn call_auxiliary(
+        &mut self,
+        name: &str,
+        params: &[WasmValue],
+        max_cycles: usize,
+        access: MemoryAccess,
+    ) -> Result<Vec<WasmValue>> {
+        self.exec_handle.call_auxiliary(name, params, max_cycles, access)
+    }
+}
+
+/// An event reported to the trace hook of an [`ExecHandle`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    /// An instruction is about to be executed
+    Instruction {
+        /// Address of the function the instruction belongs to
+        func: FuncAddr,
+        /// Index of the instruction in the function body
+        instr_ptr: usize,
+        /// The instruction itself
+        instr: Instruction,
+    },
+    /// A function was called, either directly or through a table
+    CallEnter {
+        /// Address of the called function
+        func: FuncAddr,
+    },
+    /// A function called from Wasm returned
+    ///
+    /// The function execution was started with doesn't report a [`TraceEvent::CallEnter`] or [`TraceEvent::CallExit`].
+    CallExit {
+        /// Address of the returning function
+        func: FuncAddr,
+    },
+    /// A `memory.grow` instruction was executed
+    MemoryGrow {
+        /// Address of the memory
+        mem: MemAddr,
+        /// Size of the memory in pages before growing it
+        prev_pages: usize,
+        /// Number of pages requested
+        delta: i32,
+        /// Whether the memory was grown
+        success: bool,
+    },
+    /// A load or store is about to access an address that isn't aligned to its alignment hint
+    ///
+    /// Only reported with [`AlignmentCheck::Trace`].
+    MisalignedAccess {
+        /// Address of the function the instruction belongs to
+        func: FuncAddr,
+        /// Index of the instruction in the function body
+        instr_ptr: usize,
+        /// Address of the memory
+        mem: MemAddr,
+        /// The address of the access, including the static offset
+        offset: u64,
+        /// The alignment hint of the access in bytes
+        align: u32,
+    },
+}
+
+pub(crate) struct TraceHook(pub(crate) Box<dyn FnMut(TraceEvent)>);
+
+/// A `memory.grow` instruction was executed, see [`Instance::set_memory_grow_hook`](crate::Instance::set_memory_grow_hook)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryGrowEvent {
+    /// Address of the memory
+    pub mem: MemAddr,
+    /// Size of the memory in pages before growing it
+    pub old_pages: usize,
+    /// Size of the memory in pages the guest asked for
+    pub new_pages: usize,
+    /// Whether the memory was grown
+    pub success: bool,
+}
+
+type MemoryGrowFn = dyn FnMut(&MemoryGrowEvent);
+
+/// Shared, so forks of an instance report to the same hook
+#[derive(Clone)]
+pub(crate) struct MemoryGrowHook(pub(crate) Rc<RefCell<MemoryGrowFn>>);
+
+impl core::fmt::Debug for MemoryGrowHook {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MemoryGrowHook").finish_non_exhaustive()
+    }
+}
+
+impl core::fmt::Debug for TraceHook {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TraceHook").finish_non_exhaustive()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[archive(check_bytes)]
 pub(crate) struct SerializationState {
     pub(crate) stack: Stack,
-    pub(crate) memory: Vec<u8>,
+    pub(crate) memory: SparseMemory,
     pub(crate) globals: Vec<RawWasmValue>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::error::Trap;
+    use crate::imports::{Extern, Imports};
+    use crate::test_utils::{instantiate, parse};
+    use crate::types::{value::ValType, FuncType};
+    use crate::Instance;
+
+    #[test]
+    fn test_trace_hook() {
+        let mut imports = Imports::new();
+        imports.define("env", "host", Extern::typed_func(|_, ()| Ok(()))).unwrap();
+        let instance = instantiate(
+            r#"
+            (module
+                (import "env" "host" (func $host))
+                (memory 1)
+                (func $inner (result i32)
+                    (call $host)
+                    (memory.grow (i32.const 1)))
+                (func (export "outer") (result i32)
+                    (call $inner)))
+            "#,
+            imports,
+        );
+        let mut exec = instance.exported_func_untyped("outer").unwrap().call(Vec::new(), None).unwrap();
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let hook_events = events.clone();
+        exec.set_trace_hook(move |event| {
+            if !matches!(event, TraceEvent::Instruction { .. }) {
+                hook_events.borrow_mut().push(event)
+            }
+        });
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(1)]));
+
+        assert_eq!(
+            *events.borrow(),
+            [
+                TraceEvent::CallEnter { func: 1 },
+                TraceEvent::CallEnter { func: 0 },
+                TraceEvent::CallExit { func: 0 },
+                TraceEvent::MemoryGrow { mem: 0, prev_pages: 1, delta: 1, success: true },
+                TraceEvent::CallExit { func: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_breakpoints_and_step() {
+        let module = parse(
+            r#"
+            (module
+                (func (export "sum") (param i32) (result i32) (local i32)
+                    (loop $l
+                        (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                        (br_if $l (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+                    (local.get 1)))
+            "#,
+        );
+        let ret = module.funcs[0].instructions.len() - 1;
+        let instance = Instance::instantiate(module, Imports::new()).unwrap();
+        let mut exec =
+            instance.exported_func_untyped("sum").unwrap().call(alloc::vec![WasmValue::I32(3)], None).unwrap();
+
+        let StepResult::Paused(frame) = exec.step(0).unwrap() else { panic!("expected a paused execution") };
+        assert_eq!((frame.instr_ptr, frame.depth, frame.at_breakpoint), (0, 1, false));
+        assert_eq!(frame.locals, [WasmValue::I32(3), WasmValue::I32(0)]);
+
+        let StepResult::Paused(frame) = exec.step(2).unwrap() else { panic!("expected a paused execution") };
+        assert_eq!(frame.instr_ptr, 2);
+
+        exec.add_breakpoint(0, ret);
+        let Ok(CallResult::Breakpoint(frame)) = exec.run(usize::MAX) else { panic!("expected a breakpoint") };
+        assert_eq!((frame.instr_ptr, frame.at_breakpoint), (ret, true));
+        assert_eq!(frame.locals, [WasmValue::I32(0), WasmValue::I32(6)]);
+
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(6)]));
+    }
+
+    #[test]
+    fn test_stack_limits() {
+        let module = parse(
+            r#"
+            (module
+                (func $rec (export "rec") (param i32) (result i32) (local i64 i64 i64)
+                    (if (result i32) (local.get 0)
+                        (then (call $rec (i32.sub (local.get 0) (i32.const 1))))
+                        (else (i32.const 0)))))
+            "#,
+        );
+        let run = |depth: i32, limits: StackLimits| {
+            let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+            let mut exec =
+                instance.exported_func_untyped("rec").unwrap().call(alloc::vec![WasmValue::I32(depth)], None);
+            let exec = exec.as_mut().unwrap();
+            exec.set_stack_limits(limits);
+            exec.run(usize::MAX)
+        };
+
+        assert!(matches!(run(1023, StackLimits::default()), Ok(CallResult::Done(_))));
+        let err = run(1024, StackLimits::default()).unwrap_err();
+        assert!(matches!(err.trap(), Some(Trap::StackExhausted { limit: "call frames", max: 1024 })));
+
+        let limits = StackLimits { max_locals: 40, ..Default::default() };
+        assert!(matches!(run(9, limits), Ok(CallResult::Done(_))));
+        assert!(matches!(run(10, limits).unwrap_err().trap(), Some(Trap::StackExhausted { limit: "locals", .. })));
+    }
+
+    #[test]
+    fn test_fuel_check_interval() {
+        let instance = instantiate(
+            r#"
+            (module
+                (func (export "count") (param i32) (result i32) (local i32)
+                    (loop $l
+                        (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+                        (br_if $l (i32.lt_u (local.get 1) (local.get 0))))
+                    (local.get 1)))
+            "#,
+            Imports::new(),
+        );
+
+        // returns the number of instructions executed by every call to `run`
+        let run = |interval: usize| {
+            let config = ExecConfig { fuel_check_interval: interval, ..Default::default() };
+            let func = instance.clone().exported_func_untyped("count").unwrap();
+            let mut exec = func.call_with_config(alloc::vec![WasmValue::I32(100)], &config).unwrap();
+            assert_eq!(exec.fuel_check_interval(), interval);
+
+            let executed = Rc::new(RefCell::new(0));
+            let hook_executed = executed.clone();
+            exec.set_trace_hook(move |event| {
+                if matches!(event, TraceEvent::Instruction { .. }) {
+                    *hook_executed.borrow_mut() += 1;
+                }
+            });
+
+            let mut runs = Vec::new();
+            loop {
+                let res = exec.run(99).unwrap();
+                runs.push(executed.replace(0));
+                if let CallResult::Done(res) = res {
+                    assert_eq!(res, [WasmValue::I32(100)]);
+                    return runs;
+                }
+            }
+        };
+
+        // the budget is never exceeded, no matter how rarely it is checked
+        let precise = run(1);
+        assert!(precise[..precise.len() - 1].iter().all(|&n| n == 100));
+        assert_eq!(run(16), precise);
+        assert_eq!(run(1000), precise);
+        assert_eq!(run(0), precise);
+    }
+
+    #[test]
+    fn test_stack_pool() {
+        let instance = instantiate(
+            r#"(module (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1))))"#,
+            Imports::new(),
+        );
+        let config = ExecConfig { value_stack_capacity: 16, call_stack_capacity: 4, ..Default::default() };
+        let mut pool = StackPool::new(config);
+
+        let mut func = instance.exported_func_untyped("add").unwrap();
+        for i in 0..3 {
+            let mut exec = func.call_pooled(alloc::vec![WasmValue::I32(i), WasmValue::I32(1)], &mut pool).unwrap();
+            assert!(pool.is_empty());
+            assert_eq!(exec.stack.values.capacity(), 16);
+            assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(i + 1)]));
+            func = pool.recycle(exec);
+            assert_eq!(pool.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        use crate::error::SnapshotError;
+
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                (global $g (mut i32) (i32.const 0))
+                (func (export "run") (result i32)
+                    (loop $l
+                        (global.set $g (i32.add (global.get $g) (i32.const 1)))
+                        (br_if $l (i32.lt_u (global.get $g) (i32.const 100))))
+                    (global.get $g)))
+            "#,
+        );
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(10), Ok(CallResult::Incomplete)));
+        let state = exec.serialize(AlignedVec::new()).unwrap();
+
+        let restore = |state: &[u8]| match Instance::instantiate_with_state(module.clone(), Imports::new(), state) {
+            Err(Error::Snapshot(err)) => err,
+            res => panic!("expected a snapshot error, got {:?}", res.map(|_| ())),
+        };
+        let edited = |offset: usize, bytes: &[u8]| {
+            let mut edited = AlignedVec::new();
+            edited.extend_from_slice(&state);
+            edited[offset..offset + bytes.len()].copy_from_slice(bytes);
+            edited
+        };
+
+        let len = state.len();
+        assert_eq!(restore(&state[..len - 1]), SnapshotError::Truncated { expected: len, actual: len - 1 });
+        assert_eq!(restore(&state[..20]), SnapshotError::Truncated { expected: 48, actual: 20 });
+        let version = crate::snapshot::VERSION;
+        let skewed = restore(&edited(8, &(version + 1).to_le_bytes()));
+        assert_eq!(skewed, SnapshotError::VersionSkew { found: version + 1, expected: version });
+        assert!(matches!(restore(b"not a snapshot"), SnapshotError::Corrupt { offset: 0, .. }));
+        assert!(matches!(restore(&edited(len - 8, &[0xff; 8])), SnapshotError::Corrupt { offset: 48, .. }));
+
+        let mut trailing = AlignedVec::new();
+        trailing.extend_from_slice(&state);
+        trailing.extend_from_slice(&[0; 16]);
+        assert!(matches!(restore(&trailing), SnapshotError::Corrupt { offset, .. } if offset == len));
+
+        let other = parse("(module (memory 1) (global (mut i32) (i32.const 1)))");
+        let Err(Error::Snapshot(err)) = Instance::instantiate_with_state(other.clone(), Imports::new(), &state) else {
+            panic!("expected a snapshot error")
+        };
+        let fingerprints = (module.fingerprint().unwrap(), other.fingerprint().unwrap());
+        assert_eq!(err, SnapshotError::ModuleMismatch { expected: fingerprints.1, found: fingerprints.0 });
+
+        let (instance, stack) = Instance::instantiate_with_state(module, Imports::new(), &state).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(100)]));
+    }
+
+    #[test]
+    fn test_snapshot_invalid_stack() {
+        use crate::error::SnapshotError;
+
+        let module = parse(
+            r#"
+            (module
+                (import "env" "host" (func))
+                (global $g (mut i32) (i32.const 0))
+                (func (export "run") (result i32) (local i32)
+                    (block
+                        (loop $l
+                            (global.set $g (i32.add (global.get $g) (i32.const 1)))
+                            (br_if $l (i32.lt_u (global.get $g) (i32.const 100)))))
+                    (global.get $g)))
+            "#,
+        );
+        let imports = || {
+            let mut imports = Imports::new();
+            imports.define("env", "host", Extern::typed_func(|_, ()| Ok(()))).unwrap();
+            imports
+        };
+        let instance = Instance::instantiate(module.clone(), imports()).unwrap();
+        let fingerprint = instance.fingerprint().unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(10), Ok(CallResult::Incomplete)));
+        let state = exec.serialize(AlignedVec::new()).unwrap();
+
+        let restore = |edit: &dyn Fn(&mut SerializationState)| {
+            let mut edited = crate::snapshot::read(&state, fingerprint).unwrap();
+            edit(&mut edited);
+            let edited = crate::snapshot::write(&edited, fingerprint, AlignedVec::new()).unwrap();
+            match Instance::instantiate_with_state(module.clone(), imports(), &edited) {
+                Err(Error::Snapshot(SnapshotError::Corrupt { offset: 48, message })) => message,
+                res => panic!("expected a corrupt snapshot, got {:?}", res.map(|_| ())),
+            }
+        };
+
+        assert!(restore(&|s| s.stack.call_stack.frames[0].func_instance = 7).contains("unknown function"));
+        assert!(restore(&|s| s.stack.call_stack.frames[0].func_instance = 0).contains("host function"));
+        assert!(restore(&|s| s.stack.call_stack.frames[0].instr_ptr = 1000).contains("instruction pointer"));
+        assert!(restore(&|s| s.stack.call_stack.frames[0].locals = 3).contains("type"));
+        assert!(restore(&|s| s.stack.call_stack.frames[0].stack_ptr = u32::MAX).contains("locals"));
+        assert!(restore(&|s| s.stack.call_stack.frames[0].block_ptr = 5).contains("block pointer"));
+        assert!(restore(&|s| s.stack.blocks.0[0].stack_ptr = 1000).contains("block"));
+        assert!(restore(&|s| s.stack.blocks.0[1].end_instr_offset = u32::MAX).contains("block"));
+        assert!(restore(&|s| s.globals.push(RawWasmValue::default())).contains("globals"));
+        assert!(restore(&|s| s.globals.clear()).contains("globals"));
+        let region = crate::guest::GuestRegion { ptr: 8, len: 4, align: 4 };
+        assert!(restore(&|s| s.stack.param_regions.push(region)).contains("parameter region"));
+
+        // restoring into forks reuses the fingerprint of the template
+        let template = Instance::instantiate(module, imports()).unwrap();
+        let (instance, stack) = template.fork_with_state(&state).unwrap();
+        assert!(Rc::ptr_eq(&template.fingerprint, &instance.fingerprint));
+        assert_eq!(template.fingerprint.get(), Some(&fingerprint));
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(100)]));
+    }
+
+    #[test]
+    fn test_serialize_live_stack() {
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                (func $deep (param i32) (local i64 i64 i64 i64)
+                    (if (local.get 0) (then (call $deep (i32.sub (local.get 0) (i32.const 1))))))
+                (func (export "run") (param i32)
+                    (call $deep (local.get 0))
+                    (loop $l (br $l))))
+            "#,
+        );
+
+        // the stacks grew while recursing, only what's left once the calls returned is serialized
+        let snapshot = |depth| {
+            let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+            let mut exec =
+                instance.exported_func_untyped("run").unwrap().call(alloc::vec![WasmValue::I32(depth)], None).unwrap();
+            assert!(matches!(exec.run(100_000), Ok(CallResult::Incomplete)));
+            assert_eq!(exec.stack.call_stack.len(), 1);
+            exec.serialize(AlignedVec::new()).unwrap()
+        };
+        assert_eq!(snapshot(1000).len(), snapshot(1).len());
+    }
+
+    #[test]
+    fn test_call_auxiliary() {
+        let instance = instantiate(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (global $progress (mut i32) (i32.const 0))
+                (func (export "main") (param i32) (result i32)
+                    (loop $l
+                        (global.set $progress (i32.add (global.get $progress) (i32.const 1)))
+                        (br_if $l (i32.lt_u (global.get $progress) (local.get 0))))
+                    (i32.add (global.get $progress) (i32.load (i32.const 0))))
+                (func (export "status") (param i32) (result i32)
+                    (i32.mul (global.get $progress) (local.get 0)))
+                (func (export "poke") (i32.store (i32.const 0) (i32.const 1000)))
+                (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+                (func (export "spin") (loop $l (br $l))))
+            "#,
+            Imports::new(),
+        );
+        let mut exec =
+            instance.exported_func_untyped("main").unwrap().call(alloc::vec![WasmValue::I32(100)], None).unwrap();
+        assert!(matches!(exec.run(50), Ok(CallResult::Incomplete)));
+        let backtrace = exec.backtrace().unwrap();
+
+        let status = exec.call_auxiliary("status", &[WasmValue::I32(2)], 100, MemoryAccess::ReadOnly).unwrap();
+        let WasmValue::I32(progress) = status[0] else { panic!("expected an i32") };
+        assert!(progress > 0 && progress % 2 == 0);
+        assert_eq!(exec.backtrace().unwrap(), backtrace);
+
+        let err = exec.call_auxiliary("poke", &[], 100, MemoryAccess::ReadOnly).unwrap_err();
+        assert!(matches!(err, Error::Trap(Trap::ReadOnlyMemory { offset: 0, len: 4 })));
+        let grown = exec.call_auxiliary("grow", &[], 100, MemoryAccess::ReadOnly).unwrap();
+        assert_eq!(grown, [WasmValue::I32(-1)]);
+        let err = exec.call_auxiliary("spin", &[], 100, MemoryAccess::ReadOnly).unwrap_err();
+        assert!(matches!(err, Error::AuxiliaryBudgetExceeded { max_cycles: 100 }));
+        assert!(matches!(
+            exec.call_auxiliary("status", &[], 100, MemoryAccess::ReadOnly),
+            Err(Error::ParamCountMismatch { .. })
+        ));
+
+        // the main call continues unaffected, but sees what a read-write call wrote
+        exec.call_auxiliary("poke", &[], 100, MemoryAccess::ReadWrite).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(1100)]));
+    }
+
+    #[test]
+    fn test_resume_in_degenerate_blocks() {
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                (func (export "empty") (param i32) (result i32)
+                    (block) (loop) (if (local.get 0) (then) (else)) (if (local.get 0) (then))
+                    (block (block (loop (loop))))
+                    (local.get 0))
+                (func (export "params") (param i32) (result i32)
+                    (local.get 0)
+                    (block (param i32) (result i32))
+                    (loop (param i32) (result i32))
+                    (if (param i32) (result i32) (local.get 0) (then))
+                    (if (param i32) (result i32) (local.get 0) (then) (else)))
+                (func (export "br") (param i32) (result i32)
+                    (block (br 0))
+                    (block (br_if 0 (local.get 0)))
+                    (block $a (block (br $a)))
+                    (block $b (if (local.get 0) (then (br $b)) (else (br $b))))
+                    (block (result i32) (br 0 (i32.const 7)))
+                    (block $c (result i32) (loop (result i32) (br $c (i32.const 1))))
+                    (i32.add)
+                    (i32.add (local.get 0)))
+                (func (export "br_table") (param i32) (result i32)
+                    (block $2 (block $1 (block $0 (br_table $0 $1 $2 (local.get 0)))))
+                    (block $y (result i32)
+                        (block $x (result i32) (br_table $x $y (i32.const 10) (local.get 0))))
+                    (i32.add (local.get 0)))
+                (func (export "loops") (param i32) (result i32) (local i32 i32)
+                    (loop $outer
+                        (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+                        (loop $inner
+                            (local.set 2 (i32.add (local.get 2) (i32.const 1)))
+                            (br_if $inner (i32.lt_u (local.get 2) (local.get 1)))
+                            (br_if $outer (i32.lt_u (local.get 1) (local.get 0)))))
+                    (i32.add (local.get 1) (local.get 2)))
+                (func (export "loop_params") (param i32) (result i32) (local i32)
+                    (i32.const 0)
+                    (loop $l (param i32) (result i32)
+                        (local.tee 1 (i32.add (i32.const 1)))
+                        (br_if $l (i32.lt_u (local.get 1) (local.get 0))))))
+            "#,
+        );
+
+        for name in ["empty", "params", "br", "br_table", "loops", "loop_params"] {
+            for param in [0, 1, 2, 5] {
+                let call = |stack| {
+                    let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+                    instance.exported_func_untyped(name).unwrap().call(alloc::vec![WasmValue::I32(param)], stack)
+                };
+                let Ok(CallResult::Done(expected)) = call(None).unwrap().run(usize::MAX) else {
+                    panic!("{} didn't return", name)
+                };
+
+                // pause before every instruction and continue from a snapshot, `run(0)` executes a single one
+                let mut exec = call(None).unwrap();
+                let res = loop {
+                    match exec.run(0).unwrap() {
+                        CallResult::Done(res) => break res,
+                        CallResult::Incomplete => {}
+                        res => panic!("unexpected result {:?}", res),
+                    }
+                    let state = exec.serialize(AlignedVec::new()).unwrap();
+                    let (instance, stack) =
+                        Instance::instantiate_with_state(module.clone(), Imports::new(), &state).unwrap();
+                    let func = instance.exported_func_untyped(name).unwrap();
+                    exec = func.call(alloc::vec![WasmValue::I32(param)], Some(stack)).unwrap();
+                };
+                assert_eq!(res, expected, "{}({})", name, param);
+            }
+        }
+    }
+
+    #[test]
+    fn test_safe_points_only() {
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                (func $inc (param i32) (result i32) (i32.add (local.get 0) (i32.const 1)))
+                (func (export "main") (param i32) (result i32) (local i32)
+                    (loop $l
+                        (local.set 1 (call $inc (local.get 1)))
+                        (local.set 1 (i32.add (local.get 1) (i32.const 2)))
+                        (br_if $l (i32.lt_u (local.get 1) (local.get 0))))
+                    (local.get 1)))
+            "#,
+        );
+        let loop_start = module.funcs[1].instructions.iter().position(|i| matches!(i, Instruction::Loop(..)));
+        let call = |config| {
+            let instance = Instance::instantiate_with_config(module.clone(), Imports::new(), config).unwrap();
+            instance.exported_func_untyped("main").unwrap().call(alloc::vec![WasmValue::I32(300)], None).unwrap()
+        };
+        let config = ExecConfig::new().with_safe_points_only(true);
+        let mut exec = call(config);
+
+        let mut seen = Vec::new();
+        let res = loop {
+            // budgets that end at every instruction of the loop
+            match exec.run(1 + seen.len() % 7).unwrap() {
+                CallResult::Done(res) => break res,
+                CallResult::Incomplete => {}
+                res => panic!("unexpected result {:?}", res),
+            }
+            let frame = &exec.backtrace().unwrap()[0];
+            let instr = &exec.instance().module.funcs[frame.func as usize].instructions[frame.instr_ptr];
+            let point = exec.pause_point().unwrap();
+            match point {
+                SafePoint::FunctionEntry => assert_eq!(frame.instr_ptr, 0),
+                SafePoint::Call => assert!(matches!(instr, Instruction::Call(_))),
+                SafePoint::LoopBackEdge => assert_eq!(Some(frame.instr_ptr - 1), loop_start),
+            }
+            seen.push(point);
+        };
+        assert_eq!(res, [WasmValue::I32(300)]);
+        for point in [SafePoint::FunctionEntry, SafePoint::Call, SafePoint::LoopBackEdge] {
+            assert!(seen.contains(&point), "never paused at {:?}", point);
+        }
+
+        // the pause point is saved with the stack
+        let mut exec = call(config);
+        assert!(matches!(exec.run(3), Ok(CallResult::Incomplete)));
+        let point = exec.pause_point();
+        let state = exec.serialize(AlignedVec::new()).unwrap();
+        let (instance, stack) = Instance::instantiate_with_state(module.clone(), Imports::new(), &state).unwrap();
+        let exec = instance.exported_func_untyped("main").unwrap().call(alloc::vec![WasmValue::I32(300)], Some(stack));
+        assert_eq!(exec.unwrap().pause_point(), point);
+
+        // without the option, the budget is exact
+        let mut exec = call(ExecConfig::new());
+        assert!(matches!(exec.run(3), Ok(CallResult::Incomplete)));
+        assert_eq!(exec.pause_point(), None);
+    }
+
+    #[test]
+    fn test_safe_points_overrun_returns() {
+        // every function runs a few instructions after its call returns, none of which is a safe point
+        let tail = "(i32.add (i32.const 1)) ".repeat(8);
+        let wat = alloc::format!(
+            r#"
+            (module
+                (func $c (result i32) (i32.const 0) {tail})
+                (func $b (result i32) (call $c) {tail})
+                (func (export "a") (result i32) (call $b) {tail}))
+            "#
+        );
+        let module = parse(&wat);
+        let longest = module.funcs.iter().map(|func| func.instructions.len() as u64).max().unwrap();
+
+        let instance =
+            Instance::instantiate_with_config(module, Imports::new(), ExecConfig::new().with_safe_points_only(true));
+        let mut exec = instance.unwrap().exported_func_untyped("a").unwrap().call(Vec::new(), None).unwrap();
+        let mut overrun = 0;
+        let res = loop {
+            let res = exec.run(0).unwrap();
+            overrun = overrun.max(exec.stats().fuel);
+            match res {
+                CallResult::Done(res) => break res,
+                CallResult::Incomplete => {}
+                res => panic!("unexpected result {:?}", res),
+            }
+        };
+        assert_eq!(res, [WasmValue::I32(24)]);
+
+        // pausing inside `$c` runs the rest of all three functions
+        assert!(overrun > longest, "{} <= {}", overrun, longest);
+    }
+
+    #[test]
+    fn test_alignment_check() {
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                (func (export "load") (param i32) (result i32)
+                    (i32.load align=4 (local.get 0)))
+                (func (export "load_byte") (param i32) (result i32)
+                    (i32.load8_u (local.get 0)))
+                (func (export "store") (param i32)
+                    (i64.store offset=2 align=2 (local.get 0) (i64.const 1)))
+                (func (export "store_local") (param i32)
+                    local.get 0
+                    i32.const 1
+                    i32.store align=4))
+            "#,
+        );
+        let run = |name, addr, check| {
+            let config = ExecConfig::new().with_alignment_check(check);
+            let instance = Instance::instantiate_with_config(module.clone(), Imports::new(), config).unwrap();
+            let mut exec =
+                instance.exported_func_untyped(name).unwrap().call(alloc::vec![WasmValue::I32(addr)], None)?;
+            let events = Rc::new(RefCell::new(Vec::new()));
+            let hook_events = events.clone();
+            exec.set_trace_hook(move |event| {
+                if let TraceEvent::MisalignedAccess { offset, align, .. } = event {
+                    hook_events.borrow_mut().push((offset, align))
+                }
+            });
+            exec.run(usize::MAX)?;
+            Ok::<_, Error>(events.take())
+        };
+
+        for name in ["load", "store", "store_local"] {
+            assert_eq!(run(name, 2, AlignmentCheck::Off).unwrap(), []);
+            assert_eq!(run(name, 8, AlignmentCheck::Trap).unwrap(), []);
+        }
+        assert!(matches!(
+            run("load", 2, AlignmentCheck::Trap),
+            Err(Error::Trap(Trap::MisalignedAccess { offset: 2, align: 4 }))
+        ));
+        assert!(matches!(
+            run("store", 1, AlignmentCheck::Trap),
+            Err(Error::Trap(Trap::MisalignedAccess { offset: 3, align: 2 }))
+        ));
+        assert!(matches!(run("store_local", 6, AlignmentCheck::Trap), Err(Error::Trap(Trap::MisalignedAccess { .. }))));
+        assert_eq!(run("load_byte", 3, AlignmentCheck::Trap).unwrap(), []);
+
+        // misaligned accesses are still executed when they're only traced
+        assert_eq!(run("load", 6, AlignmentCheck::Trace).unwrap(), [(6, 4)]);
+        assert_eq!(run("store", 1, AlignmentCheck::Trace).unwrap(), [(3, 2)]);
+        assert_eq!(run("store_local", 2, AlignmentCheck::Trace).unwrap(), [(2, 4)]);
+    }
+
+    #[test]
+    fn test_serialize_sparse_memory() {
+        let module = parse(
+            r#"
+            (module
+                (memory (export "memory") 16)
+                (func (export "run") (result i32)
+                    (i32.store (i32.const 0x20000) (i32.const 42))
+                    (drop (memory.grow (i32.const 1)))
+                    (i32.load (i32.const 0x20000))))
+            "#,
+        );
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(4), Ok(CallResult::Incomplete)));
+
+        let state = exec.serialize(AlignedVec::new()).unwrap();
+        assert!(state.len() < 2 * crate::PAGE_SIZE);
+
+        let (mut instance, stack) = Instance::instantiate_with_state(module, Imports::new(), &state).unwrap();
+        assert_eq!(instance.exported_memory_mut("memory").unwrap().page_count(), 17);
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(42)]));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_file_backed_memory() {
+        use crate::MemoryBackend;
+
+        let module = parse(
+            r#"
+            (module
+                (memory (export "memory") 16)
+                (func (export "run") (result i32)
+                    (i32.store (i32.const 0x20000) (i32.const 42))
+                    (drop (memory.grow (i32.const 1)))
+                    (i32.store (i32.const 0x100000) (i32.const 1))
+                    (i32.load (i32.const 0x20000))))
+            "#,
+        );
+        let path = std::env::temp_dir().join(alloc::format!("reef-memory-{}", std::process::id()));
+
+        let mut instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        instance.set_memory_backend(MemoryBackend::File(path.clone())).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(8), Ok(CallResult::Incomplete)));
+
+        // the snapshot only refers to the file, which already has the contents of the memory
+        let state = exec.serialize(AlignedVec::new()).unwrap();
+        assert!(state.len() < 1024);
+        let file = std::fs::read(&path).unwrap();
+        assert_eq!(file.len(), 17 * crate::PAGE_SIZE);
+        assert_eq!(file[0x20000], 42);
+
+        // forks don't write to the file
+        let mut fork = exec.into_instance().fork();
+        fork.exported_memory_mut("memory").unwrap().store(0x20000, 1, &[7]).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[0x20000], 42);
+
+        // the host has to pass the file again, the snapshot doesn't name it
+        assert!(Instance::instantiate_with_state(module.clone(), Imports::new(), &state).is_err());
+        let missing = MemoryBackend::File(path.with_extension("missing"));
+        assert!(Instance::instantiate_with_state_and_backend(module.clone(), Imports::new(), &state, missing).is_err());
+        assert!(!path.with_extension("missing").exists());
+
+        let backend = MemoryBackend::File(path.clone());
+        let (instance, stack) =
+            Instance::instantiate_with_state_and_backend(module, Imports::new(), &state, backend).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(Vec::new(), Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(42)]));
+        exec.serialize(AlignedVec::new()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[0x100000], 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let module = parse(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (global $calls (mut i64) (i64.const 0))
+                (func $fib (param i32) (result i32)
+                    (global.set $calls (i64.add (global.get $calls) (i64.const 1)))
+                    (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+                        (then (local.get 0))
+                        (else (i32.add
+                            (call $fib (i32.sub (local.get 0) (i32.const 1)))
+                            (call $fib (i32.sub (local.get 0) (i32.const 2)))))))
+                (func (export "run") (param $n i32) (result i64)
+                    (local $i i32)
+                    (loop $fill
+                        (i32.store (i32.mul (local.get $i) (i32.const 4)) (call $fib (local.get $i)))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $fill (i32.lt_u (local.get $i) (local.get $n))))
+                    (i64.add (global.get $calls) (i64.extend_i32_u (i32.load (i32.const 40))))))
+            "#,
+        );
+        let params = alloc::vec![WasmValue::I32(12)];
+
+        let run = |mut instance: Instance, stack: Option<Stack>, max_cycles: usize| {
+            let mut exec = instance.exported_func_untyped("run").unwrap().call(params.clone(), stack).unwrap();
+            let res = exec.run(max_cycles).unwrap();
+            let state = exec.serialize(AlignedVec::new()).unwrap();
+            instance = Instance::from(exec);
+            let memory = instance.exported_memory_mut("memory").unwrap().load(0, 64).unwrap().to_vec();
+            (res, state, memory)
+        };
+
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let (expected, _, expected_memory) = run(instance, None, usize::MAX);
+        let CallResult::Done(expected) = expected else { panic!("uninterrupted run didn't finish") };
+
+        // Pause at pseudo-random budgets, restoring into a fresh instance every time
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..8 {
+            let (mut instance, mut stack) = (Instance::instantiate(module.clone(), Imports::new()).unwrap(), None);
+            let (res, memory) = loop {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                let (res, state, memory) = run(instance, stack, 1 + (seed % 2000) as usize);
+                if !matches!(res, CallResult::Incomplete) {
+                    break (res, memory);
+                }
+                let restored = Instance::instantiate_with_state(module.clone(), Imports::new(), &state).unwrap();
+                (instance, stack) = (restored.0, Some(restored.1));
+            };
+            assert!(matches!(res, CallResult::Done(res) if res == expected));
+            assert_eq!(memory, expected_memory);
+        }
+    }
+
+    #[test]
+    fn test_invalid_bytecode() {
+        use crate::types::instructions::Instruction;
+
+        let module = parse(
+            r#"
+            (module
+                (func (export "run") (param i32)
+                    (block (block (br_table 0 1 (local.get 0))))))
+            "#,
+        );
+        let run = |module, params| Instance::instantiate(module, Imports::new()).unwrap().call_in_place("run", params);
+
+        // a function without its final `return` runs off the end of its instructions
+        let mut truncated = module.clone();
+        let mut instrs = truncated.funcs[0].instructions.to_vec();
+        instrs.pop();
+        truncated.funcs[0].instructions = instrs.into_boxed_slice();
+        assert!(matches!(run(truncated, alloc::vec![WasmValue::I32(0)]), Err(Error::InvalidBytecode(_))));
+
+        // a br_table missing its labels
+        let mut module = module;
+        let instrs = module.funcs[0].instructions.iter().filter(|i| !matches!(i, Instruction::BrLabel(_))).cloned();
+        module.funcs[0].instructions = instrs.collect();
+        assert!(matches!(run(module, alloc::vec![WasmValue::I32(0)]), Err(Error::InvalidBytecode(_))));
+
+        // writing to an immutable global never passes validation
+        let mut module = parse(
+            r#"(module (global $g (mut i32) (i32.const 0)) (func (export "run") (global.set $g (i32.const 1))))"#,
+        );
+        let mut globals = module.globals.to_vec();
+        globals[0].ty.mutable = false;
+        module.globals = globals.into_boxed_slice();
+        assert!(matches!(run(module, Vec::new()), Err(Error::InvalidBytecode(_))));
+    }
+
+    #[test]
+    fn test_high_addresses() {
+        let mut instance = instantiate(
+            r#"
+            (module
+                (memory 1)
+                (func (export "store") (param i32) (i32.store offset=1 (local.get 0) (i32.const 7)))
+                (func (export "load") (param i32) (result i32) (i32.load offset=0xfffffff0 (local.get 0))))
+            "#,
+            Imports::new(),
+        );
+        let mut run = |name: &str, addr: i32| instance.call_in_place(name, alloc::vec![WasmValue::I32(addr)]);
+
+        // addresses are unsigned, so these are past the end of memory instead of wrapping around
+        assert!(matches!(run("store", -1), Err(Error::Trap(Trap::MemoryOutOfBounds { .. }))));
+        assert!(matches!(run("store", i32::MIN), Err(Error::Trap(Trap::MemoryOutOfBounds { .. }))));
+        assert!(matches!(run("load", 0x20), Err(Error::Trap(Trap::MemoryOutOfBounds { .. }))));
+        assert!(run("store", 0).is_ok());
+    }
+
+    #[test]
+    fn test_host_callback() {
+        let module = parse(
+            r#"
+            (module
+                (import "reef" "map" (func $map (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\01\00\00\00\02\00\00\00\03\00\00\00")
+                (func (export "apply") (param i32) (result i32)
+                    (i32.mul (local.get 0) (i32.const 10)))
+                (func (export "main") (result i32) (local $i i32) (local $sum i32)
+                    (loop $next
+                        (local.set $sum (i32.add (local.get $sum) (call $map (i32.mul (local.get $i) (i32.const 4)))))
+                        (br_if $next (i32.lt_u (local.tee $i (i32.add (local.get $i) (i32.const 1))) (i32.const 3))))
+                    (local.get $sum)))
+            "#,
+        );
+
+        let ty = FuncType { params: Box::new([ValType::I32]), results: Box::new([ValType::I32]) };
+        let map = Extern::func(&ty, |mut ctx, args| {
+            let ptr = i32::try_from(args[0]).unwrap() as usize;
+            let item = i32::from_le_bytes(ctx.exported_memory("memory")?.load(ptr, 4)?.try_into().unwrap());
+            ctx.call_exported("apply", alloc::vec![WasmValue::I32(item)])?;
+            Ok(Vec::new())
+        });
+        let mut imports = Imports::new();
+        imports.define("reef", "map", map).unwrap();
+        let instance = Instance::instantiate(module.clone(), imports).unwrap();
+
+        // the callback runs on the interpreter's stack, so it can be paused like any other call
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        exec.start_recording();
+        let res = loop {
+            match exec.run(3).unwrap() {
+                CallResult::Done(res) => break res,
+                CallResult::Incomplete => {}
+                CallResult::Breakpoint(_) | CallResult::Exit(_) => unreachable!(),
+            }
+        };
+        assert_eq!(res, [WasmValue::I32(60)]);
+
+        let log = exec.take_recording().unwrap();
+        assert_eq!(log.len(), 3);
+        let mut imports = Imports::new();
+        let replay_map =
+            Extern::typed_func(|_, _: i32| -> Result<i32> { panic!("imports must not be called during replay") });
+        imports.define("reef", "map", replay_map).unwrap();
+        let instance = Instance::instantiate(module, imports).unwrap();
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        exec.replay(log);
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(60)]));
+    }
+
+    #[test]
+    fn test_return_drops_values() {
+        // the values below the results are left behind by the `return` out of the nested block
+        let instance = instantiate(
+            r#"
+            (module
+                (func $pair (result i32 i64)
+                    (i32.const 9)
+                    (block (i32.const 1) (i64.const 2) (return))
+                    (unreachable))
+                (func (export "pair") (result i32 i64)
+                    (call $pair)))
+            "#,
+            Imports::new(),
+        );
+
+        let mut exec = instance.exported_func_untyped("pair").unwrap().call(Vec::new(), None).unwrap();
+        assert!(
+            matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(1), WasmValue::I64(2)])
+        );
+        assert_eq!(exec.stack.values.len(), 2);
+    }
+
+    #[test]
+    fn test_locals_on_value_stack() {
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                ;; sum of n + (n - 1) + ... + 1, keeping n in a local across the recursive call
+                (func $sum (export "sum") (param $n i32) (result i32) (local $n2 i32)
+                    (local.set $n2 (i32.mul (local.get $n) (i32.const 2)))
+                    (if (result i32) (i32.eqz (local.get $n))
+                        (then (i32.const 0))
+                        (else
+                            (i32.add
+                                (call $sum (i32.sub (local.get $n) (i32.const 1)))
+                                (i32.div_u (local.get $n2) (i32.const 2)))))))
+            "#,
+        );
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let mut exec =
+            instance.exported_func_untyped("sum").unwrap().call(alloc::vec![WasmValue::I32(10)], None).unwrap();
+        exec.add_breakpoint(0, 0);
+        for _ in 0..4 {
+            assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Breakpoint(_))));
+        }
+
+        // every frame sees its own locals, which are all that is on the value stack here
+        let backtrace = exec.backtrace().unwrap();
+        let locals: Vec<_> = backtrace.iter().map(|frame| frame.locals.clone()).collect();
+        assert_eq!(locals[0], [WasmValue::I32(7), WasmValue::I32(0)]);
+        assert_eq!(locals[1], [WasmValue::I32(8), WasmValue::I32(16)]);
+        assert_eq!(locals[3], [WasmValue::I32(10), WasmValue::I32(20)]);
+        assert_eq!(exec.stack.values.len(), 8);
+
+        let state = exec.serialize(AlignedVec::new()).unwrap();
+        let (instance, stack) = Instance::instantiate_with_state(module, Imports::new(), &state).unwrap();
+        let mut exec =
+            instance.exported_func_untyped("sum").unwrap().call(alloc::vec![WasmValue::I32(10)], Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(55)]));
+    }
+
+    #[test]
+    fn test_prepared_call() {
+        let instance = instantiate(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "score") (param i32 i64) (result i64) (local $x i64)
+                    (local.set $x (i64.extend_i32_u (i32.load (local.get 0))))
+                    (i64.add (local.get $x) (local.get 1))))
+            "#,
+            Imports::new(),
+        );
+        let mut prepared = instance.exported_func_untyped("score").unwrap().prepare().unwrap();
+
+        let mut params = [WasmValue::I32(16), WasmValue::I64(0)];
+        for record in 0..100u32 {
+            prepared.instance_mut().exported_memory_mut("memory").unwrap().store(16, 4, &record.to_le_bytes()).unwrap();
+            params[1] = WasmValue::I64(1000);
+            assert_eq!(prepared.call(&params).unwrap(), [WasmValue::I64(record as i64 + 1000)]);
+        }
+
+        // calls can be run in steps and abandoned
+        prepared.start(&params).unwrap();
+        assert!(prepared.run(1).unwrap().is_none());
+        prepared.start(&[WasmValue::I32(16), WasmValue::I64(1)]).unwrap();
+        assert_eq!(prepared.run(usize::MAX).unwrap(), Some(&[WasmValue::I64(100)][..]));
+        assert!(prepared.run(usize::MAX).is_err());
+
+        assert!(matches!(prepared.start(&params[..1]), Err(Error::ParamCountMismatch { expected: 2, got: 1 })));
+        assert!(matches!(
+            prepared.call(&[WasmValue::I32(16), WasmValue::I32(1)]),
+            Err(Error::ParamTypeMismatch { .. })
+        ));
+        assert_eq!(prepared.into_instance().exported_memory("memory").unwrap().load(16, 1).unwrap(), [99]);
+    }
+
+    #[test]
+    fn test_stateful_host_funcs() {
+        let mut count = 0;
+        let next = Extern::typed_func(move |_, ()| -> Result<i32> {
+            count += 1;
+            Ok(count)
+        });
+        type Args = (i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32);
+        let sum = Extern::typed_func(|_, (a, b, c, d, e, f, g, h, i, j, k, l, m, n, o, p): Args| -> Result<i64> {
+            Ok([a, b, c, d, e, f, g, h, i, j, k, l, m, n, o, p].iter().map(|&x| x as i64).sum())
+        });
+        let ty = FuncType { params: Box::new([ValType::I32]), results: Box::new([ValType::I32]) };
+        let dyn_ = Extern::func_untyped(ty, |_, args| match args[0] {
+            WasmValue::I32(0) => Ok(alloc::vec![WasmValue::I32(1)]),
+            _ => Ok(alloc::vec![WasmValue::I64(1)]),
+        });
+
+        let mut imports = Imports::new();
+        imports.define("env", "next", next).unwrap();
+        imports.define("env", "sum", sum).unwrap();
+        imports.define("env", "dyn", dyn_).unwrap();
+        let instance = instantiate(
+            r#"
+            (module
+                (import "env" "next" (func $next (result i32)))
+                (import "env" "sum"
+                    (func $sum (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32) (result i64)))
+                (import "env" "dyn" (func $dyn (param i32) (result i32)))
+                (func (export "main") (result i64)
+                    (drop (call $next))
+                    (call $sum (call $next) (i32.const 2) (i32.const 3) (i32.const 4) (i32.const 5) (i32.const 6)
+                        (i32.const 7) (i32.const 8) (i32.const 9) (i32.const 10) (i32.const 11) (i32.const 12)
+                        (i32.const 13) (i32.const 14) (i32.const 15) (i32.const 16)))
+                (func (export "dyn") (param i32) (result i32)
+                    (call $dyn (local.get 0))))
+            "#,
+            imports,
+        );
+
+        // the counter's state is kept between calls
+        let mut exec = instance.clone().exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I64(137)]));
+        let mut exec = instance.clone().exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I64(139)]));
+
+        // results of untyped functions are checked against their type
+        let dyn_ = instance.exported_func::<i32, i32>("dyn").unwrap();
+        let mut exec = dyn_.call(0, None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done(1))));
+        let mut exec = exec.into_instance().unwrap().exported_func::<i32, i32>("dyn").unwrap().call(1, None).unwrap();
+        assert!(exec.run(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_exit() {
+        let module = parse(
+            r#"
+            (module
+                (import "reef" "abort" (func $abort (param i32)))
+                (func (export "main") (param i32) (result i32)
+                    (if (local.get 0) (then (call $abort (local.get 0))))
+                    (i32.const 7)))
+            "#,
+        );
+        let imports = || {
+            let mut imports = Imports::new();
+            imports.namespace("reef").func("abort", |_, code: i32| -> Result<()> { Err(Error::exit(code)) }).finish();
+            imports
+        };
+
+        let instance = Instance::instantiate(module.clone(), imports()).unwrap();
+        let mut exec =
+            instance.clone().exported_func_untyped("main").unwrap().call(alloc::vec![3.into()], None).unwrap();
+        exec.start_recording();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Exit(3))));
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Exit(3))));
+        let log = exec.take_recording().unwrap();
+        assert_eq!(log.len(), 1);
+
+        let mut exec = instance.clone().exported_func::<i32, i32>("main").unwrap().call(0, None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done(7))));
+        let mut exec = exec.into_instance().unwrap().exported_func::<i32, i32>("main").unwrap().call(2, None).unwrap();
+        assert!(matches!(exec.step(usize::MAX), Ok(StepResult::Exit(2))));
+
+        // exits are part of the replay log
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(alloc::vec![3.into()], None).unwrap();
+        exec.replay(log);
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Exit(3))));
+
+        let mut instance = exec.into_instance();
+        let err = instance.call_in_place("main", alloc::vec![WasmValue::I32(1)]).unwrap_err();
+        assert!(matches!(err, Error::Trap(Trap::Exit(1))));
+        assert_eq!(err.code(), 112);
+    }
+
+    #[test]
+    fn test_exec_stats() {
+        let instance = instantiate(
+            r#"
+            (module
+                (memory 1)
+                (func $rec (param i32) (result i32)
+                    (if (result i32) (local.get 0)
+                        (then (call $rec (i32.sub (local.get 0) (i32.const 1))))
+                        (else (i32.const 0))))
+                (func (export "main") (result i32)
+                    (drop (memory.grow (i32.const 2)))
+                    (call $rec (i32.const 3))))
+            "#,
+            Imports::new(),
+        );
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        exec.enable_coverage();
+
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(_))));
+        let executed = exec.coverage().funcs.iter().flatten().sum::<u64>();
+        let stats = exec.stats();
+        assert_eq!(
+            stats,
+            ExecStats { instructions: executed, fuel: executed, calls: 4, max_call_depth: 5, memory_grown: 2 }
+        );
+
+        // a run that runs out of cycles uses up all of them
+        let mut exec = exec.into_instance().exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(9), Ok(CallResult::Incomplete)));
+        assert_eq!(exec.stats().instructions, 10);
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(_))));
+        assert_eq!(exec.stats().instructions, executed - 10);
+    }
+
+    #[test]
+    fn test_run_for() {
+        let instance = instantiate(
+            r#"
+            (module
+                (func (export "spin") (loop $l (br $l)))
+                (func (export "count") (param i32) (result i32)
+                    (loop $l (br_if $l (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+                    (local.get 0)))
+            "#,
+            Imports::new(),
+        );
+
+        let mut exec = instance.exported_func_untyped("spin").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run_for(std::time::Duration::ZERO), Ok(CallResult::Incomplete)));
+        let one_slice = exec.stats().instructions;
+        assert!(one_slice > 0);
+
+        // a fake clock that runs out after the third slice
+        let mut ticks = 0;
+        let out_of_time = || {
+            ticks += 1;
+            ticks == 3
+        };
+        assert!(matches!(exec.run_slices(out_of_time), Ok(CallResult::Incomplete)));
+        assert_eq!(ticks, 3);
+        assert_eq!(exec.stats().instructions, 3 * one_slice);
+
+        let count = exec.into_instance().exported_func::<i32, i32>("count").unwrap();
+        let mut exec = count.call(100_000, None).unwrap();
+        assert!(matches!(exec.run_for(std::time::Duration::from_secs(60)), Ok(CallResultTyped::Done(0))));
+        assert!(exec.stats().instructions > 300_000);
+    }
+
+    #[test]
+    fn test_wide_tuples() {
+        let mut imports = Imports::new();
+        let host = Extern::typed_func(
+            |_, (a, b, c, d, e, f, g, h, i, j): (i64, i64, i64, i64, i64, i64, i64, i64, i64, i64)| {
+                Ok(a + b + c + d + e + f + g + h + i + j)
+            },
+        );
+        imports.define("env", "host", host).unwrap();
+        let instance = instantiate(
+            r#"
+            (module
+                (import "env" "host" (func $host (param i64 i64 i64 i64 i64 i64 i64 i64 i64 i64) (result i64)))
+                (func (export "pairs")
+                    (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)
+                    (result i32 i32 i32 i32 i32 i32 i32 i32)
+                    (i32.add (local.get 0) (local.get 1)) (i32.add (local.get 2) (local.get 3))
+                    (i32.add (local.get 4) (local.get 5)) (i32.add (local.get 6) (local.get 7))
+                    (i32.add (local.get 8) (local.get 9)) (i32.add (local.get 10) (local.get 11))
+                    (i32.add (local.get 12) (local.get 13)) (i32.add (local.get 14) (local.get 15)))
+                (func (export "mixed") (result i32 i64 i64 i32)
+                    (i32.const 21) (i64.const 42) (i64.const 2) (i32.const 1))
+                (func (export "call_host") (result i64)
+                    (call $host
+                        (i64.const 0) (i64.const 1) (i64.const 2) (i64.const 3) (i64.const 4)
+                        (i64.const 5) (i64.const 6) (i64.const 7) (i64.const 8) (i64.const 9))))
+            "#,
+            imports,
+        );
+
+        type Params = (i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32);
+        type Results = (i32, i32, i32, i32, i32, i32, i32, i32);
+        let func = instance.exported_func::<Params, Results>("pairs").unwrap();
+        let mut exec = func.call((0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done((1, 5, 9, 13, 17, 21, 25, 29)))));
+
+        let func = Instance::from(exec).exported_func::<(), (i32, i64, i64, i32)>("mixed").unwrap();
+        let mut exec = func.call((), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done((21, 42, 2, 1)))));
+
+        let func = Instance::from(exec).exported_func::<(), i64>("call_host").unwrap();
+        let mut exec = func.call((), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done(45))));
+    }
+}
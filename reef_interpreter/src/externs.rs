@@ -0,0 +1,215 @@
+//! Host objects that are passed to the guest as opaque handles
+//!
+//! Hosts often need to give a guest access to objects that can't live in guest memory, like open files or
+//! connections. [`ExternRefs`] stores them on the [`Instance`](crate::Instance) and hands out [`ExternRef`]s, which
+//! the guest receives as an `externref` or, for modules without reference types, as an `i32`. When the guest passes
+//! a handle back, the host looks the object up again.
+//!
+//! Objects are dropped when they are removed or when the last instance holding them is dropped. Forks of an instance
+//! share the objects that existed when it was forked. Objects aren't part of serialized state.
+
+use alloc::{rc::Rc, vec::Vec};
+use core::any::Any;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use crate::types::{value::WasmValue, ExternAddr};
+
+/// A handle to a host object of type `T` stored in [`ExternRefs`]
+pub struct ExternRef<T> {
+    addr: ExternAddr,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ExternRef<T> {
+    /// A handle from an address the guest passed back to the host
+    ///
+    /// Nothing is checked here, looking up a handle with the wrong type or that was removed returns `None`.
+    pub fn from_addr(addr: ExternAddr) -> Self {
+        Self { addr, _marker: PhantomData }
+    }
+
+    /// The address of the object, which is what the guest sees
+    pub fn addr(&self) -> ExternAddr {
+        self.addr
+    }
+
+    /// The handle as an `i32`, for modules without reference types
+    pub fn to_i32(&self) -> i32 {
+        self.addr as i32
+    }
+}
+
+impl<T> Clone for ExternRef<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ExternRef<T> {}
+
+impl<T> PartialEq for ExternRef<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr == other.addr
+    }
+}
+
+impl<T> Eq for ExternRef<T> {}
+
+impl<T> Debug for ExternRef<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("ExternRef").field(&self.addr).finish()
+    }
+}
+
+impl<T> From<ExternRef<T>> for WasmValue {
+    fn from(value: ExternRef<T>) -> Self {
+        WasmValue::RefExtern(value.addr)
+    }
+}
+
+/// Host objects owned by an instance, see the [module documentation](self)
+#[derive(Default, Clone)]
+pub struct ExternRefs {
+    objects: Vec<Option<Rc<dyn Any>>>,
+    /// Slots of removed objects, reused before the table grows
+    free: Vec<ExternAddr>,
+}
+
+impl ExternRefs {
+    /// Store `value` and get a handle to pass to the guest
+    ///
+    /// Addresses of removed objects are reused, so a handle must not be used after its object was removed.
+    pub fn insert<T: Any>(&mut self, value: T) -> ExternRef<T> {
+        let value: Rc<dyn Any> = Rc::new(value);
+        let addr = match self.free.pop() {
+            Some(addr) => {
+                self.objects[addr as usize] = Some(value);
+                addr
+            }
+            None => {
+                self.objects.push(Some(value));
+                (self.objects.len() - 1) as ExternAddr
+            }
+        };
+        ExternRef::from_addr(addr)
+    }
+
+    /// Get the object behind a handle
+    pub fn get<T: Any>(&self, handle: ExternRef<T>) -> Option<&T> {
+        self.objects.get(handle.addr as usize)?.as_deref()?.downcast_ref()
+    }
+
+    /// Get the object behind a handle mutably
+    ///
+    /// Returns `None` while the object is shared with a fork of the instance.
+    pub fn get_mut<T: Any>(&mut self, handle: ExternRef<T>) -> Option<&mut T> {
+        Rc::get_mut(self.objects.get_mut(handle.addr as usize)?.as_mut()?)?.downcast_mut()
+    }
+
+    /// Get the object behind an `externref` or `i32` the guest passed to the host
+    pub fn get_value<T: Any>(&self, value: WasmValue) -> Option<&T> {
+        match value {
+            WasmValue::RefExtern(addr) => self.get(ExternRef::from_addr(addr)),
+            WasmValue::I32(addr) => self.get(ExternRef::from_addr(addr as ExternAddr)),
+            _ => None,
+        }
+    }
+
+    /// Remove the object behind a handle, returning it if it's not shared with a fork of the instance
+    ///
+    /// Nothing is removed if the handle doesn't point to an object of type `T`.
+    pub fn remove<T: Any>(&mut self, handle: ExternRef<T>) -> Option<T> {
+        let slot = self.objects.get_mut(handle.addr as usize)?;
+        if !slot.as_deref()?.is::<T>() {
+            return None;
+        }
+
+        let object = slot.take()?;
+        self.free.push(handle.addr);
+        Rc::try_unwrap(object.downcast::<T>().ok()?).ok()
+    }
+
+    /// Number of stored objects
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    /// Whether no objects are stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Debug for ExternRefs {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ExternRefs").field("len", &self.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, string::String, vec};
+
+    use super::*;
+    use crate::exec::CallResult;
+    use crate::imports::{Extern, Imports};
+    use crate::types::{value::ValType, FuncType};
+    use crate::{parse_bytes, Instance};
+
+    #[test]
+    fn test_extern_refs_in_guest() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "open" (func $open (result externref)))
+                (import "env" "len" (func $len (param externref) (result i32)))
+                (func (export "main") (result i32)
+                    (call $len (call $open))))
+            "#,
+        )
+        .unwrap();
+
+        let mut imports = Imports::new();
+        let open = FuncType { params: Box::new([]), results: Box::new([ValType::RefExtern]) };
+        let open = Extern::func(&open, |mut ctx, _| Ok(vec![ctx.externs_mut().insert(String::from("hello")).into()]));
+        imports.define("env", "open", open).unwrap();
+        let len = FuncType { params: Box::new([ValType::RefExtern]), results: Box::new([ValType::I32]) };
+        let len = Extern::func(&len, |ctx, args| {
+            Ok(vec![WasmValue::I32(ctx.externs().get_value::<String>(args[0]).map_or(-1, |s| s.len() as i32))])
+        });
+        imports.define("env", "len", len).unwrap();
+
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), imports).unwrap();
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(5)]));
+        assert_eq!(exec.into_instance().externs().len(), 1);
+    }
+
+    #[test]
+    fn test_extern_refs() {
+        let mut externs = ExternRefs::default();
+        let file = externs.insert(String::from("file"));
+        let conn = externs.insert(42u64);
+        assert_eq!(externs.len(), 2);
+
+        assert_eq!(externs.get(file).map(|s| s.as_str()), Some("file"));
+        assert_eq!(externs.get_value::<u64>(conn.into()), Some(&42));
+        assert_eq!(externs.get_value::<u64>(WasmValue::I32(conn.to_i32())), Some(&42));
+        assert_eq!(externs.get(ExternRef::<u64>::from_addr(file.addr())), None);
+
+        *externs.get_mut(conn).unwrap() += 1;
+        let fork = externs.clone();
+        assert!(externs.get_mut(conn).is_none());
+        assert_eq!(fork.get(conn), Some(&43));
+        drop(fork);
+
+        assert_eq!(externs.remove(ExternRef::<u64>::from_addr(file.addr())), None);
+        assert_eq!(externs.remove(conn), Some(43));
+        assert_eq!(externs.get(conn), None);
+        assert_eq!(externs.len(), 1);
+
+        let reused = externs.insert(7u8);
+        assert_eq!(reused.addr(), conn.addr());
+    }
+}
@@ -1,18 +1,13 @@
 //! Types related to getting handles for functions in a Wasm module
 
-use alloc::{
-    boxed::Box,
-    format,
-    string::{String, ToString},
-    vec,
-    vec::Vec,
-};
+use alloc::{boxed::Box, format, rc::Rc, string::ToString, vec, vec::Vec};
 
 use crate::error::{Error, Result};
-use crate::exec::{ExecHandle, ExecHandleTyped};
+use crate::exec::{ExecConfig, ExecHandle, ExecHandleTyped, PreparedCall, StackPool};
+use crate::guest::GuestRegion;
 use crate::imports::Function;
 use crate::instance::Instance;
-use crate::runtime::{CallFrame, RawWasmValue, Stack};
+use crate::runtime::{CallFrame, Stack};
 use crate::types::{
     value::{ValType, WasmValue},
     FuncType,
@@ -28,25 +23,68 @@ pub struct FuncHandle {
     pub(crate) ty: FuncType,
 
     /// The name of the function, if it has one
-    pub name: Option<String>,
+    pub name: Option<Rc<str>>,
 }
 
 impl FuncHandle {
+    /// The type of the function
+    pub fn ty(&self) -> &FuncType {
+        &self.ty
+    }
+
+    /// A [`FuncId`] for this function that can be bound to an instance again later
+    pub fn id(&self) -> FuncId {
+        FuncId { addr: self.addr, ty: self.ty.clone(), name: self.name.clone() }
+    }
+
+    /// Give up the handle and get back the instance it was called on
+    pub fn into_instance(self) -> Instance {
+        self.instance
+    }
+
+    /// Prepare the function to be called many times, see [`PreparedCall`]
+    ///
+    /// The stack is created from the instance's [`ExecConfig`].
+    pub fn prepare(self) -> Result<PreparedCall> {
+        let config = self.instance.exec_config;
+        PreparedCall::new(self, &config)
+    }
+
+    /// Prepare the function to be called many times with a stack created from `config`
+    pub fn prepare_with_config(self, config: &ExecConfig) -> Result<PreparedCall> {
+        PreparedCall::new(self, config)
+    }
+
     /// Start or resume execution of function
+    ///
+    /// A new stack is created from the instance's [`ExecConfig`].
     pub fn call(self, params: Vec<WasmValue>, stack: Option<Stack>) -> Result<ExecHandle> {
-        let func_ty = &self.ty;
-
-        if unlikely(func_ty.params.len() != params.len()) {
-            return Err(Error::Other(format!(
-                "param count mismatch: expected {}, got {}",
-                func_ty.params.len(),
-                params.len()
-            )));
-        }
+        let config = self.instance.exec_config;
+        self.start(params, stack, |call_frame| Stack::new(call_frame, &config))
+    }
 
-        if !(func_ty.params.iter().zip(&params).all(|(ty, param)| ty == &param.val_type())) {
-            return Err(Error::Other("Type mismatch".into()));
-        }
+    /// Start execution of function with a new stack created from `config`
+    pub fn call_with_config(self, params: Vec<WasmValue>, config: &ExecConfig) -> Result<ExecHandle> {
+        self.start(params, None, |call_frame| Stack::new(call_frame, config))
+    }
+
+    /// Start execution of function with a stack taken from `pool`
+    pub fn call_pooled(self, params: Vec<WasmValue>, pool: &mut StackPool) -> Result<ExecHandle> {
+        self.start(params, None, |call_frame| pool.take(call_frame))
+    }
+
+    fn start(
+        self,
+        params: Vec<WasmValue>,
+        stack: Option<Stack>,
+        new_stack: impl FnOnce(CallFrame) -> Stack,
+    ) -> Result<ExecHandle> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!("call", func = self.addr, name = self.name.as_deref(), resume = stack.is_some())
+                .entered();
+
+        check_params(&self.ty, &params)?;
 
         let func = self.instance.funcs.get_or_instance(self.addr, "function")?;
 
@@ -54,15 +92,117 @@ impl FuncHandle {
             Some(stack) => stack,
             None => match &func {
                 Function::Wasm(wasm_func) => {
-                    let call_frame_params = params.iter().map(|v| RawWasmValue::from(*v));
-                    let call_frame = CallFrame::new(self.addr, wasm_func, call_frame_params, 0);
-                    Stack::new(call_frame)
+                    let mut stack = new_stack(CallFrame::new(self.addr, wasm_func, 0, 0));
+                    stack.values.extend_from_typed(&params);
+                    stack.values.enter_frame(wasm_func)?;
+                    stack
                 }
                 Function::Host(_) => return Err(Error::Other("Can't call Host function directly".to_string())),
             },
         };
 
-        Ok(ExecHandle { func_handle: self, stack })
+        Ok(self.into_exec(stack))
+    }
+
+    pub(crate) fn into_exec(self, stack: Stack) -> ExecHandle {
+        ExecHandle {
+            func_handle: self,
+            stack,
+            trace_hook: None,
+            breakpoints: Vec::new(),
+            at_breakpoint: false,
+            profiler: None,
+            coverage: None,
+            host_calls: None,
+            exit_code: None,
+            stats: Default::default(),
+        }
+    }
+}
+
+/// Check that `params` can be passed to a function of type `ty`
+pub(crate) fn check_params(ty: &FuncType, params: &[WasmValue]) -> Result<()> {
+    if unlikely(ty.params.len() != params.len()) {
+        return Err(Error::ParamCountMismatch { expected: ty.params.len(), got: params.len() });
+    }
+
+    for (index, (ty, param)) in ty.params.iter().zip(params).enumerate() {
+        if *ty != param.val_type() {
+            return Err(Error::ParamTypeMismatch { expected: *ty, got: param.val_type(), index });
+        }
+    }
+    Ok(())
+}
+
+/// An exported function that doesn't hold on to an instance
+///
+/// Unlike a [`FuncHandle`] this is plain data, so it can be looked up once, kept in a host struct and bound to the
+/// instance again for every call. The instance can be used freely in between, including being serialized and
+/// restored for the next quantum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncId {
+    pub(crate) addr: u32,
+    pub(crate) ty: FuncType,
+    pub(crate) name: Option<Rc<str>>,
+}
+
+impl FuncId {
+    /// The type of the function
+    pub fn ty(&self) -> &FuncType {
+        &self.ty
+    }
+
+    /// The name the function was exported as
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Get a handle to call the function on `instance`
+    ///
+    /// Fails if `instance` doesn't have a function of the same type at this address, e.g. because it was created
+    /// from a different module.
+    pub fn bind(&self, instance: Instance) -> Result<FuncHandle> {
+        if unlikely(instance.get_func(self.addr)?.ty() != &self.ty) {
+            return Err(Error::Other(format!("function {} has a different type in this instance", self.addr)));
+        }
+
+        Ok(FuncHandle { instance, addr: self.addr, ty: self.ty.clone(), name: self.name.clone() })
+    }
+
+    /// Bind the function to `instance` and start executing it, see [`FuncHandle::call`]
+    pub fn call(&self, instance: Instance, params: Vec<WasmValue>, stack: Option<Stack>) -> Result<ExecHandle> {
+        self.bind(instance)?.call(params, stack)
+    }
+
+    /// Attach parameter and result types to the function
+    pub fn typed<P, R>(self) -> FuncIdTyped<P, R> {
+        FuncIdTyped { func: self, _marker: core::marker::PhantomData }
+    }
+}
+
+/// A typed [`FuncId`]
+#[derive(Debug)]
+pub struct FuncIdTyped<P, R> {
+    /// The underlying function id
+    pub func: FuncId,
+    _marker: core::marker::PhantomData<fn(P) -> R>,
+}
+
+impl<P, R> Clone for FuncIdTyped<P, R> {
+    fn clone(&self) -> Self {
+        Self { func: self.func.clone(), _marker: core::marker::PhantomData }
+    }
+}
+
+impl<P: GuestParams, R: FromWasmValueTuple> FuncIdTyped<P, R> {
+    /// Get a typed handle to call the function on `instance`, see [`FuncId::bind`]
+    pub fn bind(&self, instance: Instance) -> Result<FuncHandleTyped<P, R>> {
+        Ok(FuncHandleTyped { func: self.func.bind(instance)?, _marker: core::marker::PhantomData })
+    }
+
+    /// Bind the function to `instance` and start executing it, see [`FuncHandleTyped::call`]
+    pub fn call(&self, instance: Instance, params: P, stack: Option<Stack>) -> Result<ExecHandleTyped<R>> {
+        self.bind(instance)?.call(params, stack)
     }
 }
 
@@ -88,15 +228,124 @@ pub trait FromWasmValueTuple {
         Self: Sized;
 }
 
-impl<P: IntoWasmValueTuple, R: FromWasmValueTuple> FuncHandleTyped<P, R> {
+impl<P: GuestParams, R: FromWasmValueTuple> FuncHandleTyped<P, R> {
     /// See [`FuncHandle::call`]
+    ///
+    /// Parameters that live in guest memory, like `&[u8]` and `&str`, are copied into memory allocated with
+    /// [`Instance::alloc_in_guest`] and freed once the call is done. They stay with the execution when it is turned
+    /// into an [`ExecHandle`] or serialized, see [`ExecHandle::free_params`].
     pub fn call(self, params: P, stack: Option<Stack>) -> Result<ExecHandleTyped<R>> {
-        let exec_handle = self.func.call(params.into_wasm_value_tuple(), stack)?;
+        self.start(params, |func, params| func.call(params, stack))
+    }
+
+    /// See [`FuncHandle::call_with_config`]
+    pub fn call_with_config(self, params: P, config: &ExecConfig) -> Result<ExecHandleTyped<R>> {
+        self.start(params, |func, params| func.call_with_config(params, config))
+    }
+
+    /// See [`FuncHandle::call_pooled`]
+    pub fn call_pooled(self, params: P, pool: &mut StackPool) -> Result<ExecHandleTyped<R>> {
+        self.start(params, |func, params| func.call_pooled(params, pool))
+    }
+
+    fn start(
+        mut self,
+        params: P,
+        call: impl FnOnce(FuncHandle, Vec<WasmValue>) -> Result<ExecHandle>,
+    ) -> Result<ExecHandleTyped<R>> {
+        let mut regions = Vec::new();
+        let params = match params.lower(&mut self.func.instance, &mut regions) {
+            Ok(params) => params,
+            Err(err) => {
+                for region in regions {
+                    let _ = self.func.instance.free_in_guest(region);
+                }
+                return Err(err);
+            }
+        };
 
+        let mut exec_handle = call(self.func, params)?;
+        exec_handle.stack.param_regions.extend(regions);
         Ok(ExecHandleTyped { exec_handle, _marker: Default::default() })
     }
 }
 
+/// A value that can be passed to a typed guest function
+///
+/// Numbers are passed as they are. Byte slices and strings are copied into memory allocated in the guest and
+/// passed as a `(ptr, len)` pair of `i32`s. Empty ones are passed as `(0, 0)` without allocating.
+pub trait GuestParam {
+    /// Append the values this parameter is passed as to `values`
+    ///
+    /// Guest memory allocated for the parameter is added to `regions`.
+    fn lower(self, instance: &mut Instance, regions: &mut Vec<GuestRegion>, values: &mut Vec<WasmValue>) -> Result<()>;
+}
+
+impl<T: Into<WasmValue>> GuestParam for T {
+    #[inline]
+    fn lower(self, _: &mut Instance, _: &mut Vec<GuestRegion>, values: &mut Vec<WasmValue>) -> Result<()> {
+        values.push(self.into());
+        Ok(())
+    }
+}
+
+impl GuestParam for &[u8] {
+    fn lower(self, instance: &mut Instance, regions: &mut Vec<GuestRegion>, values: &mut Vec<WasmValue>) -> Result<()> {
+        if self.is_empty() {
+            values.extend([WasmValue::I32(0), WasmValue::I32(0)]);
+            return Ok(());
+        }
+
+        let len = u32::try_from(self.len()).map_err(|_| Error::Other("parameter too large".to_string()))?;
+        let region = instance.alloc_in_guest(len, 1)?;
+        regions.push(region);
+        instance.get_mem_mut(0)?.store(region.offset(), self.len(), self)?;
+        values.extend([WasmValue::I32(region.ptr as i32), WasmValue::I32(len as i32)]);
+        Ok(())
+    }
+}
+
+impl GuestParam for &str {
+    #[inline]
+    fn lower(self, instance: &mut Instance, regions: &mut Vec<GuestRegion>, values: &mut Vec<WasmValue>) -> Result<()> {
+        GuestParam::lower(self.as_bytes(), instance, regions, values)
+    }
+}
+
+/// The parameters of a typed guest function, see [`GuestParam`]
+pub trait GuestParams {
+    /// Convert the parameters to the values passed to the function
+    fn lower(self, instance: &mut Instance, regions: &mut Vec<GuestRegion>) -> Result<Vec<WasmValue>>;
+}
+
+impl<T: GuestParam> GuestParams for T {
+    #[inline]
+    fn lower(self, instance: &mut Instance, regions: &mut Vec<GuestRegion>) -> Result<Vec<WasmValue>> {
+        let mut values = Vec::with_capacity(2);
+        GuestParam::lower(self, instance, regions, &mut values)?;
+        Ok(values)
+    }
+}
+
+macro_rules! impl_guest_params {
+    ($($T:ident),*) => {
+        impl<$($T),*> GuestParams for ($($T,)*)
+        where
+            $($T: GuestParam),*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            #[inline]
+            fn lower(self, instance: &mut Instance, regions: &mut Vec<GuestRegion>) -> Result<Vec<WasmValue>> {
+                let ($($T,)*) = self;
+                #[allow(unused_mut)]
+                let mut values = Vec::new();
+                $(GuestParam::lower($T, instance, regions, &mut values)?;)*
+                Ok(values)
+            }
+        }
+    }
+}
+
 macro_rules! impl_into_wasm_value_tuple {
     ($($T:ident),*) => {
         impl<$($T),*> IntoWasmValueTuple for ($($T,)*)
@@ -205,6 +454,30 @@ impl ToValType for f64 {
     }
 }
 
+impl ToValType for u32 {
+    fn to_val_type() -> ValType {
+        ValType::I32
+    }
+}
+
+impl ToValType for u64 {
+    fn to_val_type() -> ValType {
+        ValType::I64
+    }
+}
+
+impl ToValType for u8 {
+    fn to_val_type() -> ValType {
+        ValType::I32
+    }
+}
+
+impl ToValType for bool {
+    fn to_val_type() -> ValType {
+        ValType::I32
+    }
+}
+
 macro_rules! impl_val_types_from_tuple {
     ($($t:ident),+) => {
         impl<$($t),+> ValTypesFromTuple for ($($t,)+)
@@ -237,11 +510,19 @@ impl_from_wasm_value_tuple_single!(i32);
 impl_from_wasm_value_tuple_single!(i64);
 impl_from_wasm_value_tuple_single!(f32);
 impl_from_wasm_value_tuple_single!(f64);
+impl_from_wasm_value_tuple_single!(u32);
+impl_from_wasm_value_tuple_single!(u64);
+impl_from_wasm_value_tuple_single!(u8);
+impl_from_wasm_value_tuple_single!(bool);
 
 impl_into_wasm_value_tuple_single!(i32);
 impl_into_wasm_value_tuple_single!(i64);
 impl_into_wasm_value_tuple_single!(f32);
 impl_into_wasm_value_tuple_single!(f64);
+impl_into_wasm_value_tuple_single!(u32);
+impl_into_wasm_value_tuple_single!(u64);
+impl_into_wasm_value_tuple_single!(u8);
+impl_into_wasm_value_tuple_single!(bool);
 
 impl_val_types_from_tuple!(T1);
 impl_val_types_from_tuple!(T1, T2);
@@ -249,6 +530,16 @@ impl_val_types_from_tuple!(T1, T2, T3);
 impl_val_types_from_tuple!(T1, T2, T3, T4);
 impl_val_types_from_tuple!(T1, T2, T3, T4, T5);
 impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
 
 impl_from_wasm_value_tuple!();
 impl_from_wasm_value_tuple!(T1);
@@ -257,6 +548,34 @@ impl_from_wasm_value_tuple!(T1, T2, T3);
 impl_from_wasm_value_tuple!(T1, T2, T3, T4);
 impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5);
 impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
+
+impl_guest_params!();
+impl_guest_params!(T1);
+impl_guest_params!(T1, T2);
+impl_guest_params!(T1, T2, T3);
+impl_guest_params!(T1, T2, T3, T4);
+impl_guest_params!(T1, T2, T3, T4, T5);
+impl_guest_params!(T1, T2, T3, T4, T5, T6);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+impl_guest_params!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
 
 impl_into_wasm_value_tuple!();
 impl_into_wasm_value_tuple!(T1);
@@ -265,3 +584,13 @@ impl_into_wasm_value_tuple!(T1, T2, T3);
 impl_into_wasm_value_tuple!(T1, T2, T3, T4);
 impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5);
 impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
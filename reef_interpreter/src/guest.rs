@@ -0,0 +1,264 @@
+//! Allocating guest memory through the guest's own allocator
+
+use alloc::{format, vec::Vec};
+
+use crate::error::{Error, Result};
+use crate::exec::CallResult;
+use crate::types::{value::WasmValue, ExternVal, ExternalKind};
+use crate::Instance;
+
+/// Names of the exports a guest uses to allocate and free memory
+///
+/// The allocation export takes `(size)` or `(size, align)` and returns a pointer. The free export takes `(ptr)`,
+/// `(ptr, size)` or `(ptr, size, align)`. All values are `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestAllocator {
+    /// The name of the allocation function
+    pub alloc: &'static str,
+    /// The name of the function freeing memory
+    pub free: &'static str,
+    /// The cycle budget of every call to the allocator
+    ///
+    /// Calls that don't finish within it fail with [`Error::AllocatorBudgetExceeded`], leaving the guest's allocator
+    /// in whatever state it was interrupted in.
+    pub max_cycles: usize,
+}
+
+impl GuestAllocator {
+    /// The default [`GuestAllocator::max_cycles`], which is plenty for a single allocation
+    pub const DEFAULT_MAX_CYCLES: usize = 10_000_000;
+
+    /// The allocator exported by `wasm-bindgen` generated modules
+    pub const WASM_BINDGEN: Self =
+        Self { alloc: "__wbindgen_malloc", free: "__wbindgen_free", max_cycles: Self::DEFAULT_MAX_CYCLES };
+    /// The C allocator, e.g. of modules built with wasi-libc or emscripten
+    pub const LIBC: Self = Self { alloc: "malloc", free: "free", max_cycles: Self::DEFAULT_MAX_CYCLES };
+
+    /// Allocators tried in order by [`Instance::alloc_in_guest`]
+    pub const CONVENTIONAL: [Self; 2] = [Self::WASM_BINDGEN, Self::LIBC];
+}
+
+/// A block of memory allocated in the guest, see [`Instance::alloc_in_guest`]
+///
+/// The region was checked to be non-null, aligned and within the first memory when it was allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct GuestRegion {
+    /// The address of the region in the first memory
+    pub ptr: u32,
+    /// The size of the region in bytes
+    pub len: u32,
+    /// The alignment the region was allocated with
+    pub align: u32,
+}
+
+impl GuestRegion {
+    /// The address of the region as an offset usable with [`MemoryRefMut`](crate::reference::MemoryRefMut)
+    pub fn offset(&self) -> usize {
+        self.ptr as usize
+    }
+}
+
+impl Instance {
+    /// Allocate `size` bytes aligned to `align` in the guest's memory using its exported allocator
+    ///
+    /// Uses the first allocator of [`GuestAllocator::CONVENTIONAL`] the module exports.
+    pub fn alloc_in_guest(&mut self, size: u32, align: u32) -> Result<GuestRegion> {
+        let allocator = self.conventional_allocator()?;
+        self.alloc_in_guest_with(allocator, size, align)
+    }
+
+    /// Allocate `size` bytes aligned to `align` in the guest's memory using the given allocator exports
+    pub fn alloc_in_guest_with(&mut self, allocator: GuestAllocator, size: u32, align: u32) -> Result<GuestRegion> {
+        if !align.is_power_of_two() {
+            return Err(Error::Other(format!("alignment {} is not a power of two", align)));
+        }
+
+        let params = self.guest_alloc_params(allocator.alloc, &[size, align])?;
+        let ptr = match self.call_allocator(allocator, allocator.alloc, params)?[..] {
+            [WasmValue::I32(ptr)] => ptr as u32,
+            _ => return Err(Error::Other(format!("`{}` has to return a single i32", allocator.alloc))),
+        };
+
+        if ptr == 0 {
+            return Err(Error::Other(format!("guest failed to allocate {} bytes", size)));
+        }
+        if ptr % align != 0 {
+            return Err(Error::Other(format!("guest returned address {:#x} not aligned to {}", ptr, align)));
+        }
+        let mem_size = self.get_mem(0)?.data.len();
+        if ptr as usize + size as usize > mem_size {
+            return Err(Error::Other(format!("guest returned region {:#x}+{} outside of memory", ptr, size)));
+        }
+
+        Ok(GuestRegion { ptr, len: size, align })
+    }
+
+    /// Free a region allocated with [`alloc_in_guest`](Instance::alloc_in_guest)
+    pub fn free_in_guest(&mut self, region: GuestRegion) -> Result<()> {
+        let allocator = self.conventional_allocator()?;
+        self.free_in_guest_with(allocator, region)
+    }
+
+    /// Free a region allocated with [`alloc_in_guest_with`](Instance::alloc_in_guest_with)
+    pub fn free_in_guest_with(&mut self, allocator: GuestAllocator, region: GuestRegion) -> Result<()> {
+        let params = self.guest_alloc_params(allocator.free, &[region.ptr, region.len, region.align])?;
+        self.call_allocator(allocator, allocator.free, params)?;
+        Ok(())
+    }
+
+    fn call_allocator(
+        &mut self,
+        allocator: GuestAllocator,
+        name: &str,
+        params: Vec<WasmValue>,
+    ) -> Result<Vec<WasmValue>> {
+        match self.call_in_place_for(name, params, allocator.max_cycles)? {
+            CallResult::Done(values) => Ok(values),
+            CallResult::Exit(code) => Err(Error::exit(code)),
+            CallResult::Incomplete | CallResult::Breakpoint(_) => {
+                Err(Error::AllocatorBudgetExceeded { max_cycles: allocator.max_cycles })
+            }
+        }
+    }
+
+    fn conventional_allocator(&self) -> Result<GuestAllocator> {
+        GuestAllocator::CONVENTIONAL
+            .into_iter()
+            .find(|a| matches!(self.export_addr(a.alloc), Some(ExternVal::Func(_))))
+            .ok_or_else(|| Error::export_not_found(GuestAllocator::LIBC.alloc, ExternalKind::Func))
+    }
+
+    /// Pass as many leading `args` as the export takes
+    fn guest_alloc_params(&self, name: &str, args: &[u32]) -> Result<Vec<WasmValue>> {
+        let Some(ExternVal::Func(addr)) = self.export_addr(name) else {
+            return Err(Error::export_not_found(name, ExternalKind::Func));
+        };
+
+        let param_count = self.get_func(addr)?.ty().params.len();
+        if param_count == 0 || param_count > args.len() {
+            return Err(Error::Other(format!("`{}` takes {} parameters", name, param_count)));
+        }
+        Ok(args[..param_count].iter().map(|arg| WasmValue::I32(*arg as i32)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{CallResultTyped, ExecHandle};
+    use crate::imports::Imports;
+    use crate::parse_bytes;
+
+    const BUMP_ALLOCATOR: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 8))
+            (global $freed (export "freed") (mut i32) (i32.const 0))
+            (func (export "malloc") (param $size i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (i32.and (i32.add (global.get $next) (i32.const 7)) (i32.const -8)))
+                (global.set $next (i32.add (local.get $ptr) (local.get $size)))
+                (local.get $ptr))
+            (func (export "free") (param $ptr i32)
+                (global.set $freed (local.get $ptr)))
+            (func (export "sum") (param $ptr i32) (param $len i32) (param $acc i32) (result i32)
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.eqz (local.get $len)))
+                        (local.set $acc (i32.add (local.get $acc) (i32.load8_u (local.get $ptr))))
+                        (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+                        (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+                        (br $next)))
+                (local.get $acc)))
+    "#;
+
+    #[test]
+    fn test_alloc_in_guest() {
+        let wasm = wat::parse_str(BUMP_ALLOCATOR).unwrap();
+        let mut instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), Imports::new()).unwrap();
+
+        let a = instance.alloc_in_guest(5, 1).unwrap();
+        let b = instance.alloc_in_guest(16, 8).unwrap();
+        assert_eq!(a, GuestRegion { ptr: 8, len: 5, align: 1 });
+        assert_eq!(b, GuestRegion { ptr: 16, len: 16, align: 8 });
+
+        instance.exported_memory_mut("memory").unwrap().store(b.offset(), 3, b"abc").unwrap();
+        instance.free_in_guest(b).unwrap();
+        assert_eq!(instance.exported_global("freed").unwrap().get(), WasmValue::I32(16));
+
+        assert!(instance.alloc_in_guest(1 << 20, 8).is_err());
+        assert!(instance.alloc_in_guest(4, 3).is_err());
+        assert!(instance.alloc_in_guest_with(GuestAllocator::WASM_BINDGEN, 4, 4).is_err());
+
+        // The instance is still usable after failed calls
+        assert_eq!(instance.exported_memory("memory").unwrap().load(16, 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_allocator_budget() {
+        let wasm = wat::parse_str(BUMP_ALLOCATOR).unwrap();
+        let mut instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), Imports::new()).unwrap();
+
+        let allocator = GuestAllocator { max_cycles: 3, ..GuestAllocator::LIBC };
+        let err = instance.alloc_in_guest_with(allocator, 4, 4).unwrap_err();
+        assert!(matches!(err, Error::AllocatorBudgetExceeded { max_cycles: 3 }));
+
+        let region = instance.alloc_in_guest_with(GuestAllocator { max_cycles: 100, ..allocator }, 4, 4).unwrap();
+        assert_eq!(region, GuestRegion { ptr: 8, len: 4, align: 4 });
+    }
+
+    #[test]
+    fn test_slice_params() {
+        let wasm = wat::parse_str(BUMP_ALLOCATOR).unwrap();
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), Imports::new()).unwrap();
+
+        let data = [1u8, 2, 3];
+        let func = instance.exported_func::<(&[u8], i32), i32>("sum").unwrap();
+        let mut exec = func.call((&data[..], 10), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done(16))));
+
+        let mut instance = Instance::from(exec);
+        assert_eq!(instance.exported_global("freed").unwrap().get(), WasmValue::I32(8));
+
+        let func = instance.exported_func::<(&str, i32), i32>("sum").unwrap();
+        let mut exec = func.call(("\x01\x01", 0), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done(2))));
+
+        let func = Instance::from(exec).exported_func::<(&str, i32), i32>("sum").unwrap();
+        let mut exec = func.call(("", 5), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done(5))));
+    }
+
+    #[test]
+    fn test_slice_params_untyped() {
+        let wasm = wat::parse_str(BUMP_ALLOCATOR).unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let freed = |instance: &mut Instance| instance.exported_global("freed").unwrap().get();
+
+        // the parameters stay with the execution when it loses its types
+        let func = instance.exported_func::<(&[u8], i32), i32>("sum").unwrap();
+        let mut exec = ExecHandle::from(func.call((&[1u8, 2][..], 0), None).unwrap());
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(_))));
+        assert_eq!(freed(&mut exec.func_handle.instance), WasmValue::I32(8));
+
+        // and when it is serialized
+        let func = exec.into_instance().exported_func::<(&[u8], i32), i32>("sum").unwrap();
+        let mut exec = ExecHandle::from(func.call((&[1u8, 2][..], 0), None).unwrap());
+        assert!(matches!(exec.run(1), Ok(CallResult::Incomplete)));
+        let state = exec.serialize(rkyv::AlignedVec::new()).unwrap();
+        let (mut instance, stack) = Instance::instantiate_with_state(module, Imports::new(), &state).unwrap();
+        assert_eq!(freed(&mut instance), WasmValue::I32(8));
+        let params = alloc::vec![WasmValue::I32(0); 3];
+        let mut exec = instance.exported_func_untyped("sum").unwrap().call(params, Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(3)]));
+        assert_eq!(freed(&mut exec.func_handle.instance), WasmValue::I32(16));
+
+        // or freed by hand when the execution is given up
+        let func = exec.into_instance().exported_func::<(&[u8], i32), i32>("sum").unwrap();
+        let mut exec = ExecHandle::from(func.call((&[1u8][..], 0), None).unwrap());
+        exec.free_params().unwrap();
+        assert_eq!(freed(&mut exec.func_handle.instance), WasmValue::I32(24));
+    }
+}
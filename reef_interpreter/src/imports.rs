@@ -1,33 +1,38 @@
 //! Types for resources that a Wasm module requires
 
 use alloc::{
-    boxed::Box,
     collections::BTreeMap,
     format,
+    rc::Rc,
     string::{String, ToString},
     vec::Vec,
 };
+use core::cell::RefCell;
 use core::fmt::Debug;
 
-use crate::error::{Error, LinkingError, Result};
+use crate::error::{Error, LinkingError, Result, Trap};
+use crate::externs::ExternRefs;
 use crate::func::{FromWasmValueTuple, IntoWasmValueTuple, ValTypesFromTuple};
 use crate::reference::{MemoryRef, MemoryRefMut};
+use crate::runtime::RawWasmValue;
 use crate::store::memory::MemoryInstance;
 use crate::types::{
-    value::WasmValue, ExternalKind, FuncAddr, GlobalAddr, GlobalType, Import, MemAddr, MemoryType, Module, TableAddr,
-    TableType,
+    value::WasmValue, ExternalKind, FuncAddr, GlobalAddr, GlobalType, Import, ImportKind, MemAddr, MemoryType, Module,
+    TableAddr, TableType,
 };
 use crate::types::{FuncType, WasmFunction};
 use crate::VecExt;
 
 /// The internal representation of a function
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Function {
     /// A host function
     Host(HostFunction),
 
     /// A pointer to a WebAssembly function
-    Wasm(WasmFunction),
+    ///
+    /// Shared, so calls and clones of the function table never copy function bodies.
+    Wasm(Rc<WasmFunction>),
 }
 
 impl Function {
@@ -40,6 +45,7 @@ impl Function {
 }
 
 /// A host function
+#[derive(Clone)]
 pub struct HostFunction {
     pub(crate) ty: FuncType,
     pub(crate) func: HostFuncInner,
@@ -53,17 +59,40 @@ impl HostFunction {
 
     /// Call the function
     pub fn call(&self, ctx: FuncContext<'_>, args: &[WasmValue]) -> Result<Vec<WasmValue>> {
-        (self.func)(ctx, args)
+        let mut func = self
+            .func
+            .try_borrow_mut()
+            .map_err(|_| Error::Other("Host function called while it is already running".to_string()))?;
+        (*func)(ctx, args)
     }
 }
 
-pub(crate) type HostFuncInner = Box<dyn Fn(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>>>;
+/// Host functions can keep state between calls, forks of an instance share the function and its state
+pub(crate) type HostFuncInner = Rc<RefCell<dyn FnMut(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>>>>;
 
 /// The context of a host-function call
 #[derive(Debug)]
 pub struct FuncContext<'i> {
     pub(crate) module: &'i Module,
     pub(crate) memories: &'i mut Vec<MemoryInstance>,
+    pub(crate) externs: &'i mut ExternRefs,
+    pub(crate) callback: &'i mut Option<Callback>,
+}
+
+/// A guest function a host function asked to be called in its place, see [`FuncContext::call_exported`]
+#[derive(Debug)]
+pub(crate) struct Callback {
+    pub(crate) func: FuncAddr,
+    pub(crate) params: Vec<WasmValue>,
+}
+
+/// What a call to a host function evaluated to
+#[derive(Debug)]
+pub(crate) enum HostReturn {
+    /// The results of the host function
+    Results(Vec<WasmValue>),
+    /// A guest function to call, whose results become the results of the host function
+    Callback { func: FuncAddr, params: Vec<RawWasmValue> },
 }
 
 impl FuncContext<'_> {
@@ -82,16 +111,39 @@ impl FuncContext<'_> {
         Ok(MemoryRefMut { instance: self.memories.get_mut_or_instance(self.exported_memory_addr(name)?, "memory")? })
     }
 
+    /// Host objects passed to the guest as opaque handles, see [`ExternRefs`]
+    pub fn externs(&self) -> &ExternRefs {
+        self.externs
+    }
+
+    /// Host objects passed to the guest as opaque handles, see [`ExternRefs`]
+    pub fn externs_mut(&mut self) -> &mut ExternRefs {
+        self.externs
+    }
+
+    /// Call an exported guest function once this host function returns
+    ///
+    /// The guest function runs on the interpreter's stack in place of the host function, so it can be paused and
+    /// serialized like any other call. Its results become the results of the host function, which has to return
+    /// no values itself and must have the same result types as the guest function.
+    ///
+    /// Only one call can be made, later calls replace earlier ones.
+    pub fn call_exported(&mut self, name: &str, params: Vec<WasmValue>) -> Result<()> {
+        let func = self.exported_addr(name, ExternalKind::Func)?;
+        *self.callback = Some(Callback { func, params });
+        Ok(())
+    }
+
     fn exported_memory_addr(&self, name: &str) -> Result<u32> {
-        let export = self
-            .module
-            .exports
-            .iter()
-            .find(|e| &*e.name == name)
-            .ok_or_else(|| Error::Other(format!("Export not found: {}", name)))?;
-
-        if export.kind != ExternalKind::Memory {
-            return Err(Error::Other(format!("Export is not a memory: {}", name)));
+        self.exported_addr(name, ExternalKind::Memory)
+    }
+
+    fn exported_addr(&self, name: &str, kind: ExternalKind) -> Result<u32> {
+        let export =
+            self.module.exports.iter().find(|e| &*e.name == name).ok_or_else(|| Error::export_not_found(name, kind))?;
+
+        if export.kind != kind {
+            return Err(Error::export_not_found(name, kind));
         };
 
         Ok(export.index)
@@ -151,17 +203,52 @@ impl Extern {
     }
 
     /// Create a new function import
+    ///
+    /// The function can keep state between calls. Forks of an instance share the function and its state.
     pub fn func(
         ty: &FuncType,
-        func: impl Fn(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + 'static,
+        func: impl FnMut(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + 'static,
     ) -> Self {
-        Self::Function(Some(Function::Host(HostFunction { func: Box::new(func), ty: ty.clone() })))
+        Self::host_func(ty.clone(), func)
+    }
+
+    /// Create a new function import whose type is only known at runtime
+    ///
+    /// Meant for bridging layers that forward calls to dynamically typed code. Unlike [`Extern::func`], the
+    /// results are checked against `ty` after every call.
+    pub fn func_untyped(
+        ty: FuncType,
+        mut func: impl FnMut(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + 'static,
+    ) -> Self {
+        let results_ty = ty.results.clone();
+        let inner_func = move |ctx: FuncContext<'_>, args: &[WasmValue]| -> Result<Vec<WasmValue>> {
+            let FuncContext { module, memories, externs, callback } = ctx;
+            let results = func(FuncContext { module, memories, externs, callback: &mut *callback }, args)?;
+
+            // a host function that calls back into the guest returns no values itself, the interpreter checks those
+            let matches = results.len() == results_ty.len()
+                && results.iter().zip(&*results_ty).all(|(v, ty)| v.val_type() == *ty);
+            if matches || callback.is_some() {
+                return Ok(results);
+            }
+            Err(Error::Other(format!("Host function returned {:?}, expected values of type {:?}", results, results_ty)))
+        };
+        Self::host_func(ty, inner_func)
+    }
+
+    fn host_func(
+        ty: FuncType,
+        func: impl FnMut(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + 'static,
+    ) -> Self {
+        Self::Function(Some(Function::Host(HostFunction { func: Rc::new(RefCell::new(func)), ty })))
     }
 
     /// Create a new typed function import
+    ///
+    /// Takes up to 16 parameters as a tuple and returns up to 16 results.
     // TODO: currently, this is slower than `Extern::func` because of the type conversions.
     //       we should be able to optimize this and make it even faster than `Extern::func`.
-    pub fn typed_func<P, R>(func: impl Fn(FuncContext<'_>, P) -> Result<R> + 'static) -> Self
+    pub fn typed_func<P, R>(mut func: impl FnMut(FuncContext<'_>, P) -> Result<R> + 'static) -> Self
     where
         P: FromWasmValueTuple + ValTypesFromTuple,
         R: IntoWasmValueTuple + ValTypesFromTuple + Debug,
@@ -173,7 +260,7 @@ impl Extern {
         };
 
         let ty = FuncType { params: P::val_types(), results: R::val_types() };
-        Self::Function(Some(Function::Host(HostFunction { func: Box::new(inner_func), ty })))
+        Self::host_func(ty, inner_func)
     }
 
     /// Get the kind of the external value
@@ -185,6 +272,17 @@ impl Extern {
             Self::Function { .. } => ExternalKind::Func,
         }
     }
+
+    /// Whether both values have the same type, in which case one replaces the other when defined under the same name
+    fn same_type(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Global { ty: a, .. }, Self::Global { ty: b, .. }) => a == b,
+            (Self::Table { ty: a, .. }, Self::Table { ty: b, .. }) => a == b,
+            (Self::Memory { ty: a }, Self::Memory { ty: b }) => a == b,
+            (Self::Function(a), Self::Function(b)) => a.as_ref().map(Function::ty) == b.as_ref().map(Function::ty),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
@@ -210,7 +308,200 @@ impl From<&Import> for ExternName {
 
 // #[derive(Clone)]
 pub struct Imports {
-    values: BTreeMap<ExternName, Extern>,
+    values: BTreeMap<ExternName, Vec<Extern>>,
+    stubs: Option<StubBehavior>,
+    quotas: BTreeMap<ExternName, ImportQuota>,
+    #[cfg(feature = "std")]
+    output: Option<crate::output::OutputCapture>,
+}
+
+/// What generated stubs for unresolved function imports do when called, see [`Imports::allow_unresolved`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StubBehavior {
+    /// Abort the execution with a [`Trap::HostError`](crate::error::Trap::HostError) naming the import
+    Trap,
+    /// Return the default value (zero or null) for every result
+    ReturnZeroes,
+}
+
+/// Limits on how often the guest calls a function import and how much data it passes to it, see [`Imports::set_quota`]
+///
+/// A quota without limits only meters the import, see [`Instance::import_usage`](crate::Instance::import_usage).
+///
+/// ```
+/// # use reef_interpreter::imports::{ImportQuota, StubBehavior};
+/// // `reef.log(ptr, len)`: at most 100 calls per quantum and 1 MiB in total, dropping what's over the limit
+/// let quota = ImportQuota::new()
+///     .with_max_calls(Some(100))
+///     .with_max_bytes(1, Some(1 << 20))
+///     .with_on_exceeded(StubBehavior::ReturnZeroes);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportQuota {
+    /// Maximum number of calls during one quantum, a single call to [`ExecHandle::run`](crate::exec::ExecHandle::run)
+    ///
+    /// [`ExecHandle::run_for`](crate::exec::ExecHandle::run_for) counts as one quantum, [`ExecHandle::step`]
+    /// doesn't start a new one.
+    ///
+    /// [`ExecHandle::step`]: crate::exec::ExecHandle::step
+    pub max_calls: Option<u32>,
+    /// The parameter holding the number of bytes passed to the import, like `len` of `reef.log(ptr, len)`
+    ///
+    /// Bytes are only counted if this is set to an `i32` or `i64` parameter, which is read as unsigned.
+    pub len_param: Option<usize>,
+    /// Maximum number of bytes passed to the import over the lifetime of the instance
+    pub max_bytes: Option<u64>,
+    /// What calls beyond a limit do, they never reach the host function
+    ///
+    /// [`StubBehavior::Trap`] aborts the execution with [`Trap::ImportQuotaExceeded`].
+    pub on_exceeded: StubBehavior,
+}
+
+impl Default for ImportQuota {
+    fn default() -> Self {
+        Self { max_calls: None, len_param: None, max_bytes: None, on_exceeded: StubBehavior::Trap }
+    }
+}
+
+impl ImportQuota {
+    /// Create a quota without limits that traps once a limit is set and exceeded
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set [`ImportQuota::max_calls`]
+    pub fn with_max_calls(mut self, max_calls: Option<u32>) -> Self {
+        self.max_calls = max_calls;
+        self
+    }
+
+    /// Set [`ImportQuota::max_bytes`], counting the bytes given by parameter `len_param`
+    pub fn with_max_bytes(mut self, len_param: usize, max_bytes: Option<u64>) -> Self {
+        self.len_param = Some(len_param);
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Set [`ImportQuota::on_exceeded`]
+    pub fn with_on_exceeded(mut self, behavior: StubBehavior) -> Self {
+        self.on_exceeded = behavior;
+        self
+    }
+}
+
+/// How much the guest used a function import with a quota, see [`Instance::import_usage`](crate::Instance::import_usage)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportUsage {
+    /// Number of calls that reached the host function
+    pub calls: u64,
+    /// Number of bytes passed to the host function, see [`ImportQuota::len_param`]
+    pub bytes: u64,
+    /// Number of calls that exceeded a limit
+    pub rejected: u64,
+}
+
+#[derive(Debug, Clone)]
+struct QuotaState {
+    name: ExternName,
+    quota: ImportQuota,
+    usage: ImportUsage,
+    /// Calls during the current quantum
+    quantum_calls: u32,
+}
+
+/// The quotas of an instance's function imports and how much of them is used
+///
+/// Imports of the same name share their state, even if they have different types.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ImportQuotas {
+    funcs: BTreeMap<FuncAddr, usize>,
+    states: Vec<QuotaState>,
+}
+
+impl ImportQuotas {
+    pub(crate) fn add(&mut self, func: FuncAddr, import: &Import, quota: ImportQuota) {
+        let name = ExternName::from(import);
+        let idx = match self.states.iter().position(|state| state.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.states.push(QuotaState { name, quota, usage: ImportUsage::default(), quantum_calls: 0 });
+                self.states.len() - 1
+            }
+        };
+        self.funcs.insert(func, idx);
+    }
+
+    /// Start a new quantum, resetting the call counts
+    pub(crate) fn start_quantum(&mut self) {
+        self.states.iter_mut().for_each(|state| state.quantum_calls = 0);
+    }
+
+    pub(crate) fn usage(&self, module: &str, name: &str) -> Option<ImportUsage> {
+        let state = self.states.iter().find(|state| state.name.module == module && state.name.name == name)?;
+        Some(state.usage)
+    }
+
+    /// Count a call to `func` with `params`, returning whether it may reach the host function
+    #[inline]
+    pub(crate) fn check(&mut self, func: FuncAddr, params: &[WasmValue]) -> Result<bool> {
+        let Some(&idx) = self.funcs.get(&func) else {
+            return Ok(true);
+        };
+
+        let state = &mut self.states[idx];
+        let len = match state.quota.len_param.and_then(|idx| params.get(idx)) {
+            Some(WasmValue::I32(len)) => *len as u32 as u64,
+            Some(WasmValue::I64(len)) => *len as u64,
+            _ => 0,
+        };
+        let calls_exceeded = state.quota.max_calls.is_some_and(|max| state.quantum_calls >= max);
+        let bytes_exceeded = state.quota.max_bytes.is_some_and(|max| state.usage.bytes.saturating_add(len) > max);
+        if calls_exceeded || bytes_exceeded {
+            state.usage.rejected += 1;
+            return match state.quota.on_exceeded {
+                StubBehavior::Trap => {
+                    Err(Trap::ImportQuotaExceeded { module: state.name.module.clone(), name: state.name.name.clone() }
+                        .into())
+                }
+                StubBehavior::ReturnZeroes => Ok(false),
+            };
+        }
+
+        state.quantum_calls += 1;
+        state.usage.calls += 1;
+        state.usage.bytes += len;
+        Ok(true)
+    }
+}
+
+/// Builder for the imports of one module, see [`Imports::namespace`]
+#[derive(Debug)]
+pub struct Namespace<'a> {
+    imports: &'a mut Imports,
+    module: String,
+}
+
+impl<'a> Namespace<'a> {
+    /// Define an import of this module
+    pub fn define(self, name: &str, value: Extern) -> Self {
+        let name = ExternName { module: self.module.clone(), name: name.to_string() };
+        self.imports.insert(name, value);
+        self
+    }
+
+    /// Define a typed function import of this module, see [`Extern::typed_func`]
+    pub fn func<P, R>(self, name: &str, func: impl FnMut(FuncContext<'_>, P) -> Result<R> + 'static) -> Self
+    where
+        P: FromWasmValueTuple + ValTypesFromTuple,
+        R: IntoWasmValueTuple + ValTypesFromTuple + Debug,
+    {
+        self.define(name, Extern::typed_func(func))
+    }
+
+    /// Go back to the import set to define other modules
+    pub fn finish(self) -> &'a mut Imports {
+        self.imports
+    }
 }
 
 pub(crate) struct ResolvedImports {
@@ -229,24 +520,140 @@ impl ResolvedImports {
 impl Imports {
     /// Create a new empty import set
     pub fn new() -> Self {
-        Imports { values: BTreeMap::new() }
+        Self::default()
     }
 
     /// Merge two import sets
     pub fn merge(mut self, other: Self) -> Self {
-        self.values.extend(other.values);
+        self.extend(other);
+        self
+    }
+
+    /// Add all imports of `other`, replacing imports with the same name and type
+    ///
+    /// Lets hosts compose reusable import bundles. If `other` allows unresolved imports and this set doesn't,
+    /// its stub behavior is used.
+    pub fn extend(&mut self, other: Self) -> &mut Self {
+        for (name, values) in other.values {
+            values.into_iter().for_each(|value| self.insert(name.clone(), value));
+        }
+        self.stubs = self.stubs.or(other.stubs);
+        self.quotas.extend(other.quotas);
+        #[cfg(feature = "std")]
+        {
+            self.output = self.output.take().or(other.output);
+        }
+        self
+    }
+
+    /// Define several imports of the module `module` by chaining calls on the returned builder
+    pub fn namespace(&mut self, module: &str) -> Namespace<'_> {
+        Namespace { imports: self, module: module.to_string() }
+    }
+
+    /// Fill function imports that weren't defined with stubs matching their signature
+    ///
+    /// Lets modules import optional functionality they never use (like WASI functions) without
+    /// having to define each of them. Other kinds of imports still have to be defined.
+    pub fn allow_unresolved(&mut self, behavior: StubBehavior) -> &mut Self {
+        self.stubs = Some(behavior);
+        self
+    }
+
+    /// Limit how often the guest calls the function import `module.name` and how much data it passes to it
+    ///
+    /// Quotas are enforced by the interpreter for calls from the guest, whichever host function the import is
+    /// linked to, including the ones of [`Imports::allow_unresolved`]. Usage is kept per instance: forks get a copy
+    /// of it and instances restored from a snapshot start from zero. Setting a quota again replaces it.
+    pub fn set_quota(&mut self, module: &str, name: &str, quota: ImportQuota) -> &mut Self {
+        self.quotas.insert(ExternName { module: module.to_string(), name: name.to_string() }, quota);
+        self
+    }
+
+    /// The quota of `import`, see [`Imports::set_quota`]
+    pub(crate) fn quota(&self, import: &Import) -> Option<ImportQuota> {
+        self.quotas.get(&ExternName::from(import)).copied()
+    }
+
+    /// Define the imports guests commonly print through, writing what they print to the sinks of `capture`
+    ///
+    /// See [`output`](crate::output) for the imports this covers. Definitions of the same imports with
+    /// [`Imports::define`] take precedence, and unresolved imports are only stubbed if they aren't covered.
+    #[cfg(feature = "std")]
+    pub fn capture_output(&mut self, capture: crate::output::OutputCapture) -> &mut Self {
+        self.output = Some(capture);
         self
     }
 
     /// Define an import
+    ///
+    /// Modules may import the same name several times with different types, so a value only replaces an earlier
+    /// definition of the same name if both have the same type. Each import is then linked to the definition
+    /// matching its type.
     pub fn define(&mut self, module: &str, name: &str, value: Extern) -> Result<&mut Self> {
-        self.values.insert(ExternName { module: module.to_string(), name: name.to_string() }, value);
+        self.insert(ExternName { module: module.to_string(), name: name.to_string() }, value);
         Ok(self)
     }
 
-    pub(crate) fn take(&mut self, import: &Import) -> Option<Extern> {
+    fn insert(&mut self, name: ExternName, value: Extern) {
+        let values = self.values.entry(name).or_default();
+        match values.iter_mut().find(|other| other.same_type(&value)) {
+            Some(other) => *other = value,
+            None => values.push(value),
+        }
+    }
+
+    /// The definition to link `import` to, with `ty` being the type of function imports
+    ///
+    /// Functions can be imported any number of times, other values are removed once they're taken.
+    pub(crate) fn take(&mut self, import: &Import, ty: Option<&FuncType>) -> Option<Extern> {
         let name = ExternName::from(import);
-        self.values.remove(&name)
+        let Some(values) = self.values.get_mut(&name) else {
+            #[cfg(feature = "std")]
+            if let Some(func) = self.output.as_ref().and_then(|output| output.func(import, ty?)) {
+                return Some(func);
+            }
+            return Some(Self::stub(self.stubs?, import, ty?.clone()));
+        };
+
+        // without a definition of the right type, linking the first one reports the mismatch
+        let idx = values.iter().position(|value| Self::matches(import, value, ty)).unwrap_or(0);
+        if let Extern::Function(Some(func)) = &values[idx] {
+            return Some(Extern::Function(Some(func.clone())));
+        }
+
+        let value = values.remove(idx);
+        if values.is_empty() {
+            self.values.remove(&name);
+        }
+        Some(value)
+    }
+
+    fn matches(import: &Import, value: &Extern, ty: Option<&FuncType>) -> bool {
+        match (value, &import.kind) {
+            (Extern::Global { ty, .. }, ImportKind::Global(import_ty)) => ty == import_ty,
+            (Extern::Table { ty, .. }, ImportKind::Table(import_ty)) => {
+                Self::compare_table_types(import, ty, import_ty).is_ok()
+            }
+            (Extern::Memory { ty }, ImportKind::Memory(import_ty)) => {
+                Self::compare_memory_types(import, ty, import_ty, None).is_ok()
+            }
+            (Extern::Function(Some(func)), ImportKind::Function(_)) => Some(func.ty()) == ty,
+            _ => false,
+        }
+    }
+
+    fn stub(behavior: StubBehavior, import: &Import, ty: FuncType) -> Extern {
+        match behavior {
+            StubBehavior::Trap => {
+                let message = format!("called unresolved import {}.{}", import.module, import.name);
+                Extern::func(&ty, move |_, _| Err(Error::host_trap(message.clone())))
+            }
+            StubBehavior::ReturnZeroes => {
+                let results: Vec<WasmValue> = ty.results.iter().map(|ty| ty.default_value()).collect();
+                Extern::func(&ty, move |_, _| Ok(results.clone()))
+            }
+        }
     }
 
     pub(crate) fn compare_types<T: Debug + PartialEq>(import: &Import, actual: &T, expected: &T) -> Result<()> {
@@ -305,3 +712,159 @@ impl Imports {
 
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Trap;
+    use crate::exec::CallResult;
+    use crate::{parse_bytes, Instance};
+
+    #[test]
+    fn test_unresolved_stubs() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "wasi" "fd_close" (func $close (param i32) (result i32)))
+                (func (export "run") (result i32) (call $close (i32.const 3))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let run = |behavior| {
+            let mut imports = Imports::new();
+            imports.allow_unresolved(behavior);
+            let instance = Instance::instantiate(module.clone(), imports).unwrap();
+            instance.exported_func_untyped("run").unwrap().call(Vec::new(), None).unwrap().run(usize::MAX)
+        };
+
+        let res = run(StubBehavior::ReturnZeroes);
+        assert!(matches!(res, Ok(CallResult::Done(res)) if res == [WasmValue::I32(0)]));
+
+        let Err(Error::Trap(Trap::HostError { message, .. })) = run(StubBehavior::Trap) else {
+            panic!("expected a trap")
+        };
+        assert_eq!(message, "called unresolved import wasi.fd_close");
+
+        let wasm = wat::parse_str(r#"(module (import "env" "g" (global i32)))"#).unwrap();
+        let mut imports = Imports::new();
+        imports.allow_unresolved(StubBehavior::Trap);
+        let err = Instance::instantiate(parse_bytes(&wasm).unwrap(), imports).unwrap_err();
+        assert!(matches!(err, Error::ImportUnresolved { .. }));
+    }
+
+    #[test]
+    fn test_namespace() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "reef" "log" (func $log (param i32)))
+                (import "reef" "progress" (func $progress (param i32 i32) (result i32)))
+                (import "env" "base" (global $base i32))
+                (func (export "run") (result i32)
+                    (call $log (i32.const 1))
+                    (i32.add (global.get $base) (call $progress (i32.const 2) (i32.const 3)))))
+            "#,
+        )
+        .unwrap();
+
+        let mut bundle = Imports::new();
+        bundle.define("env", "base", Extern::global(WasmValue::I32(100), false)).unwrap();
+        let mut imports = Imports::new();
+        imports
+            .namespace("reef")
+            .func("log", |_, _: i32| Ok(()))
+            .func("progress", |_, (done, total): (i32, i32)| Ok(done * 10 + total))
+            .finish()
+            .extend(bundle);
+
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), imports).unwrap();
+        let res = instance.exported_func_untyped("run").unwrap().call(Vec::new(), None).unwrap().run(usize::MAX);
+        assert!(matches!(res, Ok(CallResult::Done(res)) if res == [WasmValue::I32(123)]));
+    }
+
+    #[test]
+    fn test_duplicate_imports() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "get" (func $get (result i32)))
+                (import "env" "get" (func $get_arg (param i32) (result i32)))
+                (import "env" "get" (func $get_again (result i32)))
+                (import "env" "get" (global $get i32))
+                (func (export "run") (result i32)
+                    (i32.add
+                        (i32.add (call $get) (call $get_arg (i32.const 20)))
+                        (i32.add (call $get_again) (global.get $get)))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+
+        let mut imports = Imports::new();
+        imports
+            .namespace("env")
+            .func("get", |_, ()| Ok(0))
+            .func("get", |_, arg: i32| Ok(arg * 10))
+            .define("get", Extern::global(WasmValue::I32(1000), false))
+            // replaces the first definition, which has the same type
+            .func("get", |_, ()| Ok(1));
+
+        let instance = Instance::instantiate(module.clone(), imports).unwrap();
+        let res = instance.exported_func_untyped("run").unwrap().call(Vec::new(), None).unwrap().run(usize::MAX);
+        assert!(matches!(res, Ok(CallResult::Done(res)) if res == [WasmValue::I32(1202)]));
+
+        let mut imports = Imports::new();
+        imports.namespace("env").func("get", |_, ()| Ok(1)).define("get", Extern::global(WasmValue::I32(0), false));
+        let err = Instance::instantiate(module, imports).unwrap_err();
+        assert!(matches!(err, Error::ImportSignatureMismatch { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_quota() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "reef" "log" (func $log (param i32 i32) (result i32)))
+                (func (export "run") (param $n i32) (result i32) (local $sum i32)
+                    (loop $loop
+                        (local.set $sum (i32.add (local.get $sum) (call $log (i32.const 0) (i32.const 10))))
+                        (br_if $loop (local.tee $n (i32.sub (local.get $n) (i32.const 1)))))
+                    (local.get $sum)))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let instantiate = |quota| {
+            let mut imports = Imports::new();
+            imports.namespace("reef").func("log", |_, (_ptr, len): (i32, i32)| Ok(len));
+            imports.set_quota("reef", "log", quota);
+            Instance::instantiate(module.clone(), imports).unwrap()
+        };
+        let run = |instance: Instance, n: i32| {
+            let mut exec =
+                instance.exported_func_untyped("run").unwrap().call(alloc::vec![WasmValue::I32(n)], None).unwrap();
+            let res = exec.run(usize::MAX);
+            (exec.into_instance(), res)
+        };
+
+        let quota = ImportQuota::new()
+            .with_max_calls(Some(3))
+            .with_max_bytes(1, Some(45))
+            .with_on_exceeded(StubBehavior::ReturnZeroes);
+        let (instance, res) = run(instantiate(quota), 5);
+        assert!(matches!(res, Ok(CallResult::Done(res)) if res == [WasmValue::I32(30)]));
+        assert_eq!(instance.import_usage("reef", "log"), Some(ImportUsage { calls: 3, bytes: 30, rejected: 2 }));
+
+        // a new run starts a new quantum, but the bytes add up
+        let (instance, res) = run(instance, 2);
+        assert!(matches!(res, Ok(CallResult::Done(res)) if res == [WasmValue::I32(10)]));
+        assert_eq!(instance.import_usage("reef", "log"), Some(ImportUsage { calls: 4, bytes: 40, rejected: 3 }));
+        assert_eq!(instance.import_usage("reef", "other"), None);
+
+        let (instance, res) = run(instantiate(ImportQuota::new().with_max_calls(Some(1))), 2);
+        let Err(Error::Trap(trap @ Trap::ImportQuotaExceeded { .. })) = res else { panic!("expected a trap") };
+        assert_eq!(trap.to_string(), "import quota exceeded: reef.log");
+        assert_eq!(instance.import_usage("reef", "log"), Some(ImportUsage { calls: 1, bytes: 0, rejected: 1 }));
+    }
+}
@@ -1,32 +1,58 @@
-use alloc::{format, string::ToString, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::cell::{OnceCell, RefCell};
+use core::ops::Bound;
 
-use rkyv::Deserialize;
+use rkyv::AlignedVec;
 
-use crate::error::{Error, LinkingError, Result, Trap};
-use crate::exec::SerializationState;
-use crate::func::{FromWasmValueTuple, FuncHandle, FuncHandleTyped, IntoWasmValueTuple};
-use crate::imports::{Extern, Function, Imports, ResolvedImports};
-use crate::reference::{MemoryRef, MemoryRefMut};
+use crate::error::{Error, LinkingError, Result, SnapshotError, Trap};
+use crate::exec::{CallResult, ExecConfig, ExecHandle, ExecHandleTyped, MemoryGrowEvent, MemoryGrowHook};
+use crate::externs::ExternRefs;
+use crate::func::{FromWasmValueTuple, FuncHandle, FuncHandleTyped, FuncId, GuestParams};
+use crate::imports::{Extern, FuncContext, Function, ImportQuotas, ImportUsage, Imports, ResolvedImports};
+use crate::reference::{GlobalRef, InstanceView, MemoryRef, MemoryRefMut, MemoryView};
 use crate::runtime::{RawWasmValue, Stack};
 use crate::store::{
     data::DataInstance,
     element::ElementInstance,
     global::GlobalInstance,
     memory::MemoryInstance,
-    table::{TableElement, TableInstance},
+    table::{TableElement, TableInstance, LAZY_ELEMENTS_MIN},
+    MemoryBackend, MemoryStats,
 };
+use crate::types::value::WasmValue;
 use crate::types::{
-    instructions::ConstInstruction, Addr, Data, DataAddr, DataKind, ElementItem, ElementKind, ExternVal, FuncAddr,
-    FuncType, Global, GlobalAddr, ImportKind, MemAddr, MemoryArch, MemoryType, Module, TableAddr, TableType,
-    WasmFunction,
+    instructions::ConstInstruction, Addr, DataAddr, DataKind, ElementItem, ElementKind, ExportType, ExternVal,
+    ExternalKind, FuncAddr, FuncType, Global, GlobalAddr, Import, ImportKind, MemAddr, MemoryArch, MemoryType, Module,
+    TableAddr, TableType, WasmFunction,
 };
-use crate::{VecExt, CALL_STACK_SIZE};
+use crate::{unlikely, Fingerprint, VecExt, CALL_STACK_SIZE};
+
+/// Target features a module may require, see [`Module::required_features`]
+const SUPPORTED_FEATURES: &[&str] = &[
+    "bulk-memory",
+    "bulk-memory-opt",
+    "call-indirect-overlong",
+    "multivalue",
+    "mutable-globals",
+    "nontrapping-fptoint",
+    "reference-types",
+    "sign-ext",
+];
 
 /// An instantiated Wasm module on which function can be called
 #[allow(dead_code)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Instance {
-    pub(crate) module: Module,
+    pub(crate) module: Rc<Module>,
+    /// Exports by name, shared between all instances of the module
+    pub(crate) exports: Rc<BTreeMap<Rc<str>, ExternVal>>,
 
     pub(crate) funcs: Vec<Function>,
     pub(crate) tables: Vec<TableInstance>,
@@ -34,54 +60,388 @@ pub struct Instance {
     pub(crate) globals: Vec<GlobalInstance>,
     pub(crate) elements: Vec<ElementInstance>,
     pub(crate) datas: Vec<DataInstance>,
+    pub(crate) externs: ExternRefs,
+    pub(crate) quotas: ImportQuotas,
+
+    pub(crate) grow_hook: Option<MemoryGrowHook>,
+    pub(crate) exec_config: ExecConfig,
+    /// The fingerprint of the module, computed when it's first needed for a snapshot
+    ///
+    /// Shared by all instances with the same `module`, like forks and the instances of a pool.
+    pub(crate) fingerprint: Rc<OnceCell<Fingerprint>>,
 }
 
 impl Instance {
     /// Instantiate the module with the given imports
     pub fn instantiate(module: Module, imports: Imports) -> Result<Self> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("instantiate", funcs = module.funcs.len()).entered();
+
+        let module = Rc::new(module);
+        Self::link(module.clone(), imports)?.finish(module)
+    }
+
+    /// Instantiate the module with the given imports, mapping the data segments in `shared` instead of copying them
+    ///
+    /// `shared` has to be created from the same module, see [`SharedSegments`](crate::SharedSegments), otherwise an
+    /// error is returned. The modules are compared by their [fingerprint](Module::fingerprint).
+    #[cfg(feature = "mmap")]
+    pub fn instantiate_with_shared_segments(
+        module: Module,
+        imports: Imports,
+        shared: &crate::SharedSegments,
+    ) -> Result<Self> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("instantiate", funcs = module.funcs.len()).entered();
+
+        if module.fingerprint()? != shared.fingerprint {
+            return Err(Error::Other("Shared segments were created for a different module".into()));
+        }
+
+        let module = Rc::new(module);
+        let mut linked = Self::link(module.clone(), imports)?;
+        linked.instance.get_mem_mut(0)?.map_shared(shared)?;
+        linked.shared = shared.segments().into();
+        linked.finish(module)
+    }
+
+    /// Parse a module from `reader` and instantiate it with the given imports
+    ///
+    /// Imports are resolved and memories and tables are allocated as soon as the sections before the code section
+    /// have been read, so this overlaps with reading the rest of the module. Everything else is initialized once the
+    /// module is complete, including globals, since their initializers can refer to functions.
+    #[cfg(feature = "std")]
+    pub fn instantiate_streaming(reader: impl std::io::Read, imports: Imports) -> Result<Self> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("instantiate_streaming").entered();
+
+        let mut linked = None;
+        let module = crate::parser::Parser::parse_module_stream(reader, crate::ParserLimits::default(), |header| {
+            linked = Some(Self::link(Rc::new(header), imports)?);
+            Ok(())
+        })?;
+
+        let linked = linked.ok_or_else(|| Error::Other("module header was never read".to_string()))?;
+        linked.finish(Rc::new(module))
+    }
+
+    /// Resolve imports and allocate tables and memories, which only needs the module's header
+    fn link(module: Rc<Module>, imports: Imports) -> Result<Linked> {
         let mut instance = Instance { module, ..Default::default() };
 
         let mut addrs = instance.resolve_imports(imports)?;
-
-        addrs.funcs.extend(instance.init_funcs(instance.module.funcs.clone().into())?);
         addrs.tables.extend(instance.init_tables(instance.module.table_types.clone().into())?);
         addrs.memories.extend(instance.init_memories(instance.module.memory_types.clone().into())?);
 
-        let global_addrs =
-            instance.init_globals(addrs.globals, instance.module.globals.clone().into(), &addrs.funcs)?;
+        Ok(Linked { instance, addrs, shared: Vec::new() })
+    }
 
-        let elem_trapped = instance.init_elements(&addrs.tables, &addrs.funcs, &global_addrs)?;
-        if let Some(trap) = elem_trapped {
-            return Err(Error::Trap(trap));
+    /// Instantiate the module with the given imports, using `config` for all executions started on the instance
+    ///
+    /// Like [`Instance::instantiate`], this doesn't run the start function.
+    pub fn instantiate_with_config(module: Module, imports: Imports, config: ExecConfig) -> Result<Self> {
+        let mut instance = Self::instantiate(module, imports)?;
+        instance.exec_config = config;
+        Ok(instance)
+    }
+
+    /// Instantiate the module with the given imports and run its start function with a budget of `max_cycles`
+    ///
+    /// Fails with [`Error::StartBudgetExceeded`] if the start function doesn't finish within the budget, use
+    /// [`Instance::instantiate_resumable`] to run it in multiple steps instead.
+    pub fn instantiate_start(module: Module, imports: Imports, max_cycles: usize) -> Result<Self> {
+        let mut handle = Self::instantiate_resumable(module, imports)?;
+        if let CallResult::Exit(code) = handle.run(max_cycles)? {
+            return Err(Error::exit(code));
+        }
+        if !handle.is_done() {
+            return Err(Error::StartBudgetExceeded { max_cycles });
         }
+        handle.into_instance()
+    }
 
-        let data_trapped = instance.init_datas(&addrs.memories, instance.module.data.clone().into())?;
-        if let Some(trap) = data_trapped {
-            return Err(Error::Trap(trap));
+    /// Instantiate the module with the given imports, handling its start function according to `policy`
+    pub fn instantiate_with_start_policy(module: Module, imports: Imports, policy: StartPolicy) -> Result<Self> {
+        match policy {
+            StartPolicy::Skip => Self::instantiate(module, imports),
+            StartPolicy::Run { max_cycles } => Self::instantiate_start(module, imports, max_cycles),
+            StartPolicy::Deny if module.start_func.is_some() => Err(Error::StartDenied),
+            StartPolicy::Deny => Self::instantiate(module, imports),
         }
+    }
 
-        Ok(instance)
+    /// Instantiate the module with the given imports, returning a handle to run its start function
+    pub fn instantiate_resumable(module: Module, imports: Imports) -> Result<InstantiateHandle> {
+        Self::instantiate(module, imports)?.start(None)
+    }
+
+    /// Restore the state serialized with [`InstantiateHandle::serialize`] to continue running the start function
+    pub fn instantiate_resumable_with_state(
+        module: Module,
+        imports: Imports,
+        state: &[u8],
+    ) -> Result<InstantiateHandle> {
+        let (instance, stack) = Self::instantiate_with_state(module, imports, state)?;
+        instance.start(Some(stack))
+    }
+
+    fn start(mut self, stack: Option<Stack>) -> Result<InstantiateHandle> {
+        let Some(addr) = self.module.start_func else {
+            return Ok(InstantiateHandle { state: StartState::Done(Box::new(self)) });
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(func = addr, "start");
+
+        let state = match self.get_func(addr)? {
+            Function::Host(host_func) => {
+                let func = host_func.clone();
+                let mut callback = None;
+                let ctx = FuncContext {
+                    module: &self.module,
+                    memories: &mut self.memories,
+                    externs: &mut self.externs,
+                    callback: &mut callback,
+                };
+                func.call(ctx, &[])?;
+                if unlikely(callback.is_some()) {
+                    return Err(Error::Other("A host start function can't call back into the guest".to_string()));
+                }
+                StartState::Done(Box::new(self))
+            }
+            Function::Wasm(wasm_func) => {
+                let ty = wasm_func.ty.clone();
+                StartState::Running(Box::new(
+                    FuncHandle { instance: self, addr, ty, name: None }.call(Vec::new(), stack)?,
+                ))
+            }
+        };
+        Ok(InstantiateHandle { state })
     }
 
     /// Instantiate the module with the given imports and restore state to resume execution of a function
+    ///
+    /// `state` has to be aligned to 16 bytes, like the [`AlignedVec`] returned by [`ExecHandle::serialize`].
+    /// Snapshots that can't be restored are rejected with [`Error::Snapshot`], telling apart truncated and corrupt
+    /// snapshots and ones taken from another module or by an incompatible version of this crate.
     pub fn instantiate_with_state(module: Module, imports: Imports, state: &[u8]) -> Result<(Self, Stack)> {
-        let mut instance = Self::instantiate(module, imports)?;
+        Self::instantiate_with_state_and_backend(module, imports, state, MemoryBackend::Heap)
+    }
+
+    /// Like [`Instance::instantiate_with_state`], but with the memory stored in `backend`
+    ///
+    /// Snapshots of a memory backed by a file don't contain the memory, so they can only be restored with a
+    /// [`MemoryBackend::File`] of the file holding it, which isn't truncated. Other snapshots are copied to `backend`.
+    pub fn instantiate_with_state_and_backend(
+        module: Module,
+        imports: Imports,
+        state: &[u8],
+        backend: MemoryBackend,
+    ) -> Result<(Self, Stack)> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("restore", state_bytes = state.len()).entered();
+
+        Self::instantiate(module, imports)?.restore(state, backend)
+    }
+
+    /// Like [`Instance::instantiate_with_state`], but restores the state into a [fork](Instance::fork) of this instance
+    ///
+    /// `self` should be freshly instantiated, since the snapshot only contains the memory, globals and stack.
+    /// Unlike instantiating the module again, this doesn't have to compute the module's fingerprint for every
+    /// snapshot, which makes restoring many snapshots of the same module cheaper.
+    pub fn fork_with_state(&self, state: &[u8]) -> Result<(Self, Stack)> {
+        self.fork().restore(state, MemoryBackend::Heap)
+    }
+
+    fn restore(mut self, state: &[u8], backend: MemoryBackend) -> Result<(Self, Stack)> {
+        let corrupt =
+            |message: String| Error::Snapshot(SnapshotError::Corrupt { offset: crate::snapshot::HEADER_LEN, message });
+
+        let mut state = crate::snapshot::read(state, self.fingerprint()?)?;
+        state.stack.validate(&self.funcs).map_err(corrupt)?;
+        if state.globals.len() != self.globals.len() {
+            return Err(corrupt(format!(
+                "{} globals for a module with {} globals",
+                state.globals.len(),
+                self.globals.len()
+            )));
+        }
+        state.stack.call_stack.frames.reserve_exact(CALL_STACK_SIZE);
+
+        match self.memories.first_mut() {
+            Some(mem) => mem.restore(state.memory, backend)?,
+            None if state.memory.is_empty() => {}
+            None => return Err(corrupt("memory contents for a module without a memory".to_string())),
+        }
+        self.globals.iter_mut().zip(state.globals.iter()).for_each(|(g, v)| g.value = *v);
+
+        let mem_size = self.memories.first().map_or(0, |mem| mem.data.len());
+        if let Some(region) =
+            state.stack.param_regions.iter().find(|r| r.ptr == 0 || r.offset() + r.len as usize > mem_size)
+        {
+            return Err(corrupt(format!("parameter region {:#x}+{} outside of memory", region.ptr, region.len)));
+        }
+
+        Ok((self, state.stack))
+    }
+
+    /// The fingerprint of the module, see [`Module::fingerprint`]
+    pub(crate) fn fingerprint(&self) -> Result<Fingerprint> {
+        if let Some(fingerprint) = self.fingerprint.get() {
+            return Ok(*fingerprint);
+        }
+        let fingerprint = self.module.fingerprint()?;
+        Ok(*self.fingerprint.get_or_init(|| fingerprint))
+    }
 
-        let archived = rkyv::check_archived_root::<SerializationState>(state).unwrap();
-        let mut state: SerializationState = archived.deserialize(&mut rkyv::Infallible).unwrap();
-        state.stack.call_stack.0.reserve_exact(CALL_STACK_SIZE);
+    /// Create an independent copy of this instance in its current state
+    ///
+    /// The module, host functions and memory contents are shared with `self` instead of copied. A page of a memory is
+    /// only copied once either instance writes to it, so forking an instance that only writes to a few pages is cheap.
+    pub fn fork(&self) -> Instance {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(memories = self.memories.len(), "fork");
 
-        instance.memories[0].data = state.memory;
-        instance.globals.iter_mut().zip(state.globals.iter()).for_each(|(g, v)| g.value = *v);
+        self.clone()
+    }
+
+    /// Reset all mutable state (memories, globals, tables and segments) to that of `template`
+    fn reset_from(&mut self, template: &Instance) {
+        self.tables.clone_from(&template.tables);
+        self.memories.clone_from(&template.memories);
+        self.globals.clone_from(&template.globals);
+        self.elements.clone_from(&template.elements);
+        self.datas.clone_from(&template.datas);
+        self.externs.clone_from(&template.externs);
+        self.quotas.clone_from(&template.quotas);
+    }
+
+    /// Host objects passed to the guest as opaque handles, see [`ExternRefs`]
+    pub fn externs(&self) -> &ExternRefs {
+        &self.externs
+    }
+
+    /// Host objects passed to the guest as opaque handles, see [`ExternRefs`]
+    pub fn externs_mut(&mut self) -> &mut ExternRefs {
+        &mut self.externs
+    }
 
-        Ok((instance, state.stack))
+    /// Set a hook that is called after every `memory.grow` instruction, whether it succeeded or not
+    ///
+    /// Lets the host watch memory usage as it happens, for example to move a job to another worker before it runs out
+    /// of memory. Forks of this instance share the hook.
+    pub fn set_memory_grow_hook(&mut self, hook: impl FnMut(&MemoryGrowEvent) + 'static) {
+        self.grow_hook = Some(MemoryGrowHook(Rc::new(RefCell::new(hook))));
+    }
+
+    /// Remove the memory grow hook
+    pub fn clear_memory_grow_hook(&mut self) {
+        self.grow_hook = None;
+    }
+
+    /// The configuration for executions started on the instance, see [`Instance::instantiate_with_config`]
+    pub fn exec_config(&self) -> &ExecConfig {
+        &self.exec_config
+    }
+
+    /// Replace the configuration for executions started on the instance from now on
+    ///
+    /// Forks of the instance get a copy of the config.
+    pub fn set_exec_config(&mut self, config: ExecConfig) {
+        self.exec_config = config;
+    }
+
+    /// How much the guest used the function import `module.name`, if it has a quota
+    ///
+    /// See [`Imports::set_quota`], usage is counted since the instance was created.
+    pub fn import_usage(&self, module: &str, name: &str) -> Option<ImportUsage> {
+        self.quotas.usage(module, name)
+    }
+
+    /// Get the current resource usage of the instance
+    ///
+    /// Unlike the host process' memory usage, this only counts what this instance allocated.
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats::new(self)
+    }
+
+    /// Move the contents of the instance's memory to `backend`, see [`MemoryBackend`]
+    ///
+    /// Like serialization, this only applies to the first memory.
+    pub fn set_memory_backend(&mut self, backend: MemoryBackend) -> Result<()> {
+        self.get_mem_mut(0)?.set_backend(backend)
+    }
+
+    /// The module this instance was created from
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Get the signature of an exported function by name
+    ///
+    /// Useful to check a guest's entry point before calling it.
+    pub fn func_type(&self, name: &str) -> Option<&FuncType> {
+        self.module.export_func_type(name)
+    }
+
+    /// Iterate over the exports of the instance with their types, see [`Module::exports`]
+    pub fn exports(&self) -> impl Iterator<Item = ExportType<'_>> + '_ {
+        self.module.exports()
+    }
+
+    /// Call an exported function and run it for up to `max_cycles` without giving up ownership of the instance
+    ///
+    /// If the call doesn't finish within the budget, it's abandoned halfway and its changes to the instance stay.
+    pub(crate) fn call_in_place_for(
+        &mut self,
+        name: &str,
+        params: Vec<WasmValue>,
+        max_cycles: usize,
+    ) -> Result<CallResult> {
+        let Some(ExternVal::Func(addr)) = self.export_addr(name) else {
+            return Err(Error::export_not_found(name, ExternalKind::Func));
+        };
+        let Function::Wasm(func) = self.get_func(addr)? else {
+            return Err(Error::Other("Can't call Host function directly".to_string()));
+        };
+
+        // Check the parameters here, `call` would drop the instance if they don't match
+        let ty = func.ty.clone();
+        if ty.params.len() != params.len() {
+            return Err(Error::ParamCountMismatch { expected: ty.params.len(), got: params.len() });
+        }
+        for (index, (ty, param)) in ty.params.iter().zip(&params).enumerate() {
+            if *ty != param.val_type() {
+                return Err(Error::ParamTypeMismatch { expected: *ty, got: param.val_type(), index });
+            }
+        }
+
+        let name = self.export_name(name);
+        let func = FuncHandle { instance: core::mem::take(self), addr, ty, name };
+        let mut exec = func.call(params, None)?;
+        let res = exec.run(max_cycles);
+        *self = exec.func_handle.instance;
+        res
+    }
+
+    /// Call an exported function and run it to completion, see [`Instance::call_in_place_for`]
+    #[cfg(test)]
+    pub(crate) fn call_in_place(&mut self, name: &str, params: Vec<WasmValue>) -> Result<Vec<WasmValue>> {
+        match self.call_in_place_for(name, params, usize::MAX)? {
+            CallResult::Done(values) => Ok(values),
+            CallResult::Exit(code) => Err(Error::exit(code)),
+            CallResult::Incomplete | CallResult::Breakpoint(_) => Err(Error::FuncDidNotReturn),
+        }
     }
 
     /// Get a export by name
     pub(crate) fn export_addr(&self, name: &str) -> Option<ExternVal> {
-        let export = self.module.exports.iter().find(|e| e.name == name.into())?;
+        self.exports.get(name).cloned()
+    }
 
-        Some(ExternVal::new(export.kind, export.index))
+    /// The interned name of an export, so handles don't need to allocate their own
+    fn export_name(&self, name: &str) -> Option<Rc<str>> {
+        self.exports.get_key_value(name).map(|(name, _)| name.clone())
     }
 
     #[inline]
@@ -91,21 +451,41 @@ impl Instance {
 
     /// Get an exported function by name
     pub fn exported_func_untyped(self, name: &str) -> Result<FuncHandle> {
-        let export = self.export_addr(name).ok_or_else(|| Error::Other(format!("Export not found: {}", name)))?;
+        let FuncId { addr, ty, name } = self.exported_func_id(name)?;
+        Ok(FuncHandle { addr, name, ty, instance: self })
+    }
+
+    /// Look up an exported function by name without giving up the instance, see [`FuncId`]
+    pub fn exported_func_id(&self, name: &str) -> Result<FuncId> {
+        let export = self.export_addr(name).ok_or_else(|| Error::export_not_found(name, ExternalKind::Func))?;
         let ExternVal::Func(func_addr) = export else {
-            return Err(Error::Other(format!("Export is not a function: {}", name)));
+            return Err(Error::export_not_found(name, ExternalKind::Func));
         };
 
-        let func_inst = self.get_func(func_addr)?;
-        let ty = func_inst.ty();
+        let ty = self.get_func(func_addr)?.ty().clone();
+        Ok(FuncId { addr: func_addr, ty, name: self.export_name(name) })
+    }
 
-        Ok(FuncHandle { addr: func_addr, name: Some(name.to_string()), ty: ty.clone(), instance: self })
+    /// Iterate over the exported functions whose names start with `prefix`, sorted by name
+    ///
+    /// Lets hosts discover functions by convention, e.g. a test runner calling every `test_*` export.
+    pub fn exports_matching<'i>(&'i self, prefix: &'i str) -> impl Iterator<Item = FuncId> + 'i {
+        self.exports
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(name, _)| name.starts_with(prefix))
+            .filter_map(|(name, export)| match export {
+                ExternVal::Func(addr) => {
+                    let ty = self.get_func(*addr).ok()?.ty().clone();
+                    Some(FuncId { addr: *addr, ty, name: Some(name.clone()) })
+                }
+                _ => None,
+            })
     }
 
     /// Get a typed exported function by name
     pub fn exported_func<P, R>(self, name: &str) -> Result<FuncHandleTyped<P, R>>
     where
-        P: IntoWasmValueTuple,
+        P: GuestParams,
         R: FromWasmValueTuple,
     {
         let func = self.exported_func_untyped(name)?;
@@ -114,9 +494,9 @@ impl Instance {
 
     /// Get an exported memory by name
     pub fn exported_memory<'i>(&'i self, name: &str) -> Result<MemoryRef<'i>> {
-        let export = self.export_addr(name).ok_or_else(|| Error::Other(format!("Export not found: {}", name)))?;
+        let export = self.export_addr(name).ok_or_else(|| Error::export_not_found(name, ExternalKind::Memory))?;
         let ExternVal::Memory(mem_addr) = export else {
-            return Err(Error::Other(format!("Export is not a memory: {}", name)));
+            return Err(Error::export_not_found(name, ExternalKind::Memory));
         };
 
         self.memory(mem_addr)
@@ -124,14 +504,47 @@ impl Instance {
 
     /// Get an exported memory by name
     pub fn exported_memory_mut<'i>(&'i mut self, name: &str) -> Result<MemoryRefMut<'i>> {
-        let export = self.export_addr(name).ok_or_else(|| Error::Other(format!("Export not found: {}", name)))?;
+        let export = self.export_addr(name).ok_or_else(|| Error::export_not_found(name, ExternalKind::Memory))?;
         let ExternVal::Memory(mem_addr) = export else {
-            return Err(Error::Other(format!("Export is not a memory: {}", name)));
+            return Err(Error::export_not_found(name, ExternalKind::Memory));
         };
 
         self.memory_mut(mem_addr)
     }
 
+    /// Get an exported global by name
+    pub fn exported_global<'i>(&'i mut self, name: &str) -> Result<GlobalRef<'i>> {
+        let export = self.export_addr(name).ok_or_else(|| Error::export_not_found(name, ExternalKind::Global))?;
+        let ExternVal::Global(global_addr) = export else {
+            return Err(Error::export_not_found(name, ExternalKind::Global));
+        };
+
+        let global = self.globals.get_mut_or_instance(global_addr, "global")?;
+        Ok(GlobalRef { instance: global })
+    }
+
+    /// Take a read-only view of the exported memories and globals in their current state
+    ///
+    /// Unlike the instance, the view can be sent to another thread, e.g. to inspect a paused guest while the
+    /// scheduler decides whether to resume it. Memories are shared with the instance until either writes to them,
+    /// like with [`Instance::fork`], so the view doesn't change when execution continues.
+    pub fn freeze(&self) -> InstanceView {
+        let mut view = InstanceView::default();
+        for (name, export) in self.exports.iter() {
+            match export {
+                ExternVal::Memory(addr) => {
+                    view.memories
+                        .insert(name.to_string(), MemoryView { instance: self.memories[*addr as usize].clone() });
+                }
+                ExternVal::Global(addr) => {
+                    view.globals.insert(name.to_string(), self.globals[*addr as usize].get());
+                }
+                ExternVal::Func(_) | ExternVal::Table(_) => {}
+            }
+        }
+        view
+    }
+
     /// Get a memory by address
     pub(crate) fn memory(&self, addr: MemAddr) -> Result<MemoryRef<'_>> {
         let mem = self.get_mem(addr)?;
@@ -197,50 +610,125 @@ impl Instance {
     #[inline]
     pub(crate) fn set_global_val(&mut self, addr: MemAddr, value: RawWasmValue) -> Result<()> {
         let global = self.globals.get_mut_or_instance(addr, "global")?;
+        // validation rejects this, so only bytecode that skipped it can get here
+        if unlikely(!global.ty.mutable) {
+            return Err(Error::InvalidBytecode(format!("global.set on immutable global {}", addr)));
+        }
         global.value = value;
         Ok(())
     }
 }
 
+/// An instance whose imports are resolved, but that is missing its functions, globals and segments
+struct Linked {
+    instance: Instance,
+    addrs: ResolvedImports,
+    /// Data segments that are already in the memory, see [`SharedSegments`](crate::SharedSegments)
+    shared: Vec<u32>,
+}
+
+impl Linked {
+    /// Initialize everything else from the complete module, which has to have the same header as the linked one
+    fn finish(self, module: Rc<Module>) -> Result<Instance> {
+        if let Some(feature) = module.required_features().find(|f| !SUPPORTED_FEATURES.contains(f)) {
+            return Err(Error::UnsupportedFeature(format!("`{}`, required by the module's target features", feature)));
+        }
+
+        let Linked { mut instance, mut addrs, shared } = self;
+        instance.exports =
+            Rc::new(module.exports.iter().map(|e| (Rc::from(&*e.name), ExternVal::new(e.kind, e.index))).collect());
+        instance.module = module;
+
+        addrs.funcs.extend(instance.init_funcs(instance.module.funcs.clone().into())?);
+
+        let global_addrs =
+            instance.init_globals(addrs.globals, instance.module.globals.clone().into(), &addrs.funcs)?;
+
+        let elem_trapped = instance.init_elements(&addrs.tables, &addrs.funcs, &global_addrs)?;
+        if let Some(trap) = elem_trapped {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(?trap, "element segment initialization trapped");
+            return Err(Error::Trap(trap));
+        }
+
+        let data_trapped = instance.init_datas(&addrs.memories, &shared)?;
+        if let Some(trap) = data_trapped {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(?trap, "data segment initialization trapped");
+            return Err(Error::Trap(trap));
+        }
+
+        Ok(instance)
+    }
+}
+
 impl Instance {
+    /// Resolve all imports of the module, reporting every import that failed to resolve at once
+    ///
+    /// A single failure is returned as is, several are collected into [`Error::Imports`].
     pub(crate) fn resolve_imports(&mut self, mut imports: Imports) -> Result<ResolvedImports> {
         let mut addrs = ResolvedImports::new();
+        let mut errors = Vec::new();
 
-        for import in self.module.imports.iter() {
-            let val = imports.take(import).ok_or_else(|| LinkingError::unknown_import(import))?;
+        let module = self.module.clone();
+        for import in module.imports.iter() {
+            let func_type = match import.kind {
+                ImportKind::Function(ty) => module.func_types.get(ty as usize),
+                _ => None,
+            };
+            let res = match imports.take(import, func_type) {
+                Some(val) => self.resolve_import(import, val, &mut addrs),
+                None => Err(Error::import_unresolved(import)),
+            };
+            let is_func = matches!(import.kind, ImportKind::Function(_));
+            if let (Ok(()), true, Some(quota)) = (&res, is_func, imports.quota(import)) {
+                self.quotas.add(*addrs.funcs.last().expect("function import was just added"), import, quota);
+            }
+            if let Err(err) = res {
+                errors.push(err);
+            }
+        }
 
-            // A link to something that needs to be added to the store
-            match (val, &import.kind) {
-                (Extern::Global { ty, val }, ImportKind::Global(import_ty)) => {
-                    Imports::compare_types(import, &ty, import_ty)?;
-                    addrs.globals.push(self.globals.add(GlobalInstance::new(ty, val.into())) as u32);
-                }
-                (Extern::Table { ty, .. }, ImportKind::Table(import_ty)) => {
-                    Imports::compare_table_types(import, &ty, import_ty)?;
-                    addrs.tables.push(self.tables.add(TableInstance::new(ty)) as u32);
-                }
-                (Extern::Memory { ty }, ImportKind::Memory(import_ty)) => {
-                    Imports::compare_memory_types(import, &ty, import_ty, None)?;
-                    if let MemoryArch::I64 = ty.arch {
-                        return Err(Error::UnsupportedFeature("64-bit memories".to_string()));
-                    }
-                    addrs.memories.push(self.memories.add(MemoryInstance::new(ty)) as u32);
+        match errors.len() {
+            0 => Ok(addrs),
+            1 => Err(errors.remove(0)),
+            _ => Err(Error::Imports(errors)),
+        }
+    }
+
+    fn resolve_import(&mut self, import: &Import, val: Extern, addrs: &mut ResolvedImports) -> Result<()> {
+        // A link to something that needs to be added to the store
+        match (val, &import.kind) {
+            (Extern::Global { ty, val }, ImportKind::Global(import_ty)) => {
+                Imports::compare_types(import, &ty, import_ty)?;
+                addrs.globals.push(self.globals.add(GlobalInstance::new(ty, val.into())) as u32);
+            }
+            (Extern::Table { ty, .. }, ImportKind::Table(import_ty)) => {
+                Imports::compare_table_types(import, &ty, import_ty)?;
+                addrs.tables.push(self.tables.add(TableInstance::new(ty)) as u32);
+            }
+            (Extern::Memory { ty }, ImportKind::Memory(import_ty)) => {
+                Imports::compare_memory_types(import, &ty, import_ty, None)?;
+                if let MemoryArch::I64 = ty.arch {
+                    return Err(Error::UnsupportedFeature("64-bit memories".to_string()));
                 }
-                (Extern::Function(Some(extern_func)), ImportKind::Function(ty)) => {
-                    let import_func_type = self
-                        .module
-                        .func_types
-                        .get(*ty as usize)
-                        .ok_or_else(|| LinkingError::incompatible_import_type(import))?;
-
-                    Imports::compare_types(import, extern_func.ty(), import_func_type)?;
-                    addrs.funcs.push(self.funcs.add(extern_func) as u32);
+                addrs.memories.push(self.memories.add(MemoryInstance::new(ty)) as u32);
+            }
+            (Extern::Function(Some(extern_func)), ImportKind::Function(ty)) => {
+                let import_func_type = self
+                    .module
+                    .func_types
+                    .get(*ty as usize)
+                    .ok_or_else(|| LinkingError::incompatible_import_type(import))?;
+
+                if extern_func.ty() != import_func_type {
+                    return Err(Error::import_signature_mismatch(import, import_func_type, extern_func.ty()));
                 }
-                _ => return Err(LinkingError::incompatible_import_type(import).into()),
+                addrs.funcs.push(self.funcs.add(extern_func) as u32);
             }
+            _ => return Err(LinkingError::incompatible_import_type(import).into()),
         }
-
-        Ok(addrs)
+        Ok(())
     }
 
     /// Add functions to the store, returning their addresses in the store
@@ -248,7 +736,7 @@ impl Instance {
         let func_count = self.funcs.len();
         let mut func_addrs = Vec::with_capacity(func_count);
         for (i, func) in funcs.into_iter().enumerate() {
-            self.funcs.push(Function::Wasm(func));
+            self.funcs.push(Function::Wasm(Rc::new(func)));
             func_addrs.push((i + func_count) as FuncAddr);
         }
         Ok(func_addrs)
@@ -335,7 +823,33 @@ impl Instance {
     ) -> Result<Option<Trap>> {
         // let elem_count = self.elements.len();
         // let mut elem_addrs = Vec::with_capacity(elem_count);
-        for (i, element) in self.module.elements.iter().enumerate() {
+        let funcs_are_addrs = func_addrs.iter().enumerate().all(|(idx, addr)| *addr as usize == idx);
+        let module = self.module.clone();
+        for (i, element) in module.elements.iter().enumerate() {
+            // huge active segments (like vtables) are resolved when they're used instead, see `TableInstance::init_lazy`
+            let lazy = funcs_are_addrs
+                && element.items.len() >= LAZY_ELEMENTS_MIN
+                && element.items.iter().all(|item| {
+                    matches!(
+                        item,
+                        ElementItem::Func(_)
+                            | ElementItem::Expr(ConstInstruction::RefFunc(_) | ConstInstruction::RefNull(_))
+                    )
+                });
+            if let (true, ElementKind::Active { offset, table }) = (lazy, element.kind) {
+                let offset = self.eval_i32_const(&offset)?;
+                let table = table_addrs.get(table as usize).and_then(|addr| self.tables.get_mut(*addr as usize));
+                let Some(table) = table else {
+                    return Err(Error::Other(format!("table not found for element {}", i)));
+                };
+
+                if let Err(Error::Trap(trap)) = table.init_lazy(offset, module.clone(), i) {
+                    return Ok(Some(trap));
+                }
+                self.elements.push(ElementInstance::new(element.kind, None));
+                continue;
+            }
+
             let init = element
                 .items
                 .iter()
@@ -377,13 +891,15 @@ impl Instance {
         Ok(None)
     }
 
-    /// Add data to the store, returning their addresses in the store
-    pub(crate) fn init_datas(&mut self, mem_addrs: &[MemAddr], datas: Vec<Data>) -> Result<Option<Trap>> {
+    /// Add the module's data segments to the store, skipping the `shared` segments that are already in memory
+    pub(crate) fn init_datas(&mut self, mem_addrs: &[MemAddr], shared: &[u32]) -> Result<Option<Trap>> {
         let data_count = self.datas.len();
         let mut data_addrs = Vec::with_capacity(data_count);
-        for (i, data) in datas.into_iter().enumerate() {
+        let module = self.module.clone();
+        for (i, data) in module.data.iter().enumerate() {
             let data_val = match data.kind {
-                DataKind::Active { mem: mem_addr, offset } => {
+                DataKind::Active { .. } if shared.contains(&(i as u32)) => None,
+                DataKind::Active { mem: mem_addr, ref offset } => {
                     // a. Assert: memidx == 0
                     if mem_addr != 0 {
                         return Err(Error::UnsupportedFeature("data segments for non-zero memories".to_string()));
@@ -393,7 +909,7 @@ impl Instance {
                         return Err(Error::Other(format!("memory {} not found for data segment {}", mem_addr, i)));
                     };
 
-                    let offset = self.eval_i32_const(&offset)?;
+                    let offset = self.eval_i32_const(offset)?;
                     let Some(mem) = self.memories.get_mut(*mem_addr as usize) else {
                         return Err(Error::Other(format!("memory {} not found for data segment {}", mem_addr, i)));
                     };
@@ -455,3 +971,667 @@ impl Instance {
         Ok(val)
     }
 }
+
+impl From<FuncHandle> for Instance {
+    fn from(value: FuncHandle) -> Self {
+        value.instance
+    }
+}
+
+impl From<ExecHandle> for Instance {
+    fn from(value: ExecHandle) -> Self {
+        value.func_handle.instance
+    }
+}
+
+impl<R: FromWasmValueTuple> From<ExecHandleTyped<R>> for Instance {
+    fn from(value: ExecHandleTyped<R>) -> Self {
+        ExecHandle::from(value).into()
+    }
+}
+
+/// Identifies a module added to an [`InstancePool`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(usize);
+
+/// A pool of ready-to-use instances
+///
+/// Each module is linked and instantiated once when it is [added](InstancePool::add). Instances handed out by
+/// [`get`](InstancePool::get) are [forks](Instance::fork) of that template, and instances given back with
+/// [`recycle`](InstancePool::recycle) have their memories, globals and tables reset to the state right after
+/// instantiation before they are reused.
+#[derive(Debug, Default)]
+pub struct InstancePool {
+    templates: Vec<Instance>,
+    idle: Vec<Vec<Instance>>,
+}
+
+impl InstancePool {
+    /// Create an empty pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Instantiate `module` with `imports` and add it to the pool
+    pub fn add(&mut self, module: Module, imports: Imports) -> Result<ModuleId> {
+        Ok(self.add_template(Instance::instantiate(module, imports)?))
+    }
+
+    /// Like [`add`](Self::add), but the instances of the module share the data segments in `shared`
+    #[cfg(feature = "mmap")]
+    pub fn add_with_shared_segments(
+        &mut self,
+        module: Module,
+        imports: Imports,
+        shared: &crate::SharedSegments,
+    ) -> Result<ModuleId> {
+        Ok(self.add_template(Instance::instantiate_with_shared_segments(module, imports, shared)?))
+    }
+
+    fn add_template(&mut self, template: Instance) -> ModuleId {
+        self.templates.push(template);
+        self.idle.push(Vec::new());
+        ModuleId(self.templates.len() - 1)
+    }
+
+    /// Make sure at least `count` instances of the module are ready to be handed out
+    pub fn prewarm(&mut self, id: ModuleId, count: usize) {
+        let template = &self.templates[id.0];
+        let idle = &mut self.idle[id.0];
+        let missing = count.saturating_sub(idle.len());
+        idle.extend((0..missing).map(|_| template.fork()));
+    }
+
+    /// The number of instances of the module ready to be handed out
+    pub fn idle(&self, id: ModuleId) -> usize {
+        self.idle[id.0].len()
+    }
+
+    /// Get an instance of the module in its freshly instantiated state
+    ///
+    /// # Panics
+    /// If `id` was not returned by [`add`](InstancePool::add) on this pool.
+    pub fn get(&mut self, id: ModuleId) -> Instance {
+        self.idle[id.0].pop().unwrap_or_else(|| self.templates[id.0].fork())
+    }
+
+    /// Reset an instance and return it to the pool
+    ///
+    /// Accepts anything holding an instance, e.g. a finished [`ExecHandle`]. Returns the id of the module the
+    /// instance belongs to, or `None` if it wasn't created by this pool, in which case it is dropped.
+    pub fn recycle(&mut self, instance: impl Into<Instance>) -> Option<ModuleId> {
+        let mut instance = instance.into();
+        let idx = self.templates.iter().position(|t| Rc::ptr_eq(&t.module, &instance.module))?;
+        instance.reset_from(&self.templates[idx]);
+        self.idle[idx].push(instance);
+        Some(ModuleId(idx))
+    }
+}
+
+/// What to do with a module's start function, see [`Instance::instantiate_with_start_policy`]
+///
+/// The start function runs before the host gets the instance, so it is initialization code the host has no
+/// control over. These policies let it be bounded separately from later calls, or not run at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartPolicy {
+    /// Don't run the start function, like [`Instance::instantiate`]
+    #[default]
+    Skip,
+    /// Run the start function with its own budget, like [`Instance::instantiate_start`]
+    Run {
+        /// The number of instructions the start function may execute
+        max_cycles: usize,
+    },
+    /// Fail with [`Error::StartDenied`] if the module has a start function
+    Deny,
+}
+
+/// An instance whose start function may still be running, see [`Instance::instantiate_resumable`]
+///
+/// Like an [`ExecHandle`], the start function is run in steps of a limited number of cycles and its state can be
+/// serialized to continue later with [`Instance::instantiate_resumable_with_state`].
+#[derive(Debug)]
+pub struct InstantiateHandle {
+    state: StartState,
+}
+
+#[derive(Debug)]
+enum StartState {
+    Running(Box<ExecHandle>),
+    Done(Box<Instance>),
+}
+
+impl InstantiateHandle {
+    /// Run the start function for at most `max_cycles` instructions, see [`ExecHandle::run`]
+    ///
+    /// Returns [`CallResult::Done`] without values once the start function finished. If it returns
+    /// [`CallResult::Exit`], the start function ended the execution and the instance can't be used.
+    pub fn run(&mut self, max_cycles: usize) -> Result<CallResult> {
+        let StartState::Running(exec) = &mut self.state else {
+            return Ok(CallResult::Done(Vec::new()));
+        };
+
+        let res = exec.run(max_cycles)?;
+        if let CallResult::Done(_) = res {
+            if let StartState::Running(exec) = core::mem::replace(&mut self.state, StartState::Done(Box::default())) {
+                self.state = StartState::Done(Box::new((*exec).into()));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Whether the start function finished or the module has none
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, StartState::Done(_))
+    }
+
+    /// The execution of the start function, e.g. to set breakpoints or a trace hook
+    ///
+    /// Returns `None` once it is done.
+    pub fn exec_handle_mut(&mut self) -> Option<&mut ExecHandle> {
+        match &mut self.state {
+            StartState::Running(exec) => Some(exec.as_mut()),
+            StartState::Done(_) => None,
+        }
+    }
+
+    /// Get the instance, failing if the start function hasn't finished yet
+    pub fn into_instance(self) -> Result<Instance> {
+        match self.state {
+            StartState::Done(instance) => Ok(*instance),
+            StartState::Running(_) => Err(Error::Other("start function has not finished".to_string())),
+        }
+    }
+
+    /// Serialize the state of the running start function, see [`ExecHandle::serialize`]
+    pub fn serialize(&mut self, buf: AlignedVec) -> Result<AlignedVec> {
+        match &mut self.state {
+            StartState::Running(exec) => exec.serialize(buf),
+            StartState::Done(_) => Err(Error::Other("start function has already finished".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::exec::{CallResultTyped, StackLimits};
+    use crate::test_utils::{instantiate, parse};
+    use crate::types::value::ValType;
+
+    #[test]
+    fn test_memory_stats() {
+        let mut instance = instantiate(
+            r#"
+            (module
+                (memory (export "memory") 2 8)
+                (table 3 funcref)
+                (data (i32.const 0) "reef")
+                (func (export "run")
+                    (drop (memory.grow (i32.const 3)))
+                    (i32.store (i32.const 0x30000) (i32.const 1))))
+            "#,
+            Imports::new(),
+        );
+        let stats = instance.memory_stats();
+        assert_eq!((stats.pages, stats.peak_pages, stats.dirty_pages, stats.table_entries), (2, 2, 1, 3));
+        // only the page holding the data segment is allocated
+        assert!((crate::PAGE_SIZE..2 * crate::PAGE_SIZE).contains(&stats.allocated_bytes));
+
+        instance.call_in_place("run", Vec::new()).unwrap();
+        let stats = instance.memory_stats();
+        assert_eq!((stats.pages, stats.peak_pages, stats.dirty_pages), (5, 5, 2));
+        assert_eq!(stats.touched_bytes(), 2 * crate::PAGE_SIZE);
+        assert!((2 * crate::PAGE_SIZE..3 * crate::PAGE_SIZE).contains(&stats.allocated_bytes));
+    }
+
+    #[test]
+    fn test_memory_grow_hook() {
+        let mut instance = instantiate(
+            r#"
+            (module
+                (memory 1 2)
+                (func (export "run") (result i32)
+                    (drop (memory.grow (i32.const 1)))
+                    (drop (memory.grow (i32.const 1)))
+                    (memory.grow (i32.const -1))))
+            "#,
+            Imports::new(),
+        );
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_hook = events.clone();
+        instance.set_memory_grow_hook(move |event| events_hook.borrow_mut().push(*event));
+
+        assert_eq!(instance.call_in_place("run", Vec::new()).unwrap(), [WasmValue::I32(-1)]);
+        assert_eq!(instance.memory_stats().pages, 2);
+
+        let event = |old_pages, new_pages, success| MemoryGrowEvent { mem: 0, old_pages, new_pages, success };
+        assert_eq!(*events.borrow(), [event(1, 2, true), event(2, 3, false), event(2, 2 + u32::MAX as usize, false)]);
+    }
+
+    #[test]
+    fn test_instance_exec_config() {
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                (func $rec (export "rec") (param i32) (result i32)
+                    (if (result i32) (local.get 0)
+                        (then (call $rec (i32.sub (local.get 0) (i32.const 1))))
+                        (else (i32.const 0))))
+                (func (export "grow") (result i32)
+                    (drop (memory.grow (i32.const 1)))
+                    (memory.grow (i32.const 1))))
+            "#,
+        );
+        let limits = StackLimits { max_call_frames: 8, ..Default::default() };
+        let config =
+            ExecConfig::new().with_stack_limits(limits).with_fuel_check_interval(100).with_max_memory_pages(Some(2));
+        let instance = Instance::instantiate_with_config(module, Imports::new(), config).unwrap();
+        assert_eq!(instance.fork().exec_config(), &config);
+
+        let mut exec = instance.exported_func::<i32, i32>("rec").unwrap().call(20, None).unwrap();
+        assert_eq!(exec.exec_handle.fuel_check_interval(), 100);
+        assert!(matches!(exec.run(usize::MAX), Err(Error::Trap(Trap::StackExhausted { max: 8, .. }))));
+
+        let mut instance = exec.into_instance().unwrap();
+        assert_eq!(instance.call_in_place("grow", Vec::new()).unwrap(), [WasmValue::I32(-1)]);
+        assert_eq!(instance.memory_stats().pages, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_instantiate_streaming() {
+        // hands out the module a few bytes at a time, like a slow connection
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(3);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "base" (global $base i32))
+                (memory (export "memory") 1)
+                (table 1 funcref)
+                (global $double (mut i32) (i32.const 0))
+                (elem (i32.const 0) $twice)
+                (func $twice (param i32) (result i32) (i32.mul (local.get 0) (i32.const 2)))
+                (func (export "run") (result i32)
+                    (i32.add
+                        (call_indirect (param i32) (result i32) (global.get $base) (i32.const 0))
+                        (i32.load8_u (i32.const 16))))
+                (data (i32.const 16) "\05"))
+            "#,
+        )
+        .unwrap();
+        let imports = || {
+            let mut imports = Imports::new();
+            imports.define("env", "base", Extern::global(WasmValue::I32(20), false)).unwrap();
+            imports
+        };
+
+        let mut instance = Instance::instantiate_streaming(Trickle(&wasm), imports()).unwrap();
+        assert_eq!(instance.call_in_place("run", Vec::new()).unwrap(), [WasmValue::I32(45)]);
+        assert_eq!(instance.module(), &crate::parse_bytes(&wasm).unwrap());
+
+        // errors from linking and parsing are both reported
+        assert!(matches!(
+            Instance::instantiate_streaming(Trickle(&wasm), Imports::new()),
+            Err(Error::ImportUnresolved { .. })
+        ));
+        assert!(matches!(
+            Instance::instantiate_streaming(Trickle(&wasm[..wasm.len() - 2]), imports()),
+            Err(Error::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_names_interned() {
+        let instance = instantiate(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "a") (result i32) (i32.const 1))
+                (func (export "b") (result i32) (i32.const 2)))
+            "#,
+            Imports::new(),
+        );
+
+        let a = instance.clone().exported_func_untyped("a").unwrap();
+        let a_again = instance.fork().exported_func_untyped("a").unwrap();
+        assert_eq!(a.name.as_deref(), Some("a"));
+        assert!(Rc::ptr_eq(a.name.as_ref().unwrap(), a_again.name.as_ref().unwrap()));
+
+        let b = instance.clone().exported_func::<(), i32>("b").unwrap();
+        assert!(matches!(b.call((), None).unwrap().run(usize::MAX), Ok(CallResultTyped::Done(2))));
+        assert!(matches!(instance.clone().exported_func_untyped("memory"), Err(Error::ExportNotFound { .. })));
+        assert!(matches!(instance.exported_func_untyped("c"), Err(Error::ExportNotFound { .. })));
+    }
+
+    #[test]
+    fn test_func_id() {
+        let module = parse(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "load") (param i32) (result i32) (i32.load (local.get 0))))
+            "#,
+        );
+        let mut instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+
+        let load = instance.exported_func_id("load").unwrap().typed::<i32, i32>();
+        assert_eq!(load.func.name(), Some("load"));
+
+        // The instance is still usable between calls
+        for i in 1..4 {
+            instance.exported_memory_mut("memory").unwrap().store(8, 4, &(i * 10i32).to_le_bytes()).unwrap();
+            let mut exec = load.call(instance, 8, None).unwrap();
+            assert!(matches!(exec.run(usize::MAX), Ok(CallResultTyped::Done(v)) if v == i * 10));
+            instance = exec.into_instance().unwrap();
+        }
+
+        // Ids can be bound to other instances of the same module
+        let other = Instance::instantiate(module, Imports::new()).unwrap();
+        let mut exec = load.func.call(other, alloc::vec![WasmValue::I32(8)], None).unwrap();
+        assert!(matches!(exec.run(usize::MAX).unwrap(), CallResult::Done(v) if v == [WasmValue::I32(0)]));
+
+        let mismatched = instantiate(r#"(module (func (export "load") (param i64)))"#, Imports::new());
+        assert!(load.bind(mismatched).is_err());
+    }
+
+    #[test]
+    fn test_exports_matching() {
+        let mut instance = instantiate(
+            r#"
+            (module
+                (global (export "test_global") i32 (i32.const 0))
+                (func (export "test_b") (result i32) (i32.const 2))
+                (func (export "test_a") (result i32) (i32.const 1))
+                (func (export "tes") (result i32) (i32.const 0))
+                (func (export "main") (result i32) (i32.const 0)))
+            "#,
+            Imports::new(),
+        );
+
+        let tests: Vec<_> = instance.exports_matching("test_").collect();
+        assert_eq!(tests.iter().map(|t| t.name().unwrap()).collect::<Vec<_>>(), ["test_a", "test_b"]);
+
+        for (expected, test) in (1..).zip(tests) {
+            let mut exec = test.call(instance, Vec::new(), None).unwrap();
+            assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(expected)]));
+            instance = exec.into_instance();
+        }
+        assert_eq!(instance.exports_matching("").count(), 4);
+        assert_eq!(instance.exports_matching("x").count(), 0);
+    }
+
+    #[test]
+    fn test_lazy_elements() {
+        let entries = alloc::vec!["$a $b"; 1000].join(" ");
+        let wat = format!(
+            r#"
+            (module
+                (type $t (func (result i32)))
+                (table $t 4096 funcref)
+                (func $a (result i32) (i32.const 1))
+                (func $b (result i32) (i32.const 2))
+                (elem (i32.const 8) func {entries})
+                (elem (i32.const 0) func $b $a)
+                (func (export "call") (param i32) (result i32) (call_indirect (type $t) (local.get 0)))
+                (func (export "get") (param i32) (result funcref) (table.get $t (local.get 0)))
+                (func (export "set") (param i32) (table.set $t (local.get 0) (table.get $t (i32.const 0)))))
+            "#
+        );
+        let mut instance = instantiate(&wat, Imports::new());
+        let mut call = |name: &str, param: i32| {
+            instance.call_in_place(name, alloc::vec![WasmValue::I32(param)]).map(|res| res.first().copied())
+        };
+
+        assert_eq!(call("call", 0).unwrap(), Some(WasmValue::I32(2)));
+        assert_eq!(call("call", 8).unwrap(), Some(WasmValue::I32(1)));
+        assert_eq!(call("call", 2007).unwrap(), Some(WasmValue::I32(2)));
+        assert_eq!(call("get", 2007).unwrap(), Some(WasmValue::RefFunc(1)));
+        assert_eq!(call("get", 2008).unwrap(), Some(WasmValue::RefNull(ValType::RefFunc)));
+        assert!(matches!(call("call", 2008), Err(Error::Trap(Trap::UninitializedElement { index: 2008 }))));
+
+        // writing to a lazily initialized entry keeps the others
+        call("set", 8).unwrap();
+        assert_eq!(call("call", 8).unwrap(), Some(WasmValue::I32(2)));
+        assert_eq!(call("call", 10).unwrap(), Some(WasmValue::I32(1)));
+
+        // segments that don't fit still trap when instantiating
+        let err =
+            Instance::instantiate(parse(&wat.replace("(elem (i32.const 8)", "(elem (i32.const 3000)")), Imports::new());
+        assert!(matches!(err, Err(Error::Trap(Trap::TableOutOfBounds { offset: 3000, len: 2000, .. }))));
+        // including ones whose end overflows
+        let err =
+            Instance::instantiate(parse(&wat.replace("(elem (i32.const 8)", "(elem (i32.const -1)")), Imports::new());
+        assert!(matches!(err, Err(Error::Trap(Trap::TableOutOfBounds { len: 2000, .. }))));
+    }
+
+    #[test]
+    fn test_fork() {
+        use crate::PAGE_SIZE;
+
+        let mut parent = instantiate(
+            r#"
+            (module
+                (memory (export "memory") 2)
+                (global $counter (export "counter") (mut i32) (i32.const 0))
+                (func (export "bump") (result i32)
+                    (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                    (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+                    (i32.load (i32.const 0))))
+            "#,
+            Imports::new(),
+        );
+        let shared = |parent: &Instance, fork: &Instance, page| {
+            parent.memories[0].data.shares_page(&fork.memories[0].data, page)
+        };
+        let mut fork = parent.fork();
+        assert!(shared(&parent, &fork, 0) && shared(&parent, &fork, 1));
+
+        // only the page that is written to is copied
+        fork.exported_memory_mut("memory").unwrap().store(0, 4, &5i32.to_le_bytes()).unwrap();
+        assert!(!shared(&parent, &fork, 0) && shared(&parent, &fork, 1));
+        let load =
+            |instance: &Instance, addr| instance.exported_memory("memory").unwrap().load(addr, 4).unwrap().to_vec();
+        assert_eq!(load(&parent, 0), [0; 4]);
+        assert_eq!(load(&fork, 0), 5i32.to_le_bytes());
+
+        // writes spanning pages copy both of them
+        fork.exported_memory_mut("memory").unwrap().store(PAGE_SIZE - 2, 4, &[1, 2, 3, 4]).unwrap();
+        assert!(!shared(&parent, &fork, 1));
+        assert_eq!(load(&fork, PAGE_SIZE - 2), [1, 2, 3, 4]);
+        assert_eq!(load(&parent, PAGE_SIZE - 2), [0; 4]);
+
+        assert_eq!(fork.call_in_place("bump", Vec::new()).unwrap(), [WasmValue::I32(6)]);
+        assert_eq!(parent.call_in_place("bump", Vec::new()).unwrap(), [WasmValue::I32(1)]);
+        assert_eq!(parent.globals[0].value, RawWasmValue::from(1i32));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_freeze() {
+        let instance = instantiate(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (global $progress (export "progress") (mut i32) (i32.const 0))
+                (func (export "main")
+                    (loop $l
+                        (global.set $progress (i32.add (global.get $progress) (i32.const 1)))
+                        (i32.store (i32.const 0) (global.get $progress))
+                        (br_if $l (i32.lt_u (global.get $progress) (i32.const 100))))))
+            "#,
+            Imports::new(),
+        );
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(50), Ok(CallResult::Incomplete)));
+
+        let stored = exec.instance().exported_memory("memory").unwrap().load_slice::<i32>(0, 1).unwrap()[0];
+        let view = exec.instance().freeze();
+        let monitor = std::thread::spawn(move || {
+            let WasmValue::I32(progress) = view.exported_global("progress").unwrap() else { panic!("expected an i32") };
+            assert_eq!(view.exported_memory("memory").unwrap().load_slice::<i32>(0, 1).unwrap(), [stored]);
+            assert!(view.exported_global("main").is_err());
+            (view, progress)
+        });
+        let (view, progress) = monitor.join().unwrap();
+        assert!(progress > 0 && progress < 100);
+
+        // the view keeps the state it was taken in
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(_))));
+        assert_eq!(view.exported_global("progress").unwrap(), WasmValue::I32(progress));
+        assert_eq!(view.exported_memory("memory").unwrap().load_slice::<i32>(0, 1).unwrap(), [stored]);
+        assert_eq!(exec.instance().freeze().exported_global("progress").unwrap(), WasmValue::I32(100));
+    }
+
+    #[test]
+    fn test_instance_pool() {
+        let module = parse(
+            r#"
+            (module
+                (memory 1)
+                (global $counter (mut i32) (i32.const 0))
+                (func (export "bump") (result i32)
+                    (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                    (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+                    (i32.add (i32.load (i32.const 0)) (global.get $counter))))
+            "#,
+        );
+        let mut pool = InstancePool::new();
+        let id = pool.add(module.clone(), Imports::new()).unwrap();
+        pool.prewarm(id, 2);
+        assert_eq!(pool.idle(id), 2);
+
+        for _ in 0..3 {
+            let instance = pool.get(id);
+            let mut exec = instance.exported_func_untyped("bump").unwrap().call(Vec::new(), None).unwrap();
+            assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(2)]));
+            assert_eq!(pool.recycle(exec), Some(id));
+        }
+        assert_eq!(pool.idle(id), 2);
+
+        let other = Instance::instantiate(module, Imports::new()).unwrap();
+        assert_eq!(pool.recycle(other), None);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_shared_segments() {
+        use crate::SharedSegments;
+
+        let module = parse(
+            r#"
+            (module
+                (memory 2)
+                (data (i32.const 0x100) "\01\02\03\04")
+                (data (i32.const 0x10000) "\05")
+                (data "\06")
+                (func (export "read") (param i32) (result i32) (i32.load8_u (local.get 0)))
+                (func (export "write") (param i32 i32) (i32.store8 (local.get 0) (local.get 1))))
+            "#,
+        );
+        let path = std::env::temp_dir().join(format!("reef-shared-{}", std::process::id()));
+        assert!(SharedSegments::new(&module, &[2], &path).is_err());
+        let shared = SharedSegments::new(&module, &[0], &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // segments of another module with the same memory size are rejected
+        let other = parse(r#"(module (memory 2) (data (i32.const 0x100) "\09"))"#);
+        assert!(Instance::instantiate_with_shared_segments(other, Imports::new(), &shared).is_err());
+
+        let mut pool = InstancePool::default();
+        let id = pool.add_with_shared_segments(module, Imports::new(), &shared).unwrap();
+        let call = |instance: &mut Instance, name: &str, params: &[i32]| {
+            let params = params.iter().map(|p| WasmValue::I32(*p)).collect();
+            instance.call_in_place(name, params).unwrap().first().copied()
+        };
+
+        // only the page of the segment that isn't shared was written
+        let mut a = pool.get(id);
+        assert_eq!(a.memory_stats().dirty_pages, 1);
+        assert_eq!(call(&mut a, "read", &[0x102]), Some(WasmValue::I32(3)));
+        assert_eq!(call(&mut a, "read", &[0x10000]), Some(WasmValue::I32(5)));
+
+        call(&mut a, "write", &[0x102, 9]);
+        let mut b = pool.get(id);
+        assert_eq!(call(&mut b, "read", &[0x102]), Some(WasmValue::I32(3)));
+        let mut fork = a.fork();
+        assert_eq!(call(&mut fork, "read", &[0x102]), Some(WasmValue::I32(9)));
+        assert_eq!(call(&mut fork, "read", &[0x10000]), Some(WasmValue::I32(5)));
+
+        pool.recycle(a);
+        let mut a = pool.get(id);
+        assert_eq!(call(&mut a, "read", &[0x102]), Some(WasmValue::I32(3)));
+    }
+
+    #[test]
+    fn test_resumable_start() {
+        let module = parse(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (func $init (local $i i32)
+                    (loop $fill
+                        (i32.store8 (local.get $i) (local.get $i))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $fill (i32.lt_u (local.get $i) (i32.const 100)))))
+                (start $init))
+            "#,
+        );
+
+        let err = Instance::instantiate_start(module.clone(), Imports::new(), 10).unwrap_err();
+        assert!(matches!(err, Error::StartBudgetExceeded { max_cycles: 10 }));
+
+        let mut handle = Instance::instantiate_resumable(module.clone(), Imports::new()).unwrap();
+        assert!(matches!(handle.run(50), Ok(CallResult::Incomplete)));
+        assert!(!handle.is_done());
+        let state = handle.serialize(AlignedVec::new()).unwrap();
+
+        let mut handle = Instance::instantiate_resumable_with_state(module.clone(), Imports::new(), &state).unwrap();
+        assert!(matches!(handle.run(usize::MAX), Ok(CallResult::Done(res)) if res.is_empty()));
+        let instance = handle.into_instance().unwrap();
+        assert_eq!(instance.exported_memory("memory").unwrap().load_vec(97, 4).unwrap(), [97, 98, 99, 0]);
+
+        let instance = Instance::instantiate_start(module.clone(), Imports::new(), 1000).unwrap();
+        assert_eq!(instance.exported_memory("memory").unwrap().load(99, 1).unwrap(), [99]);
+
+        let policy = StartPolicy::Run { max_cycles: 1000 };
+        let instance = Instance::instantiate_with_start_policy(module.clone(), Imports::new(), policy).unwrap();
+        assert_eq!(instance.exported_memory("memory").unwrap().load(99, 1).unwrap(), [99]);
+        let instance =
+            Instance::instantiate_with_start_policy(module.clone(), Imports::new(), StartPolicy::Skip).unwrap();
+        assert_eq!(instance.exported_memory("memory").unwrap().load(99, 1).unwrap(), [0]);
+        let err = Instance::instantiate_with_start_policy(module, Imports::new(), StartPolicy::Deny).unwrap_err();
+        assert!(matches!(err, Error::StartDenied));
+
+        let module = parse(r#"(module (func (export "f")))"#);
+        assert!(Instance::instantiate_with_start_policy(module, Imports::new(), StartPolicy::Deny).is_ok());
+    }
+
+    #[test]
+    fn test_host_start() {
+        let called = Rc::new(RefCell::new(false));
+        let mut imports = Imports::new();
+        let host_called = called.clone();
+        let init = Extern::typed_func(move |_, ()| {
+            *host_called.borrow_mut() = true;
+            Ok(())
+        });
+        imports.define("env", "init", init).unwrap();
+
+        let module = parse(r#"(module (import "env" "init" (func $init)) (start $init))"#);
+        let handle = Instance::instantiate_resumable(module, imports).unwrap();
+        assert!(handle.is_done());
+        assert!(*called.borrow());
+    }
+}
@@ -9,7 +9,26 @@
 //!
 //! ## Features
 //!- **`std`**\
-//!  Enables the use of `std` and `std::io` for parsing from files and streams. This is enabled by default.
+//!  Enables the use of `std` and `std::io` for parsing from files and streams, and the [`output`] module to capture
+//!  what guests print. This is enabled by default.
+//!- **`parallel`**\
+//!  Validates and converts function bodies on the [`rayon`](https://docs.rs/rayon) thread pool while parsing.
+//!  Speeds up parsing of large modules. Implies `std`.
+//!- **`tracing`**\
+//!  Emits [`tracing`](https://docs.rs/tracing) spans for parsing, instantiation, calls, runs and serialization,
+//!  and events for traps. Without it, no logging code is compiled in.
+//!- **`debug-server`**\
+//!  Enables the [`debug_server`] module, a Debug Adapter Protocol server for debugging guests from an IDE.
+//!  Implies `std`.
+//!- **`mmap`**\
+//!  Enables [`MemoryBackend::File`], which keeps a guest's memory in a memory-mapped file so snapshots don't copy it,
+//!  and [`SharedSegments`] to share static data between instances. Implies `std`.
+//!- **`serde`**\
+//!  Implements [`serde`](https://docs.rs/serde)'s `Serialize` and `Deserialize` for
+//!  [`WasmValue`](types::value::WasmValue), using the same `i32:42` text format as its `Display` and `FromStr` impls.
+//!- **`spec-tests`**\
+//!  Only used by this crate's own tests: also runs the official WebAssembly spec testsuite, not just the crate's own
+//!  `.wast` files. Implies `std`.
 //!
 //! ## Getting Started
 //! The easiest way to get started is to use the [`Module::parse_bytes`] function to load a
@@ -29,30 +48,48 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+pub mod analysis;
+mod archive;
+#[cfg(feature = "debug-server")]
+pub mod debug_server;
+pub mod disasm;
+mod encoder;
 pub mod error;
 pub mod exec;
+pub mod externs;
 pub mod func;
+mod guest;
 pub mod imports;
 mod instance;
+pub mod mem;
 mod module;
+#[cfg(feature = "std")]
+pub mod output;
 mod parser;
+pub mod profile;
 pub mod reference;
+pub mod replay;
 mod runtime;
+mod snapshot;
 mod store;
+#[cfg(test)]
+mod test_utils;
+pub mod transform;
 pub mod types;
 
-pub use instance::Instance;
-pub use module::parse_bytes;
+pub use archive::Fingerprint;
+pub use guest::{GuestAllocator, GuestRegion};
+pub use instance::{Instance, InstancePool, InstantiateHandle, ModuleId, StartPolicy};
+pub use mem::{MAX_PAGES, PAGE_SIZE};
+pub use module::{parse_bytes, parse_bytes_with_limits};
+pub use parser::{DenyFloats, ParserLimits, ParserSession};
+#[cfg(feature = "mmap")]
+pub use store::SharedSegments;
+pub use store::{MemoryBackend, MemoryStats};
 pub use types::Module;
 
 pub(crate) const CALL_STACK_SIZE: usize = 1024;
 
-/// Max Wasm page size
-pub const PAGE_SIZE: usize = 65536;
-/// Max number of pages for a Wasm module
-pub const MAX_PAGES: usize = 65536;
-const MAX_SIZE: u64 = PAGE_SIZE as u64 * MAX_PAGES as u64;
-
 #[cold]
 pub(crate) fn cold() {}
 
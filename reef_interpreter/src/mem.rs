@@ -0,0 +1,42 @@
+//! Sizes and limits of linear memories
+//!
+//! Memories are sized in pages of [`PAGE_SIZE`] bytes. The helpers convert between byte lengths and page counts the
+//! same way the interpreter does, e.g. to interpret [`MemoryStats`](crate::MemoryStats).
+
+use core::ops::Range;
+
+/// The size of a Wasm page in bytes
+pub const PAGE_SIZE: usize = 65536;
+/// The largest number of pages a memory can have
+pub const MAX_PAGES: usize = 65536;
+/// The largest size of a memory in bytes
+pub(crate) const MAX_SIZE: u64 = PAGE_SIZE as u64 * MAX_PAGES as u64;
+
+/// The number of pages needed to hold `len` bytes
+pub const fn pages_for_bytes(len: usize) -> usize {
+    len.div_ceil(PAGE_SIZE)
+}
+
+/// The bytes covered by the pages in `pages`
+///
+/// `byte_range_for_pages(0..n).end` is the size of a memory with `n` pages.
+pub const fn byte_range_for_pages(pages: Range<usize>) -> Range<usize> {
+    pages.start * PAGE_SIZE..pages.end * PAGE_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_math() {
+        assert_eq!(pages_for_bytes(0), 0);
+        assert_eq!(pages_for_bytes(1), 1);
+        assert_eq!(pages_for_bytes(PAGE_SIZE), 1);
+        assert_eq!(pages_for_bytes(PAGE_SIZE + 1), 2);
+
+        assert_eq!(byte_range_for_pages(0..0), 0..0);
+        assert_eq!(byte_range_for_pages(1..3), PAGE_SIZE..3 * PAGE_SIZE);
+        assert_eq!(MAX_SIZE, 1 << 32);
+    }
+}
@@ -1,7 +1,17 @@
-use crate::{error::Result, parser::Parser, types::Module};
+use crate::{
+    error::Result,
+    parser::{Parser, ParserLimits},
+    types::Module,
+};
 
 /// Parse a module from bytes. Requires `parser` feature.
 pub fn parse_bytes(wasm: &[u8]) -> Result<Module> {
     let data = Parser::parse_module_bytes(wasm)?;
     Ok(data)
 }
+
+/// Parse a module from bytes, rejecting modules that exceed the given [`ParserLimits`]
+pub fn parse_bytes_with_limits(wasm: &[u8], limits: ParserLimits) -> Result<Module> {
+    let data = Parser::parse_module_bytes_with_limits(wasm, limits)?;
+    Ok(data)
+}
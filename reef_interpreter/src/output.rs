@@ -0,0 +1,317 @@
+//! Capturing what a guest prints, see [`Imports::capture_output`](crate::imports::Imports::capture_output)
+//!
+//! Guests print through a few common imports, which an [`OutputCapture`] defines when the imports don't:
+//! * `wasi_snapshot_preview1.fd_write` to the file descriptors 1 (stdout) and 2 (stderr).
+//!   Writing to other descriptors fails with `EBADF`.
+//! * `env.puts`, which prints a nul-terminated string and a newline to stdout
+//! * `reef.log`, which prints a string given by its pointer and length and a newline to stdout
+//!
+//! The strings are read from the memory the guest exports as `memory`.
+//!
+//! Output is written to the sinks a line at a time. The rest of a line is kept until it's finished, it's longer than
+//! [`MAX_LINE_LEN`], or the capture is flushed. Once a stream reaches its size limit, further output to it is dropped,
+//! while the guest is still told that it was written, so it doesn't retry.
+
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::cell::RefCell;
+use core::fmt::Debug;
+use std::io::{self, Write};
+
+use crate::error::Result;
+use crate::imports::{Extern, FuncContext};
+use crate::reference::MemoryStringExt;
+use crate::types::{value::ValType, FuncType, Import};
+
+/// Number of bytes a line can grow to before it's written without waiting for its end
+pub const MAX_LINE_LEN: usize = 4096;
+
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+
+/// Sinks for the output of a guest, see the [module documentation](crate::output)
+///
+/// Clones share the sinks, so the host can keep one to [`flush`](OutputCapture::flush) them. Whatever is left of the
+/// last line is written once the last clone, including the ones used by instances, is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct OutputCapture(Rc<RefCell<Streams>>);
+
+#[derive(Debug, Default)]
+struct Streams {
+    stdout: Stream,
+    stderr: Stream,
+    /// Maximum number of bytes written to each stream
+    max_bytes: Option<usize>,
+}
+
+#[derive(Default)]
+struct Stream {
+    sink: Option<Box<dyn Write>>,
+    /// The unfinished line
+    line: Vec<u8>,
+    /// Bytes accepted so far, including the unfinished line
+    written: usize,
+    truncated: bool,
+}
+
+impl Debug for Stream {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Stream")
+            .field("sink", &self.sink.as_ref().map(|_| "..."))
+            .field("line", &self.line)
+            .field("written", &self.written)
+            .field("truncated", &self.truncated)
+            .finish()
+    }
+}
+
+impl Stream {
+    fn write(&mut self, data: &[u8], max_bytes: Option<usize>) -> io::Result<()> {
+        let len = max_bytes.map_or(data.len(), |max| max.saturating_sub(self.written).min(data.len()));
+        self.truncated |= len < data.len();
+        self.written += len;
+        let Some(sink) = self.sink.as_mut() else {
+            return Ok(());
+        };
+
+        self.line.extend_from_slice(&data[..len]);
+        let end = match self.line.iter().rposition(|&b| b == b'\n') {
+            Some(newline) => newline + 1,
+            None if self.line.len() > MAX_LINE_LEN => self.line.len(),
+            None => return Ok(()),
+        };
+        sink.write_all(&self.line[..end])?;
+        self.line.drain(..end);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(sink) = self.sink.as_mut() else {
+            return Ok(());
+        };
+        sink.write_all(&self.line)?;
+        self.line.clear();
+        sink.flush()
+    }
+}
+
+impl Drop for Streams {
+    fn drop(&mut self) {
+        // there's nobody left to report errors to
+        let _ = self.stdout.flush();
+        let _ = self.stderr.flush();
+    }
+}
+
+impl OutputCapture {
+    /// Create a capture without sinks, which drops all output
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write what the guest prints to stdout to `sink`
+    pub fn with_stdout(self, sink: impl Write + 'static) -> Self {
+        self.0.borrow_mut().stdout.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Write what the guest prints to stderr to `sink`
+    pub fn with_stderr(self, sink: impl Write + 'static) -> Self {
+        self.0.borrow_mut().stderr.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Limit the number of bytes written to each of stdout and stderr, `None` for no limit
+    pub fn with_max_bytes(self, max_bytes: Option<usize>) -> Self {
+        self.0.borrow_mut().max_bytes = max_bytes;
+        self
+    }
+
+    /// Write the unfinished lines and flush the sinks
+    pub fn flush(&self) -> io::Result<()> {
+        let mut streams = self.0.borrow_mut();
+        streams.stdout.flush()?;
+        streams.stderr.flush()
+    }
+
+    /// Whether output was dropped because a stream reached its size limit
+    pub fn truncated(&self) -> bool {
+        let streams = self.0.borrow();
+        streams.stdout.truncated || streams.stderr.truncated
+    }
+
+    /// The definition of a print import, if `import` is one of them and has the expected type
+    pub(crate) fn func(&self, import: &Import, ty: &FuncType) -> Option<Extern> {
+        use ValType::I32;
+
+        let streams = self.0.clone();
+        let func = match (&*import.module, &*import.name, &*ty.params, &*ty.results) {
+            ("wasi_snapshot_preview1", "fd_write", [I32, I32, I32, I32], [I32]) => {
+                Extern::typed_func(move |ctx, (fd, iovs, iovs_len, nwritten): (i32, i32, i32, i32)| {
+                    fd_write(&streams, ctx, fd, iovs as u32, iovs_len as u32, nwritten as u32)
+                })
+            }
+            ("env", "puts", [I32], []) => Extern::typed_func(move |ctx, ptr: i32| puts(&streams, ctx, ptr as u32)),
+            ("env", "puts", [I32], [I32]) => {
+                Extern::typed_func(move |ctx, ptr: i32| puts(&streams, ctx, ptr as u32).map(|()| 0))
+            }
+            ("reef", "log", [I32, I32], []) => {
+                Extern::typed_func(move |ctx: FuncContext<'_>, (ptr, len): (i32, i32)| {
+                    let memory = ctx.exported_memory("memory")?;
+                    let line = memory.load(ptr as u32 as usize, len as u32 as usize)?;
+                    print_line(&streams, line)
+                })
+            }
+            _ => return None,
+        };
+        Some(func)
+    }
+}
+
+/// Write a string and a newline to stdout
+fn print_line(streams: &RefCell<Streams>, line: &[u8]) -> Result<()> {
+    let streams = &mut *streams.borrow_mut();
+    streams.stdout.write(line, streams.max_bytes)?;
+    streams.stdout.write(b"\n", streams.max_bytes)?;
+    Ok(())
+}
+
+fn puts(streams: &RefCell<Streams>, ctx: FuncContext<'_>, ptr: u32) -> Result<()> {
+    let memory = ctx.exported_memory("memory")?;
+    let len = (memory.instance.page_count() * crate::PAGE_SIZE).saturating_sub(ptr as usize);
+    print_line(streams, memory.load_cstr_until_nul(ptr as usize, len)?.to_bytes())
+}
+
+/// Write the buffers of an iovec array to stdout or stderr, and the number of bytes written to `nwritten`
+fn fd_write(
+    streams: &RefCell<Streams>,
+    mut ctx: FuncContext<'_>,
+    fd: i32,
+    iovs: u32,
+    iovs_len: u32,
+    nwritten: u32,
+) -> Result<i32> {
+    let mut memory = ctx.exported_memory_mut("memory")?;
+    let streams = &mut *streams.borrow_mut();
+    let stream = match fd {
+        1 => &mut streams.stdout,
+        2 => &mut streams.stderr,
+        _ => return Ok(ERRNO_BADF),
+    };
+
+    let iovs = memory.load_slice::<u32>(iovs as usize, iovs_len as usize * 2)?;
+    let mut written = 0u32;
+    for iov in iovs.chunks_exact(2) {
+        stream.write(memory.load(iov[0] as usize, iov[1] as usize)?, streams.max_bytes)?;
+        written = written.wrapping_add(iov[1]);
+    }
+    memory.store(nwritten as usize, 4, &written.to_le_bytes())?;
+    Ok(ERRNO_SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+    use std::io;
+
+    use super::*;
+    use crate::exec::CallResult;
+    use crate::imports::Imports;
+    use crate::types::value::WasmValue;
+    use crate::{parse_bytes, Instance};
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    const WAT: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (import "env" "puts" (func $puts (param i32) (result i32)))
+            (import "reef" "log" (func $log (param i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "hello\nwor")
+            (data (i32.const 16) "ld\00")
+            ;; iovecs: "hello\nwor" and "ld"
+            (data (i32.const 32) "\00\00\00\00\09\00\00\00\10\00\00\00\02\00\00\00")
+            (func (export "write") (param $fd i32) (result i32)
+                (call $fd_write (local.get $fd) (i32.const 32) (i32.const 2) (i32.const 48)))
+            (func (export "written") (result i32) (i32.load (i32.const 48)))
+            (func (export "puts") (result i32) (call $puts (i32.const 16)))
+            (func (export "log") (call $log (i32.const 0) (i32.const 5))))
+    "#;
+
+    fn run(instance: &mut Option<Instance>, name: &str, params: Vec<WasmValue>) -> Vec<WasmValue> {
+        let func = instance.take().unwrap().exported_func_untyped(name).unwrap();
+        let mut exec = func.call(params, None).unwrap();
+        let Ok(CallResult::Done(results)) = exec.run(usize::MAX) else { panic!("expected the call to return") };
+        *instance = Some(exec.into_instance());
+        results
+    }
+
+    #[test]
+    fn test_capture_output() {
+        let module = parse_bytes(&wat::parse_str(WAT).unwrap()).unwrap();
+        let (stdout, stderr) = (Shared::default(), Shared::default());
+        let capture = OutputCapture::new().with_stdout(stdout.clone()).with_stderr(stderr.clone());
+        let mut imports = Imports::new();
+        imports.capture_output(capture.clone());
+        let instance = &mut Some(Instance::instantiate(module, imports).unwrap());
+
+        // only finished lines are written
+        assert_eq!(run(instance, "write", alloc::vec![WasmValue::I32(1)]), [WasmValue::I32(0)]);
+        assert_eq!(run(instance, "written", Vec::new()), [WasmValue::I32(11)]);
+        assert_eq!(stdout.text(), "hello\n");
+        assert_eq!(run(instance, "write", alloc::vec![WasmValue::I32(2)]), [WasmValue::I32(0)]);
+        assert_eq!(stderr.text(), "hello\n");
+        assert_eq!(run(instance, "write", alloc::vec![WasmValue::I32(3)]), [WasmValue::I32(ERRNO_BADF)]);
+
+        assert_eq!(run(instance, "puts", Vec::new()), [WasmValue::I32(0)]);
+        assert_eq!(stdout.text(), "hello\nworldld\n");
+        run(instance, "log", Vec::new());
+        assert_eq!(stdout.text(), "hello\nworldld\nhello\n");
+
+        assert_eq!(stderr.text(), "hello\n");
+        capture.flush().unwrap();
+        assert_eq!(stderr.text(), "hello\nworld");
+        assert!(!capture.truncated());
+    }
+
+    #[test]
+    fn test_capture_output_limit() {
+        let module = parse_bytes(&wat::parse_str(WAT).unwrap()).unwrap();
+        let stdout = Shared::default();
+        let capture = OutputCapture::new().with_stdout(stdout.clone()).with_max_bytes(Some(8));
+        let mut imports = Imports::new();
+        imports.capture_output(capture.clone());
+        // explicit definitions take precedence
+        imports.define("reef", "log", Extern::typed_func(|_, _: (i32, i32)| Ok(()))).unwrap();
+        let instance = &mut Some(Instance::instantiate(module, imports).unwrap());
+
+        // the guest is told everything was written
+        assert_eq!(run(instance, "write", alloc::vec![WasmValue::I32(1)]), [WasmValue::I32(0)]);
+        assert_eq!(run(instance, "written", Vec::new()), [WasmValue::I32(11)]);
+        run(instance, "log", Vec::new());
+        run(instance, "puts", Vec::new());
+        assert_eq!(stdout.text(), "hello\n");
+        assert!(capture.truncated());
+
+        // the rest is written once the instance and its imports are dropped
+        drop((instance.take(), capture));
+        assert_eq!(stdout.text(), "hello\nwo");
+    }
+}
@@ -1,4 +1,4 @@
-use alloc::{boxed::Box, format, string::ToString, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, format, string::ToString, vec::Vec};
 
 use crate::parser::{
     error::{ParseError, Result},
@@ -7,7 +7,7 @@ use crate::parser::{
 };
 use crate::types::{
     self,
-    instructions::{BlockArgs, ConstInstruction, MemoryArg},
+    instructions::{BlockArgs, BranchHint, ConstInstruction, MemoryArg},
     value::ValType,
     ElementItem, Export, ExternalKind, FuncType, Global, GlobalType, Import, ImportKind, MemoryArch, MemoryType,
     TableType,
@@ -179,9 +179,81 @@ pub(crate) fn convert_module_export(export: wasmparser::Export<'_>) -> Result<Ex
     Ok(Export { index: export.index, name: Box::from(export.name), kind })
 }
 
+pub(crate) fn convert_func_names(names: wasmparser::NameSectionReader<'_>) -> Result<Vec<(u32, Box<str>)>> {
+    let mut func_names = Vec::new();
+    for subsection in names {
+        if let wasmparser::Name::Function(map) = subsection? {
+            for naming in map {
+                let naming = naming?;
+                func_names.push((naming.index, Box::from(naming.name)));
+            }
+        }
+    }
+
+    func_names.sort_by_key(|(idx, _)| *idx);
+    func_names.dedup_by_key(|(idx, _)| *idx);
+    Ok(func_names)
+}
+
+pub(crate) fn convert_producers(
+    producers: wasmparser::ProducersSectionReader<'_>,
+) -> Result<Vec<types::ProducersField>> {
+    producers
+        .into_iter()
+        .map(|field| {
+            let field = field?;
+            let values = field
+                .values
+                .into_iter()
+                .map(|value| {
+                    let value = value?;
+                    Ok(types::ProducerVersion { name: Box::from(value.name), version: Box::from(value.version) })
+                })
+                .collect::<Result<_>>()?;
+            Ok(types::ProducersField { name: Box::from(field.name), values })
+        })
+        .collect()
+}
+
+/// Read the `target_features` custom section, a vector of policy prefixes and feature names
+pub(crate) fn convert_target_features(data: &[u8], offset: usize) -> Result<Vec<types::TargetFeature>> {
+    let mut reader = wasmparser::BinaryReader::new(data, offset, wasmparser::WasmFeatures::default());
+    let count = reader.read_var_u32()?;
+    let mut features = Vec::with_capacity(count.min(64) as usize);
+    for _ in 0..count {
+        let prefix = reader.read_u8()?;
+        let policy = types::FeaturePolicy::from_prefix(prefix)
+            .ok_or_else(|| ParseError::Other(format!("Invalid target feature prefix: {:#x}", prefix)))?;
+        features.push(types::TargetFeature { policy, name: Box::from(reader.read_string()?) });
+    }
+    if !reader.eof() {
+        return Err(ParseError::Other("Trailing bytes in target_features section".to_string()));
+    }
+    Ok(features)
+}
+
+/// Branch hints of every function, sorted by their offset relative to the start of the function body
+pub(crate) type BranchHints = BTreeMap<u32, Vec<(u32, BranchHint)>>;
+
+pub(crate) fn convert_branch_hints(section: wasmparser::BranchHintSectionReader<'_>) -> Result<BranchHints> {
+    let mut funcs = BranchHints::new();
+    for func in section {
+        let func = func?;
+        let mut hints = Vec::new();
+        for hint in func.hints {
+            let hint = hint?;
+            hints.push((hint.func_offset, if hint.taken { BranchHint::Likely } else { BranchHint::Unlikely }));
+        }
+        hints.sort_by_key(|(offset, _)| *offset);
+        funcs.insert(func.func, hints);
+    }
+    Ok(funcs)
+}
+
 pub(crate) fn convert_module_code(
     func: wasmparser::FunctionBody<'_>,
     validator: &mut FuncValidator<ValidatorResources>,
+    hints: &[(u32, BranchHint)],
 ) -> Result<Code> {
     let locals_reader = func.get_locals_reader()?;
     let count = locals_reader.get_count();
@@ -196,9 +268,33 @@ pub(crate) fn convert_module_code(
         }
     }
 
-    let body = process_operators(Some(validator), func)?;
+    let (body, offsets, max_stack) = process_operators(Some(validator), func, hints)?;
     let locals = locals.into_boxed_slice();
-    Ok((body, locals))
+    Ok((body, offsets, locals, max_stack))
+}
+
+/// Validate and convert function bodies on the rayon thread pool, keeping their order
+#[cfg(feature = "parallel")]
+pub(crate) fn convert_module_code_parallel(
+    first_func: u32,
+    bodies: Vec<(wasmparser::FuncToValidate<ValidatorResources>, wasmparser::FunctionBody<'_>)>,
+    hints: &BranchHints,
+) -> Result<Vec<Code>> {
+    use rayon::prelude::*;
+
+    bodies
+        .into_par_iter()
+        .enumerate()
+        .map_init(wasmparser::FuncValidatorAllocations::default, |allocations, (i, (func, body))| {
+            let mut validator = func.into_validator(core::mem::take(allocations));
+            let body_start = body.range().start;
+            let func_hints = hints.get(&(first_func + i as u32)).map_or(&[][..], |hints| hints);
+            let code = convert_module_code(body, &mut validator, func_hints)
+                .map_err(|err| err.in_function(first_func + i as u32, body_start));
+            *allocations = validator.into_allocations();
+            code
+        })
+        .collect()
 }
 
 pub(crate) fn convert_module_type(ty: wasmparser::RecGroup) -> Result<FuncType> {
@@ -241,8 +337,12 @@ pub(crate) fn convert_valtype(valtype: &wasmparser::ValType) -> ValType {
     }
 }
 
-pub(crate) fn convert_memarg(memarg: wasmparser::MemArg) -> MemoryArg {
-    MemoryArg { offset: memarg.offset, mem_addr: memarg.memory }
+pub(crate) fn convert_memarg(memarg: wasmparser::MemArg) -> Result<MemoryArg> {
+    let offset = memarg
+        .offset
+        .try_into()
+        .map_err(|_| ParseError::UnsupportedOperator(format!("Memory offset is too large: {}", memarg.offset)))?;
+    Ok(MemoryArg { offset, mem_addr: memarg.memory, align: memarg.align })
 }
 
 pub(crate) fn process_const_operators(ops: OperatorsReader<'_>) -> Result<ConstInstruction> {
@@ -1,7 +1,10 @@
-use alloc::string::{String, ToString};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+};
 use core::fmt::{Debug, Display};
 
-use wasmparser::Encoding;
+use wasmparser::{Encoding, Payload};
 
 #[derive(Debug)]
 /// Errors that can occur when parsing a WebAssembly module
@@ -34,8 +37,205 @@ pub enum ParseError {
     },
     /// The end of the module was not reached
     EndNotReached,
+    /// The module exceeds one of the configured [`ParserLimits`](crate::ParserLimits)
+    LimitExceeded {
+        /// The name of the limit
+        limit: &'static str,
+        /// The configured maximum
+        max: u64,
+        /// The value found in the module
+        actual: u64,
+    },
+    /// A loop that might never end can be reached from the start function,
+    /// see [`ParserLimits::reject_unbounded_start_loops`](crate::ParserLimits::reject_unbounded_start_loops)
+    UnboundedStartLoop {
+        /// The address of the function containing the loop
+        func: u32,
+        /// The index of the loop instruction in the function
+        instr: usize,
+        /// The byte offset of the loop in the module
+        offset: u32,
+    },
     /// An unknown error occurred
     Other(String),
+    /// An error that occurred at a known location in the module
+    At {
+        /// Where in the module the error occurred
+        location: ErrorLocation,
+        /// The underlying error
+        error: Box<ParseError>,
+    },
+}
+
+/// The location in a module where a [`ParseError`] occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// The byte offset in the module
+    pub offset: usize,
+    /// The section containing the offset
+    pub section: SectionKind,
+    /// The index of the function, for errors in the code section
+    pub func: Option<u32>,
+    /// The byte offset relative to the start of the function body, for errors in the code section
+    pub instr_offset: Option<usize>,
+}
+
+/// The kind of a module section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum SectionKind {
+    Header,
+    Custom,
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Export,
+    Start,
+    Element,
+    DataCount,
+    Code,
+    Data,
+    Unknown,
+}
+
+impl SectionKind {
+    /// The kind of section a payload belongs to and the offset it starts at
+    pub(crate) fn of(payload: &Payload<'_>) -> (Self, usize) {
+        use wasmparser::Payload::*;
+
+        let kind = match payload {
+            Version { range, .. } => return (Self::Header, range.start),
+            End(offset) => return (Self::Unknown, *offset),
+            CodeSectionEntry(body) => return (Self::Code, body.range().start),
+            CustomSection(_) => Self::Custom,
+            TypeSection(_) => Self::Type,
+            ImportSection(_) => Self::Import,
+            FunctionSection(_) => Self::Function,
+            TableSection(_) => Self::Table,
+            MemorySection(_) => Self::Memory,
+            GlobalSection(_) => Self::Global,
+            ExportSection(_) => Self::Export,
+            StartSection { .. } => Self::Start,
+            ElementSection(_) => Self::Element,
+            DataCountSection { .. } => Self::DataCount,
+            CodeSectionStart { .. } => Self::Code,
+            DataSection(_) => Self::Data,
+            _ => Self::Unknown,
+        };
+        (kind, payload.as_section().map(|(_, range)| range.start).unwrap_or_default())
+    }
+}
+
+impl Display for SectionKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Self::Header => "header",
+            Self::Custom => "custom",
+            Self::Type => "type",
+            Self::Import => "import",
+            Self::Function => "function",
+            Self::Table => "table",
+            Self::Memory => "memory",
+            Self::Global => "global",
+            Self::Export => "export",
+            Self::Start => "start",
+            Self::Element => "element",
+            Self::DataCount => "data count",
+            Self::Code => "code",
+            Self::Data => "data",
+            Self::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Display for ErrorLocation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "error at offset {:#x} in {} section", self.offset, self.section)?;
+        if let Some(func) = self.func {
+            write!(f, ", func {}", func)?;
+        }
+        if let Some(instr_offset) = self.instr_offset {
+            write!(f, " (+{:#x})", instr_offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// The section wasmparser is reading, to locate errors it reports before a payload is complete
+#[derive(Debug, Default)]
+pub(crate) struct ReadPosition {
+    /// Function bodies left in the code section
+    bodies_left: u32,
+    /// Where the last payload started
+    offset: usize,
+}
+
+impl ReadPosition {
+    /// Move past a payload, returning its section and offset
+    pub(crate) fn advance(&mut self, payload: &Payload<'_>) -> (SectionKind, usize) {
+        match payload {
+            Payload::CodeSectionStart { count, .. } => self.bodies_left = *count,
+            Payload::CodeSectionEntry(_) => self.bodies_left = self.bodies_left.saturating_sub(1),
+            _ => {}
+        }
+        let (section, offset) = SectionKind::of(payload);
+        self.offset = offset;
+        (section, offset)
+    }
+
+    /// Locate an error reading the next payload, which is a function body or the header of the next section
+    pub(crate) fn locate(&self, err: wasmparser::BinaryReaderError) -> ParseError {
+        let section = if self.bodies_left > 0 { SectionKind::Code } else { SectionKind::Unknown };
+        ParseError::from(err).located(section, self.offset)
+    }
+}
+
+impl ParseError {
+    /// The location of the error, if known
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            Self::At { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Parse { offset, .. } => Some(*offset),
+            Self::At { location, .. } => Some(location.offset),
+            _ => None,
+        }
+    }
+
+    /// Attach a location to the error, preferring the offset reported by wasmparser
+    pub(crate) fn located(self, section: SectionKind, offset: usize) -> Self {
+        if let Self::At { .. } = self {
+            return self;
+        }
+
+        let offset = self.offset().unwrap_or(offset);
+        Self::At { location: ErrorLocation { offset, section, func: None, instr_offset: None }, error: Box::new(self) }
+    }
+
+    /// Attach the location of a function body to the error
+    pub(crate) fn in_function(self, func: u32, body_start: usize) -> Self {
+        if let Self::At { .. } = self {
+            return self;
+        }
+
+        let offset = self.offset().unwrap_or(body_start);
+        let location = ErrorLocation {
+            offset,
+            section: SectionKind::Code,
+            func: Some(func),
+            instr_offset: Some(offset.saturating_sub(body_start)),
+        };
+        Self::At { location, error: Box::new(self) }
+    }
 }
 
 impl Display for ParseError {
@@ -54,13 +254,26 @@ impl Display for ParseError {
                 write!(f, "invalid local count: expected {}, actual {}", expected, actual)
             }
             Self::EndNotReached => write!(f, "end of module not reached"),
+            Self::LimitExceeded { limit, max, actual } => {
+                write!(f, "limit exceeded: {} is {}, the maximum is {}", limit, actual, max)
+            }
+            Self::UnboundedStartLoop { func, offset, .. } => {
+                write!(f, "loop at offset {:#x} in func {} might keep the start function from returning", offset, func)
+            }
             Self::Other(message) => write!(f, "unknown error: {}", message),
+            Self::At { location, error } => write!(f, "{}: {}", location, error),
         }
     }
 }
 
-#[cfg(any(feature = "std", all(not(feature = "std"), feature = "nightly")))]
-impl crate::std::error::Error for ParseError {}
+impl core::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::At { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<wasmparser::BinaryReaderError> for ParseError {
     fn from(value: wasmparser::BinaryReaderError) -> Self {
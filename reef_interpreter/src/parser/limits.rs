@@ -0,0 +1,247 @@
+use wasmparser::FunctionBody;
+
+use crate::parser::error::{ParseError, Result};
+
+/// Limits on the size and complexity of a module, enforced while it is parsed
+///
+/// Modules exceeding any of these limits are rejected with [`ParseError::LimitExceeded`]
+/// before the offending section is converted, so untrusted modules can't make the parser allocate unbounded memory.
+/// The default places no limits on a module.
+///
+/// Use [`ParserLimits::deny_floats`] to only admit integer-only modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserLimits {
+    /// Maximum number of functions, including imported functions
+    pub max_functions: u32,
+    /// Maximum size of a single function body in bytes
+    pub max_function_size: u32,
+    /// Maximum number of locals declared by a single function, excluding its parameters
+    pub max_locals: u32,
+    /// Maximum number of pages a memory may declare, both as its initial and maximum size
+    pub max_memory_pages: u64,
+    /// Maximum initial size of a table
+    pub max_table_size: u32,
+    /// Maximum number of bytes across all data segments
+    pub max_data_bytes: u64,
+    /// How to handle floating point instructions, `None` allows them
+    pub deny_floats: Option<DenyFloats>,
+    /// Reject modules whose start function might never return, see [`ParseError::UnboundedStartLoop`]
+    ///
+    /// The start function runs while instantiating, where the host can't stop it, so every loop in it or in the
+    /// functions it calls has to call an imported function, like the hook added by gas metering. Leave this disabled
+    /// for modules that are instrumented with [`Module::inject_gas_metering`](crate::types::Module::inject_gas_metering)
+    /// after parsing.
+    pub reject_unbounded_start_loops: bool,
+}
+
+/// What to do with floating point instructions when they are denied, see [`ParserLimits::deny_floats`]
+///
+/// Floating point results can differ between hosts in NaN bit patterns, so jobs that have to be
+/// strictly deterministic can be restricted to integer arithmetic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenyFloats {
+    /// Reject modules using floating point instructions or types while validating them
+    Reject,
+    /// Accept such modules, but trap with [`Trap::FloatDenied`](crate::error::Trap::FloatDenied)
+    /// once a floating point instruction is executed
+    ///
+    /// Useful for guests that link float code (like float formatting) they never run.
+    Trap,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_functions: u32::MAX,
+            max_function_size: u32::MAX,
+            max_locals: u32::MAX,
+            max_memory_pages: u64::MAX,
+            max_table_size: u32::MAX,
+            max_data_bytes: u64::MAX,
+            deny_floats: None,
+            reject_unbounded_start_loops: false,
+        }
+    }
+}
+
+impl ParserLimits {
+    pub(crate) fn check(limit: &'static str, max: u64, actual: u64) -> Result<()> {
+        match actual > max {
+            true => Err(ParseError::LimitExceeded { limit, max, actual }),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_memory(&self, initial: u64, maximum: Option<u64>) -> Result<()> {
+        Self::check("memory pages", self.max_memory_pages, initial)?;
+        Self::check("memory pages", self.max_memory_pages, maximum.unwrap_or(0))
+    }
+
+    pub(crate) fn check_table(&self, initial: u64) -> Result<()> {
+        Self::check("table size", self.max_table_size.into(), initial)
+    }
+
+    /// Check the size and local count of a function body without converting it
+    pub(crate) fn check_function_body(&self, body: &FunctionBody<'_>) -> Result<()> {
+        Self::check("function size", self.max_function_size.into(), body.range().len() as u64)?;
+
+        let mut locals = 0u64;
+        for local in body.get_locals_reader()? {
+            locals += u64::from(local?.0);
+            Self::check("locals", self.max_locals.into(), locals)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // (module (memory 2))
+    const MEMORY_MODULE: &[u8] = &[0, b'a', b's', b'm', 1, 0, 0, 0, 5, 3, 1, 0, 2];
+
+    #[test]
+    fn test_memory_pages_limit() {
+        let limits = ParserLimits { max_memory_pages: 1, ..Default::default() };
+        let ParseError::At { location, error } =
+            Parser::parse_module_bytes_with_limits(MEMORY_MODULE, limits).unwrap_err()
+        else {
+            panic!("expected a located error")
+        };
+        assert_eq!(location.section, crate::parser::error::SectionKind::Memory);
+        assert_eq!(location.offset, 10);
+        assert!(matches!(*error, ParseError::LimitExceeded { limit: "memory pages", max: 1, actual: 2 }));
+
+        let limits = ParserLimits { max_memory_pages: 2, ..Default::default() };
+        assert!(Parser::parse_module_bytes_with_limits(MEMORY_MODULE, limits).is_ok());
+    }
+
+    /// The limit parsing `wat` exceeds and the value exceeding it, wherever it was exceeded
+    fn exceeded(wat: &str, limits: ParserLimits) -> Option<(&'static str, u64)> {
+        let mut err = match Parser::parse_module_bytes_with_limits(wat::parse_str(wat).unwrap(), limits) {
+            Ok(_) => return None,
+            Err(err) => err,
+        };
+        while let ParseError::At { error, .. } = err {
+            err = *error;
+        }
+        match err {
+            ParseError::LimitExceeded { limit, actual, .. } => Some((limit, actual)),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_functions_limit() {
+        let wat = r#"(module (import "env" "f" (func)) (func) (func))"#;
+        assert_eq!(exceeded(wat, ParserLimits { max_functions: 2, ..Default::default() }), Some(("functions", 3)));
+        assert_eq!(exceeded(wat, ParserLimits { max_functions: 3, ..Default::default() }), None);
+    }
+
+    #[test]
+    fn test_data_bytes_limit() {
+        let wat = r#"(module (memory 1) (data (i32.const 0) "abc") (data "de"))"#;
+        assert_eq!(exceeded(wat, ParserLimits { max_data_bytes: 4, ..Default::default() }), Some(("data bytes", 5)));
+        assert_eq!(exceeded(wat, ParserLimits { max_data_bytes: 5, ..Default::default() }), None);
+    }
+
+    #[test]
+    fn test_table_size_limit() {
+        for wat in [r#"(module (table 3 funcref))"#, r#"(module (import "env" "t" (table 3 funcref)))"#] {
+            assert_eq!(
+                exceeded(wat, ParserLimits { max_table_size: 2, ..Default::default() }),
+                Some(("table size", 3))
+            );
+            assert_eq!(exceeded(wat, ParserLimits { max_table_size: 3, ..Default::default() }), None);
+        }
+    }
+
+    #[test]
+    fn test_function_body_limits() {
+        let wat = r#"(module (func (local i32 i64) (local f32) (drop (i32.const 1))) (func))"#;
+        assert_eq!(exceeded(wat, ParserLimits { max_locals: 2, ..Default::default() }), Some(("locals", 3)));
+        assert_eq!(exceeded(wat, ParserLimits { max_locals: 3, ..Default::default() }), None);
+
+        let Some(("function size", size)) = exceeded(wat, ParserLimits { max_function_size: 0, ..Default::default() })
+        else {
+            panic!("expected the function size limit to be exceeded")
+        };
+        let max_function_size = size as u32 - 1;
+        let limits = ParserLimits { max_function_size, ..Default::default() };
+        assert_eq!(exceeded(wat, limits), Some(("function size", size)));
+        let limits = ParserLimits { max_function_size: size as u32, ..Default::default() };
+        assert_eq!(exceeded(wat, limits), None);
+    }
+
+    #[test]
+    fn test_deny_floats() {
+        use crate::error::{Error, Trap};
+        use crate::{exec::CallResult, imports::Imports, types::value::WasmValue, Instance};
+
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func (export "int") (result i32) (i32.add (i32.const 1) (i32.const 2)))
+                (func (export "float") (result i32) (i32.trunc_f32_s (f32.const 1.5))))
+            "#,
+        )
+        .unwrap();
+        let parse = |deny_floats| {
+            Parser::parse_module_bytes_with_limits(&wasm, ParserLimits { deny_floats, ..Default::default() })
+        };
+        let call = |module, name| {
+            let instance = Instance::instantiate(module, Imports::new()).unwrap();
+            instance.exported_func_untyped(name).unwrap().call(alloc::vec::Vec::new(), None).unwrap().run(usize::MAX)
+        };
+
+        assert!(parse(Some(DenyFloats::Reject)).is_err());
+
+        let module = parse(Some(DenyFloats::Trap)).unwrap();
+        assert!(matches!(call(module.clone(), "int"), Ok(CallResult::Done(res)) if res == [WasmValue::I32(3)]));
+        assert!(matches!(call(module, "float"), Err(Error::Trap(Trap::FloatDenied))));
+
+        let module = parse(None).unwrap();
+        assert!(matches!(call(module, "float"), Ok(CallResult::Done(res)) if res == [WasmValue::I32(1)]));
+    }
+
+    #[test]
+    fn test_reject_unbounded_start_loops() {
+        let parse = |wat| {
+            let limits = ParserLimits { reject_unbounded_start_loops: true, ..Default::default() };
+            Parser::parse_module_bytes_with_limits(wat::parse_str(wat).unwrap(), limits)
+        };
+
+        let spin = r#"
+            (module
+                (func $spin (loop $l (br $l)))
+                (func $init (call $spin))
+                (start $init))
+            "#;
+        assert!(matches!(parse(spin), Err(ParseError::UnboundedStartLoop { func: 0, instr: 0, .. })));
+        assert!(Parser::parse_module_bytes(wat::parse_str(spin).unwrap()).is_ok());
+
+        // the host can stop the loop when the import is called
+        assert!(parse(
+            r#"
+            (module
+                (import "env" "tick" (func $tick))
+                (func $init (loop $l (call $tick) (br $l)))
+                (start $init))
+            "#
+        )
+        .is_ok());
+
+        // loops outside of the start function can be stopped when they run out of fuel
+        assert!(parse(
+            r#"
+            (module
+                (func (export "spin") (loop $l (br $l)))
+                (func $init)
+                (start $init))
+            "#
+        )
+        .is_ok());
+    }
+}
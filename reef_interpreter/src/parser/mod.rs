@@ -4,23 +4,28 @@ use alloc::{string::ToString, vec::Vec};
 
 mod conversion;
 pub(crate) mod error;
+mod limits;
 pub(crate) mod module;
+mod optimize;
+mod session;
 mod visit;
 
-use crate::types::{Module, WasmFunction};
-use error::{ParseError, Result};
+use crate::types::{instructions::Instruction, Module, WasmFunction};
+use error::{ParseError, ReadPosition, Result};
+pub use limits::{DenyFloats, ParserLimits};
 use module::ModuleReader;
-use wasmparser::{Validator, WasmFeaturesInflated};
+pub use session::ParserSession;
+use wasmparser::{FuncValidatorAllocations, Validator, WasmFeaturesInflated};
 
 /// A WebAssembly parser
 #[derive(Default, Debug)]
 pub(crate) struct Parser {}
 
 impl Parser {
-    fn create_validator() -> Validator {
+    fn create_validator(floats: bool) -> Validator {
         let features = WasmFeaturesInflated {
             bulk_memory: true,
-            floats: true,
+            floats,
             multi_value: true,
             mutable_global: true,
             reference_types: true,
@@ -49,19 +54,142 @@ impl Parser {
 
     /// Parse a [`Module`] from bytes
     pub(crate) fn parse_module_bytes(wasm: impl AsRef<[u8]>) -> Result<Module> {
+        Self::parse_module_bytes_with_limits(wasm, ParserLimits::default())
+    }
+
+    /// Parse a [`Module`] from bytes, rejecting it if it exceeds the given limits
+    pub(crate) fn parse_module_bytes_with_limits(wasm: impl AsRef<[u8]>, limits: ParserLimits) -> Result<Module> {
+        Self::parse_module_bytes_reusing(wasm, limits, &mut None)
+    }
+
+    /// Parse a [`Module`] from bytes, validating functions with the given allocations and handing them back after
+    pub(crate) fn parse_module_bytes_reusing(
+        wasm: impl AsRef<[u8]>,
+        limits: ParserLimits,
+        allocations: &mut Option<FuncValidatorAllocations>,
+    ) -> Result<Module> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("parse", bytes = wasm.as_ref().len()).entered();
+
         let wasm = wasm.as_ref();
-        let mut validator = Self::create_validator();
-        let mut reader = ModuleReader::new();
+        let mut validator = Self::create_validator(limits.deny_floats != Some(DenyFloats::Reject));
+        let mut reader = ModuleReader::new(limits, allocations.take());
 
+        // Function bodies are collected here and validated all at once after the other sections are read
+        #[cfg(feature = "parallel")]
+        let mut bodies = Vec::new();
+
+        let mut position = ReadPosition::default();
         for payload in wasmparser::Parser::new(0).parse_all(wasm) {
-            reader.process_payload(payload?, &mut validator)?;
+            let payload = payload.map_err(|err| position.locate(err))?;
+            let (section, offset) = position.advance(&payload);
+
+            #[cfg(feature = "parallel")]
+            if let wasmparser::Payload::CodeSectionEntry(body) = payload {
+                let func = reader.next_func_index() + bodies.len() as u32;
+                let located = |err: ParseError| err.in_function(func, offset);
+
+                reader.check_function_body(&body).map_err(located)?;
+                bodies.push((validator.code_section_entry(&body).map_err(|err| located(err.into()))?, body));
+                continue;
+            }
+
+            reader.process_payload(payload, &mut validator).map_err(|err| err.located(section, offset))?;
+        }
+
+        #[cfg(feature = "parallel")]
+        {
+            reader.code =
+                conversion::convert_module_code_parallel(reader.next_func_index(), bodies, &reader.branch_hints)?;
+        }
+
+        Self::finish(reader, limits, allocations)
+    }
+
+    /// Parse a [`Module`] from a reader, handing everything before the code section to `on_header` as soon as it's read
+    ///
+    /// The module passed to `on_header` has no functions and data segments yet. Function bodies are read and
+    /// validated one after another, so they aren't converted in parallel even with the `parallel` feature.
+    #[cfg(feature = "std")]
+    pub(crate) fn parse_module_stream(
+        mut source: impl std::io::Read,
+        limits: ParserLimits,
+        on_header: impl FnOnce(Module) -> crate::error::Result<()>,
+    ) -> crate::error::Result<Module> {
+        // upper bound for a single read, wasmparser asks for whole sections at once
+        const MAX_READ: u64 = 64 * 1024;
+
+        let mut validator = Self::create_validator(limits.deny_floats != Some(DenyFloats::Reject));
+        let mut reader = ModuleReader::new(limits, None);
+        let mut parser = wasmparser::Parser::new(0);
+        let mut header = Some(on_header);
+        let mut buf = Vec::new();
+        let mut eof = false;
+        let mut position = ReadPosition::default();
+
+        loop {
+            let (payload, consumed) = match parser.parse(&buf, eof).map_err(|err| position.locate(err))? {
+                wasmparser::Chunk::NeedMoreData(hint) => {
+                    let len = buf.len();
+                    buf.resize(len + hint.min(MAX_READ) as usize, 0);
+                    let read = source.read(&mut buf[len..])?;
+                    buf.truncate(len + read);
+                    eof = read == 0;
+                    continue;
+                }
+                wasmparser::Chunk::Parsed { consumed, payload } => (payload, consumed),
+            };
+
+            let end = matches!(payload, wasmparser::Payload::End(_));
+            if end || matches!(payload, wasmparser::Payload::CodeSectionStart { .. }) {
+                if let Some(on_header) = header.take() {
+                    on_header(reader.header())?;
+                }
+            }
+
+            let (section, offset) = position.advance(&payload);
+            reader.process_payload(payload, &mut validator).map_err(|err| err.located(section, offset))?;
+            buf.drain(..consumed);
+
+            if end {
+                break;
+            }
         }
 
+        Ok(Self::finish(reader, limits, &mut None)?)
+    }
+
+    fn finish(
+        mut reader: ModuleReader,
+        limits: ParserLimits,
+        allocations: &mut Option<FuncValidatorAllocations>,
+    ) -> Result<Module> {
         if !reader.end_reached {
             return Err(ParseError::EndNotReached);
         }
 
-        reader.try_into()
+        *allocations = reader.take_allocations();
+        let mut module: Module = reader.try_into()?;
+        if limits.deny_floats == Some(DenyFloats::Trap) {
+            deny_floats(&mut module);
+        }
+        if limits.reject_unbounded_start_loops {
+            if let Some((func, instr)) = module.find_unbounded_start_loop() {
+                let body = &module.funcs[(func - module.imported_func_count()) as usize];
+                let offset = body.offsets.get(instr).copied().unwrap_or_default();
+                return Err(ParseError::UnboundedStartLoop { func, instr, offset });
+            }
+        }
+        Ok(module)
+    }
+}
+
+/// Replace every floating point instruction with one that traps
+fn deny_floats(module: &mut Module) {
+    for func in module.funcs.iter_mut() {
+        for instr in func.instructions.iter_mut().filter(|instr| instr.is_float()) {
+            *instr = Instruction::FloatDenied;
+        }
     }
 }
 
@@ -84,9 +212,11 @@ impl TryFrom<ModuleReader> for Module {
             .code
             .into_iter()
             .zip(code_type_addrs)
-            .map(|((instructions, locals), ty_idx)| WasmFunction {
+            .map(|((instructions, offsets, locals, max_stack), ty_idx)| WasmFunction {
                 instructions,
+                offsets,
                 locals,
+                max_stack,
                 ty: reader.func_types.get(ty_idx as usize).expect("No func type for func, this is a bug").clone(),
             })
             .collect::<Vec<_>>();
@@ -104,7 +234,37 @@ impl TryFrom<ModuleReader> for Module {
             data: reader.data.into_boxed_slice(),
             exports: reader.exports.into_boxed_slice(),
             elements: reader.elements.into_boxed_slice(),
+            func_names: reader.func_names.into_boxed_slice(),
+            producers: reader.producers.into_boxed_slice(),
+            target_features: reader.target_features.into_boxed_slice(),
             memory_types: reader.memory_types.into_boxed_slice(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::error::{ErrorLocation, SectionKind};
+
+    fn location(wasm: &[u8]) -> ErrorLocation {
+        *Parser::parse_module_bytes(wasm).unwrap_err().location().expect("expected a located error")
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let wasm = wat::parse_str(r#"(module (func) (func (result i32) nop) (func))"#).unwrap();
+        let ErrorLocation { section, func, offset, instr_offset } = location(&wasm);
+        assert_eq!((section, func), (SectionKind::Code, Some(1)));
+        assert_eq!(offset - instr_offset.unwrap(), wasm.len() - 6);
+
+        // a section header running past the end of the module
+        assert_eq!(location(b"\0asm\x01\0\0\0\x01\x10").section, SectionKind::Unknown);
+
+        // a module cut off in the middle of its last function body, which is reported where the body starts
+        let wasm = wat::parse_str(r#"(module (func (drop (i32.const 1))) (func (drop (i32.const 2))))"#).unwrap();
+        let location = location(&wasm[..wasm.len() - 2]);
+        assert_eq!(location.section, SectionKind::Code);
+        assert_eq!(location.offset, wasm.len() - 5);
+    }
+}
@@ -2,16 +2,20 @@ use alloc::{boxed::Box, format, vec::Vec};
 
 use wasmparser::{FuncValidatorAllocations, Payload, Validator};
 
-use crate::parser::{conversion, ParseError, Result};
+use crate::parser::{conversion, ParseError, ParserLimits, Result};
 use crate::types::{
-    instructions::Instruction, value::ValType, Data, Element, Export, FuncType, Global, Import, MemoryType, TableType,
+    instructions::Instruction, value::ValType, Data, Element, Export, FuncType, Global, Import, MemoryType,
+    ProducersField, TableType, TargetFeature,
 };
 
-pub(crate) type Code = (Box<[Instruction]>, Box<[ValType]>);
+/// Instructions, their offsets in the original module, locals and the maximum stack height of a function
+pub(crate) type Code = (Box<[Instruction]>, Box<[u32]>, Box<[ValType]>, u32);
 
 #[derive(Default)]
 pub(crate) struct ModuleReader {
     func_validator_allocations: Option<FuncValidatorAllocations>,
+    limits: ParserLimits,
+    data_bytes: u64,
 
     pub(crate) version: Option<u16>,
     pub(crate) start_func: Option<u32>,
@@ -25,12 +29,52 @@ pub(crate) struct ModuleReader {
     pub(crate) imports: Vec<Import>,
     pub(crate) data: Vec<Data>,
     pub(crate) elements: Vec<Element>,
+    pub(crate) func_names: Vec<(u32, Box<str>)>,
+    pub(crate) producers: Vec<ProducersField>,
+    pub(crate) target_features: Vec<TargetFeature>,
+    /// Branch hints come before the code section, so they are applied while converting function bodies
+    pub(crate) branch_hints: conversion::BranchHints,
     pub(crate) end_reached: bool,
 }
 
 impl ModuleReader {
-    pub(crate) fn new() -> ModuleReader {
-        Self::default()
+    pub(crate) fn new(limits: ParserLimits, allocations: Option<FuncValidatorAllocations>) -> ModuleReader {
+        Self { limits, func_validator_allocations: allocations, ..Self::default() }
+    }
+
+    /// Everything read so far except for functions and data segments, which come last in a module
+    #[cfg(feature = "std")]
+    pub(crate) fn header(&self) -> crate::types::Module {
+        crate::types::Module {
+            start_func: self.start_func,
+            func_types: self.func_types.clone().into_boxed_slice(),
+            exports: self.exports.clone().into_boxed_slice(),
+            globals: self.globals.clone().into_boxed_slice(),
+            table_types: self.table_types.clone().into_boxed_slice(),
+            memory_types: self.memory_types.clone().into_boxed_slice(),
+            imports: self.imports.clone().into_boxed_slice(),
+            elements: self.elements.clone().into_boxed_slice(),
+            ..Default::default()
+        }
+    }
+
+    /// Take the function validator allocations, so they can be reused for the next module
+    pub(crate) fn take_allocations(&mut self) -> Option<FuncValidatorAllocations> {
+        self.func_validator_allocations.take()
+    }
+
+    /// Check a function body against the limits, before it gets validated and converted
+    pub(crate) fn check_function_body(&self, body: &wasmparser::FunctionBody<'_>) -> Result<()> {
+        self.limits.check_function_body(body)
+    }
+
+    fn imported_func_count(&self) -> u64 {
+        self.imports.iter().filter(|i| matches!(i.kind, crate::types::ImportKind::Function(_))).count() as u64
+    }
+
+    /// The function index of the next code section entry
+    pub(crate) fn next_func_index(&self) -> u32 {
+        (self.imported_func_count() + self.code.len() as u64) as u32
     }
 
     pub(crate) fn process_payload(&mut self, payload: Payload<'_>, validator: &mut Validator) -> Result<()> {
@@ -79,6 +123,9 @@ impl ModuleReader {
                 }
 
                 validator.table_section(&reader)?;
+                for table in reader.clone() {
+                    self.limits.check_table(table?.ty.initial.into())?;
+                }
                 self.table_types = conversion::convert_module_tables(reader)?;
             }
             MemorySection(reader) => {
@@ -87,6 +134,10 @@ impl ModuleReader {
                 }
 
                 validator.memory_section(&reader)?;
+                for memory in reader.clone() {
+                    let memory = memory?;
+                    self.limits.check_memory(memory.initial, memory.maximum)?;
+                }
                 self.memory_types = conversion::convert_module_memories(reader)?;
             }
             ElementSection(reader) => {
@@ -99,6 +150,10 @@ impl ModuleReader {
                 }
 
                 validator.data_section(&reader)?;
+                for data in reader.clone() {
+                    self.data_bytes += data?.data.len() as u64;
+                    ParserLimits::check("data bytes", self.limits.max_data_bytes, self.data_bytes)?;
+                }
                 self.data = conversion::convert_module_data_sections(reader)?;
             }
             DataCountSection { count, range } => {
@@ -113,6 +168,8 @@ impl ModuleReader {
                 }
 
                 validator.function_section(&reader)?;
+                let functions = self.imported_func_count() + u64::from(reader.count());
+                ParserLimits::check("functions", self.limits.max_functions.into(), functions)?;
                 self.code_type_addrs = reader.into_iter().map(|f| Ok(f?)).collect::<Result<Vec<_>>>()?;
             }
             CodeSectionStart { count, range, .. } => {
@@ -124,9 +181,15 @@ impl ModuleReader {
                 validator.code_section_start(count, &range)?;
             }
             CodeSectionEntry(function) => {
-                let v = validator.code_section_entry(&function)?;
+                let (func, body_start) = (self.next_func_index(), function.range().start);
+                let located = |err: ParseError| err.in_function(func, body_start);
+
+                self.check_function_body(&function).map_err(located)?;
+                let v = validator.code_section_entry(&function).map_err(|err| located(err.into()))?;
                 let mut func_validator = v.into_validator(self.func_validator_allocations.take().unwrap_or_default());
-                self.code.push(conversion::convert_module_code(function, &mut func_validator)?);
+                let hints = self.branch_hints.get(&func).map_or(&[][..], |hints| hints);
+                let code = conversion::convert_module_code(function, &mut func_validator, hints).map_err(located)?;
+                self.code.push(code);
                 self.func_validator_allocations = Some(func_validator.into_allocations());
             }
             ImportSection(reader) => {
@@ -135,6 +198,13 @@ impl ModuleReader {
                 }
 
                 validator.import_section(&reader)?;
+                for import in reader.clone() {
+                    match import?.ty {
+                        wasmparser::TypeRef::Memory(ty) => self.limits.check_memory(ty.initial, ty.maximum)?,
+                        wasmparser::TypeRef::Table(ty) => self.limits.check_table(ty.initial.into())?,
+                        _ => {}
+                    }
+                }
                 self.imports = conversion::convert_module_imports(reader)?;
             }
             ExportSection(reader) => {
@@ -154,8 +224,24 @@ impl ModuleReader {
                 validator.end(offset)?;
                 self.end_reached = true;
             }
-            CustomSection(_reader) => {
-                // debug!("Skipping custom section: {:?}", _reader.name());
+            CustomSection(reader) => {
+                // Malformed custom sections must not invalidate a module, so broken ones are just ignored
+                match reader.as_known() {
+                    wasmparser::KnownCustom::Name(names) => {
+                        self.func_names = conversion::convert_func_names(names).unwrap_or_default();
+                    }
+                    wasmparser::KnownCustom::BranchHints(hints) => {
+                        self.branch_hints = conversion::convert_branch_hints(hints).unwrap_or_default();
+                    }
+                    wasmparser::KnownCustom::Producers(producers) => {
+                        self.producers = conversion::convert_producers(producers).unwrap_or_default();
+                    }
+                    _ if reader.name() == "target_features" => {
+                        self.target_features = conversion::convert_target_features(reader.data(), reader.data_offset())
+                            .unwrap_or_default();
+                    }
+                    _ => {}
+                }
             }
             UnknownSection { .. } => return Err(ParseError::UnsupportedSection("Unknown section".into())),
             section => return Err(ParseError::UnsupportedSection(format!("Unsupported section: {:?}", section))),
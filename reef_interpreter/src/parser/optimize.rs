@@ -0,0 +1,158 @@
+//! Simplifications of function bodies that don't change their behavior
+//!
+//! Runs after a function body has been converted, so it only has to deal with our own instructions.
+//! Block offsets are recomputed afterwards, as instructions are removed.
+
+use alloc::vec::Vec;
+
+use crate::types::instructions::Instruction;
+
+/// Fold constant expressions, remove `nop`s and strip code that can never be reached
+///
+/// `offsets` holds the original offset of every instruction and is kept in sync with them.
+pub(crate) fn optimize(instrs: Vec<Instruction>, offsets: Vec<u32>) -> (Vec<Instruction>, Vec<u32>) {
+    let last = instrs.len().saturating_sub(1);
+    let mut out: Vec<Instruction> = Vec::with_capacity(instrs.len());
+    let mut out_offsets: Vec<u32> = Vec::with_capacity(offsets.len());
+
+    // nesting depth inside of unreachable code
+    let mut unreachable: Option<u32> = None;
+    // number of br_table labels to copy before code becomes unreachable
+    let mut labels = 0;
+
+    for (idx, (instr, offset)) in instrs.into_iter().zip(offsets).enumerate() {
+        if let Some(depth) = unreachable.as_mut() {
+            match instr {
+                // the final return is the end of the function
+                _ if idx == last => unreachable = None,
+                Instruction::Block(..) | Instruction::Loop(..) | Instruction::If(..) => {
+                    *depth += 1;
+                    continue;
+                }
+                Instruction::Else(_) | Instruction::EndBlockFrame if *depth == 0 => unreachable = None,
+                Instruction::EndBlockFrame => {
+                    *depth -= 1;
+                    continue;
+                }
+                _ => continue,
+            }
+        }
+
+        match instr {
+            Instruction::Nop => continue,
+            Instruction::BrLabel(_) if labels > 0 => {
+                out.push(instr);
+                out_offsets.push(offset);
+                labels -= 1;
+                if labels == 0 {
+                    unreachable = Some(0);
+                }
+                continue;
+            }
+            Instruction::BrTable(_, len) if len > 0 => labels = len,
+            Instruction::Unreachable | Instruction::Br(_) | Instruction::BrTable(..) | Instruction::Return
+                if idx != last =>
+            {
+                unreachable = Some(0)
+            }
+            _ => {}
+        }
+
+        out.push(instr);
+        out_offsets.push(offset);
+        fold(&mut out, &mut out_offsets);
+    }
+
+    (out, out_offsets)
+}
+
+/// Fold the last instructions if they only operate on constants
+///
+/// A folded constant keeps the offset of its first operand.
+fn fold(out: &mut Vec<Instruction>, offsets: &mut Vec<u32>) {
+    use Instruction::*;
+
+    loop {
+        let (folded, operands) = match out.as_slice() {
+            [.., I32Const(a), I32AddConst(b)] => (I32Const(a.wrapping_add(*b)), 2),
+            [.., I32Const(a), I32Const(b), op] => {
+                let folded = match op {
+                    I32Add => I32Const(a.wrapping_add(*b)),
+                    I32Sub => I32Const(a.wrapping_sub(*b)),
+                    I32Mul => I32Const(a.wrapping_mul(*b)),
+                    I32And => I32Const(a & b),
+                    I32Or => I32Const(a | b),
+                    I32Xor => I32Const(a ^ b),
+                    I32Shl => I32Const(a.wrapping_shl(*b as u32)),
+                    I32ShrS => I32Const(a.wrapping_shr(*b as u32)),
+                    I32ShrU => I32Const((*a as u32).wrapping_shr(*b as u32) as i32),
+                    _ => return,
+                };
+                (folded, 3)
+            }
+            [.., I64Const(a), I64Const(b), op] => {
+                let folded = match op {
+                    I64Add => I64Const(a.wrapping_add(*b)),
+                    I64Sub => I64Const(a.wrapping_sub(*b)),
+                    I64Mul => I64Const(a.wrapping_mul(*b)),
+                    I64And => I64Const(a & b),
+                    I64Or => I64Const(a | b),
+                    I64Xor => I64Const(a ^ b),
+                    I64Shl => I64Const(a.wrapping_shl(*b as u32)),
+                    I64ShrS => I64Const(a.wrapping_shr(*b as u32)),
+                    I64ShrU => I64Const((*a as u64).wrapping_shr(*b as u32) as i64),
+                    _ => return,
+                };
+                (folded, 3)
+            }
+            _ => return,
+        };
+
+        out.truncate(out.len() - operands);
+        offsets.truncate(out.len() + 1);
+        out.push(folded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::instructions::Instruction;
+
+    /// The bytecode of the first function of the module
+    fn parse(wat: &str) -> alloc::boxed::Box<[Instruction]> {
+        crate::test_utils::parse(wat).funcs[0].instructions.clone()
+    }
+
+    #[test]
+    fn test_optimize() {
+        let optimized = parse(
+            r#"
+            (module
+                (func (param i32) (result i32)
+                    nop
+                    (block $b
+                        (br_if $b (local.get 0))
+                        (br $b)
+                        (drop (i32.const 1))
+                        (block (loop (br 0))))
+                    (i64.mul (i64.const 3) (i64.add (i64.const 1) (i64.const 4)))
+                    drop
+                    (i32.sub (i32.add (i32.const 2) (i32.const 5)) (i32.const 1))
+                    return
+                    unreachable))
+            "#,
+        );
+        let expected = parse(
+            r#"
+            (module
+                (func (param i32) (result i32)
+                    (block $b
+                        (br_if $b (local.get 0))
+                        (br $b))
+                    (drop (i64.const 15))
+                    (return (i32.const 6))))
+            "#,
+        );
+        assert_eq!(optimized, expected);
+    }
+}
@@ -0,0 +1,80 @@
+use core::fmt::Debug;
+
+use wasmparser::FuncValidatorAllocations;
+
+use crate::error::Result;
+use crate::parser::{Parser, ParserLimits};
+use crate::types::Module;
+
+/// Parser state that is kept between modules
+///
+/// Validating function bodies needs a few buffers that normally get allocated for every module.
+/// A session keeps them around, so a worker parsing many modules (or the same one again and again)
+/// only allocates them once. With the `parallel` feature, bodies are validated on the thread pool
+/// with buffers of its own instead.
+///
+/// A module that fails to parse gives up the buffers, the next one allocates them again.
+#[derive(Default)]
+pub struct ParserSession {
+    limits: ParserLimits,
+    allocations: Option<FuncValidatorAllocations>,
+}
+
+impl Debug for ParserSession {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ParserSession")
+            .field("limits", &self.limits)
+            .field("allocations", &self.allocations.is_some())
+            .finish()
+    }
+}
+
+impl ParserSession {
+    /// Create a new session that places no limits on modules
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new session rejecting modules that exceed the given [`ParserLimits`]
+    pub fn with_limits(limits: ParserLimits) -> Self {
+        Self { limits, allocations: None }
+    }
+
+    /// The limits modules parsed in this session are checked against
+    pub fn limits(&self) -> ParserLimits {
+        self.limits
+    }
+
+    /// Parse a module from bytes, see [`parse_bytes`](crate::parse_bytes)
+    pub fn parse_bytes(&mut self, wasm: &[u8]) -> Result<Module> {
+        Ok(Parser::parse_module_bytes_reusing(wasm, self.limits, &mut self.allocations)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocations_reused() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func (export "add") (param i32 i32) (result i32)
+                    (i32.add (local.get 0) (local.get 1))))
+            "#,
+        )
+        .unwrap();
+
+        let mut session = ParserSession::new();
+        let module = session.parse_bytes(&wasm).unwrap();
+        #[cfg(not(feature = "parallel"))]
+        assert!(session.allocations.is_some());
+        assert_eq!(session.parse_bytes(&wasm).unwrap(), module);
+        assert!(session.parse_bytes(&wasm[..wasm.len() - 1]).is_err());
+        assert_eq!(session.parse_bytes(&wasm).unwrap(), module);
+
+        let limits = ParserLimits { max_functions: 0, ..Default::default() };
+        assert!(ParserSession::with_limits(limits).parse_bytes(&wasm).is_err());
+    }
+}
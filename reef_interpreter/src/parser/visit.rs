@@ -5,8 +5,10 @@ use wasmparser::{FuncValidator, FunctionBody, VisitOperator, WasmModuleResources
 use crate::parser::{
     conversion::{convert_blocktype, convert_heaptype, convert_memarg, convert_valtype},
     error::{ParseError, Result},
+    optimize::optimize,
 };
-use crate::types::instructions::Instruction;
+use crate::transform::relink;
+use crate::types::instructions::{BranchHint, I32Cmp, Instruction};
 
 struct ValidateThenVisit<'a, T, U>(T, &'a mut U);
 macro_rules! validate_then_visit {
@@ -29,26 +31,49 @@ where
     wasmparser::for_each_operator!(validate_then_visit);
 }
 
+/// Instructions of a function body, their offsets and the maximum height of the operand stack
+type Body = (Box<[Instruction]>, Box<[u32]>, u32);
+
+/// Convert the operators of a function body, the stack height is only known when validating
+///
+/// `hints` are the branch hints of the function, sorted by their offset relative to the start of the body.
 pub(crate) fn process_operators<R: WasmModuleResources>(
     validator: Option<&mut FuncValidator<R>>,
     body: FunctionBody<'_>,
-) -> Result<Box<[Instruction]>> {
+    hints: &[(u32, BranchHint)],
+) -> Result<Body> {
+    let body_start = body.range().start;
+    let hint_at = |offset: usize| match hints.binary_search_by_key(&((offset - body_start) as u32), |h| h.0) {
+        Ok(idx) => hints[idx].1,
+        Err(_) => BranchHint::Unknown,
+    };
+
     let mut reader = body.get_operators_reader()?;
     let remaining = reader.get_binary_reader().bytes_remaining();
     let mut builder = FunctionBuilder::new(remaining);
+    let mut max_stack = 0;
     if let Some(validator) = validator {
         while !reader.eof() {
-            let validate = validator.visitor(reader.original_position());
+            let offset = reader.original_position();
+            builder.hint = hint_at(offset);
+            let validate = validator.visitor(offset);
             reader.visit_operator(&mut ValidateThenVisit(validate, &mut builder))???;
+            builder.record_offset(offset);
+            max_stack = max_stack.max(validator.operand_stack_height());
         }
         validator.finish(reader.original_position())?;
     } else {
         while !reader.eof() {
+            let offset = reader.original_position();
+            builder.hint = hint_at(offset);
             reader.visit_operator(&mut builder)??;
+            builder.record_offset(offset);
         }
     }
 
-    Ok(builder.instructions.into_boxed_slice())
+    let (mut instructions, offsets) = optimize(builder.instructions, builder.offsets);
+    relink(&mut instructions).map_err(|e| ParseError::Other(e.to_string()))?;
+    Ok((instructions.into_boxed_slice(), offsets.into_boxed_slice(), max_stack))
 }
 
 macro_rules! define_operands {
@@ -89,10 +114,11 @@ macro_rules! define_mem_operands {
         $(
             #[inline(always)]
             fn $name(&mut self, mem_arg: wasmparser::MemArg) -> Self::Output {
-                let arg = convert_memarg(mem_arg);
+                let arg = convert_memarg(mem_arg)?;
                 self.instructions.push(Instruction::$instr {
                     offset: arg.offset,
                     mem_addr: arg.mem_addr,
+                    align: arg.align,
                 });
                 Ok(())
             }
@@ -102,12 +128,29 @@ macro_rules! define_mem_operands {
 
 pub(crate) struct FunctionBuilder {
     instructions: Vec<Instruction>,
+    /// Offset of the operator each instruction was converted from, in the original module
+    offsets: Vec<u32>,
     label_ptrs: Vec<usize>,
+    /// Branch hint for the operator that is visited next
+    hint: BranchHint,
 }
 
 impl FunctionBuilder {
     pub(crate) fn new(instr_capacity: usize) -> Self {
-        Self { instructions: Vec::with_capacity(instr_capacity / 4), label_ptrs: Vec::with_capacity(256) }
+        Self {
+            instructions: Vec::with_capacity(instr_capacity / 4),
+            offsets: Vec::with_capacity(instr_capacity / 4),
+            label_ptrs: Vec::with_capacity(256),
+            hint: BranchHint::Unknown,
+        }
+    }
+
+    /// Attribute the instructions pushed by the last operator to its offset
+    ///
+    /// Fused instructions replace the ones they were built from, so they keep the offset of the first operator.
+    fn record_offset(&mut self, offset: usize) {
+        self.offsets.truncate(self.instructions.len());
+        self.offsets.resize(self.instructions.len(), offset as u32);
     }
 
     #[cold]
@@ -146,7 +189,6 @@ impl<'a> wasmparser::VisitOperator<'a> for FunctionBuilder {
 
     define_primitive_operands! {
         visit_br, Instruction::Br, u32,
-        visit_br_if, Instruction::BrIf, u32,
         visit_global_get, Instruction::GlobalGet, u32,
         visit_global_set, Instruction::GlobalSet, u32,
         visit_i32_const, Instruction::I32Const, i32,
@@ -329,10 +371,10 @@ impl<'a> wasmparser::VisitOperator<'a> for FunctionBuilder {
 
     #[inline(always)]
     fn visit_i32_store(&mut self, memarg: wasmparser::MemArg) -> Self::Output {
-        let arg = convert_memarg(memarg);
-        let i32store = Instruction::I32Store { offset: arg.offset, mem_addr: arg.mem_addr };
+        let arg = convert_memarg(memarg)?;
+        let i32store = Instruction::I32Store { offset: arg.offset, mem_addr: arg.mem_addr, align: arg.align };
 
-        if self.instructions.len() < 3 || arg.mem_addr > 0xFF || arg.offset > 0xFFFF_FFFF {
+        if self.instructions.len() < 3 || arg.mem_addr > 0xFF {
             return self.visit(i32store);
         }
 
@@ -343,8 +385,9 @@ impl<'a> wasmparser::VisitOperator<'a> for FunctionBuilder {
                 self.visit(Instruction::I32StoreLocal {
                     local: a,
                     const_i32: b,
-                    offset: arg.offset as u32,
+                    offset: arg.offset,
                     mem_addr: arg.mem_addr as u8,
+                    align: arg.align,
                 })
             }
             _ => self.visit(i32store),
@@ -405,10 +448,36 @@ impl<'a> wasmparser::VisitOperator<'a> for FunctionBuilder {
                 self.instructions.pop();
                 self.visit(Instruction::I32LocalGetConstAdd(a, b))
             }
+            [_, Instruction::LocalGet2(a, b)] => {
+                self.instructions.pop();
+                self.visit(Instruction::I32AddLocals(a, b))
+            }
+            [_, Instruction::I32Const(b)] => {
+                self.instructions.pop();
+                self.visit(Instruction::I32AddConst(b))
+            }
             _ => self.visit(Instruction::I32Add),
         }
     }
 
+    #[inline(always)]
+    fn visit_br_if(&mut self, label: u32) -> Self::Output {
+        let hint = self.hint;
+        let Some(instruction) = self.instructions.last_mut() else {
+            return self.visit(Instruction::BrIf(label, hint));
+        };
+
+        match instruction {
+            Instruction::I32Eqz => *instruction = Instruction::I32EqzBrIf(label, hint),
+            _ => match I32Cmp::from_instruction(instruction) {
+                Some(cmp) => *instruction = Instruction::I32CmpBrIf(cmp, label, hint),
+                None => return self.visit(Instruction::BrIf(label, hint)),
+            },
+        };
+
+        Ok(())
+    }
+
     #[inline(always)]
     fn visit_block(&mut self, blockty: wasmparser::BlockType) -> Self::Output {
         self.label_ptrs.push(self.instructions.len());
@@ -424,7 +493,7 @@ impl<'a> wasmparser::VisitOperator<'a> for FunctionBuilder {
     #[inline(always)]
     fn visit_if(&mut self, ty: wasmparser::BlockType) -> Self::Output {
         self.label_ptrs.push(self.instructions.len());
-        self.visit(Instruction::If(convert_blocktype(ty).into(), 0, 0))
+        self.visit(Instruction::If(convert_blocktype(ty).into(), 0, 0, self.hint))
     }
 
     #[inline(always)]
@@ -457,7 +526,7 @@ impl<'a> wasmparser::VisitOperator<'a> for FunctionBuilder {
                 let if_label_pointer = self.label_ptrs.pop().ok_or_else(error)?;
 
                 let if_instruction = &mut self.instructions[if_label_pointer];
-                let Instruction::If(_, else_offset, end_offset) = if_instruction else {
+                let Instruction::If(_, else_offset, end_offset, _) = if_instruction else {
                     return Err(error());
                 };
 
@@ -471,7 +540,7 @@ impl<'a> wasmparser::VisitOperator<'a> for FunctionBuilder {
             }
             Some(Instruction::Block(_, end_offset))
             | Some(Instruction::Loop(_, end_offset))
-            | Some(Instruction::If(_, _, end_offset)) => {
+            | Some(Instruction::If(_, _, end_offset, _)) => {
                 *end_offset = (current_instr_ptr - label_pointer)
                     .try_into()
                     .expect("else_instr_end_offset is too large, interpreter does not support  blocks that large");
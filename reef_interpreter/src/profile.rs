@@ -0,0 +1,226 @@
+//! Flat profiles and coverage of function executions
+//!
+//! Enable profiling with [`ExecHandle::enable_profiling`](crate::exec::ExecHandle::enable_profiling)
+//! and retrieve the results with [`ExecHandle::profile`](crate::exec::ExecHandle::profile).
+//! Coverage is collected in the same way with [`ExecHandle::enable_coverage`](crate::exec::ExecHandle::enable_coverage).
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+use crate::imports::Function;
+use crate::types::{FuncAddr, Module};
+
+/// Execution counts of a single function
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FuncProfile {
+    /// Number of times the function was called
+    pub calls: u64,
+    /// Number of instructions executed in the function itself
+    pub instructions: u64,
+    /// Number of cycles (of the `max_cycles` passed to `run`) spent in the function, including its callees
+    ///
+    /// Recursive calls are counted once for every active call.
+    pub fuel: u64,
+}
+
+/// Per-function execution counts, indexed by function address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// The counts of every function in the instance, including imported ones
+    pub funcs: Vec<FuncProfile>,
+}
+
+impl Profile {
+    /// Render the profile as a table, sorted by fuel and using function names from the `name` section if available
+    ///
+    /// Functions that were never called are left out.
+    pub fn render(&self, module: &Module) -> String {
+        let mut funcs: Vec<_> = self.funcs.iter().enumerate().filter(|(_, f)| f.calls > 0 || f.fuel > 0).collect();
+        funcs.sort_by(|(_, a), (_, b)| b.fuel.cmp(&a.fuel).then(b.instructions.cmp(&a.instructions)));
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{:>12} {:>12} {:>12}  function", "fuel", "self", "calls");
+        for (addr, func) in funcs {
+            let _ = write!(out, "{:>12} {:>12} {:>12}  ", func.fuel, func.instructions, func.calls);
+            let _ = match module.func_name(addr as FuncAddr) {
+                Some(name) => writeln!(out, "{}", name),
+                None => writeln!(out, "func[{}]", addr),
+            };
+        }
+        out
+    }
+}
+
+/// Execution counts of every instruction, indexed by function address and instruction index
+///
+/// Instruction indices refer to the interpreter's bytecode, like [`TraceEvent::Instruction`](crate::exec::TraceEvent::Instruction).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// The counts of every function in the instance, imported functions have no instructions
+    pub funcs: Vec<Vec<u64>>,
+}
+
+impl Coverage {
+    pub(crate) fn new(funcs: &[Function]) -> Self {
+        let funcs = funcs
+            .iter()
+            .map(|func| match func {
+                Function::Wasm(func) => vec![0; func.instructions.len()],
+                Function::Host(_) => Vec::new(),
+            })
+            .collect();
+        Self { funcs }
+    }
+
+    #[inline(always)]
+    pub(crate) fn hit(&mut self, func: FuncAddr, instr_ptr: usize) {
+        if let Some(count) = self.funcs.get_mut(func as usize).and_then(|f| f.get_mut(instr_ptr)) {
+            *count += 1;
+        }
+    }
+
+    /// How often the instruction at `instr_ptr` in the function at `func` was executed
+    pub fn count(&self, func: FuncAddr, instr_ptr: usize) -> u64 {
+        self.funcs.get(func as usize).and_then(|f| f.get(instr_ptr)).copied().unwrap_or(0)
+    }
+
+    /// Indices of the instructions in the function at `func` that were never executed
+    pub fn uncovered(&self, func: FuncAddr) -> impl Iterator<Item = usize> + '_ {
+        let counts = self.funcs.get(func as usize).map(Vec::as_slice).unwrap_or_default();
+        counts.iter().enumerate().filter(|(_, count)| **count == 0).map(|(idx, _)| idx)
+    }
+
+    /// Fraction of instructions across all functions that were executed at least once
+    pub fn ratio(&self) -> f64 {
+        let (covered, total) = self
+            .funcs
+            .iter()
+            .flatten()
+            .fold((0u64, 0u64), |(covered, total), count| (covered + u64::from(*count > 0), total + 1));
+        match total {
+            0 => 1.0,
+            total => covered as f64 / total as f64,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    profile: Profile,
+    /// Total number of cycles executed
+    cycles: u64,
+    /// Functions that haven't returned yet, with the cycle count when they were entered
+    active: Vec<(FuncAddr, u64)>,
+}
+
+impl Profiler {
+    pub(crate) fn new(func_count: usize, call_stack: impl Iterator<Item = FuncAddr>) -> Self {
+        let mut profiler = Self {
+            profile: Profile { funcs: vec![FuncProfile::default(); func_count] },
+            cycles: 0,
+            active: Vec::new(),
+        };
+        call_stack.for_each(|func| profiler.call_enter(func));
+        profiler
+    }
+
+    #[inline(always)]
+    pub(crate) fn instruction(&mut self, func: FuncAddr) {
+        self.cycles += 1;
+        if let Some(f) = self.profile.funcs.get_mut(func as usize) {
+            f.instructions += 1;
+        }
+    }
+
+    pub(crate) fn call_enter(&mut self, func: FuncAddr) {
+        if let Some(f) = self.profile.funcs.get_mut(func as usize) {
+            f.calls += 1;
+        }
+        self.active.push((func, self.cycles));
+    }
+
+    pub(crate) fn call_exit(&mut self) {
+        if let Some((func, entered)) = self.active.pop() {
+            if let Some(f) = self.profile.funcs.get_mut(func as usize) {
+                f.fuel += self.cycles - entered;
+            }
+        }
+    }
+
+    /// The profile so far, counting the fuel of functions that are still running up to now
+    pub(crate) fn profile(&self) -> Profile {
+        let mut profile = self.profile.clone();
+        for (func, entered) in self.active.iter() {
+            if let Some(f) = profile.funcs.get_mut(*func as usize) {
+                f.fuel += self.cycles - entered;
+            }
+        }
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::CallResult;
+    use crate::imports::Imports;
+    use crate::{parse_bytes, Instance};
+
+    #[test]
+    fn test_profile() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func $inner (param i32) (result i32)
+                    (i32.mul (local.get 0) (i32.const 2)))
+                (func $outer (export "outer") (result i32)
+                    (i32.add (call $inner (i32.const 1)) (call $inner (call $inner (i32.const 2))))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let inner_len = module.funcs[0].instructions.len() as u64;
+        let outer_len = module.funcs[1].instructions.len() as u64;
+
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("outer").unwrap().call(alloc::vec::Vec::new(), None).unwrap();
+        exec.enable_profiling();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(_))));
+
+        let profile = exec.profile();
+        assert_eq!((profile.funcs[0].calls, profile.funcs[0].instructions), (3, 3 * inner_len));
+        assert_eq!(profile.funcs[0].fuel, profile.funcs[0].instructions);
+        assert_eq!((profile.funcs[1].calls, profile.funcs[1].instructions), (1, outer_len));
+        assert_eq!(profile.funcs[1].fuel, 3 * inner_len + outer_len);
+
+        let rendered = profile.render(&module);
+        let mut lines = rendered.lines().skip(1);
+        assert!(lines.next().unwrap().ends_with(" outer"));
+        assert!(lines.next().unwrap().ends_with(" inner"));
+    }
+
+    #[test]
+    fn test_coverage() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func (export "abs") (param i32) (result i32)
+                    (if (result i32) (i32.lt_s (local.get 0) (i32.const 0))
+                        (then (i32.sub (i32.const 0) (local.get 0)))
+                        (else (local.get 0)))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let instance = Instance::instantiate(module, Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("abs").unwrap().call(alloc::vec![5.into()], None).unwrap();
+        exec.enable_coverage();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(_))));
+
+        let coverage = exec.coverage();
+        let uncovered: alloc::vec::Vec<_> = coverage.uncovered(0).collect();
+        assert!(!uncovered.is_empty());
+        assert!(coverage.ratio() < 1.0);
+        assert_eq!(coverage.count(0, 0), 1);
+        assert!(uncovered.iter().all(|idx| coverage.count(0, *idx) == 0));
+    }
+}
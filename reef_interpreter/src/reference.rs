@@ -1,15 +1,18 @@
 //! References to parts of instatiated Wasm modules
 
 use alloc::{
+    collections::BTreeMap,
     ffi::CString,
     string::{String, ToString},
+    vec,
     vec::Vec,
 };
 use core::ffi::CStr;
 
 use crate::error::{Error, Result};
+use crate::replay::MemoryWrite;
 use crate::store::{global::GlobalInstance, memory::MemoryInstance};
-use crate::types::value::WasmValue;
+use crate::types::{value::WasmValue, ExternalKind};
 
 // This module essentially contains the public APIs to interact with the data stored in the store
 
@@ -25,11 +28,73 @@ pub struct MemoryRefMut<'m> {
     pub(crate) instance: &'m mut MemoryInstance,
 }
 
+/// A read-only copy of the exported memories and globals of an instance, see [`Instance::freeze`](crate::Instance::freeze)
+#[derive(Debug, Clone, Default)]
+pub struct InstanceView {
+    pub(crate) memories: BTreeMap<String, MemoryView>,
+    pub(crate) globals: BTreeMap<String, WasmValue>,
+}
+
+impl InstanceView {
+    /// Get an exported memory by name
+    pub fn exported_memory(&self, name: &str) -> Result<&MemoryView> {
+        self.memories.get(name).ok_or_else(|| Error::export_not_found(name, ExternalKind::Memory))
+    }
+
+    /// Get the value of an exported global by name
+    pub fn exported_global(&self, name: &str) -> Result<WasmValue> {
+        self.globals.get(name).copied().ok_or_else(|| Error::export_not_found(name, ExternalKind::Global))
+    }
+}
+
+/// A read-only copy of a memory, see [`InstanceView`]
+#[derive(Debug, Clone)]
+pub struct MemoryView {
+    pub(crate) instance: MemoryInstance,
+}
+
+impl MemoryView {
+    /// Load a slice of memory
+    pub fn load(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.instance.load(offset, len)
+    }
+
+    /// Load a slice of memory into a vector
+    pub fn load_vec(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
+        self.load(offset, len).map(|x| x.to_vec())
+    }
+
+    /// Load `count` consecutive little-endian values of type `T` starting at `offset`
+    pub fn load_slice<T: Pod>(&self, offset: usize, count: usize) -> Result<Vec<T>> {
+        load_pod_vec(&self.instance, offset, count)
+    }
+
+    /// Get the size of the memory in pages
+    pub fn page_count(&self) -> usize {
+        self.instance.page_count()
+    }
+}
+
+impl MemoryRefLoad for MemoryView {
+    /// Load a slice of memory
+    fn load(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.instance.load(offset, len)
+    }
+
+    fn size(&self) -> usize {
+        self.instance.data.len()
+    }
+}
+
 impl<'a> MemoryRefLoad for MemoryRef<'a> {
     /// Load a slice of memory
     fn load(&self, offset: usize, len: usize) -> Result<&[u8]> {
         self.instance.load(offset, len)
     }
+
+    fn size(&self) -> usize {
+        self.instance.data.len()
+    }
 }
 
 impl<'a> MemoryRefLoad for MemoryRefMut<'a> {
@@ -37,6 +102,10 @@ impl<'a> MemoryRefLoad for MemoryRefMut<'a> {
     fn load(&self, offset: usize, len: usize) -> Result<&[u8]> {
         self.instance.load(offset, len)
     }
+
+    fn size(&self) -> usize {
+        self.instance.data.len()
+    }
 }
 
 impl MemoryRef<'_> {
@@ -45,10 +114,15 @@ impl MemoryRef<'_> {
         self.instance.load(offset, len)
     }
 
-    /// Load a slice of memory as a vector
+    /// Load a slice of memory into a vector
     pub fn load_vec(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
         self.load(offset, len).map(|x| x.to_vec())
     }
+
+    /// Load `count` consecutive little-endian values of type `T` starting at `offset`
+    pub fn load_slice<T: Pod>(&self, offset: usize, count: usize) -> Result<Vec<T>> {
+        load_pod_vec(self.instance, offset, count)
+    }
 }
 
 impl MemoryRefMut<'_> {
@@ -57,14 +131,23 @@ impl MemoryRefMut<'_> {
         self.instance.load(offset, len)
     }
 
-    /// Load a slice of memory as a vector
+    /// Load a slice of memory into a vector
     pub fn load_vec(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
         self.load(offset, len).map(|x| x.to_vec())
     }
 
+    /// Load `count` consecutive little-endian values of type `T` starting at `offset`
+    pub fn load_slice<T: Pod>(&self, offset: usize, count: usize) -> Result<Vec<T>> {
+        load_pod_vec(self.instance, offset, count)
+    }
+
     /// Grow the memory by the given number of pages
     pub fn grow(&mut self, delta_pages: i32) -> Option<i32> {
-        self.instance.grow(delta_pages)
+        let prev_pages = self.instance.grow(delta_pages)?;
+        if let Some(writes) = &mut self.instance.host_writes {
+            writes.push(MemoryWrite::Grow { delta_pages });
+        }
+        Some(prev_pages)
     }
 
     /// Get the current size of the memory in pages
@@ -74,23 +157,104 @@ impl MemoryRefMut<'_> {
 
     /// Copy a slice of memory to another place in memory
     pub fn copy_within(&mut self, src: usize, dst: usize, len: usize) -> Result<()> {
-        self.instance.copy_within(src, dst, len)
+        self.instance.copy_within(dst, src, len)?;
+        self.instance.record_host_write(dst, len);
+        Ok(())
     }
 
     /// Fill a slice of memory with a value
     pub fn fill(&mut self, offset: usize, len: usize, val: u8) -> Result<()> {
-        self.instance.fill(offset, len, val)
+        self.instance.fill(offset, len, val)?;
+        self.instance.record_host_write(offset, len);
+        Ok(())
     }
 
     /// Store a slice of memory
     pub fn store(&mut self, offset: usize, len: usize, data: &[u8]) -> Result<()> {
-        self.instance.store(offset, len, data)
+        self.instance.store(offset, len, data)?;
+        self.instance.record_host_write(offset, len);
+        Ok(())
+    }
+
+    /// Store a string as UTF-8 without a terminator, returning the number of bytes written
+    pub fn store_string(&mut self, offset: usize, string: &str) -> Result<usize> {
+        self.store(offset, string.len(), string.as_bytes())?;
+        Ok(string.len())
+    }
+
+    /// Store a string as UTF-8 followed by a nul byte, returning the number of bytes written including the nul
+    pub fn store_cstring(&mut self, offset: usize, string: &CStr) -> Result<usize> {
+        let bytes = string.to_bytes_with_nul();
+        self.store(offset, bytes.len(), bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Store a string as little-endian UTF-16 without a terminator, returning the number of bytes written
+    pub fn store_string_utf16le(&mut self, offset: usize, string: &str) -> Result<usize> {
+        let units: Vec<u16> = string.encode_utf16().collect();
+        self.store_slice(offset, &units)?;
+        Ok(units.len() * 2)
+    }
+
+    /// Store `values` as consecutive little-endian values starting at `offset`
+    pub fn store_slice<T: Pod>(&mut self, offset: usize, values: &[T]) -> Result<()> {
+        let len = values.len().checked_mul(T::SIZE).ok_or_else(|| self.instance.trap_oob(offset, usize::MAX))?;
+        let mut bytes = vec![0; len];
+        for (value, chunk) in values.iter().zip(bytes.chunks_exact_mut(T::SIZE)) {
+            value.write_le_bytes(chunk);
+        }
+        self.store(offset, len, &bytes)
     }
 }
 
+fn load_pod_vec<T: Pod>(memory: &MemoryInstance, offset: usize, count: usize) -> Result<Vec<T>> {
+    let len = count.checked_mul(T::SIZE).ok_or_else(|| memory.trap_oob(offset, usize::MAX))?;
+    let bytes = memory.load(offset, len)?;
+    Ok(bytes.chunks_exact(T::SIZE).map(T::from_le_bytes).collect())
+}
+
+/// A plain value type that can be copied to and from memory in little-endian byte order
+///
+/// Used by [`MemoryRef::load_slice`] and [`MemoryRefMut::store_slice`], implemented for all primitive integer and
+/// float types.
+pub trait Pod: Copy {
+    /// The size of the value in bytes
+    const SIZE: usize;
+
+    /// Read a value from exactly [`SIZE`](Pod::SIZE) little-endian bytes
+    fn from_le_bytes(bytes: &[u8]) -> Self;
+
+    /// Write the value into exactly [`SIZE`](Pod::SIZE) bytes in little-endian order
+    fn write_le_bytes(self, bytes: &mut [u8]);
+}
+
+macro_rules! impl_pod {
+    ($($type:ty),*) => {
+        $(
+            impl Pod for $type {
+                const SIZE: usize = core::mem::size_of::<$type>();
+
+                #[inline]
+                fn from_le_bytes(bytes: &[u8]) -> Self {
+                    <$type>::from_le_bytes(bytes.try_into().expect("slice has the size of the value"))
+                }
+
+                #[inline]
+                fn write_le_bytes(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_le_bytes())
+                }
+            }
+        )*
+    }
+}
+
+impl_pod!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, f32, f64);
+
 #[doc(hidden)]
 pub trait MemoryRefLoad {
     fn load(&self, offset: usize, len: usize) -> Result<&[u8]>;
+    /// The size of the memory in bytes
+    fn size(&self) -> usize;
     fn load_vec(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
         self.load(offset, len).map(|x| x.to_vec())
     }
@@ -105,8 +269,10 @@ pub trait MemoryStringExt: MemoryRefLoad {
     }
 
     /// Load a C-style string from memory, stopping at the first nul byte
+    ///
+    /// At most `max_len` bytes including the nul are read, fewer if the memory ends before that.
     fn load_cstr_until_nul(&self, offset: usize, max_len: usize) -> Result<&CStr> {
-        let bytes = self.load(offset, max_len)?;
+        let bytes = self.load(offset, max_len.min(self.size().saturating_sub(offset)))?;
         CStr::from_bytes_until_nul(bytes).map_err(|_| Error::Other("Invalid C-style string".to_string()))
     }
 
@@ -116,30 +282,47 @@ pub trait MemoryStringExt: MemoryRefLoad {
         String::from_utf8(bytes.to_vec()).map_err(|_| Error::Other("Invalid UTF-8 string".to_string()))
     }
 
-    /// Load a C-style string from memory
-    fn load_cstring(&self, offset: usize, len: usize) -> Result<CString> {
-        Ok(CString::from(self.load_cstr(offset, len)?))
+    /// Load a nul-terminated C-style string of at most `max_len` bytes from memory
+    ///
+    /// See [`load_cstr_until_nul`](MemoryStringExt::load_cstr_until_nul).
+    fn load_cstring(&self, offset: usize, max_len: usize) -> Result<CString> {
+        Ok(CString::from(self.load_cstr_until_nul(offset, max_len)?))
     }
 
     /// Load a C-style string from memory, stopping at the first nul byte
+    ///
+    /// Same as [`load_cstring`](MemoryStringExt::load_cstring).
     fn load_cstring_until_nul(&self, offset: usize, max_len: usize) -> Result<CString> {
-        Ok(CString::from(self.load_cstr_until_nul(offset, max_len)?))
+        self.load_cstring(offset, max_len)
     }
 
     /// Load a JavaScript-style utf-16 string from memory
+    ///
+    /// A trailing odd byte is ignored, see [`load_string_utf16le`](MemoryStringExt::load_string_utf16le) for a
+    /// stricter version.
     fn load_js_string(&self, offset: usize, len: usize) -> Result<String> {
-        let bytes = self.load(offset, len)?;
-        let mut string = String::new();
-        for i in 0..(len / 2) {
-            let c = u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]);
-            string.push(char::from_u32(c as u32).ok_or_else(|| Error::Other("Invalid UTF-16 string".to_string()))?);
+        self.load_string_utf16le(offset, len & !1)
+    }
+
+    /// Load a little-endian UTF-16 string of `len` bytes from memory, as used by .NET and AssemblyScript
+    ///
+    /// Surrogate pairs are decoded, unpaired surrogates are an error.
+    fn load_string_utf16le(&self, offset: usize, len: usize) -> Result<String> {
+        if !len.is_multiple_of(2) {
+            return Err(Error::Other("Invalid UTF-16 string: odd byte length".to_string()));
         }
-        Ok(string)
+
+        let bytes = self.load(offset, len)?;
+        let units = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
+        char::decode_utf16(units)
+            .collect::<core::result::Result<String, _>>()
+            .map_err(|_| Error::Other("Invalid UTF-16 string".to_string()))
     }
 }
 
 impl MemoryStringExt for MemoryRef<'_> {}
 impl MemoryStringExt for MemoryRefMut<'_> {}
+impl MemoryStringExt for MemoryView {}
 
 /// A reference to a global instance
 #[derive(Debug)]
@@ -158,3 +341,57 @@ impl<'i> GlobalRef<'i> {
         self.instance.set(val)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MemoryType;
+
+    #[test]
+    fn test_pod_slices() {
+        let mut memory = MemoryInstance::new(MemoryType::new_32(1, None));
+        let mut mem = MemoryRefMut { instance: &mut memory };
+
+        mem.store_slice(8, &[1u32, 0xdead_beef, 3]).unwrap();
+        assert_eq!(mem.load(12, 4).unwrap(), [0xef, 0xbe, 0xad, 0xde]);
+        assert_eq!(mem.load_slice::<u32>(8, 3).unwrap(), [1, 0xdead_beef, 3]);
+        assert_eq!(mem.load_vec(8, 2).unwrap(), [1, 0]);
+
+        mem.store_slice(0x100, &[1.5f64, -2.0]).unwrap();
+        assert_eq!(MemoryRef { instance: mem.instance }.load_slice::<f64>(0x100, 2).unwrap(), [1.5, -2.0]);
+
+        assert!(mem.store_slice(crate::PAGE_SIZE - 4, &[0u64]).is_err());
+        assert!(mem.load_slice::<u64>(crate::PAGE_SIZE - 4, 1).is_err());
+        assert!(mem.load_slice::<u64>(0, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut memory = MemoryInstance::new(MemoryType::new_32(1, None));
+        let mut mem = MemoryRefMut { instance: &mut memory };
+
+        assert_eq!(mem.store_string(0, "héllo").unwrap(), 6);
+        assert_eq!(mem.load_string(0, 6).unwrap(), "héllo");
+
+        assert_eq!(mem.store_cstring(16, c"abc").unwrap(), 4);
+        assert_eq!(mem.load_cstring(16, 64).unwrap().as_bytes(), b"abc");
+        assert_eq!(mem.load_cstring_until_nul(16, 64).unwrap().as_bytes(), b"abc");
+        assert!(mem.load_cstring(16, 3).is_err());
+
+        // strings at the end of the memory can be loaded with a larger maximum length
+        let end = crate::PAGE_SIZE;
+        mem.store_cstring(end - 4, c"xyz").unwrap();
+        assert_eq!(mem.load_cstring(end - 4, 64).unwrap().as_bytes(), b"xyz");
+        mem.store(end - 1, 1, b"!").unwrap();
+        assert!(mem.load_cstring(end - 4, 64).is_err());
+        assert!(mem.load_cstring(end + 1, 64).is_err());
+
+        assert_eq!(mem.store_string_utf16le(32, "a😀").unwrap(), 6);
+        assert_eq!(mem.load_string_utf16le(32, 6).unwrap(), "a😀");
+        assert_eq!(mem.load_js_string(32, 7).unwrap(), "a😀");
+        assert!(mem.load_string_utf16le(32, 7).is_err());
+        assert!(mem.load_string_utf16le(32, 4).is_err());
+
+        assert!(mem.store_string(crate::PAGE_SIZE - 2, "abc").is_err());
+    }
+}
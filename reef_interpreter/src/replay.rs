@@ -0,0 +1,285 @@
+//! Recording and replaying the results of host function calls
+//!
+//! Host functions are the only source of non-determinism for a Wasm guest: clocks, random numbers and
+//! everything else a guest learns about the outside world passes through an import.
+//! Recording their results with [`ExecHandle::start_recording`](crate::exec::ExecHandle::start_recording)
+//! and feeding them back with [`ExecHandle::replay`](crate::exec::ExecHandle::replay) reproduces an execution
+//! without calling the real imports.
+//!
+//! Return values, calls back into the guest, exits (see [`Error::exit`]) and what host functions write to guest
+//! memory through [`MemoryRefMut`](crate::reference::MemoryRefMut) are recorded, so imports like WASI's
+//! `random_get` and `clock_time_get` that return their results through memory are replayed as well. Changes to
+//! globals and tables made by host functions are not part of the log.
+
+use alloc::{format, vec::Vec};
+
+use rkyv::{
+    ser::{
+        serializers::{AlignedSerializer, AllocScratch, CompositeSerializer, SharedSerializeMap},
+        Serializer,
+    },
+    AlignedVec, Deserialize,
+};
+
+use crate::error::{Error, Result, Trap};
+use crate::imports::HostReturn;
+use crate::runtime::RawWasmValue;
+use crate::store::memory::MemoryInstance;
+use crate::types::{FuncAddr, FuncType, MemAddr};
+
+/// The results of all host function calls made during an execution, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct ReplayLog {
+    calls: Vec<RecordedCall>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+struct RecordedCall {
+    func: FuncAddr,
+    /// The guest function called in place of the host function, see
+    /// [`FuncContext::call_exported`](crate::imports::FuncContext::call_exported)
+    callback: Option<FuncAddr>,
+    /// The status the host function ended the execution with, see [`Error::exit`]
+    exit: Option<i32>,
+    /// The results of the host function, or the parameters of the callback
+    results: Vec<RawWasmValue>,
+    /// What the host function wrote to guest memories, in order for each memory
+    writes: Vec<(MemAddr, MemoryWrite)>,
+}
+
+/// A change a host function made to a guest memory
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub(crate) enum MemoryWrite {
+    /// Bytes stored at `offset`
+    Store { offset: u64, data: Vec<u8> },
+    /// The memory was grown
+    Grow { delta_pages: i32 },
+}
+
+impl ReplayLog {
+    /// Number of recorded host function calls
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Whether no host function calls were recorded
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Serialize the log, e.g. to store it alongside a production trace
+    pub fn to_bytes(&self) -> Result<AlignedVec> {
+        let mut serializer = CompositeSerializer::new(
+            AlignedSerializer::new(AlignedVec::new()),
+            AllocScratch::new(),
+            SharedSerializeMap::new(),
+        );
+        serializer
+            .serialize_value(self)
+            .map_err(|e| Error::Other(format!("Failed to serialize replay log: {:?}", e)))?;
+        Ok(serializer.into_serializer().into_inner())
+    }
+
+    /// Load a log serialized with [`ReplayLog::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let archived =
+            rkyv::check_archived_root::<Self>(bytes).map_err(|e| Error::Other(format!("Invalid replay log: {}", e)))?;
+        Ok(archived.deserialize(&mut rkyv::Infallible).unwrap_or_else(|e| match e {}))
+    }
+
+    fn record(&mut self, func: FuncAddr, ret: &Result<HostReturn>, writes: Vec<(MemAddr, MemoryWrite)>) {
+        self.calls.push(match ret {
+            Ok(HostReturn::Results(results)) => RecordedCall {
+                func,
+                callback: None,
+                exit: None,
+                results: results.iter().map(|v| RawWasmValue::from(*v)).collect(),
+                writes,
+            },
+            Ok(HostReturn::Callback { func: callback, params }) => {
+                RecordedCall { func, callback: Some(*callback), exit: None, results: params.clone(), writes }
+            }
+            Err(Error::Trap(Trap::Exit(code))) => {
+                RecordedCall { func, callback: None, exit: Some(*code), results: Vec::new(), writes }
+            }
+            // other errors end the execution, there is nothing to replay
+            Err(_) => return,
+        });
+    }
+}
+
+/// How host function calls are handled by the interpreter
+#[derive(Debug)]
+pub(crate) enum HostCalls {
+    Record(ReplayLog),
+    Replay { log: ReplayLog, next: usize },
+}
+
+impl HostCalls {
+    /// Call a host function with the guest's `memories`, or take its results and memory writes from the log
+    pub(crate) fn call(
+        &mut self,
+        func: FuncAddr,
+        ty: &FuncType,
+        memories: &mut Vec<MemoryInstance>,
+        call: impl FnOnce(&mut Vec<MemoryInstance>) -> Result<HostReturn>,
+    ) -> Result<HostReturn> {
+        match self {
+            Self::Record(log) => {
+                memories.iter_mut().for_each(|mem| mem.host_writes = Some(Vec::new()));
+                let ret = call(memories);
+                let mut writes = Vec::new();
+                for (addr, mem) in memories.iter_mut().enumerate() {
+                    let mem_writes = mem.host_writes.take().unwrap_or_default();
+                    writes.extend(mem_writes.into_iter().map(|write| (addr as MemAddr, write)));
+                }
+                log.record(func, &ret, writes);
+                ret
+            }
+            Self::Replay { log, next } => {
+                let call = log.calls.get(*next).ok_or_else(|| {
+                    Error::Other(format!("Replay log exhausted at call {} to function {}", next, func))
+                })?;
+                let has_results = call.callback.is_none() && call.exit.is_none();
+                if call.func != func || (has_results && call.results.len() != ty.results.len()) {
+                    return Err(Error::Other(format!(
+                        "Execution diverged from the replay log at call {}: expected function {}, got {}",
+                        next, call.func, func
+                    )));
+                }
+
+                *next += 1;
+                for (addr, write) in call.writes.iter() {
+                    let mem = memories.get_mut(*addr as usize).ok_or_else(|| {
+                        Error::Other(format!("Replay log writes to memory {}, which doesn't exist", addr))
+                    })?;
+                    match write {
+                        MemoryWrite::Store { offset, data } => mem.store(*offset as usize, data.len(), data)?,
+                        MemoryWrite::Grow { delta_pages } => {
+                            mem.grow(*delta_pages).ok_or_else(|| {
+                                Error::Other(format!("Replay log grows memory {} beyond its limits", addr))
+                            })?;
+                        }
+                    }
+                }
+                if let Some(code) = call.exit {
+                    return Err(Error::exit(code));
+                }
+                Ok(match call.callback {
+                    Some(callback) => HostReturn::Callback { func: callback, params: call.results.clone() },
+                    None => HostReturn::Results(
+                        call.results.iter().zip(ty.results.iter()).map(|(v, ty)| v.attach_type(*ty)).collect(),
+                    ),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::exec::CallResult;
+    use crate::imports::{Extern, Imports};
+    use crate::types::value::WasmValue;
+    use crate::{parse_bytes, Instance};
+
+    fn run(random: impl Fn() -> i64 + 'static, log: Option<ReplayLog>) -> (Vec<WasmValue>, Option<ReplayLog>) {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "random" (func $random (result i64)))
+                (func (export "main") (result i64)
+                    (i64.sub (call $random) (call $random))))
+            "#,
+        )
+        .unwrap();
+
+        let mut imports = Imports::new();
+        imports.define("env", "random", Extern::typed_func(move |_, ()| Ok(random()))).unwrap();
+        let instance = Instance::instantiate(parse_bytes(&wasm).unwrap(), imports).unwrap();
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+
+        match log {
+            Some(log) => exec.replay(log),
+            None => exec.start_recording(),
+        }
+        let Ok(CallResult::Done(res)) = exec.run(usize::MAX) else { panic!("expected the function to finish") };
+        (res, exec.take_recording())
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let counter = Rc::new(Cell::new(0));
+        let (recorded, log) = run(
+            move || {
+                counter.set(counter.get() + 7);
+                counter.get()
+            },
+            None,
+        );
+        assert_eq!(recorded, [WasmValue::I64(-7)]);
+
+        let log = ReplayLog::from_bytes(&log.unwrap().to_bytes().unwrap()).unwrap();
+        assert_eq!(log.len(), 2);
+
+        let (replayed, _) = run(|| panic!("imports must not be called during replay"), Some(log));
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn test_replay_memory_writes() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "wasi" "random_get" (func $random_get (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "main") (result i64) (local $i i32) (local $sum i64)
+                    (loop $l
+                        (drop (call $random_get (i32.const 16) (i32.const 8)))
+                        (local.set $sum (i64.add (local.get $sum) (i64.load (i32.const 16))))
+                        (br_if $l (i32.lt_u (local.tee $i (i32.add (local.get $i) (i32.const 1))) (i32.const 500))))
+                    (i64.add (local.get $sum) (i64.extend_i32_u (memory.size)))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let run = |random: Option<Rc<Cell<u64>>>, log: Option<ReplayLog>| {
+            let mut imports = Imports::new();
+            let random_get = Extern::typed_func(move |mut ctx, (ptr, _len): (i32, i32)| {
+                let random = random.as_ref().expect("imports must not be called during replay");
+                random.set(random.get() + 3);
+                let mut memory = ctx.exported_memory_mut("memory")?;
+                // the first call also grows the memory, which has to be replayed as well
+                if random.get() == 3 {
+                    memory.grow(1);
+                }
+                memory.store_slice(ptr as usize, &[random.get()])?;
+                Ok(0)
+            });
+            imports.define("wasi", "random_get", random_get).unwrap();
+            let instance = Instance::instantiate(module.clone(), imports).unwrap();
+            let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+            match log {
+                Some(log) => exec.replay(log),
+                None => exec.start_recording(),
+            }
+            let Ok(CallResult::Done(res)) = exec.run(usize::MAX) else { panic!("expected the function to finish") };
+            (res, exec.take_recording())
+        };
+
+        let (recorded, log) = run(Some(Rc::new(Cell::new(0))), None);
+        assert_eq!(recorded, [WasmValue::I64((1..=500).map(|i| i * 3).sum::<i64>() + 2)]);
+
+        let log = ReplayLog::from_bytes(&log.unwrap().to_bytes().unwrap()).unwrap();
+        assert_eq!(log.len(), 500);
+        let (replayed, _) = run(None, Some(log));
+        assert_eq!(replayed, recorded);
+    }
+}
@@ -10,13 +10,13 @@
 // This is a bit hard to see from the spec, but it's vaild to use breaks to return
 // from a function, so we need to check if the label stack is empty
 macro_rules! break_to {
-    ($cf:ident, $stack:ident, $module:ident, $store:ident, $break_to_relative:expr) => {{
+    ($self:ident, $cf:ident, $stack:ident, $break_to_relative:expr) => {{
         if $cf.break_to($break_to_relative, &mut $stack.values, &mut $stack.blocks).is_none() {
             if $stack.call_stack.is_empty() {
                 return Ok(true);
             }
 
-            call!($cf, $stack, $module, $store)
+            call!($self, $cf, $stack)
         }
     }};
 }
@@ -33,17 +33,19 @@ macro_rules! mem_load {
             module: &crate::instance::Instance,
             stack: &mut crate::runtime::Stack,
             mem_addr: crate::types::MemAddr,
-            offset: u64,
+            offset: u32,
         ) -> Result<()> {
             let mem = module.get_mem(mem_addr)?;
-            let addr: usize = match offset.checked_add(stack.values.pop()?.into()).map(|a| a.try_into()) {
-                Some(Ok(a)) => a,
-                _ => {
+            // addresses are unsigned 32-bit, so this can't overflow
+            let addr = offset as u64 + u32::from(stack.values.pop()?) as u64;
+            let addr: usize = match addr.try_into() {
+                Ok(a) => a,
+                Err(_) => {
                     cold();
                     return Err(Error::Trap(crate::error::Trap::MemoryOutOfBounds {
                         offset: offset as usize,
                         len: core::mem::size_of::<$load_type>(),
-                        max: mem.max_pages(),
+                        max: mem.data.len(),
                     }));
                 }
             };
@@ -71,13 +73,13 @@ macro_rules! mem_store {
             module: &mut crate::Instance,
             stack: &mut crate::runtime::Stack,
             mem_addr: crate::types::MemAddr,
-            offset: u64,
+            offset: u32,
         ) -> Result<()> {
             let mem = module.get_mem_mut(mem_addr)?;
             let val: $store_type = stack.values.pop()?.into();
             let val = val.to_le_bytes();
-            let addr: u64 = stack.values.pop()?.into();
-            mem.store((offset + addr) as usize, val.len(), &val)?;
+            let addr = offset as u64 + u32::from(stack.values.pop()?) as u64;
+            mem.store(usize::try_from(addr).unwrap_or(usize::MAX), val.len(), &val)?;
             Ok(())
         }
 
@@ -199,9 +201,13 @@ macro_rules! checked_int_arithmetic {
 }
 
 macro_rules! call {
-    ($cf:expr, $stack:expr, $module:expr, $store:expr) => {{
+    ($self:ident, $cf:expr, $stack:expr) => {{
         let old = $cf.block_ptr;
+        let func = $cf.func_instance;
+        // drop everything the callee left below its results
+        $stack.values.truncate_keep($cf.stack_ptr, $cf.results);
         $cf = $stack.call_stack.pop()?;
+        $self.call_exit(func);
 
         if old > $cf.block_ptr {
             $stack.blocks.truncate(old);
@@ -1,16 +1,38 @@
+//! The interpreter for our internal bytecode
+//!
+//! The bytecode is a stack machine that stays close to Wasm, with a few fused instructions for common sequences.
+//! Translating it into a register-based IR (with locals and stack slots mapped to frame-relative registers)
+//! would remove most of the traffic on the value stack, but it conflicts with two things we rely on:
+//! * Execution state is serialized and resumed at any instruction, see [`crate::exec::ExecHandle::serialize`].
+//!   A register IR would have to keep its register file in the call frame and stay stable across versions.
+//! * Instruction indices are exposed to users through breakpoints, traces, coverage and the transform API,
+//!   which all assume a one-to-one mapping to the (fused) Wasm instructions.
+//!
+//! Until that changes, reducing stack traffic is done with more fused instructions instead.
+
 use alloc::format;
 use alloc::string::ToString;
-use core::ops::{BitAnd, BitOr, BitXor, Neg};
+use alloc::vec::Vec;
+use core::ops::Neg;
 
 use crate::error::{Error, Result, Trap};
-use crate::imports::{FuncContext, Function};
+use crate::exec::{AlignmentCheck, ExecStats, MemoryGrowEvent, SafePoint, TraceEvent, TraceHook};
+use crate::externs::ExternRefs;
+use crate::imports::{Callback, FuncContext, Function, HostFunction, HostReturn, ImportQuotas};
 use crate::instance::Instance;
+use crate::profile::{Coverage, Profiler};
+use crate::replay::HostCalls;
 use crate::runtime::{BlockFrame, BlockType, CallFrame, RawWasmValue, Stack};
-use crate::types::{instructions::BlockArgs, value::ValType, ElementKind};
+use crate::store::memory::MemoryInstance;
+use crate::types::{
+    instructions::{BlockArgs, Instruction},
+    value::{ValType, WasmValue},
+    ElementKind, FuncAddr, MemAddr, Module,
+};
 use crate::{cold, unlikely, VecExt};
 
 mod macros;
-mod traits;
+pub(super) mod traits;
 use {macros::*, traits::*};
 
 #[cfg(not(feature = "std"))]
@@ -22,18 +44,94 @@ use no_std_floats::NoStdFloatExt;
 
 /// The Wasm interpreter.
 #[derive(Debug, Default)]
-pub(crate) struct Interpreter {}
+pub(crate) struct Interpreter<'h> {
+    pub(crate) trace: Option<&'h mut TraceHook>,
+    pub(crate) profiler: Option<&'h mut Profiler>,
+    pub(crate) coverage: Option<&'h mut Coverage>,
+    pub(crate) host_calls: Option<&'h mut HostCalls>,
+    /// Sorted list of breakpoints
+    pub(crate) breakpoints: &'h [(FuncAddr, usize)],
+    /// Number of instructions between checks of the cycle budget
+    pub(crate) fuel_check_interval: usize,
+    /// Whether running out of budget only pauses at a [`SafePoint`]
+    pub(crate) safe_points_only: bool,
+    /// How misaligned memory accesses are handled
+    pub(crate) alignment_check: AlignmentCheck,
+    /// Instructions executed after the budget ran out, while looking for a safe point
+    pub(crate) overrun: usize,
+    /// The function, instruction and call depth of the last instruction executed while looking for a safe point
+    pub(crate) last_overrun: Option<(FuncAddr, usize, usize)>,
+    /// Whether execution is resumed from a breakpoint, which must not be hit again
+    pub(crate) resume_from_breakpoint: bool,
+    pub(crate) hit_breakpoint: bool,
+    /// Counts of the last call to `exec`, except for memory growth
+    pub(crate) stats: ExecStats,
+}
+
+impl Interpreter<'_> {
+    pub(crate) fn exec(&mut self, instance: &mut Instance, stack: &mut Stack, max_cycles: usize) -> Result<bool> {
+        self.stats = ExecStats { max_call_depth: stack.call_stack.len(), ..ExecStats::default() };
+        stack.pause_point = None;
+
+        let mut budget = max_cycles.saturating_add(1);
+        let mut fuel = 0;
+        let done = self.exec_loop(instance, stack, &mut budget, &mut fuel);
 
-impl Interpreter {
-    pub(crate) fn exec(&self, mut instance: &mut Instance, stack: &mut Stack, max_cycles: usize) -> Result<bool> {
+        // every instruction takes one cycle, except for the one a breakpoint stopped at
+        let cycles = max_cycles.saturating_add(1) - budget - fuel - self.hit_breakpoint as usize + self.overrun;
+        self.stats.instructions = cycles as u64;
+        self.stats.fuel = cycles as u64;
+        done
+    }
+
+    /// The main loop, with the remaining cycles kept by the caller so they're known however execution stops
+    #[inline(always)]
+    fn exec_loop(
+        &mut self,
+        mut instance: &mut Instance,
+        stack: &mut Stack,
+        budget: &mut usize,
+        fuel: &mut usize,
+    ) -> Result<bool> {
         let mut cf = stack.call_stack.pop()?;
-        // let mut instance = store.get_module_instance().unwrap().clone();
 
-        for _ in 0..=max_cycles {
+        // hooks and breakpoints are checked with a single branch, so they cost nothing when unused
+        let instrumented = self.is_instrumented();
+        let check_alignment = self.alignment_check != AlignmentCheck::Off;
+
+        // the budget is only consulted once `fuel` runs out, every `fuel_check_interval` instructions
+        let interval = self.fuel_check_interval.max(1);
+        loop {
             use crate::types::instructions::Instruction::*;
 
-            let curr_instr = cf.fetch_instr(&instance.funcs);
+            if unlikely(*fuel == 0) {
+                if *budget == 0 {
+                    if !self.safe_points_only {
+                        break;
+                    }
+                    if let Some(point) = self.safe_point(&cf, stack, &instance.funcs) {
+                        stack.pause_point = Some(point);
+                        break;
+                    }
+                    // continue one instruction at a time until the next safe point
+                    self.overrun += 1;
+                    self.last_overrun = Some((cf.func_instance, cf.instr_ptr, stack.call_stack.len()));
+                    *fuel = 1;
+                } else {
+                    *fuel = interval.min(*budget);
+                    *budget -= *fuel;
+                }
+            }
+            *fuel -= 1;
+
+            if unlikely(instrumented) && self.instrument(&cf, &instance.funcs) {
+                break;
+            }
 
+            let curr_instr = cf.fetch_instr(&instance.funcs)?;
+            if unlikely(check_alignment) {
+                self.check_alignment(&curr_instr, stack, &cf)?;
+            }
             match curr_instr {
                 Nop => cold(),
                 Unreachable => self.exec_unreachable()?,
@@ -44,38 +142,44 @@ impl Interpreter {
                 CallIndirect(ty, table) => {
                     skip!(self.exec_call_indirect(ty, table, stack, &mut cf, instance))
                 }
-                If(args, el, end) => skip!(self.exec_if((args).into(), el, end, stack, &mut cf, instance)),
+                If(args, el, end, _) => skip!(self.exec_if((args).into(), el, end, stack, &mut cf, instance)),
                 Loop(args, end) => self.enter_block(stack, cf.instr_ptr, end, BlockType::Loop, args, instance),
                 Block(args, end) => self.enter_block(stack, cf.instr_ptr, end, BlockType::Block, args, instance),
 
-                Br(v) => break_to!(cf, stack, module, store, v),
-                BrIf(v) => {
+                Br(v) => break_to!(self, cf, stack, v),
+                BrIf(v, _) => {
                     if i32::from(stack.values.pop()?) != 0 {
-                        break_to!(cf, stack, module, store, v);
+                        break_to!(self, cf, stack, v);
                     }
                 }
-                BrTable(default, len) => {
-                    let start = cf.instr_ptr + 1;
-                    let end = start + len as usize;
-                    if end > cf.instructions(&instance.funcs).len() {
-                        return Err(Error::Other(format!(
-                            "br_table out of bounds: {} >= {}",
-                            end,
-                            cf.instructions(&instance.funcs).len()
-                        )));
+                I32EqzBrIf(v, _) => {
+                    if i32::from(stack.values.pop()?) == 0 {
+                        break_to!(self, cf, stack, v);
                     }
-
-                    let idx: i32 = stack.values.pop()?.into();
-                    match cf.instructions(&instance.funcs)[start..end].get(idx as usize) {
-                        None => break_to!(cf, stack, module, store, default),
-                        Some(BrLabel(to)) => break_to!(cf, stack, module, store, *to),
-                        _ => return Err(Error::Other("br_table with invalid label".to_string())),
+                }
+                I32CmpBrIf(cmp, v, _) => {
+                    let b: i32 = stack.values.pop()?.into();
+                    let a: i32 = stack.values.pop()?.into();
+                    if cmp.eval(a, b) {
+                        break_to!(self, cf, stack, v);
                     }
                 }
+                BrTable(default, len) => {
+                    // the labels following the br_table are its jump table
+                    let idx: u32 = stack.values.pop()?.into();
+                    let to = match idx < len {
+                        true => match cf.instructions(&instance.funcs).get(cf.instr_ptr + 1 + idx as usize) {
+                            Some(BrLabel(to)) => *to,
+                            _ => return Err(Error::InvalidBytecode("br_table with invalid label".to_string())),
+                        },
+                        false => default,
+                    };
+                    break_to!(self, cf, stack, to);
+                }
 
                 Return => match stack.call_stack.is_empty() {
                     true => return Ok(true),
-                    false => call!(cf, stack, module, store),
+                    false => call!(self, cf, stack),
                 },
 
                 // We're essentially using else as a EndBlockFrame instruction for if blocks
@@ -85,8 +189,8 @@ impl Interpreter {
                 EndBlockFrame => self.exec_end_block(stack)?,
 
                 LocalGet(local_index) => self.exec_local_get(local_index, stack, &cf),
-                LocalSet(local_index) => self.exec_local_set(local_index, stack, &mut cf)?,
-                LocalTee(local_index) => self.exec_local_tee(local_index, stack, &mut cf)?,
+                LocalSet(local_index) => self.exec_local_set(local_index, stack, &cf)?,
+                LocalTee(local_index) => self.exec_local_tee(local_index, stack, &cf)?,
 
                 GlobalGet(global_index) => self.exec_global_get(global_index, stack, instance)?,
                 GlobalSet(global_index) => self.exec_global_set(global_index, stack, instance)?,
@@ -105,30 +209,30 @@ impl Interpreter {
                 MemoryInit(data_idx, mem_idx) => self.exec_memory_init(data_idx, mem_idx, stack, instance)?,
                 DataDrop(data_index) => instance.get_data_mut(data_index)?.drop(),
 
-                I32Store { mem_addr, offset } => mem_store!(i32, (mem_addr, offset), stack, instance),
-                I64Store { mem_addr, offset } => mem_store!(i64, (mem_addr, offset), stack, instance),
-                F32Store { mem_addr, offset } => mem_store!(f32, (mem_addr, offset), stack, instance),
-                F64Store { mem_addr, offset } => mem_store!(f64, (mem_addr, offset), stack, instance),
-                I32Store8 { mem_addr, offset } => mem_store!(i8, i32, (mem_addr, offset), stack, instance),
-                I32Store16 { mem_addr, offset } => mem_store!(i16, i32, (mem_addr, offset), stack, instance),
-                I64Store8 { mem_addr, offset } => mem_store!(i8, i64, (mem_addr, offset), stack, instance),
-                I64Store16 { mem_addr, offset } => mem_store!(i16, i64, (mem_addr, offset), stack, instance),
-                I64Store32 { mem_addr, offset } => mem_store!(i32, i64, (mem_addr, offset), stack, instance),
-
-                I32Load { mem_addr, offset } => mem_load!(i32, (mem_addr, offset), stack, instance),
-                I64Load { mem_addr, offset } => mem_load!(i64, (mem_addr, offset), stack, instance),
-                F32Load { mem_addr, offset } => mem_load!(f32, (mem_addr, offset), stack, instance),
-                F64Load { mem_addr, offset } => mem_load!(f64, (mem_addr, offset), stack, instance),
-                I32Load8S { mem_addr, offset } => mem_load!(i8, i32, (mem_addr, offset), stack, instance),
-                I32Load8U { mem_addr, offset } => mem_load!(u8, i32, (mem_addr, offset), stack, instance),
-                I32Load16S { mem_addr, offset } => mem_load!(i16, i32, (mem_addr, offset), stack, instance),
-                I32Load16U { mem_addr, offset } => mem_load!(u16, i32, (mem_addr, offset), stack, instance),
-                I64Load8S { mem_addr, offset } => mem_load!(i8, i64, (mem_addr, offset), stack, instance),
-                I64Load8U { mem_addr, offset } => mem_load!(u8, i64, (mem_addr, offset), stack, instance),
-                I64Load16S { mem_addr, offset } => mem_load!(i16, i64, (mem_addr, offset), stack, instance),
-                I64Load16U { mem_addr, offset } => mem_load!(u16, i64, (mem_addr, offset), stack, instance),
-                I64Load32S { mem_addr, offset } => mem_load!(i32, i64, (mem_addr, offset), stack, instance),
-                I64Load32U { mem_addr, offset } => mem_load!(u32, i64, (mem_addr, offset), stack, instance),
+                I32Store { mem_addr, offset, .. } => mem_store!(i32, (mem_addr, offset), stack, instance),
+                I64Store { mem_addr, offset, .. } => mem_store!(i64, (mem_addr, offset), stack, instance),
+                F32Store { mem_addr, offset, .. } => mem_store!(f32, (mem_addr, offset), stack, instance),
+                F64Store { mem_addr, offset, .. } => mem_store!(f64, (mem_addr, offset), stack, instance),
+                I32Store8 { mem_addr, offset, .. } => mem_store!(i8, i32, (mem_addr, offset), stack, instance),
+                I32Store16 { mem_addr, offset, .. } => mem_store!(i16, i32, (mem_addr, offset), stack, instance),
+                I64Store8 { mem_addr, offset, .. } => mem_store!(i8, i64, (mem_addr, offset), stack, instance),
+                I64Store16 { mem_addr, offset, .. } => mem_store!(i16, i64, (mem_addr, offset), stack, instance),
+                I64Store32 { mem_addr, offset, .. } => mem_store!(i32, i64, (mem_addr, offset), stack, instance),
+
+                I32Load { mem_addr, offset, .. } => mem_load!(i32, (mem_addr, offset), stack, instance),
+                I64Load { mem_addr, offset, .. } => mem_load!(i64, (mem_addr, offset), stack, instance),
+                F32Load { mem_addr, offset, .. } => mem_load!(f32, (mem_addr, offset), stack, instance),
+                F64Load { mem_addr, offset, .. } => mem_load!(f64, (mem_addr, offset), stack, instance),
+                I32Load8S { mem_addr, offset, .. } => mem_load!(i8, i32, (mem_addr, offset), stack, instance),
+                I32Load8U { mem_addr, offset, .. } => mem_load!(u8, i32, (mem_addr, offset), stack, instance),
+                I32Load16S { mem_addr, offset, .. } => mem_load!(i16, i32, (mem_addr, offset), stack, instance),
+                I32Load16U { mem_addr, offset, .. } => mem_load!(u16, i32, (mem_addr, offset), stack, instance),
+                I64Load8S { mem_addr, offset, .. } => mem_load!(i8, i64, (mem_addr, offset), stack, instance),
+                I64Load8U { mem_addr, offset, .. } => mem_load!(u8, i64, (mem_addr, offset), stack, instance),
+                I64Load16S { mem_addr, offset, .. } => mem_load!(i16, i64, (mem_addr, offset), stack, instance),
+                I64Load16U { mem_addr, offset, .. } => mem_load!(u16, i64, (mem_addr, offset), stack, instance),
+                I64Load32S { mem_addr, offset, .. } => mem_load!(i32, i64, (mem_addr, offset), stack, instance),
+                I64Load32U { mem_addr, offset, .. } => mem_load!(u32, i64, (mem_addr, offset), stack, instance),
 
                 I64Eqz => comp_zero!(==, i64, stack),
                 I32Eqz => comp_zero!(==, i32, stack),
@@ -171,23 +275,23 @@ impl Interpreter {
                 F32Gt => comp!(>, f32, stack),
                 F64Gt => comp!(>, f64, stack),
 
-                I64Add => arithmetic!(wrapping_add, i64, stack),
-                I32Add => arithmetic!(wrapping_add, i32, stack),
-                F32Add => arithmetic!(+, f32, stack),
-                F64Add => arithmetic!(+, f64, stack),
+                I64Add => stack.values.calculate(RawWasmValue::i64_add)?,
+                I32Add => stack.values.calculate(RawWasmValue::i32_add)?,
+                F32Add => stack.values.calculate(RawWasmValue::f32_add)?,
+                F64Add => stack.values.calculate(RawWasmValue::f64_add)?,
 
-                I32Sub => arithmetic!(wrapping_sub, i32, stack),
-                I64Sub => arithmetic!(wrapping_sub, i64, stack),
-                F32Sub => arithmetic!(-, f32, stack),
-                F64Sub => arithmetic!(-, f64, stack),
+                I32Sub => stack.values.calculate(RawWasmValue::i32_sub)?,
+                I64Sub => stack.values.calculate(RawWasmValue::i64_sub)?,
+                F32Sub => stack.values.calculate(RawWasmValue::f32_sub)?,
+                F64Sub => stack.values.calculate(RawWasmValue::f64_sub)?,
 
-                F32Div => arithmetic!(/, f32, stack),
-                F64Div => arithmetic!(/, f64, stack),
+                F32Div => stack.values.calculate(RawWasmValue::f32_div)?,
+                F64Div => stack.values.calculate(RawWasmValue::f64_div)?,
 
-                I32Mul => arithmetic!(wrapping_mul, i32, stack),
-                I64Mul => arithmetic!(wrapping_mul, i64, stack),
-                F32Mul => arithmetic!(*, f32, stack),
-                F64Mul => arithmetic!(*, f64, stack),
+                I32Mul => stack.values.calculate(RawWasmValue::i32_mul)?,
+                I64Mul => stack.values.calculate(RawWasmValue::i64_mul)?,
+                F32Mul => stack.values.calculate(RawWasmValue::f32_mul)?,
+                F64Mul => stack.values.calculate(RawWasmValue::f64_mul)?,
 
                 // these can trap
                 I32DivS => checked_int_arithmetic!(checked_div, i32, stack),
@@ -200,22 +304,22 @@ impl Interpreter {
                 I32RemU => checked_int_arithmetic!(checked_wrapping_rem, u32, stack),
                 I64RemU => checked_int_arithmetic!(checked_wrapping_rem, u64, stack),
 
-                I32And => arithmetic!(bitand, i32, stack),
-                I64And => arithmetic!(bitand, i64, stack),
-                I32Or => arithmetic!(bitor, i32, stack),
-                I64Or => arithmetic!(bitor, i64, stack),
-                I32Xor => arithmetic!(bitxor, i32, stack),
-                I64Xor => arithmetic!(bitxor, i64, stack),
-                I32Shl => arithmetic!(wasm_shl, i32, stack),
-                I64Shl => arithmetic!(wasm_shl, i64, stack),
-                I32ShrS => arithmetic!(wasm_shr, i32, stack),
-                I64ShrS => arithmetic!(wasm_shr, i64, stack),
-                I32ShrU => arithmetic!(wasm_shr, u32, stack),
-                I64ShrU => arithmetic!(wasm_shr, u64, stack),
-                I32Rotl => arithmetic!(wasm_rotl, i32, stack),
-                I64Rotl => arithmetic!(wasm_rotl, i64, stack),
-                I32Rotr => arithmetic!(wasm_rotr, i32, stack),
-                I64Rotr => arithmetic!(wasm_rotr, i64, stack),
+                I32And => stack.values.calculate(RawWasmValue::i32_and)?,
+                I64And => stack.values.calculate(RawWasmValue::i64_and)?,
+                I32Or => stack.values.calculate(RawWasmValue::i32_or)?,
+                I64Or => stack.values.calculate(RawWasmValue::i64_or)?,
+                I32Xor => stack.values.calculate(RawWasmValue::i32_xor)?,
+                I64Xor => stack.values.calculate(RawWasmValue::i64_xor)?,
+                I32Shl => stack.values.calculate(RawWasmValue::i32_shl)?,
+                I64Shl => stack.values.calculate(RawWasmValue::i64_shl)?,
+                I32ShrS => stack.values.calculate(RawWasmValue::i32_shr_s)?,
+                I64ShrS => stack.values.calculate(RawWasmValue::i64_shr_s)?,
+                I32ShrU => stack.values.calculate(RawWasmValue::i32_shr_u)?,
+                I64ShrU => stack.values.calculate(RawWasmValue::i64_shr_u)?,
+                I32Rotl => stack.values.calculate(RawWasmValue::i32_rotl)?,
+                I64Rotl => stack.values.calculate(RawWasmValue::i64_rotl)?,
+                I32Rotr => stack.values.calculate(RawWasmValue::i32_rotr)?,
+                I64Rotr => stack.values.calculate(RawWasmValue::i64_rotr)?,
 
                 I32Clz => arithmetic_single!(leading_zeros, i32, stack),
                 I64Clz => arithmetic_single!(leading_zeros, i64, stack),
@@ -295,12 +399,22 @@ impl Interpreter {
                 // custom instructions
                 LocalGet2(a, b) => self.exec_local_get2(a, b, stack, &cf),
                 LocalGet3(a, b, c) => self.exec_local_get3(a, b, c, stack, &cf),
-                LocalTeeGet(a, b) => self.exec_local_tee_get(a, b, stack, &mut cf),
-                LocalGetSet(a, b) => self.exec_local_get_set(a, b, &mut cf),
+                LocalTeeGet(a, b) => self.exec_local_tee_get(a, b, stack, &cf)?,
+                LocalGetSet(a, b) => self.exec_local_get_set(a, b, stack, &cf),
                 I64XorConstRotl(rotate_by) => self.exec_i64_xor_const_rotl(rotate_by, stack)?,
                 I32LocalGetConstAdd(local, val) => self.exec_i32_local_get_const_add(local, val, stack, &cf),
-                I32StoreLocal { local, const_i32: consti32, offset, mem_addr } => {
-                    self.exec_i32_store_local(local, consti32, offset, mem_addr, &cf, instance)?
+                I32AddLocals(a, b) => self.exec_i32_add_locals(a, b, stack, &cf),
+                I32AddConst(val) => self.exec_i32_add_const(val, stack)?,
+                I32StoreLocal { local, const_i32: consti32, offset, mem_addr, .. } => {
+                    self.exec_i32_store_local(local, consti32, offset, mem_addr, stack, &cf, instance)?
+                }
+                FloatDenied => {
+                    cold();
+                    return Err(Error::Trap(Trap::FloatDenied));
+                }
+                BrLabel(_) => {
+                    cold();
+                    return Err(Error::InvalidBytecode("br_table label outside of a jump table".to_string()));
                 }
                 i => {
                     cold();
@@ -311,11 +425,229 @@ impl Interpreter {
             cf.instr_ptr += 1;
         }
 
-        stack.call_stack.push(cf)?;
+        stack.call_stack.push(cf);
 
         Ok(false)
     }
 
+    /// The safe point execution is at, if any
+    #[cold]
+    fn safe_point(&self, cf: &CallFrame, stack: &Stack, funcs: &[Function]) -> Option<SafePoint> {
+        if cf.instr_ptr == 0 {
+            return Some(SafePoint::FunctionEntry);
+        }
+        if let Ok(Instruction::Call(_) | Instruction::CallIndirect(..)) = cf.fetch_instr(funcs) {
+            return Some(SafePoint::Call);
+        }
+
+        // only branches to a loop jump back within the same call
+        match self.last_overrun {
+            Some((func, instr_ptr, depth))
+                if func == cf.func_instance && depth == stack.call_stack.len() && cf.instr_ptr <= instr_ptr =>
+            {
+                Some(SafePoint::LoopBackEdge)
+            }
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    fn is_instrumented(&self) -> bool {
+        !self.breakpoints.is_empty() || self.trace.is_some() || self.profiler.is_some() || self.coverage.is_some()
+    }
+
+    /// Report the instruction about to be executed, returning whether execution stops at a breakpoint
+    #[cold]
+    fn instrument(&mut self, cf: &CallFrame, funcs: &[Function]) -> bool {
+        // only the first instruction after resuming can be the breakpoint we stopped at
+        let resumed = core::mem::take(&mut self.resume_from_breakpoint);
+        if !resumed && self.breakpoints.binary_search(&(cf.func_instance, cf.instr_ptr)).is_ok() {
+            self.hit_breakpoint = true;
+            return true;
+        }
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.instruction(cf.func_instance);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.hit(cf.func_instance, cf.instr_ptr);
+        }
+        // an invalid instruction pointer is reported once the instruction is executed
+        if let Ok(instr) = cf.fetch_instr(funcs) {
+            self.trace(|| TraceEvent::Instruction { func: cf.func_instance, instr_ptr: cf.instr_ptr, instr });
+        }
+        false
+    }
+
+    /// Check the address of a load or store against its alignment hint, see [`AlignmentCheck`]
+    #[cold]
+    fn check_alignment(&mut self, instr: &Instruction, stack: &Stack, cf: &CallFrame) -> Result<()> {
+        use crate::types::instructions::Instruction::*;
+
+        let (base, offset, mem, align) = match *instr {
+            I32Load { offset, mem_addr, align }
+            | I64Load { offset, mem_addr, align }
+            | F32Load { offset, mem_addr, align }
+            | F64Load { offset, mem_addr, align }
+            | I32Load8S { offset, mem_addr, align }
+            | I32Load8U { offset, mem_addr, align }
+            | I32Load16S { offset, mem_addr, align }
+            | I32Load16U { offset, mem_addr, align }
+            | I64Load8S { offset, mem_addr, align }
+            | I64Load8U { offset, mem_addr, align }
+            | I64Load16S { offset, mem_addr, align }
+            | I64Load16U { offset, mem_addr, align }
+            | I64Load32S { offset, mem_addr, align }
+            | I64Load32U { offset, mem_addr, align } => (*stack.values.last()?, offset, mem_addr, align),
+            // the address is below the stored value
+            I32Store { offset, mem_addr, align }
+            | I64Store { offset, mem_addr, align }
+            | F32Store { offset, mem_addr, align }
+            | F64Store { offset, mem_addr, align }
+            | I32Store8 { offset, mem_addr, align }
+            | I32Store16 { offset, mem_addr, align }
+            | I64Store8 { offset, mem_addr, align }
+            | I64Store16 { offset, mem_addr, align }
+            | I64Store32 { offset, mem_addr, align } => (stack.values.last_n(2)?[0], offset, mem_addr, align),
+            I32StoreLocal { local, offset, mem_addr, align, .. } => {
+                (cf.get_local(&stack.values, local), offset, MemAddr::from(mem_addr), align)
+            }
+            _ => return Ok(()),
+        };
+
+        let addr = offset as u64 + u32::from(base) as u64;
+        let align = 1u32 << align;
+        if addr & (align as u64 - 1) == 0 {
+            return Ok(());
+        }
+        match self.alignment_check {
+            AlignmentCheck::Trap => {
+                let offset = usize::try_from(addr).unwrap_or(usize::MAX);
+                Err(Error::Trap(Trap::MisalignedAccess { offset, align: align as usize }))
+            }
+            _ => {
+                let (func, instr_ptr) = (cf.func_instance, cf.instr_ptr);
+                self.trace(|| TraceEvent::MisalignedAccess { func, instr_ptr, mem, offset: addr, align });
+                Ok(())
+            }
+        }
+    }
+
+    fn trace(&mut self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(hook) = self.trace.as_mut() {
+            (hook.0)(event());
+        }
+    }
+
+    fn exec_host_call(
+        &mut self,
+        func: FuncAddr,
+        host_func: &HostFunction,
+        params: &[WasmValue],
+        instance: (&Module, &Vec<Function>, &mut Vec<MemoryInstance>, &mut ExternRefs, &mut ImportQuotas),
+    ) -> Result<HostReturn> {
+        let (module, funcs, memories, externs, quotas) = instance;
+        let call = move |memories: &mut Vec<MemoryInstance>| {
+            let (externs, quotas) = (externs, quotas);
+            if unlikely(!quotas.check(func, params)?) {
+                return Ok(HostReturn::Results(host_func.ty.results.iter().map(|ty| ty.default_value()).collect()));
+            }
+            let mut callback = None;
+            let ctx = FuncContext { module, memories, externs, callback: &mut callback };
+            let results = host_func.call(ctx, params)?;
+            let Some(Callback { func: callback, params }) = callback else {
+                return Ok(HostReturn::Results(results));
+            };
+
+            if unlikely(!results.is_empty()) {
+                return Err(Error::Other(format!(
+                    "Host function {} returned values and called back into the guest",
+                    func
+                )));
+            }
+            let ty = funcs.get_or_instance(callback, "function")?.ty();
+            if unlikely(ty.params.len() != params.len()) {
+                return Err(Error::ParamCountMismatch { expected: ty.params.len(), got: params.len() });
+            }
+            for (index, (ty, param)) in ty.params.iter().zip(&params).enumerate() {
+                if *ty != param.val_type() {
+                    return Err(Error::ParamTypeMismatch { expected: *ty, got: param.val_type(), index });
+                }
+            }
+            Ok(HostReturn::Callback { func: callback, params: params.into_iter().map(RawWasmValue::from).collect() })
+        };
+
+        match self.host_calls.as_mut() {
+            Some(host_calls) => host_calls.call(func, &host_func.ty, memories, call),
+            None => call(memories),
+        }
+    }
+
+    /// Finish a call to a host function, entering the guest function it called back into
+    fn exec_host_return(
+        &mut self,
+        host_func: FuncAddr,
+        ret: HostReturn,
+        stack: &mut Stack,
+        cf: &mut CallFrame,
+        instance: &Instance,
+    ) -> Result<()> {
+        self.call_exit(host_func);
+        cf.instr_ptr += 1;
+
+        let (func, params) = match ret {
+            HostReturn::Results(results) => {
+                stack.values.extend_from_typed(&results);
+                return Ok(());
+            }
+            HostReturn::Callback { func, params } => (func, params),
+        };
+
+        let Function::Wasm(wasm_func) = instance.funcs.get_or_instance(func, "function")? else {
+            return Err(Error::Other(format!("Host function {} called back into host function {}", host_func, func)));
+        };
+        if unlikely(wasm_func.ty.results != instance.funcs.get_or_instance(host_func, "function")?.ty().results) {
+            return Err(Error::Other(format!(
+                "Host function {} called back into function {} with different results",
+                host_func, func
+            )));
+        }
+        if unlikely(wasm_func.ty.params.len() != params.len()) {
+            return Err(Error::ParamCountMismatch { expected: wasm_func.ty.params.len(), got: params.len() });
+        }
+
+        self.call_enter(func);
+        stack.values.extend(params);
+        let stack_ptr = stack.values.enter_frame(wasm_func)?;
+        let new_call_frame = CallFrame::new(func, wasm_func, stack.blocks.len() as u32, stack_ptr);
+        self.enter_call(stack, cf, new_call_frame)
+    }
+
+    #[inline(always)]
+    fn call_enter(&mut self, func: FuncAddr) {
+        self.stats.calls += 1;
+        self.trace(|| TraceEvent::CallEnter { func });
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.call_enter(func);
+        }
+    }
+
+    /// Push the caller's frame and continue with the callee
+    #[inline(always)]
+    fn enter_call(&mut self, stack: &mut Stack, cf: &mut CallFrame, callee: CallFrame) -> Result<()> {
+        stack.enter_call(core::mem::replace(cf, callee), cf)?;
+        self.stats.max_call_depth = self.stats.max_call_depth.max(stack.call_stack.len() + 1);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn call_exit(&mut self, func: FuncAddr) {
+        self.trace(|| TraceEvent::CallExit { func });
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.call_exit();
+        }
+    }
+
     #[inline(always)]
     fn exec_end_block(&self, stack: &mut Stack) -> Result<()> {
         let block = stack.blocks.pop()?;
@@ -350,20 +682,34 @@ impl Interpreter {
         const_i32: i32,
         offset: u32,
         mem_addr: u8,
+        stack: &Stack,
         cf: &CallFrame,
         instance: &mut Instance,
     ) -> Result<()> {
         let mem = instance.get_mem_mut(mem_addr as u32)?;
         let val = const_i32.to_le_bytes();
-        let addr: u64 = cf.get_local(local).into();
-        mem.store((offset as u64 + addr) as usize, val.len(), &val)?;
+        let addr = offset as u64 + u32::from(cf.get_local(&stack.values, local)) as u64;
+        mem.store(usize::try_from(addr).unwrap_or(usize::MAX), val.len(), &val)?;
         Ok(())
     }
 
     #[inline(always)]
     fn exec_i32_local_get_const_add(&self, local: u32, val: i32, stack: &mut Stack, cf: &CallFrame) {
-        let local: i32 = cf.get_local(local).into();
-        stack.values.push((local + val).into());
+        let local: i32 = cf.get_local(&stack.values, local).into();
+        stack.values.push(local.wrapping_add(val).into());
+    }
+
+    #[inline(always)]
+    fn exec_i32_add_locals(&self, a: u32, b: u32, stack: &mut Stack, cf: &CallFrame) {
+        let (a, b): (i32, i32) = (cf.get_local(&stack.values, a).into(), cf.get_local(&stack.values, b).into());
+        stack.values.push(a.wrapping_add(b).into());
+    }
+
+    #[inline(always)]
+    fn exec_i32_add_const(&self, val: i32, stack: &mut Stack) -> Result<()> {
+        let res = stack.values.last_mut()?;
+        *res = i32::from(*res).wrapping_add(val).into();
+        Ok(())
     }
 
     #[inline(always)]
@@ -377,48 +723,51 @@ impl Interpreter {
 
     #[inline(always)]
     fn exec_local_get(&self, local_index: u32, stack: &mut Stack, cf: &CallFrame) {
-        stack.values.push(cf.get_local(local_index));
+        stack.values.push(cf.get_local(&stack.values, local_index));
     }
 
     #[inline(always)]
     fn exec_local_get2(&self, a: u32, b: u32, stack: &mut Stack, cf: &CallFrame) {
-        stack.values.push(cf.get_local(a));
-        stack.values.push(cf.get_local(b));
+        stack.values.push(cf.get_local(&stack.values, a));
+        stack.values.push(cf.get_local(&stack.values, b));
     }
 
     #[inline(always)]
     fn exec_local_get3(&self, a: u32, b: u32, c: u32, stack: &mut Stack, cf: &CallFrame) {
-        stack.values.push(cf.get_local(a));
-        stack.values.push(cf.get_local(b));
-        stack.values.push(cf.get_local(c));
+        stack.values.push(cf.get_local(&stack.values, a));
+        stack.values.push(cf.get_local(&stack.values, b));
+        stack.values.push(cf.get_local(&stack.values, c));
     }
 
     #[inline(always)]
-    fn exec_local_get_set(&self, a: u32, b: u32, cf: &mut CallFrame) {
-        cf.set_local(b, cf.get_local(a))
+    fn exec_local_get_set(&self, a: u32, b: u32, stack: &mut Stack, cf: &CallFrame) {
+        let value = cf.get_local(&stack.values, a);
+        cf.set_local(&mut stack.values, b, value)
     }
 
     #[inline(always)]
-    fn exec_local_set(&self, local_index: u32, stack: &mut Stack, cf: &mut CallFrame) -> Result<()> {
-        cf.set_local(local_index, stack.values.pop()?);
+    fn exec_local_set(&self, local_index: u32, stack: &mut Stack, cf: &CallFrame) -> Result<()> {
+        let value = stack.values.pop()?;
+        cf.set_local(&mut stack.values, local_index, value);
         Ok(())
     }
 
     #[inline(always)]
-    fn exec_local_tee(&self, local_index: u32, stack: &mut Stack, cf: &mut CallFrame) -> Result<()> {
-        cf.set_local(local_index, *stack.values.last()?);
+    fn exec_local_tee(&self, local_index: u32, stack: &mut Stack, cf: &CallFrame) -> Result<()> {
+        let value = *stack.values.last()?;
+        cf.set_local(&mut stack.values, local_index, value);
         Ok(())
     }
 
     #[inline(always)]
-    fn exec_local_tee_get(&self, a: u32, b: u32, stack: &mut Stack, cf: &mut CallFrame) {
-        let last =
-            stack.values.last().expect("localtee: stack is empty. this should have been validated by the parser");
-        cf.set_local(a, *last);
+    fn exec_local_tee_get(&self, a: u32, b: u32, stack: &mut Stack, cf: &CallFrame) -> Result<()> {
+        let last = *stack.values.last()?;
+        cf.set_local(&mut stack.values, a, last);
         stack.values.push(match a == b {
-            true => *last,
-            false => cf.get_local(b),
+            true => last,
+            false => cf.get_local(&stack.values, b),
         });
+        Ok(())
     }
 
     #[inline(always)]
@@ -499,19 +848,30 @@ impl Interpreter {
     }
 
     #[inline(always)]
-    fn exec_memory_grow(&self, addr: u32, byte: u8, stack: &mut Stack, instance: &mut Instance) -> Result<()> {
+    fn exec_memory_grow(&mut self, addr: u32, byte: u8, stack: &mut Stack, instance: &mut Instance) -> Result<()> {
         if unlikely(byte != 0) {
             return Err(Error::UnsupportedFeature("memory.grow with byte != 0".to_string()));
         }
 
+        let max_pages = instance.exec_config.max_memory_pages;
         let mem = instance.get_mem_mut(addr)?;
         let prev_size = mem.page_count() as i32;
         let pages_delta = stack.values.last_mut()?;
-        *pages_delta = match mem.grow(i32::from(*pages_delta)) {
-            Some(_) => prev_size.into(),
-            None => (-1).into(),
+        let delta = i32::from(*pages_delta);
+        // the delta is unsigned
+        let limited = max_pages.is_some_and(|max| prev_size as usize + delta as u32 as usize > max);
+        let success = !limited && mem.grow(delta).is_some();
+        *pages_delta = match success {
+            true => prev_size.into(),
+            false => (-1).into(),
         };
 
+        if let Some(hook) = instance.grow_hook.as_ref() {
+            // the delta is unsigned
+            let new_pages = prev_size as usize + delta as u32 as usize;
+            (hook.0.borrow_mut())(&MemoryGrowEvent { mem: addr, old_pages: prev_size as usize, new_pages, success });
+        }
+        self.trace(|| TraceEvent::MemoryGrow { mem: addr, prev_pages: prev_size as usize, delta, success });
         Ok(())
     }
 
@@ -527,10 +887,8 @@ impl Interpreter {
             mem_from.copy_within(dst as usize, src as usize, size as usize)?;
         } else {
             // copy between two memories
-            todo!("Copy between different memories not supported");
-            // let mem_from = instance.get_mem(from)?;
-            // let mut mem_to = instance.get_mem_mut(to)?;
-            // mem_to.copy_from_slice(dst as usize, mem_from.load(src as usize, size as usize)?)?;
+            cold();
+            return Err(Error::UnsupportedFeature("memory.copy between different memories".to_string()));
         }
         Ok(())
     }
@@ -574,33 +932,36 @@ impl Interpreter {
     }
 
     #[inline(always)]
-    fn exec_call(&self, v: u32, stack: &mut Stack, cf: &mut CallFrame, instance: &mut Instance) -> Result<()> {
+    fn exec_call(&mut self, v: u32, stack: &mut Stack, cf: &mut CallFrame, instance: &mut Instance) -> Result<()> {
         let func_inst = instance.funcs.get_or_instance(v, "function")?;
+        self.call_enter(v);
         let wasm_func = match &func_inst {
             Function::Wasm(wasm_func) => wasm_func,
             Function::Host(host_func) => {
                 let params = stack.values.pop_params(&host_func.ty.params)?;
-                let res = (host_func.func)(
-                    FuncContext { module: &instance.module, memories: &mut instance.memories },
-                    &params,
-                )?;
-                stack.values.extend_from_typed(&res);
-                cf.instr_ptr += 1;
-                return Ok(());
+                let instance_parts = (
+                    &*instance.module,
+                    &instance.funcs,
+                    &mut instance.memories,
+                    &mut instance.externs,
+                    &mut instance.quotas,
+                );
+                let ret = self.exec_host_call(v, host_func, &params, instance_parts)?;
+                return self.exec_host_return(v, ret, stack, cf, instance);
             }
         };
 
-        let params = stack.values.pop_n_rev(wasm_func.ty.params.len())?;
-        let new_call_frame = CallFrame::new(v, wasm_func, params, stack.blocks.len() as u32);
+        let stack_ptr = stack.values.enter_frame(wasm_func)?;
+        let new_call_frame = CallFrame::new(v, wasm_func, stack.blocks.len() as u32, stack_ptr);
 
         cf.instr_ptr += 1; // skip the call instruction
-        stack.call_stack.push(core::mem::replace(cf, new_call_frame))?;
+        self.enter_call(stack, cf, new_call_frame)?;
         Ok(())
     }
 
     #[inline(always)]
     fn exec_call_indirect(
-        &self,
+        &mut self,
         type_addr: u32,
         table_addr: u32,
         stack: &mut Stack,
@@ -611,10 +972,12 @@ impl Interpreter {
         let table_idx: u32 = stack.values.pop()?.into();
 
         // verify that the table is of the right type, this should be validated by the parser already
-        let func_ref = {
-            assert!(table.kind.element_type == ValType::RefFunc, "table is not of type funcref");
-            table.get(table_idx)?.addr().ok_or(Trap::UninitializedElement { index: table_idx as usize })?
-        };
+        if unlikely(table.kind.element_type != ValType::RefFunc) {
+            return Err(Error::InvalidBytecode(
+                "call_indirect through a table that is not of type funcref".to_string(),
+            ));
+        }
+        let func_ref = table.get(table_idx)?.addr().ok_or(Trap::UninitializedElement { index: table_idx as usize })?;
 
         let func_inst = instance.funcs.get_or_instance(func_ref, "function")?;
         let call_ty = instance.func_ty(type_addr);
@@ -631,15 +994,17 @@ impl Interpreter {
                 }
 
                 // let host_func = host_func.clone();
+                self.call_enter(func_ref);
                 let params = stack.values.pop_params(&host_func.ty.params)?;
-                let res = (host_func.func)(
-                    FuncContext { module: &instance.module, memories: &mut instance.memories },
-                    &params,
-                )?;
-                stack.values.extend_from_typed(&res);
-
-                cf.instr_ptr += 1;
-                return Ok(());
+                let instance_parts = (
+                    &*instance.module,
+                    &instance.funcs,
+                    &mut instance.memories,
+                    &mut instance.externs,
+                    &mut instance.quotas,
+                );
+                let ret = self.exec_host_call(func_ref, host_func, &params, instance_parts)?;
+                return self.exec_host_return(func_ref, ret, stack, cf, instance);
             }
         };
 
@@ -649,16 +1014,18 @@ impl Interpreter {
             );
         }
 
-        let params = stack.values.pop_n_rev(wasm_func.ty.params.len())?;
-        let new_call_frame = CallFrame::new(func_ref, wasm_func, params, stack.blocks.len() as u32);
+        self.call_enter(func_ref);
+        let stack_ptr = stack.values.enter_frame(wasm_func)?;
+        let new_call_frame = CallFrame::new(func_ref, wasm_func, stack.blocks.len() as u32, stack_ptr);
 
         cf.instr_ptr += 1; // skip the call instruction
-        stack.call_stack.push(core::mem::replace(cf, new_call_frame))?;
+        self.enter_call(stack, cf, new_call_frame)?;
 
         Ok(())
     }
 
     #[inline(always)]
+    #[allow(clippy::too_many_arguments)]
     fn exec_if(
         &self,
         args: BlockArgs,
@@ -3,4 +3,5 @@ mod stack;
 mod value;
 
 pub(crate) use stack::*;
+pub use stack::{Stack, StackLimits};
 pub(crate) use value::RawWasmValue;
@@ -12,6 +12,11 @@ impl BlockStack {
         Self(Vec::with_capacity(128))
     }
 
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+
     #[inline(always)]
     pub(crate) fn len(&self) -> usize {
         self.0.len()
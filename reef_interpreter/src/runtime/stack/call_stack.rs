@@ -1,34 +1,56 @@
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{string::ToString, vec::Vec};
 use core::hint::unreachable_unchecked;
 
-use crate::error::{Error, Result, Trap};
+use crate::cold;
+use crate::error::{Error, Result};
 use crate::imports::Function;
-use crate::runtime::{BlockType, RawWasmValue};
+use crate::runtime::{BlockType, RawWasmValue, ValueStack};
 use crate::types::{instructions::Instruction, FuncAddr, LocalAddr, WasmFunction};
-use crate::{cold, unlikely, CALL_STACK_SIZE};
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[archive(check_bytes)]
-pub(crate) struct CallStack(pub(crate) Vec<CallFrame>);
+pub(crate) struct CallStack {
+    pub(crate) frames: Vec<CallFrame>,
+    /// Number of parameters and locals of all frames
+    locals: usize,
+}
 
 impl CallStack {
     #[inline]
-    pub(crate) fn new(initial_frame: CallFrame) -> Self {
-        let mut stack = Vec::new();
-        stack.reserve_exact(CALL_STACK_SIZE);
-        stack.push(initial_frame);
-        Self(stack)
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let mut frames = Vec::new();
+        frames.reserve_exact(capacity);
+        Self { frames, locals: 0 }
+    }
+
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+        self.locals = 0;
     }
 
     #[inline]
     pub(crate) fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.frames.is_empty()
+    }
+
+    #[inline(always)]
+    pub(crate) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    #[inline(always)]
+    pub(crate) fn locals(&self) -> usize {
+        self.locals
     }
 
     #[inline(always)]
     pub(crate) fn pop(&mut self) -> Result<CallFrame> {
-        match self.0.pop() {
-            Some(frame) => Ok(frame),
+        match self.frames.pop() {
+            Some(frame) => {
+                self.locals -= frame.locals as usize;
+                Ok(frame)
+            }
             None => {
                 cold();
                 Err(Error::CallStackUnderflow)
@@ -36,13 +58,11 @@ impl CallStack {
         }
     }
 
+    /// Push a frame without checking any limits, see [`Stack::enter_call`](super::Stack::enter_call)
     #[inline(always)]
-    pub(crate) fn push(&mut self, call_frame: CallFrame) -> Result<()> {
-        if unlikely(self.0.len() >= self.0.capacity()) {
-            return Err(Trap::CallStackOverflow.into());
-        }
-        self.0.push(call_frame);
-        Ok(())
+    pub(crate) fn push(&mut self, call_frame: CallFrame) {
+        self.locals += call_frame.locals as usize;
+        self.frames.push(call_frame);
     }
 }
 
@@ -51,14 +71,18 @@ impl CallStack {
 pub(crate) struct CallFrame {
     pub(crate) instr_ptr: usize,
     pub(crate) block_ptr: u32,
+    /// Position of the frame's parameters and locals on the value stack, the frame's values start after them
+    pub(crate) stack_ptr: u32,
+    /// Number of parameters and locals
+    pub(crate) locals: u32,
+    pub(crate) results: u32,
     pub(crate) func_instance: FuncAddr,
-    pub(crate) locals: Box<[RawWasmValue]>,
 }
 
 impl CallFrame {
     #[inline(always)]
-    pub(crate) fn fetch_instr(&self, funcs: &[Function]) -> Instruction {
-        // SAFETY: this is verified by the parser/validator
+    pub(crate) fn fetch_instr(&self, funcs: &[Function]) -> Result<Instruction> {
+        // SAFETY: this is verified by the parser/validator, and by `Stack::validate` for restored stacks
         let func = unsafe { funcs.get_unchecked(self.func_instance as usize) };
         let wasm_func = match func {
             Function::Wasm(wasm_func) => wasm_func,
@@ -70,10 +94,10 @@ impl CallFrame {
             }
         };
         match wasm_func.instructions.get(self.instr_ptr) {
-            Some(instr) => instr.clone(),
+            Some(instr) => Ok(instr.clone()),
             None => {
                 cold();
-                panic!("Instruction pointer out of bounds");
+                Err(Error::InvalidBytecode("instruction pointer out of bounds".to_string()))
             }
         }
     }
@@ -122,38 +146,33 @@ impl CallFrame {
         Some(())
     }
 
+    /// A frame for `wasm_func`, whose parameters and locals are on the value stack starting at `stack_ptr`,
+    /// see [`ValueStack::enter_frame`]
     #[inline(always)]
-    pub(crate) fn new(
-        wasm_func_addr: FuncAddr,
-        wasm_func: &WasmFunction,
-        params: impl ExactSizeIterator<Item = RawWasmValue>,
-        block_ptr: u32,
-    ) -> Self {
-        let locals = {
-            let total_size = wasm_func.locals.len() + params.len();
-            let mut locals = Vec::new();
-            locals.reserve_exact(total_size);
-            locals.extend(params);
-            locals.resize_with(total_size, RawWasmValue::default);
-            locals.into_boxed_slice()
-        };
-
-        Self { instr_ptr: 0, func_instance: wasm_func_addr, locals, block_ptr }
+    pub(crate) fn new(wasm_func_addr: FuncAddr, wasm_func: &WasmFunction, block_ptr: u32, stack_ptr: u32) -> Self {
+        let locals = (wasm_func.ty.params.len() + wasm_func.locals.len()) as u32;
+        let results = wasm_func.ty.results.len() as u32;
+        Self { instr_ptr: 0, func_instance: wasm_func_addr, locals, block_ptr, stack_ptr, results }
     }
 
     #[inline(always)]
-    pub(crate) fn set_local(&mut self, local_index: LocalAddr, value: RawWasmValue) {
-        self.locals[local_index as usize] = value;
+    pub(crate) fn set_local(&self, values: &mut ValueStack, local_index: LocalAddr, value: RawWasmValue) {
+        values.set(self.stack_ptr + local_index, value);
     }
 
     #[inline(always)]
-    pub(crate) fn get_local(&self, local_index: LocalAddr) -> RawWasmValue {
-        self.locals[local_index as usize]
+    pub(crate) fn get_local(&self, values: &ValueStack, local_index: LocalAddr) -> RawWasmValue {
+        values.get(self.stack_ptr + local_index)
+    }
+
+    /// The parameters and locals of the frame
+    pub(crate) fn locals<'a>(&self, values: &'a ValueStack) -> &'a [RawWasmValue] {
+        values.range(self.stack_ptr, self.locals)
     }
 
     #[inline(always)]
     pub(crate) fn instructions<'a>(&self, funcs: &'a [Function]) -> &'a [Instruction] {
-        // SAFETY: this is verified by the parser/validator
+        // SAFETY: this is verified by the parser/validator, and by `Stack::validate` for restored stacks
         let func = unsafe { funcs.get_unchecked(self.func_instance as usize) };
         &match func {
             Function::Wasm(wasm_func) => wasm_func,
@@ -4,7 +4,37 @@ mod value_stack;
 
 pub(crate) use block_stack::{BlockFrame, BlockStack, BlockType};
 pub(crate) use call_stack::{CallFrame, CallStack};
-pub(crate) use value_stack::ValueStack;
+pub(crate) use value_stack::{ValueStack, MIN_VALUE_STACK_SIZE};
+
+use alloc::{format, string::String, vec::Vec};
+
+use crate::error::{Result, Trap};
+use crate::exec::{AlignmentCheck, ExecConfig, SafePoint};
+use crate::guest::GuestRegion;
+use crate::imports::Function;
+use crate::{unlikely, CALL_STACK_SIZE};
+
+/// Limits on the size of a [`Stack`]
+///
+/// Exceeding one of them traps with [`Trap::StackExhausted`].
+/// The value stack is only checked when a function is called, so a single function can briefly push
+/// more values than allowed, bounded by the size of its body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct StackLimits {
+    /// The maximum number of nested calls
+    pub max_call_frames: usize,
+    /// The maximum number of values on the value stack, including the parameters and locals of all active calls
+    pub max_values: usize,
+    /// The maximum number of parameters and locals of all active calls combined
+    pub max_locals: usize,
+}
+
+impl Default for StackLimits {
+    fn default() -> Self {
+        Self { max_call_frames: CALL_STACK_SIZE, max_values: 1024 * 1024, max_locals: 1024 * 1024 }
+    }
+}
 
 /// A WebAssembly Stack
 #[derive(Debug, Clone, PartialEq, Eq, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -13,10 +43,118 @@ pub struct Stack {
     pub(crate) values: ValueStack,
     pub(crate) blocks: BlockStack,
     pub(crate) call_stack: CallStack,
+    pub(crate) limits: StackLimits,
+    /// See [`ExecConfig::fuel_check_interval`]
+    pub(crate) fuel_check_interval: usize,
+    /// See [`ExecConfig::safe_points_only`]
+    pub(crate) safe_points_only: bool,
+    /// See [`ExecConfig::alignment_check`]
+    pub(crate) alignment_check: AlignmentCheck,
+    /// Where execution last paused because it ran out of budget, see [`ExecHandle::pause_point`](crate::exec::ExecHandle::pause_point)
+    pub(crate) pause_point: Option<SafePoint>,
+    /// Guest memory holding the parameters of a typed call, freed once it is done
+    pub(crate) param_regions: Vec<GuestRegion>,
 }
 
 impl Stack {
-    pub(crate) fn new(call_frame: CallFrame) -> Self {
-        Self { values: ValueStack::default(), blocks: BlockStack::new(), call_stack: CallStack::new(call_frame) }
+    pub(crate) fn new(call_frame: CallFrame, config: &ExecConfig) -> Self {
+        let mut stack = Self {
+            values: ValueStack::with_capacity(config.value_stack_capacity),
+            blocks: BlockStack::new(),
+            call_stack: CallStack::with_capacity(config.call_stack_capacity),
+            limits: config.stack_limits,
+            fuel_check_interval: config.fuel_check_interval,
+            safe_points_only: config.safe_points_only,
+            alignment_check: config.alignment_check,
+            pause_point: None,
+            param_regions: Vec::new(),
+        };
+        stack.call_stack.push(call_frame);
+        stack
+    }
+
+    /// Clear the stack to start a new call, keeping its allocations
+    pub(crate) fn reset(&mut self, call_frame: CallFrame, config: &ExecConfig) {
+        self.restart(call_frame);
+        self.limits = config.stack_limits;
+        self.fuel_check_interval = config.fuel_check_interval;
+        self.safe_points_only = config.safe_points_only;
+        self.alignment_check = config.alignment_check;
+    }
+
+    /// Clear the stack to start a new call, keeping its allocations and limits
+    pub(crate) fn restart(&mut self, call_frame: CallFrame) {
+        self.values.clear();
+        self.blocks.clear();
+        self.call_stack.clear();
+        self.call_stack.push(call_frame);
+        self.pause_point = None;
+        self.param_regions.clear();
+    }
+
+    /// Push the frame of a calling function, checking the limits before `callee` starts executing
+    #[inline(always)]
+    pub(crate) fn enter_call(&mut self, caller: CallFrame, callee: &CallFrame) -> Result<()> {
+        let limits = &self.limits;
+        if unlikely(self.call_stack.len() + 2 > limits.max_call_frames) {
+            return Err(Trap::StackExhausted { limit: "call frames", max: limits.max_call_frames }.into());
+        }
+        if unlikely(self.values.len() > limits.max_values) {
+            return Err(Trap::StackExhausted { limit: "values", max: limits.max_values }.into());
+        }
+        if unlikely(self.call_stack.locals() + (caller.locals + callee.locals) as usize > limits.max_locals) {
+            return Err(Trap::StackExhausted { limit: "locals", max: limits.max_locals }.into());
+        }
+
+        self.call_stack.push(caller);
+        Ok(())
+    }
+
+    /// Check that a stack restored from a snapshot fits the functions of the instance it runs on
+    ///
+    /// The interpreter trusts the frames it creates itself, see [`CallFrame::fetch_instr`], so frames from outside
+    /// have to be checked before they are run.
+    pub(crate) fn validate(&self, funcs: &[Function]) -> core::result::Result<(), String> {
+        let values = self.values.len();
+        let blocks = self.blocks.len();
+        let frames = &self.call_stack.frames;
+        let mut locals = 0usize;
+
+        for (i, frame) in frames.iter().enumerate() {
+            let func = match funcs.get(frame.func_instance as usize) {
+                Some(Function::Wasm(func)) => func,
+                Some(Function::Host(_)) => return Err(format!("frame {i} runs host function {}", frame.func_instance)),
+                None => return Err(format!("frame {i} runs unknown function {}", frame.func_instance)),
+            };
+            if frame.instr_ptr >= func.instructions.len() {
+                return Err(format!("frame {i} has instruction pointer {} out of bounds", frame.instr_ptr));
+            }
+            if frame.locals as usize != func.ty.params.len() + func.locals.len()
+                || frame.results as usize != func.ty.results.len()
+            {
+                return Err(format!("frame {i} doesn't match the type of function {}", frame.func_instance));
+            }
+            if frame.stack_ptr as usize + frame.locals as usize > values {
+                return Err(format!("frame {i} has locals beyond the value stack"));
+            }
+
+            // the blocks of a frame start at its block pointer and end where the next frame's start
+            let blocks_end = frames.get(i + 1).map_or(blocks, |next| next.block_ptr as usize);
+            if frame.block_ptr as usize > blocks_end || blocks_end > blocks {
+                return Err(format!("frame {i} has block pointer {} out of bounds", frame.block_ptr));
+            }
+            for block in &self.blocks.0[frame.block_ptr as usize..blocks_end] {
+                let end = block.instr_ptr.checked_add(block.end_instr_offset as usize);
+                if end.is_none_or(|end| end >= func.instructions.len()) || block.stack_ptr as usize > values {
+                    return Err(format!("block in frame {i} is out of bounds"));
+                }
+            }
+            locals += frame.locals as usize;
+        }
+
+        if locals != self.call_stack.locals() {
+            return Err("call stack locals don't match its frames".into());
+        }
+        Ok(())
     }
 }
@@ -3,6 +3,7 @@ use alloc::vec::Vec;
 use crate::error::{Error, Result};
 use crate::runtime::RawWasmValue;
 use crate::types::value::{ValType, WasmValue};
+use crate::types::WasmFunction;
 use crate::{cold, unlikely};
 
 pub(crate) const MIN_VALUE_STACK_SIZE: usize = 1024 * 128;
@@ -18,6 +19,20 @@ impl Default for ValueStack {
 }
 
 impl ValueStack {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+
     #[inline]
     pub(crate) fn extend_from_typed(&mut self, values: &[WasmValue]) {
         self.0.extend(values.iter().map(|v| RawWasmValue::from(*v)));
@@ -70,6 +85,41 @@ impl ValueStack {
         self.0.drain(remove_start_index..remove_end_index);
     }
 
+    /// Set up the parameters and locals of a function that is called with its parameters on top of the stack
+    ///
+    /// The remaining locals are pushed zeroed, and space for the values the function pushes is reserved up front.
+    /// Returns the position of the first parameter, the frame's `stack_ptr`.
+    #[inline(always)]
+    pub(crate) fn enter_frame(&mut self, func: &WasmFunction) -> Result<u32> {
+        let params = func.ty.params.len();
+        if unlikely(self.0.len() < params) {
+            return Err(Error::ValueStackUnderflow);
+        }
+        let stack_ptr = (self.0.len() - params) as u32;
+        self.0.reserve(func.locals.len() + func.max_stack as usize);
+        self.0.resize(self.0.len() + func.locals.len(), RawWasmValue::default());
+        Ok(stack_ptr)
+    }
+
+    #[inline(always)]
+    pub(crate) fn get(&self, index: u32) -> RawWasmValue {
+        self.0[index as usize]
+    }
+
+    #[inline(always)]
+    pub(crate) fn set(&mut self, index: u32, value: RawWasmValue) {
+        self.0[index as usize] = value;
+    }
+
+    pub(crate) fn range(&self, start: u32, len: u32) -> &[RawWasmValue] {
+        &self.0[start as usize..(start + len) as usize]
+    }
+
+    #[inline]
+    pub(crate) fn extend(&mut self, values: impl IntoIterator<Item = RawWasmValue>) {
+        self.0.extend(values);
+    }
+
     #[inline(always)]
     pub(crate) fn push(&mut self, value: RawWasmValue) {
         self.0.push(value);
@@ -157,6 +207,24 @@ mod tests {
         assert_eq!(stack.len(), 0);
     }
 
+    #[test]
+    fn test_enter_frame_reserves() {
+        use crate::types::FuncType;
+
+        let func = WasmFunction {
+            instructions: Default::default(),
+            offsets: Default::default(),
+            locals: [ValType::I32; 2].into(),
+            ty: FuncType { params: [ValType::I32].into(), results: Default::default() },
+            max_stack: 100,
+        };
+        let mut stack = ValueStack::with_capacity(0);
+        stack.push(7.into());
+        assert_eq!(stack.enter_frame(&func).unwrap(), 0);
+        assert_eq!(stack.len(), 3);
+        assert!(stack.capacity() >= 103);
+    }
+
     #[test]
     fn test_truncate_keep() {
         macro_rules! test_macro {
@@ -1,5 +1,7 @@
 use core::fmt::Debug;
+use core::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Sub};
 
+use super::interpreter::traits::WasmIntOps;
 use crate::types::value::{ValType, WasmValue};
 
 /// A raw wasm value.
@@ -9,18 +11,18 @@ use crate::types::value::{ValType, WasmValue};
 /// See [`WasmValue`] for the public representation.
 #[derive(Clone, Copy, Default, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[archive(check_bytes)]
-pub struct RawWasmValue([u8; 8]);
+pub struct RawWasmValue(u64);
 
 impl Debug for RawWasmValue {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "RawWasmValue({})", 0)
+        write!(f, "RawWasmValue({:#x})", self.0)
     }
 }
 
 impl RawWasmValue {
     #[inline(always)]
     pub fn raw_value(&self) -> [u8; 8] {
-        self.0
+        self.0.to_ne_bytes()
     }
 
     #[inline]
@@ -28,8 +30,8 @@ impl RawWasmValue {
         match ty {
             ValType::I32 => WasmValue::I32(self.into()),
             ValType::I64 => WasmValue::I64(self.into()),
-            ValType::F32 => WasmValue::F32(f32::from_bits(self.into())),
-            ValType::F64 => WasmValue::F64(f64::from_bits(self.into())),
+            ValType::F32 => WasmValue::F32(self.into()),
+            ValType::F64 => WasmValue::F64(self.into()),
             ValType::RefExtern => match i64::from(self) {
                 v if v < 0 => WasmValue::RefNull(ValType::RefExtern),
                 addr => WasmValue::RefExtern(addr as u32),
@@ -61,16 +63,16 @@ macro_rules! impl_from_raw_wasm_value {
     ($type:ty, $to_raw:expr, $from_raw:expr) => {
         // Implement From<$type> for RawWasmValue
         impl From<$type> for RawWasmValue {
-            #[inline]
+            #[inline(always)]
             fn from(value: $type) -> Self {
                 #[allow(clippy::redundant_closure_call)]
-                Self(u64::to_ne_bytes($to_raw(value)))
+                Self($to_raw(value))
             }
         }
 
         // Implement From<RawWasmValue> for $type
         impl From<RawWasmValue> for $type {
-            #[inline]
+            #[inline(always)]
             fn from(value: RawWasmValue) -> Self {
                 #[allow(clippy::redundant_closure_call)]
                 $from_raw(value.0)
@@ -79,21 +81,64 @@ macro_rules! impl_from_raw_wasm_value {
     };
 }
 
-// This all looks like a lot of extra steps, but the compiler will optimize it all away.
-impl_from_raw_wasm_value!(i32, |x| x as u64, |x: [u8; 8]| i32::from_ne_bytes(x[0..4].try_into().unwrap()));
-impl_from_raw_wasm_value!(i64, |x| x as u64, |x: [u8; 8]| i64::from_ne_bytes(x[0..8].try_into().unwrap()));
-impl_from_raw_wasm_value!(u8, |x| x as u64, |x: [u8; 8]| u8::from_ne_bytes(x[0..1].try_into().unwrap()));
-impl_from_raw_wasm_value!(u16, |x| x as u64, |x: [u8; 8]| u16::from_ne_bytes(x[0..2].try_into().unwrap()));
-impl_from_raw_wasm_value!(u32, |x| x as u64, |x: [u8; 8]| u32::from_ne_bytes(x[0..4].try_into().unwrap()));
-impl_from_raw_wasm_value!(u64, |x| x, |x: [u8; 8]| u64::from_ne_bytes(x[0..8].try_into().unwrap()));
-impl_from_raw_wasm_value!(i8, |x| x as u64, |x: [u8; 8]| i8::from_ne_bytes(x[0..1].try_into().unwrap()));
-impl_from_raw_wasm_value!(i16, |x| x as u64, |x: [u8; 8]| i16::from_ne_bytes(x[0..2].try_into().unwrap()));
-impl_from_raw_wasm_value!(f32, |x| f32::to_bits(x) as u64, |x: [u8; 8]| f32::from_ne_bytes(
-    x[0..4].try_into().unwrap()
-));
-impl_from_raw_wasm_value!(f64, f64::to_bits, |x: [u8; 8]| f64::from_bits(u64::from_ne_bytes(
-    x[0..8].try_into().unwrap()
-)));
+// Integers are stored sign-extended, floats as their bit pattern, so every conversion is a plain cast.
+impl_from_raw_wasm_value!(i32, |x| x as u64, |x| x as i32);
+impl_from_raw_wasm_value!(i64, |x| x as u64, |x| x as i64);
+impl_from_raw_wasm_value!(u8, |x| x as u64, |x| x as u8);
+impl_from_raw_wasm_value!(u16, |x| x as u64, |x| x as u16);
+impl_from_raw_wasm_value!(u32, |x| x as u64, |x| x as u32);
+impl_from_raw_wasm_value!(u64, |x| x, |x| x);
+impl_from_raw_wasm_value!(i8, |x| x as u64, |x| x as i8);
+impl_from_raw_wasm_value!(i16, |x| x as u64, |x| x as i16);
+impl_from_raw_wasm_value!(f32, |x| f32::to_bits(x) as u64, |x| f32::from_bits(x as u32));
+impl_from_raw_wasm_value!(f64, f64::to_bits, f64::from_bits);
+
+/// Binary operations working directly on the raw bits, used by the interpreter for the hottest instructions
+macro_rules! impl_raw_binops {
+    ($($name:ident($ty:ty) => $op:ident;)*) => {
+        impl RawWasmValue {
+            $(
+                #[inline(always)]
+                pub(crate) fn $name(self, rhs: Self) -> Self {
+                    Self::from(<$ty>::from(self).$op(<$ty>::from(rhs)))
+                }
+            )*
+        }
+    };
+}
+
+impl_raw_binops! {
+    i32_add(i32) => wrapping_add;
+    i32_sub(i32) => wrapping_sub;
+    i32_mul(i32) => wrapping_mul;
+    i32_and(i32) => bitand;
+    i32_or(i32) => bitor;
+    i32_xor(i32) => bitxor;
+    i32_shl(i32) => wasm_shl;
+    i32_shr_s(i32) => wasm_shr;
+    i32_shr_u(u32) => wasm_shr;
+    i32_rotl(i32) => wasm_rotl;
+    i32_rotr(i32) => wasm_rotr;
+    i64_add(i64) => wrapping_add;
+    i64_sub(i64) => wrapping_sub;
+    i64_mul(i64) => wrapping_mul;
+    i64_and(i64) => bitand;
+    i64_or(i64) => bitor;
+    i64_xor(i64) => bitxor;
+    i64_shl(i64) => wasm_shl;
+    i64_shr_s(i64) => wasm_shr;
+    i64_shr_u(u64) => wasm_shr;
+    i64_rotl(i64) => wasm_rotl;
+    i64_rotr(i64) => wasm_rotr;
+    f32_add(f32) => add;
+    f32_sub(f32) => sub;
+    f32_mul(f32) => mul;
+    f32_div(f32) => div;
+    f64_add(f64) => add;
+    f64_sub(f64) => sub;
+    f64_mul(f64) => mul;
+    f64_div(f64) => div;
+}
 
 #[cfg(test)]
 mod tests {
@@ -117,4 +162,16 @@ mod tests {
              i32 => i32::MAX, i64 => i64::MAX, u8 => u8::MAX, u16 => u16::MAX, u32 => u32::MAX, u64 => u64::MAX, i8 => i8::MAX, i16 => i16::MAX, f32 => f32::MAX, f64 => f64::MAX
         }
     }
+
+    #[test]
+    fn test_raw_binops() {
+        assert_eq!(i32::from(RawWasmValue::from(i32::MAX).i32_add(RawWasmValue::from(1))), i32::MIN);
+        assert_eq!(RawWasmValue::from(-1i32).i32_add(RawWasmValue::from(0i32)), RawWasmValue::from(-1i32));
+        assert_eq!(i32::from(RawWasmValue::from(-8i32).i32_shr_s(RawWasmValue::from(1i32))), -4);
+        assert_eq!(i32::from(RawWasmValue::from(-8i32).i32_shr_u(RawWasmValue::from(33i32))), 0x7fff_fffc);
+        assert_eq!(i64::from(RawWasmValue::from(i64::MIN).i64_sub(RawWasmValue::from(1i64))), i64::MAX);
+        assert_eq!(i64::from(RawWasmValue::from(1i64).i64_rotr(RawWasmValue::from(1i64))), i64::MIN);
+        assert_eq!(f32::from(RawWasmValue::from(1.5f32).f32_mul(RawWasmValue::from(2.0f32))), 3.0);
+        assert!(f64::from(RawWasmValue::from(0.0f64).f64_div(RawWasmValue::from(0.0f64))).is_nan());
+    }
 }
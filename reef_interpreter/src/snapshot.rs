@@ -0,0 +1,178 @@
+//! Framing of serialized executions, see [`ExecHandle::serialize`](crate::exec::ExecHandle::serialize)
+//!
+//! A snapshot starts with a fixed size header, followed by the archived [`SerializationState`]:
+//!
+//! | Offset | Size | Content |
+//! |--------|------|---------|
+//! | 0 | 8 | [`MAGIC`] |
+//! | 8 | 4 | [`VERSION`], little endian |
+//! | 12 | 4 | reserved, zero |
+//! | 16 | 8 | length of the archive, little endian |
+//! | 24 | 8 | reserved, zero |
+//! | 32 | 16 | [`Fingerprint`] of the module, little endian |
+//!
+//! The header is a multiple of 16 bytes long, so the archive keeps the alignment of the snapshot.
+
+use alloc::string::ToString;
+
+use rkyv::ser::serializers::{AlignedSerializer, AllocScratch, CompositeSerializer, SharedSerializeMap};
+use rkyv::ser::Serializer;
+use rkyv::{AlignedVec, Deserialize};
+
+use crate::error::{Error, Result, SnapshotError};
+use crate::exec::SerializationState;
+use crate::Fingerprint;
+
+const MAGIC: [u8; 8] = *b"REEFSNAP";
+/// Has to be bumped whenever the layout of [`SerializationState`] changes
+pub(crate) const VERSION: u32 = 5;
+pub(crate) const HEADER_LEN: usize = 48;
+
+/// Serialize `state` into `buf`, replacing its contents
+pub(crate) fn write(state: &SerializationState, module: Fingerprint, mut buf: AlignedVec) -> Result<AlignedVec> {
+    buf.clear();
+    buf.extend_from_slice(&[0; HEADER_LEN]);
+
+    let mut serializer =
+        CompositeSerializer::new(AlignedSerializer::new(buf), AllocScratch::new(), SharedSerializeMap::new());
+    serializer.serialize_value(state).map_err(|e| Error::Other(alloc::format!("Failed to serialize state: {}", e)))?;
+    let mut buf = serializer.into_serializer().into_inner();
+
+    let len = (buf.len() - HEADER_LEN) as u64;
+    buf[..8].copy_from_slice(&MAGIC);
+    buf[8..12].copy_from_slice(&VERSION.to_le_bytes());
+    buf[16..24].copy_from_slice(&len.to_le_bytes());
+    buf[32..48].copy_from_slice(&module.0.to_le_bytes());
+    Ok(buf)
+}
+
+/// Check the header of a snapshot of an instance of `module` and deserialize its state
+pub(crate) fn read(bytes: &[u8], module: Fingerprint) -> Result<SerializationState, SnapshotError> {
+    let magic_len = bytes.len().min(MAGIC.len());
+    if bytes[..magic_len] != MAGIC[..magic_len] {
+        return Err(SnapshotError::Corrupt { offset: 0, message: "not a snapshot".to_string() });
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(SnapshotError::Truncated { expected: HEADER_LEN, actual: bytes.len() });
+    }
+
+    let version = u32::from_le_bytes(bytes[8..12].try_into().expect("slice has 4 bytes"));
+    if version != VERSION {
+        return Err(SnapshotError::VersionSkew { found: version, expected: VERSION });
+    }
+
+    let len = u64::from_le_bytes(bytes[16..24].try_into().expect("slice has 8 bytes"));
+    let end = usize::try_from(len).ok().and_then(|len| len.checked_add(HEADER_LEN)).unwrap_or(usize::MAX);
+    if bytes.len() < end {
+        return Err(SnapshotError::Truncated { expected: end, actual: bytes.len() });
+    }
+    if bytes.len() > end {
+        return Err(SnapshotError::Corrupt { offset: end, message: "trailing bytes".to_string() });
+    }
+
+    let found = Fingerprint(u128::from_le_bytes(bytes[32..48].try_into().expect("slice has 16 bytes")));
+    if found != module {
+        return Err(SnapshotError::ModuleMismatch { expected: module, found });
+    }
+
+    let archived = rkyv::check_archived_root::<SerializationState>(&bytes[HEADER_LEN..])
+        .map_err(|e| SnapshotError::Corrupt { offset: HEADER_LEN, message: e.to_string() })?;
+    // valid archives can still describe memories that don't fit together
+    archived.memory.check(bytes)?;
+    Ok(archived.deserialize(&mut rkyv::Infallible).unwrap_or_else(|e| match e {}))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::CallResult;
+    use crate::imports::Imports;
+    use crate::types::value::WasmValue;
+    use crate::{parse_bytes, Instance, PAGE_SIZE};
+
+    #[test]
+    fn test_snapshot_many_pages() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory 600)
+                (func (export "run") (result i32) (local $i i32)
+                    (loop $fill
+                        (i32.store8 (i32.mul (local.get $i) (i32.const 0x10000)) (i32.const 1))
+                        (br_if $fill (i32.lt_u (local.tee $i (i32.add (local.get $i) (i32.const 1))) (i32.const 600))))
+                    (loop $wait
+                        (br_if $wait (i32.lt_u (local.tee $i (i32.add (local.get $i) (i32.const 1))) (i32.const 10000))))
+                    (i32.load8_u (i32.const 0x2570000))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(alloc::vec::Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(20_000), Ok(CallResult::Incomplete)));
+
+        let state = exec.serialize(rkyv::AlignedVec::new()).unwrap();
+        assert!(state.len() > 600 * PAGE_SIZE);
+
+        let (instance, stack) = Instance::instantiate_with_state(module, Imports::new(), &state).unwrap();
+        let mut exec =
+            instance.exported_func_untyped("run").unwrap().call(alloc::vec::Vec::new(), Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(1)]));
+    }
+
+    #[test]
+    fn test_snapshot_many_functions() {
+        // the fingerprint of the module is part of the snapshot, so archiving the module has to work as well
+        let funcs = "(func (drop (i32.add (i32.const 1) (i32.const 2))))".repeat(1200);
+        let wasm = wat::parse_str(alloc::format!(
+            r#"
+            (module
+                (memory 1)
+                {}
+                (func (export "run") (result i32) (local $i i32)
+                    (loop $l
+                        (call 0)
+                        (br_if $l (i32.lt_u (local.tee $i (i32.add (local.get $i) (i32.const 1))) (i32.const 100))))
+                    (local.get $i)))
+            "#,
+            funcs
+        ))
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        assert!(module.funcs.len() > 1000);
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(alloc::vec::Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(50), Ok(CallResult::Incomplete)));
+        let state = exec.serialize(rkyv::AlignedVec::new()).unwrap();
+
+        let (instance, stack) = Instance::instantiate_with_state(module, Imports::new(), &state).unwrap();
+        let mut exec =
+            instance.exported_func_untyped("run").unwrap().call(alloc::vec::Vec::new(), Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(100)]));
+    }
+
+    #[test]
+    fn test_snapshot_without_memory() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (global $g (mut i32) (i32.const 0))
+                (func (export "run") (result i32)
+                    (loop $l
+                        (global.set $g (i32.add (global.get $g) (i32.const 1)))
+                        (br_if $l (i32.lt_u (global.get $g) (i32.const 100))))
+                    (global.get $g)))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let instance = Instance::instantiate(module.clone(), Imports::new()).unwrap();
+        let mut exec = instance.exported_func_untyped("run").unwrap().call(alloc::vec::Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(10), Ok(CallResult::Incomplete)));
+        let state = exec.serialize(rkyv::AlignedVec::new()).unwrap();
+
+        let (instance, stack) = Instance::instantiate_with_state(module, Imports::new(), &state).unwrap();
+        let mut exec =
+            instance.exported_func_untyped("run").unwrap().call(alloc::vec::Vec::new(), Some(stack)).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(100)]));
+    }
+}
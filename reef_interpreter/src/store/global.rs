@@ -8,7 +8,7 @@ use crate::unlikely;
 /// A WebAssembly Global Instance
 ///
 /// See <https://webassembly.github.io/spec/core/exec/runtime.html#global-instances>
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct GlobalInstance {
     pub(crate) value: RawWasmValue,
     pub(crate) ty: GlobalType,
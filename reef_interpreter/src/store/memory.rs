@@ -1,17 +1,56 @@
-use alloc::{vec, vec::Vec};
+#[cfg(feature = "mmap")]
+use alloc::format;
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use core::cell::UnsafeCell;
+use core::ops::Range;
 
-use crate::error::{Error, Result, Trap};
+use crate::error::{Error, Result, SnapshotError, Trap};
+use crate::mem::{byte_range_for_pages, pages_for_bytes, MAX_PAGES, MAX_SIZE, PAGE_SIZE};
+use crate::replay::MemoryWrite;
 use crate::types::MemoryType;
-use crate::{MAX_PAGES, MAX_SIZE, PAGE_SIZE};
+use crate::unlikely;
+
+/// Where the contents of a memory are stored, see [`Instance::set_memory_backend`](crate::Instance::set_memory_backend)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MemoryBackend {
+    /// A buffer on the heap, which snapshots copy
+    #[default]
+    Heap,
+
+    /// A memory-mapped file, which is created or truncated
+    ///
+    /// Snapshots flush the mapping to the file and only store the size of the memory, so taking one doesn't copy the
+    /// memory. The file always holds the latest contents, which means that only the most recent snapshot of the
+    /// memory can be restored. The snapshot doesn't say where the file is, the host passes it again to
+    /// [`Instance::instantiate_with_state_and_backend`](crate::Instance::instantiate_with_state_and_backend).
+    ///
+    /// Forks of an instance get a copy of the memory on the heap.
+    #[cfg(feature = "mmap")]
+    File(std::path::PathBuf),
+}
 
 /// A WebAssembly Memory Instance
 ///
+/// Clones share the pages of a memory on the heap until either of them writes to a page, see
+/// [`Instance::fork`](crate::Instance::fork).
+///
 /// See <https://webassembly.github.io/spec/core/exec/runtime.html#memory-instances>
 #[derive(Debug)]
 pub(crate) struct MemoryInstance {
     pub(crate) kind: MemoryType,
-    pub(crate) data: Vec<u8>,
+    pub(crate) data: MemoryData,
     pub(crate) page_count: usize,
+    /// The largest page count the memory ever had
+    pub(crate) peak_pages: usize,
+    /// Bitmap of the pages that have been written to
+    dirty: Vec<u64>,
+    /// Whether writes trap, see [`MemoryAccess::ReadOnly`](crate::exec::MemoryAccess::ReadOnly)
+    pub(crate) read_only: bool,
+    /// Changes made by the host function that is being recorded, see [`ReplayLog`](crate::replay::ReplayLog)
+    pub(crate) host_writes: Option<Vec<MemoryWrite>>,
+    /// Copies of the loads that span several pages
+    spans: Spans,
 }
 
 impl MemoryInstance {
@@ -20,14 +59,52 @@ impl MemoryInstance {
 
         Self {
             kind,
-            data: vec![0; PAGE_SIZE * kind.page_count_initial as usize],
+            data: MemoryData::heap(kind.page_count_initial as usize),
             page_count: kind.page_count_initial as usize,
+            peak_pages: kind.page_count_initial as usize,
+            dirty: vec![0; (kind.page_count_initial as usize).div_ceil(64)],
+            read_only: false,
+            host_writes: None,
+            spans: Spans::default(),
         }
     }
 
+    /// Mark the pages overlapping `start..end` as written to
+    #[inline(always)]
+    fn mark_dirty(&mut self, start: usize, end: usize) {
+        if end > start {
+            for page in start / PAGE_SIZE..pages_for_bytes(end) {
+                self.dirty[page / 64] |= 1 << (page % 64);
+            }
+        }
+    }
+
+    /// Record that a host function wrote `len` bytes at `addr`, if its call is recorded
+    pub(crate) fn record_host_write(&mut self, addr: usize, len: usize) {
+        if let Some(writes) = &mut self.host_writes {
+            let mut data = vec![0; len];
+            self.data.read(addr, &mut data);
+            writes.push(MemoryWrite::Store { offset: addr as u64, data });
+        }
+    }
+
+    /// The number of pages that have been written to
+    pub(crate) fn dirty_pages(&self) -> usize {
+        self.dirty.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    /// Fail if the memory is read-only and `len` bytes at `addr` would be written
+    #[inline(always)]
+    fn check_writable(&self, addr: usize, len: usize) -> Result<()> {
+        if unlikely(self.read_only && len > 0) {
+            return Err(Error::Trap(Trap::ReadOnlyMemory { offset: addr, len }));
+        }
+        Ok(())
+    }
+
     #[inline(never)]
     #[cold]
-    fn trap_oob(&self, addr: usize, len: usize) -> Error {
+    pub(crate) fn trap_oob(&self, addr: usize, len: usize) -> Error {
         Error::Trap(Trap::MemoryOutOfBounds { offset: addr, len, max: self.data.len() })
     }
 
@@ -40,7 +117,10 @@ impl MemoryInstance {
             return Err(self.trap_oob(addr, data.len()));
         }
 
-        self.data[addr..end].copy_from_slice(data);
+        self.check_writable(addr, len)?;
+        self.spans.clear();
+        self.data.write(addr, data);
+        self.mark_dirty(addr, end);
         Ok(())
     }
 
@@ -57,7 +137,14 @@ impl MemoryInstance {
             return Err(self.trap_oob(addr, len));
         }
 
-        Ok(&self.data[addr..end])
+        match self.data.get(addr, end) {
+            Some(bytes) => Ok(bytes),
+            None => {
+                let mut bytes = vec![0; len].into_boxed_slice();
+                self.data.read(addr, &mut bytes);
+                Ok(self.spans.push(bytes))
+            }
+        }
     }
 
     // this is a workaround since we can't use generic const expressions yet (https://github.com/rust-lang/rust/issues/76560)
@@ -69,9 +156,13 @@ impl MemoryInstance {
         if end > self.data.len() {
             return Err(self.trap_oob(addr, SIZE));
         }
-        let val = T::from_le_bytes(match self.data[addr..end].try_into() {
-            Ok(bytes) => bytes,
-            Err(_) => unreachable!("checked bounds above"),
+        let val = T::from_le_bytes(match self.data.get(addr, end).map(<[u8; SIZE]>::try_from) {
+            Some(Ok(bytes)) => bytes,
+            _ => {
+                let mut bytes = [0; SIZE];
+                self.data.read(addr, &mut bytes);
+                bytes
+            }
         });
 
         Ok(val)
@@ -88,22 +179,13 @@ impl MemoryInstance {
             return Err(self.trap_oob(addr, len));
         }
 
-        self.data[addr..end].fill(val);
+        self.check_writable(addr, len)?;
+        self.spans.clear();
+        self.data.fill(addr, end, val);
+        self.mark_dirty(addr, end);
         Ok(())
     }
 
-    // needed for copy between different memories
-    //
-    // pub(crate) fn copy_from_slice(&mut self, dst: usize, src: &[u8]) -> Result<()> {
-    //     let end = dst.checked_add(src.len()).ok_or_else(|| self.trap_oob(dst, src.len()))?;
-    //     if end > self.data.len() {
-    //         return Err(self.trap_oob(dst, src.len()));
-    //     }
-
-    //     self.data[dst..end].copy_from_slice(src);
-    //     Ok(())
-    // }
-
     pub(crate) fn copy_within(&mut self, dst: usize, src: usize, len: usize) -> Result<()> {
         // Calculate the end of the source slice
         let src_end = src.checked_add(len).ok_or_else(|| self.trap_oob(src, len))?;
@@ -118,11 +200,19 @@ impl MemoryInstance {
         }
 
         // Perform the copy
+        self.check_writable(dst, len)?;
+        self.spans.clear();
         self.data.copy_within(src..src_end, dst);
+        self.mark_dirty(dst, dst_end);
         Ok(())
     }
 
     pub(crate) fn grow(&mut self, pages_delta: i32) -> Option<i32> {
+        // `memory.grow` takes an unsigned delta, anything above `i32::MAX` pages can never fit
+        if pages_delta < 0 || (self.read_only && pages_delta > 0) {
+            return None;
+        }
+
         let current_pages = self.page_count();
         let new_pages = current_pages as i64 + pages_delta as i64;
 
@@ -134,17 +224,551 @@ impl MemoryInstance {
             return None;
         }
 
-        let new_size = new_pages as usize * PAGE_SIZE;
+        let new_size = byte_range_for_pages(0..new_pages as usize).end;
         if new_size as u64 > MAX_SIZE {
             return None;
         }
 
-        // Zero initialize the new pages
-        self.data.resize(new_size, 0);
+        // New pages are zero, on the heap they're only allocated once they're written to
+        self.spans.clear();
+        if !self.data.resize(new_size) {
+            return None;
+        }
         self.page_count = new_pages as usize;
+        self.peak_pages = self.peak_pages.max(self.page_count);
+        self.dirty.resize(self.page_count.div_ceil(64), 0);
         debug_assert!(current_pages <= i32::MAX as usize, "page count should never be greater than i32::MAX");
         Some(current_pages as i32)
     }
+
+    /// Move the contents of the memory to `backend`
+    pub(crate) fn set_backend(&mut self, backend: MemoryBackend) -> Result<()> {
+        self.spans.clear();
+        self.data = match backend {
+            MemoryBackend::Heap => self.data.to_heap(),
+            #[cfg(feature = "mmap")]
+            MemoryBackend::File(path) => {
+                let mut file = MappedFile::open(path, self.data.len(), true)?;
+                self.data.read(0, &mut file.map);
+                MemoryData::File(file)
+            }
+        };
+        Ok(())
+    }
+
+    /// Map the image of `shared` in place of the zeroed memory of a new instance
+    #[cfg(feature = "mmap")]
+    pub(crate) fn map_shared(&mut self, shared: &SharedSegments) -> Result<()> {
+        if shared.len != self.data.len() {
+            return Err(Error::Other("Shared segments were created for a different memory size".into()));
+        }
+        self.spans.clear();
+        self.data = MemoryData::Shared(SharedMap::new(shared.file.clone())?);
+        Ok(())
+    }
+
+    /// Take a snapshot of the memory, see [`MemoryBackend`]
+    pub(crate) fn snapshot(&self) -> Result<SparseMemory> {
+        #[cfg(feature = "mmap")]
+        if let MemoryData::File(file) = &self.data {
+            file.map.flush()?;
+            return Ok(SparseMemory { page_count: self.page_count as u32, pages: Vec::new(), file_backed: true });
+        }
+
+        Ok(SparseMemory::new(&self.data))
+    }
+
+    /// Replace the contents of the memory with a snapshot
+    ///
+    /// The pages included in the snapshot count as written to. Snapshots of file-backed memories map the file of
+    /// `backend` again, which has to hold the contents of the memory, only pages written after that count. Other
+    /// snapshots are moved to `backend` once they're restored.
+    pub(crate) fn restore(&mut self, snapshot: SparseMemory, backend: MemoryBackend) -> Result<()> {
+        self.spans.clear();
+        self.dirty = vec![0; (snapshot.page_count as usize).div_ceil(64)];
+        let backend = match (snapshot.file_backed, backend) {
+            #[cfg(feature = "mmap")]
+            (true, MemoryBackend::File(path)) => {
+                let len = byte_range_for_pages(0..snapshot.page_count as usize).end;
+                self.data = MemoryData::File(MappedFile::open(path, len, false)?);
+                MemoryBackend::Heap
+            }
+            (true, _) => {
+                return Err(Error::Other("A snapshot of a file-backed memory has to be restored with its file".into()))
+            }
+            (false, backend) => {
+                for (page, _) in snapshot.pages.iter() {
+                    self.dirty[*page as usize / 64] |= 1 << (page % 64);
+                }
+                self.data = MemoryData::Heap(snapshot.into_pages());
+                backend
+            }
+        };
+
+        self.page_count = pages_for_bytes(self.data.len());
+        self.peak_pages = self.peak_pages.max(self.page_count);
+        if backend != MemoryBackend::Heap {
+            self.set_backend(backend)?;
+        }
+        Ok(())
+    }
+}
+
+impl Clone for MemoryInstance {
+    fn clone(&self) -> Self {
+        // a clone writing to a shared mapping would change the memory of the original
+        let data = match &self.data {
+            MemoryData::Heap(pages) => MemoryData::Heap(pages.clone()),
+            #[cfg(feature = "mmap")]
+            MemoryData::File(_) => self.data.to_heap(),
+            // only pages that were written are copied, the rest stay shared with the image
+            #[cfg(feature = "mmap")]
+            MemoryData::Shared(map) => match map.fork(&self.dirty) {
+                Ok(map) => MemoryData::Shared(map),
+                Err(_) => self.data.to_heap(),
+            },
+        };
+
+        Self {
+            kind: self.kind,
+            data,
+            page_count: self.page_count,
+            peak_pages: self.peak_pages,
+            dirty: self.dirty.clone(),
+            read_only: self.read_only,
+            host_writes: None,
+            spans: Spans::default(),
+        }
+    }
+}
+
+/// Copies of loads that span several pages of a memory on the heap, which aren't stored contiguously
+///
+/// Loads only borrow the memory, so the copies have to live as long as the memory. They're freed when it's
+/// borrowed mutably the next time.
+#[derive(Default)]
+struct Spans(UnsafeCell<Vec<Box<[u8]>>>);
+
+impl Spans {
+    fn push(&self, bytes: Box<[u8]>) -> &[u8] {
+        let copy: *const [u8] = &*bytes;
+        // SAFETY: `Spans` isn't `Sync` and no reference to the vector outlives this call. The returned slice points
+        // into the box, which doesn't move when the vector grows and is only dropped by `clear`, which needs `&mut self`.
+        unsafe {
+            (*self.0.get()).push(bytes);
+            &*copy
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.get_mut().clear();
+    }
+}
+
+impl core::fmt::Debug for Spans {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Spans").finish_non_exhaustive()
+    }
+}
+
+/// A page of a memory on the heap, shared between clones of the memory until one of them writes to it
+type Page = Arc<[u8]>;
+
+/// The contents of every page that was never written to, which isn't allocated
+static ZERO_PAGE: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+fn zeroed_page() -> Page {
+    Arc::from(&ZERO_PAGE[..])
+}
+
+/// Split `start..end` at page boundaries, into the index of each page and the range within it
+fn page_ranges(start: usize, end: usize) -> impl Iterator<Item = (usize, Range<usize>)> {
+    (start / PAGE_SIZE..pages_for_bytes(end)).map(move |page| {
+        let page_start = page * PAGE_SIZE;
+        (page, start.max(page_start) - page_start..end.min(page_start + PAGE_SIZE) - page_start)
+    })
+}
+
+/// The contents of a memory, see [`MemoryBackend`]
+#[derive(Debug)]
+pub(crate) enum MemoryData {
+    /// The pages of the memory, which are copied one at a time when they're shared
+    ///
+    /// Pages are only allocated once they're written to, the others are `None`.
+    Heap(Vec<Option<Page>>),
+    #[cfg(feature = "mmap")]
+    File(MappedFile),
+    #[cfg(feature = "mmap")]
+    Shared(SharedMap),
+}
+
+impl MemoryData {
+    fn heap(pages: usize) -> Self {
+        Self::Heap(vec![None; pages])
+    }
+
+    /// The number of bytes that are allocated, which is less than the size of a memory on the heap with pages that
+    /// were never written to
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        match self {
+            Self::Heap(pages) => byte_range_for_pages(0..pages.iter().flatten().count()).end,
+            #[cfg(feature = "mmap")]
+            _ => self.len(),
+        }
+    }
+
+    /// The size of the memory in bytes
+    #[inline(always)]
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Heap(pages) => byte_range_for_pages(0..pages.len()).end,
+            #[cfg(feature = "mmap")]
+            Self::File(file) => file.map.len(),
+            #[cfg(feature = "mmap")]
+            Self::Shared(map) => map.map.len(),
+        }
+    }
+
+    /// The bytes `start..end` if they're stored contiguously, the range has to be in bounds
+    #[inline(always)]
+    fn get(&self, start: usize, end: usize) -> Option<&[u8]> {
+        match self {
+            Self::Heap(pages) => {
+                let offset = start % PAGE_SIZE;
+                match pages.get(start / PAGE_SIZE) {
+                    Some(page) if offset + (end - start) <= PAGE_SIZE => {
+                        Some(&page.as_deref().unwrap_or(&ZERO_PAGE)[offset..offset + (end - start)])
+                    }
+                    _ if start == end => Some(&[]),
+                    _ => None,
+                }
+            }
+            #[cfg(feature = "mmap")]
+            Self::File(file) => Some(&file.map[start..end]),
+            #[cfg(feature = "mmap")]
+            Self::Shared(map) => Some(&map.map[start..end]),
+        }
+    }
+
+    /// The page at index `page`, which has to be in bounds, or `None` if it was never allocated and only contains
+    /// zeros
+    fn page(&self, page: usize) -> Option<&[u8]> {
+        match self {
+            Self::Heap(pages) => pages[page].as_deref(),
+            #[cfg(feature = "mmap")]
+            Self::File(file) => Some(&file.map[byte_range_for_pages(page..page + 1)]),
+            #[cfg(feature = "mmap")]
+            Self::Shared(map) => Some(&map.map[byte_range_for_pages(page..page + 1)]),
+        }
+    }
+
+    /// Copy the bytes starting at `start` to `buf`, the range has to be in bounds
+    fn read(&self, start: usize, buf: &mut [u8]) {
+        if let Some(bytes) = self.get(start, start + buf.len()) {
+            buf.copy_from_slice(bytes);
+            return;
+        }
+        let mut buf = buf;
+        for (page, range) in page_ranges(start, start + buf.len()) {
+            let (head, tail) = buf.split_at_mut(range.len());
+            head.copy_from_slice(&self.page(page).unwrap_or(&ZERO_PAGE)[range]);
+            buf = tail;
+        }
+    }
+
+    /// Call `f` with the mutable parts of the pages covering `start..end`, copying shared pages first
+    fn for_each_mut(&mut self, start: usize, end: usize, mut f: impl FnMut(&mut [u8])) {
+        match self {
+            Self::Heap(pages) => {
+                for (page, range) in page_ranges(start, end) {
+                    f(&mut Arc::make_mut(pages[page].get_or_insert_with(zeroed_page))[range]);
+                }
+            }
+            #[cfg(feature = "mmap")]
+            Self::File(file) => f(&mut file.map[start..end]),
+            #[cfg(feature = "mmap")]
+            Self::Shared(map) => f(&mut map.map[start..end]),
+        }
+    }
+
+    /// Copy `data` to the memory at `start`, the range has to be in bounds
+    fn write(&mut self, start: usize, data: &[u8]) {
+        let mut data = data;
+        self.for_each_mut(start, start + data.len(), |bytes| {
+            let (head, tail) = data.split_at(bytes.len());
+            bytes.copy_from_slice(head);
+            data = tail;
+        });
+    }
+
+    fn fill(&mut self, start: usize, end: usize, val: u8) {
+        self.for_each_mut(start, end, |bytes| bytes.fill(val));
+    }
+
+    fn copy_within(&mut self, src: Range<usize>, dst: usize) {
+        match self {
+            Self::Heap(_) => {
+                let mut bytes = vec![0; src.len()];
+                self.read(src.start, &mut bytes);
+                self.write(dst, &bytes);
+            }
+            #[cfg(feature = "mmap")]
+            Self::File(file) => file.map.copy_within(src, dst),
+            #[cfg(feature = "mmap")]
+            Self::Shared(map) => map.map.copy_within(src, dst),
+        }
+    }
+
+    /// A copy of the contents on the heap
+    fn to_heap(&self) -> Self {
+        match self {
+            Self::Heap(pages) => Self::Heap(pages.clone()),
+            #[cfg(feature = "mmap")]
+            _ => Self::Heap((0..pages_for_bytes(self.len())).map(|page| self.page(page).map(Page::from)).collect()),
+        }
+    }
+
+    /// Whether the page at index `page` is shared with `other`
+    #[cfg(test)]
+    pub(crate) fn shares_page(&self, other: &Self, page: usize) -> bool {
+        match (self, other) {
+            (Self::Heap(pages), Self::Heap(other)) => match (&pages[page], &other[page]) {
+                (Some(page), Some(other)) => Arc::ptr_eq(page, other),
+                (page, other) => page.is_none() && other.is_none(),
+            },
+            #[cfg(feature = "mmap")]
+            _ => false,
+        }
+    }
+
+    /// Resize to `len` bytes, new bytes are zero. Returns whether the memory could be resized
+    fn resize(&mut self, len: usize) -> bool {
+        match self {
+            Self::Heap(pages) => {
+                pages.resize(pages_for_bytes(len), None);
+                true
+            }
+            #[cfg(feature = "mmap")]
+            Self::File(file) => file.resize(len).is_ok(),
+            // a private mapping can't grow, so the memory moves to the heap
+            #[cfg(feature = "mmap")]
+            Self::Shared(_) => {
+                *self = self.to_heap();
+                self.resize(len)
+            }
+        }
+    }
+}
+
+/// A file mapped into memory with `MAP_SHARED`, so the file always has the contents of the memory
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub(crate) struct MappedFile {
+    file: std::fs::File,
+    map: memmap2::MmapMut,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+    /// Map the file at `path` with a size of `len` bytes, creating or truncating it with `truncate`
+    ///
+    /// Otherwise, the file has to exist with exactly that size.
+    fn open(path: std::path::PathBuf, len: usize, truncate: bool) -> Result<Self> {
+        let file =
+            std::fs::OpenOptions::new().read(true).write(true).create(truncate).truncate(truncate).open(&path)?;
+        match truncate {
+            true => file.set_len(len as u64)?,
+            false if file.metadata()?.len() != len as u64 => {
+                return Err(Error::Other(format!("Memory file has {} bytes, expected {}", file.metadata()?.len(), len)))
+            }
+            false => {}
+        }
+        // Safety: the file is only accessed through this mapping. Like any other file, it can still be changed by
+        // other processes, which is the host's responsibility.
+        let map = unsafe { memmap2::MmapMut::map_mut(&file)? };
+        Ok(Self { file, map })
+    }
+
+    fn resize(&mut self, len: usize) -> Result<()> {
+        self.file.set_len(len as u64)?;
+        // Safety: see `open`
+        self.map = unsafe { memmap2::MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+}
+
+/// Data segments stored once and mapped into the memory of many instances of a module
+///
+/// Large static data, like lookup tables, doesn't have to be copied into the memory of every instance. The initial
+/// contents of the selected segments are written to a file once, which every instance created with
+/// [`Instance::instantiate_with_shared_segments`](crate::Instance::instantiate_with_shared_segments) maps privately.
+/// The operating system shares the pages between the instances and only copies the pages a guest writes to.
+///
+/// Forks of such an instance map the file again and only copy the pages written to so far. Growing the memory
+/// moves it to the heap.
+#[cfg(feature = "mmap")]
+#[derive(Debug, Clone)]
+pub struct SharedSegments {
+    file: Arc<std::fs::File>,
+    /// The module the segments were created from
+    pub(crate) fingerprint: crate::Fingerprint,
+    /// The initial size of the memory in bytes
+    len: usize,
+    segments: Box<[u32]>,
+}
+
+#[cfg(feature = "mmap")]
+impl SharedSegments {
+    /// Write the initial contents of the data segments `segments` of `module` to the file at `path`
+    ///
+    /// The file is created or truncated, and only accessed through the returned value afterwards, so on Unix it can
+    /// be removed right away. Segments have to be active segments of a memory defined by the module with constant
+    /// offsets. To check that they don't overlap other active segments, those need constant offsets as well.
+    pub fn new(module: &crate::Module, segments: &[u32], path: impl AsRef<std::path::Path>) -> Result<Self> {
+        use crate::types::{instructions::ConstInstruction, DataKind, ImportKind};
+        use std::io::{Seek, SeekFrom, Write};
+
+        if module.imports.iter().any(|import| matches!(import.kind, ImportKind::Memory(_))) {
+            return Err(Error::UnsupportedFeature("shared segments of imported memories".into()));
+        }
+        let Some(memory) = module.memory_types.first() else {
+            return Err(Error::Other("Shared segments need a memory".into()));
+        };
+        let len = byte_range_for_pages(0..memory.page_count_initial as usize).end;
+        let fingerprint = module.fingerprint()?;
+
+        let range = |idx: usize| match module.data.get(idx).map(|data| &data.kind) {
+            Some(DataKind::Active { mem: 0, offset: ConstInstruction::I32Const(offset) }) => {
+                let start = *offset as u32 as usize;
+                Some(start..start + module.data[idx].data.len())
+            }
+            _ => None,
+        };
+
+        let mut file =
+            std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path.as_ref())?;
+        file.set_len(len as u64)?;
+        for &segment in segments {
+            let Some(shared) = range(segment as usize).filter(|range| range.end <= len) else {
+                return Err(Error::Other(format!("Data segment {} can't be shared", segment)));
+            };
+
+            let overlaps = module.data.iter().enumerate().any(|(idx, data)| {
+                idx != segment as usize
+                    && matches!(data.kind, DataKind::Active { .. })
+                    && range(idx).is_none_or(|other| other.start < shared.end && shared.start < other.end)
+            });
+            if overlaps {
+                return Err(Error::Other(format!("Data segment {} overlaps another active segment", segment)));
+            }
+
+            file.seek(SeekFrom::Start(shared.start as u64))?;
+            file.write_all(&module.data[segment as usize].data)?;
+        }
+        file.flush()?;
+
+        Ok(Self { file: Arc::new(file), fingerprint, len, segments: segments.into() })
+    }
+
+    /// The indices of the shared data segments
+    pub fn segments(&self) -> &[u32] {
+        &self.segments
+    }
+}
+
+/// A private, copy-on-write mapping of the file of [`SharedSegments`]
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub(crate) struct SharedMap {
+    image: Arc<std::fs::File>,
+    map: memmap2::MmapMut,
+}
+
+#[cfg(feature = "mmap")]
+impl SharedMap {
+    fn new(image: Arc<std::fs::File>) -> Result<Self> {
+        // Safety: the file is never written after `SharedSegments::new`, and writes to a private mapping aren't
+        // written back to it
+        let map = unsafe { memmap2::MmapOptions::new().map_copy(&*image)? };
+        Ok(Self { image, map })
+    }
+
+    /// Map the image again and copy the pages marked in the bitmap `dirty`
+    fn fork(&self, dirty: &[u64]) -> Result<Self> {
+        let mut fork = Self::new(self.image.clone())?;
+        for page in (0..pages_for_bytes(self.map.len())).filter(|page| dirty[page / 64] & (1 << (page % 64)) != 0) {
+            let range = byte_range_for_pages(page..page + 1);
+            fork.map[range.clone()].copy_from_slice(&self.map[range]);
+        }
+        Ok(fork)
+    }
+}
+
+/// The contents of a memory with all pages that only contain zeros left out
+///
+/// Used in snapshots, as most of a guest's memory is usually never touched.
+#[derive(Debug, Clone, PartialEq, Eq, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub(crate) struct SparseMemory {
+    /// The size of the memory in pages
+    page_count: u32,
+    /// The index and contents of every page that isn't all zeros
+    pages: Vec<(u32, Vec<u8>)>,
+    /// Whether the contents are in the file backing the memory, in which case `pages` is empty, see
+    /// [`MemoryBackend::File`]
+    file_backed: bool,
+}
+
+impl SparseMemory {
+    pub(crate) fn new(data: &MemoryData) -> Self {
+        let page_count = pages_for_bytes(data.len());
+        let pages = (0..page_count)
+            .filter_map(|idx| Some((idx, data.page(idx)?)))
+            .filter(|(_, page)| page.iter().any(|b| *b != 0))
+            .map(|(idx, page)| (idx as u32, page.to_vec()))
+            .collect();
+        Self { page_count: page_count as u32, pages, file_backed: false }
+    }
+
+    /// Whether this is the snapshot of a memory of size zero, which is also used for modules without a memory
+    pub(crate) fn is_empty(&self) -> bool {
+        self.page_count == 0 && !self.file_backed
+    }
+
+    /// The pages of the memory, which has to be checked with [`ArchivedSparseMemory::check`] first
+    pub(crate) fn into_pages(self) -> Vec<Option<Page>> {
+        let mut pages = vec![None; self.page_count as usize];
+        for (idx, page) in self.pages {
+            pages[idx as usize] = Some(Page::from(page));
+        }
+        pages
+    }
+}
+
+impl ArchivedSparseMemory {
+    /// Check that every page of the archived memory is a whole page within its size, with `snapshot` being the
+    /// bytes containing it to report offsets relative to
+    pub(crate) fn check(&self, snapshot: &[u8]) -> Result<(), SnapshotError> {
+        let corrupt = |at: *const u8, message| SnapshotError::Corrupt {
+            offset: (at as usize).saturating_sub(snapshot.as_ptr() as usize),
+            message,
+        };
+
+        if self.page_count as usize > MAX_PAGES {
+            let at = &self.page_count as *const u32 as *const u8;
+            return Err(corrupt(at, alloc::format!("memory of {} pages", self.page_count)));
+        }
+        for (page, data) in self.pages.iter() {
+            if *page >= self.page_count {
+                let at = page as *const u32 as *const u8;
+                return Err(corrupt(at, alloc::format!("page {} of a memory of {} pages", page, self.page_count)));
+            }
+            if data.len() != PAGE_SIZE {
+                return Err(corrupt(data.as_ptr(), alloc::format!("page {} has {} bytes", page, data.len())));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A trait for types that can be loaded from memory
@@ -169,3 +793,45 @@ macro_rules! impl_mem_loadable_for_primitive {
 impl_mem_loadable_for_primitive!(
     u8, 1, i8, 1, u16, 2, i16, 2, u32, 4, i32, 4, f32, 4, u64, 8, i64, 8, f64, 8, u128, 16, i128, 16
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_memory_check() {
+        let check = |page_count, pages| {
+            let bytes = rkyv::to_bytes::<_, 0>(&SparseMemory { page_count, pages, file_backed: false }).unwrap();
+            rkyv::check_archived_root::<SparseMemory>(&bytes).unwrap().check(&bytes)
+        };
+
+        assert_eq!(check(2, vec![(0, vec![1; PAGE_SIZE]), (1, vec![2; PAGE_SIZE])]), Ok(()));
+        assert!(matches!(check(2, vec![(2, vec![1; PAGE_SIZE])]), Err(SnapshotError::Corrupt { .. })));
+        assert!(matches!(check(2, vec![(1, vec![1; PAGE_SIZE + 1])]), Err(SnapshotError::Corrupt { .. })));
+        assert!(matches!(check(MAX_PAGES as u32 + 1, Vec::new()), Err(SnapshotError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn test_lazy_pages() {
+        let mut memory = MemoryInstance::new(MemoryType::new_32(0, None));
+        assert_eq!(memory.grow(1000), Some(0));
+        assert_eq!((memory.data.len(), memory.data.allocated_bytes()), (1000 * PAGE_SIZE, 0));
+
+        // reading and snapshotting don't allocate pages either
+        assert_eq!(memory.load_as::<4, u32>(500 * PAGE_SIZE).unwrap(), 0);
+        assert_eq!(memory.load(PAGE_SIZE - 2, 4).unwrap(), [0; 4]);
+        assert!(memory.snapshot().unwrap().pages.is_empty());
+        assert_eq!(memory.data.allocated_bytes(), 0);
+
+        memory.store(500 * PAGE_SIZE - 2, 4, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(memory.data.allocated_bytes(), 2 * PAGE_SIZE);
+        assert_eq!(memory.load(500 * PAGE_SIZE - 2, 4).unwrap(), [1, 2, 3, 4]);
+        let snapshot = memory.snapshot().unwrap();
+        assert_eq!(snapshot.pages.iter().map(|(page, _)| *page).collect::<Vec<_>>(), [499, 500]);
+
+        let mut restored = MemoryInstance::new(MemoryType::new_32(0, None));
+        restored.restore(snapshot, MemoryBackend::Heap).unwrap();
+        assert_eq!((restored.data.len(), restored.data.allocated_bytes()), (1000 * PAGE_SIZE, 2 * PAGE_SIZE));
+        assert_eq!(restored.load(500 * PAGE_SIZE - 2, 4).unwrap(), [1, 2, 3, 4]);
+    }
+}
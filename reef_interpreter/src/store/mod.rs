@@ -1,5 +1,62 @@
+use core::mem::size_of;
+
+use crate::mem::byte_range_for_pages;
+
 pub(crate) mod data;
 pub(crate) mod element;
 pub(crate) mod global;
 pub(crate) mod memory;
 pub(crate) mod table;
+
+pub use memory::MemoryBackend;
+#[cfg(feature = "mmap")]
+pub use memory::SharedSegments;
+
+/// Resource usage of an instance, see [`Instance::memory_stats`](crate::Instance::memory_stats)
+///
+/// Counts are summed up across all memories and tables of the instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryStats {
+    /// The current size of the memories in pages
+    pub pages: usize,
+    /// The largest size the memories ever had in pages
+    pub peak_pages: usize,
+    /// The number of pages that have been written to, including by data segments
+    pub dirty_pages: usize,
+    /// The number of elements in the tables
+    pub table_entries: usize,
+    /// The bytes allocated for memories, tables, globals and segments
+    ///
+    /// Pages of a memory on the heap are only allocated once they're written to.
+    pub allocated_bytes: usize,
+}
+
+impl MemoryStats {
+    /// The number of bytes in pages that have been written to
+    pub fn touched_bytes(&self) -> usize {
+        byte_range_for_pages(0..self.dirty_pages).end
+    }
+
+    pub(crate) fn new(instance: &crate::Instance) -> Self {
+        let mut stats = Self::default();
+        for memory in instance.memories.iter() {
+            stats.pages += memory.page_count;
+            stats.peak_pages += memory.peak_pages;
+            stats.dirty_pages += memory.dirty_pages();
+            stats.allocated_bytes += memory.data.allocated_bytes();
+        }
+        for table in instance.tables.iter() {
+            stats.table_entries += table.elements.len();
+            stats.allocated_bytes += table.elements.len() * size_of::<table::TableElement>();
+        }
+        for elem in instance.elements.iter() {
+            stats.allocated_bytes +=
+                elem.items.as_ref().map_or(0, |items| items.len() * size_of::<table::TableElement>());
+        }
+        for data in instance.datas.iter() {
+            stats.allocated_bytes += data.data.as_ref().map_or(0, |data| data.len());
+        }
+        stats.allocated_bytes += instance.globals.len() * size_of::<global::GlobalInstance>();
+        stats
+    }
+}
@@ -1,26 +1,37 @@
-use alloc::{vec, vec::Vec};
+use alloc::{rc::Rc, vec, vec::Vec};
+use core::ops::Range;
 
 use crate::error::{Error, Result, Trap};
 use crate::types::{
+    instructions::ConstInstruction,
     value::{ValType, WasmValue},
-    Addr, TableAddr, TableType,
+    Addr, ElementItem, Module, TableAddr, TableType,
 };
 use crate::unlikely;
 
 const MAX_TABLE_SIZE: u32 = 10000000;
 
+/// Active element segments with at least this many entries are initialized lazily, see [`TableInstance::init_lazy`]
+///
+/// Smaller segments are copied right away, so tables of most modules don't pay for the lookup of lazy entries.
+pub(crate) const LAZY_ELEMENTS_MIN: usize = 1024;
+
 /// A WebAssembly Table Instance
 ///
 /// See <https://webassembly.github.io/spec/core/exec/runtime.html#table-instances>
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct TableInstance {
     pub(crate) elements: Vec<TableElement>,
     pub(crate) kind: TableType,
+    /// Element segments whose entries haven't been copied into `elements` yet, see [`TableInstance::init_lazy`]
+    ///
+    /// They don't overlap each other and take precedence over `elements`.
+    lazy: Vec<LazyElements>,
 }
 
 impl TableInstance {
     pub(crate) fn new(kind: TableType) -> Self {
-        Self { elements: vec![TableElement::Uninitialized; kind.size_initial as usize], kind }
+        Self { elements: vec![TableElement::Uninitialized; kind.size_initial as usize], kind, lazy: Vec::new() }
     }
 
     pub(crate) fn get_wasm_val(&self, addr: TableAddr) -> Result<WasmValue> {
@@ -33,13 +44,25 @@ impl TableInstance {
         })
     }
 
-    pub(crate) fn get(&self, addr: TableAddr) -> Result<&TableElement> {
-        self.elements.get(addr as usize).ok_or_else(|| Error::Trap(Trap::UndefinedElement { index: addr as usize }))
+    pub(crate) fn get(&self, addr: TableAddr) -> Result<TableElement> {
+        let element = *self
+            .elements
+            .get(addr as usize)
+            .ok_or_else(|| Error::Trap(Trap::UndefinedElement { index: addr as usize }))?;
+
+        if unlikely(!self.lazy.is_empty()) {
+            if let Some(lazy) = self.lazy.iter().find(|lazy| lazy.range().contains(&(addr as usize))) {
+                return Ok(LazyElements::resolve(&lazy.items()[addr as usize - lazy.start]));
+            }
+        }
+        Ok(element)
     }
 
     pub(crate) fn set(&mut self, table_idx: TableAddr, value: Addr) -> Result<()> {
-        self.grow_to_fit(table_idx as usize + 1)
-            .map(|_| self.elements[table_idx as usize] = TableElement::Initialized(value))
+        self.grow_to_fit(table_idx as usize + 1)?;
+        self.materialize(table_idx as usize..table_idx as usize + 1);
+        self.elements[table_idx as usize] = TableElement::Initialized(value);
+        Ok(())
     }
 
     pub(crate) fn grow_to_fit(&mut self, new_size: usize) -> Result<()> {
@@ -68,10 +91,49 @@ impl TableInstance {
             return Err(Trap::TableOutOfBounds { offset, len: init.len(), max: self.elements.len() }.into());
         }
 
+        self.materialize(offset..end);
         self.elements[offset..end].copy_from_slice(init);
         Ok(())
     }
 
+    /// Initialize the table with the active element segment `elem` of `module` without resolving its entries
+    ///
+    /// Entries are resolved when they're read, so instantiating a module with a huge segment only costs as much as
+    /// the entries the guest uses. Like [`init_raw`](Self::init_raw), this traps if the segment doesn't fit.
+    /// The segment may only contain function references and null references.
+    pub(crate) fn init_lazy(&mut self, offset: i32, module: Rc<Module>, elem: usize) -> Result<()> {
+        let start = offset as usize;
+        let len = module.elements[elem].items.len();
+        let end = start
+            .checked_add(len)
+            .ok_or_else(|| Error::Trap(Trap::TableOutOfBounds { offset: start, len, max: self.elements.len() }))?;
+
+        if end > self.elements.len() {
+            return Err(Trap::TableOutOfBounds { offset: start, len, max: self.elements.len() }.into());
+        }
+
+        self.materialize(start..end);
+        self.lazy.push(LazyElements { start, module, elem });
+        Ok(())
+    }
+
+    /// Copy the lazily initialized entries overlapping `range` into `elements`, so they can be written to
+    fn materialize(&mut self, range: Range<usize>) {
+        let mut i = 0;
+        while i < self.lazy.len() {
+            let lazy = &self.lazy[i];
+            if lazy.start >= range.end || lazy.range().end <= range.start {
+                i += 1;
+                continue;
+            }
+
+            let lazy = self.lazy.swap_remove(i);
+            for (element, item) in self.elements[lazy.start..].iter_mut().zip(lazy.items()) {
+                *element = LazyElements::resolve(item);
+            }
+        }
+    }
+
     // Initialize the table with the given elements (resolves function references)
     pub(crate) fn init(&mut self, offset: i32, init: &[TableElement]) -> Result<()> {
         let init = init.iter().map(|item| item.map(|addr| addr)).collect::<Vec<_>>();
@@ -79,6 +141,37 @@ impl TableInstance {
     }
 }
 
+/// An active element segment that was copied into a table lazily
+#[derive(Debug, Clone)]
+struct LazyElements {
+    /// The first table entry the segment covers
+    start: usize,
+    module: Rc<Module>,
+    /// The index of the segment in the module
+    elem: usize,
+}
+
+impl LazyElements {
+    fn items(&self) -> &[ElementItem] {
+        &self.module.elements[self.elem].items
+    }
+
+    /// The table entries the segment covers, which were checked to fit the table in [`TableInstance::init_lazy`]
+    fn range(&self) -> Range<usize> {
+        self.start..self.start + self.items().len()
+    }
+
+    /// Function indices are the same as the addresses in the store, so entries can be resolved without the instance
+    fn resolve(item: &ElementItem) -> TableElement {
+        match item {
+            ElementItem::Func(addr) | ElementItem::Expr(ConstInstruction::RefFunc(addr)) => {
+                TableElement::Initialized(*addr)
+            }
+            _ => TableElement::Uninitialized,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum TableElement {
     Uninitialized,
@@ -154,7 +247,7 @@ mod tests {
 
         let elem = table_instance.get(0);
         assert!(
-            elem.is_ok() && matches!(elem.unwrap(), &TableElement::Initialized(1)),
+            elem.is_ok() && matches!(elem.unwrap(), TableElement::Initialized(1)),
             "Getting table element failed or returned incorrect value"
         );
     }
@@ -184,7 +277,7 @@ mod tests {
         for i in 0..5 {
             let elem = table_instance.get(i);
             assert!(
-                elem.is_ok() && matches!(elem.unwrap(), &TableElement::Initialized(_)),
+                elem.is_ok() && matches!(elem.unwrap(), TableElement::Initialized(_)),
                 "Element not initialized correctly at index {}",
                 i
             );
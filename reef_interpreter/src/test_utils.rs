@@ -0,0 +1,16 @@
+//! Helpers shared by the unit tests
+//!
+//! Run exported functions to completion with [`Instance::call_in_place`].
+
+use crate::imports::Imports;
+use crate::{parse_bytes, Instance, Module};
+
+/// Parse a module from the text format
+pub(crate) fn parse(wat: &str) -> Module {
+    parse_bytes(&wat::parse_str(wat).unwrap()).unwrap()
+}
+
+/// Parse a module from the text format and instantiate it
+pub(crate) fn instantiate(wat: &str, imports: Imports) -> Instance {
+    Instance::instantiate(parse(wat), imports).unwrap()
+}
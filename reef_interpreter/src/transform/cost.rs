@@ -0,0 +1,393 @@
+use alloc::{format, vec::Vec};
+
+use crate::error::{Error, Result};
+use crate::types::instructions::Instruction;
+
+/// A group of instructions that take roughly the same time to execute
+///
+/// Fused instructions created by the parser belong to the class of the most expensive operation they contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InstructionClass {
+    /// Constants and `ref.null`/`ref.func`
+    Const,
+    /// Local variable accesses
+    Local,
+    /// Global variable and single table element accesses
+    Global,
+    /// Blocks, branches, returns and stack manipulation like `drop` and `select`
+    Control,
+    /// Direct and indirect calls
+    Call,
+    /// Memory loads
+    Load,
+    /// Memory stores
+    Store,
+    /// Integer arithmetic, bitwise operations and comparisons, except for divisions
+    IntArith,
+    /// Integer divisions and remainders
+    IntDiv,
+    /// Floating point arithmetic and comparisons
+    Float,
+    /// Conversions between and within number types
+    Conversion,
+    /// Memory and table management and bulk operations, charged independently of the number of bytes touched
+    Bulk,
+}
+
+impl InstructionClass {
+    /// All classes, in the order they are stored in a serialized [`CostModel`]
+    pub const ALL: [InstructionClass; 12] = [
+        Self::Const,
+        Self::Local,
+        Self::Global,
+        Self::Control,
+        Self::Call,
+        Self::Load,
+        Self::Store,
+        Self::IntArith,
+        Self::IntDiv,
+        Self::Float,
+        Self::Conversion,
+        Self::Bulk,
+    ];
+
+    /// The class of an instruction
+    #[rustfmt::skip]
+    pub fn of(instr: &Instruction) -> Self {
+        use Instruction::*;
+        match instr {
+            I32Const(_) | I64Const(_) | F32Const(_) | F64Const(_) | RefNull(_) | RefFunc(_) => Self::Const,
+
+            LocalGet(_) | LocalSet(_) | LocalTee(_)
+            | LocalTeeGet(..) | LocalGet2(..) | LocalGet3(..) | LocalGetSet(..) => Self::Local,
+
+            GlobalGet(_) | GlobalSet(_) | TableGet(_) | TableSet(_) | TableSize(_) => Self::Global,
+
+            Call(_) | CallIndirect(..) => Self::Call,
+
+            I32Load { .. } | I64Load { .. } | F32Load { .. } | F64Load { .. }
+            | I32Load8S { .. } | I32Load8U { .. } | I32Load16S { .. } | I32Load16U { .. }
+            | I64Load8S { .. } | I64Load8U { .. } | I64Load16S { .. } | I64Load16U { .. }
+            | I64Load32S { .. } | I64Load32U { .. } => Self::Load,
+
+            I32Store { .. } | I64Store { .. } | F32Store { .. } | F64Store { .. }
+            | I32Store8 { .. } | I32Store16 { .. } | I64Store8 { .. } | I64Store16 { .. } | I64Store32 { .. }
+            | I32StoreLocal { .. } => Self::Store,
+
+            I32DivS | I32DivU | I32RemS | I32RemU | I64DivS | I64DivU | I64RemS | I64RemU => Self::IntDiv,
+
+            I32Eqz | I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS | I32GeU
+            | I64Eqz | I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS | I64GeU
+            | I32Clz | I32Ctz | I32Popcnt | I32Add | I32Sub | I32Mul
+            | I64Clz | I64Ctz | I64Popcnt | I64Add | I64Sub | I64Mul
+            | I32And | I32Or | I32Xor | I32Shl | I32ShrS | I32ShrU | I32Rotl | I32Rotr
+            | I64And | I64Or | I64Xor | I64Shl | I64ShrS | I64ShrU | I64Rotl | I64Rotr
+            | I32LocalGetConstAdd(..) | I64XorConstRotl(_) | I32AddLocals(..) | I32AddConst(_) | RefIsNull => {
+                Self::IntArith
+            }
+
+            F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge
+            | F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge
+            | F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt
+            | F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign
+            | F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt
+            | F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => Self::Float,
+
+            I32WrapI64 | I32TruncF32S | I32TruncF32U | I32TruncF64S | I32TruncF64U | I32Extend8S | I32Extend16S
+            | I64Extend8S | I64Extend16S | I64Extend32S | I64ExtendI32S | I64ExtendI32U
+            | I64TruncF32S | I64TruncF32U | I64TruncF64S | I64TruncF64U
+            | F32ConvertI32S | F32ConvertI32U | F32ConvertI64S | F32ConvertI64U | F32DemoteF64
+            | F64ConvertI32S | F64ConvertI32U | F64ConvertI64S | F64ConvertI64U | F64PromoteF32
+            | I32ReinterpretF32 | I64ReinterpretF64 | F32ReinterpretI32 | F64ReinterpretI64
+            | I32TruncSatF32S | I32TruncSatF32U | I32TruncSatF64S | I32TruncSatF64U
+            | I64TruncSatF32S | I64TruncSatF32U | I64TruncSatF64S | I64TruncSatF64U => Self::Conversion,
+
+            MemorySize(..) | MemoryGrow(..) | MemoryInit(..) | MemoryCopy(..) | MemoryFill(_) | DataDrop(_)
+            | TableInit(..) | TableCopy { .. } | TableGrow(_) | TableFill(_) => Self::Bulk,
+
+            BrLabel(_) | I32EqzBrIf(..) | I32CmpBrIf(..) | FloatDenied | Unreachable | Nop | Block(..) | Loop(..)
+            | If(..) | Else(_) | EndBlockFrame | Br(_) | BrIf(..) | BrTable(..) | Return | Drop | Select(_) => {
+                Self::Control
+            }
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Relative execution cost of every [`InstructionClass`]
+///
+/// Used by [`Module::inject_gas_metering`](crate::Module::inject_gas_metering) to weigh instructions.
+/// The default model charges one unit for every instruction. A model fitted to the current host can be created
+/// with [`CostModel::calibrate`] and shared with other hosts through [`CostModel::to_bytes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CostModel {
+    costs: [u32; InstructionClass::ALL.len()],
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self::uniform(1)
+    }
+}
+
+const MAGIC: [u8; 8] = *b"REEFCOST";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+
+impl CostModel {
+    /// A model that charges `cost` for every instruction
+    pub const fn uniform(cost: u32) -> Self {
+        Self { costs: [cost; InstructionClass::ALL.len()] }
+    }
+
+    /// The cost of every instruction of a class
+    pub fn class_cost(&self, class: InstructionClass) -> u32 {
+        self.costs[class.index()]
+    }
+
+    /// Set the cost of every instruction of a class
+    pub fn set_class_cost(&mut self, class: InstructionClass, cost: u32) {
+        self.costs[class.index()] = cost;
+    }
+
+    /// The cost of an instruction
+    pub fn cost(&self, instr: &Instruction) -> u32 {
+        self.class_cost(InstructionClass::of(instr))
+    }
+
+    /// Encode the model so it can be loaded on another host with [`CostModel::from_bytes`]
+    ///
+    /// The encoding is a 16 byte header (`REEFCOST`, the format version and the number of classes as little endian
+    /// `u32`s) followed by the cost of every class in the order of [`InstructionClass::ALL`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.costs.len() * 4);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.costs.len() as u32).to_le_bytes());
+        for cost in self.costs {
+            bytes.extend_from_slice(&cost.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a model encoded with [`CostModel::to_bytes`]
+    ///
+    /// Models written before a class was added charge one unit for the instructions of that class.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = |message: &str| Error::Other(format!("Invalid cost model: {}", message));
+        if bytes.len() < HEADER_LEN || bytes[..8] != MAGIC {
+            return Err(invalid("missing header"));
+        }
+
+        let read_u32 = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"));
+        let version = read_u32(8);
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let count = read_u32(12) as usize;
+        if count > InstructionClass::ALL.len() {
+            return Err(invalid(&format!("{} classes, expected at most {}", count, InstructionClass::ALL.len())));
+        }
+        if bytes.len() != HEADER_LEN + count * 4 {
+            return Err(invalid(&format!("expected {} bytes, found {}", HEADER_LEN + count * 4, bytes.len())));
+        }
+
+        let mut model = Self::default();
+        for (idx, cost) in model.costs.iter_mut().take(count).enumerate() {
+            *cost = read_u32(HEADER_LEN + idx * 4);
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(feature = "std")]
+mod calibrate {
+    use alloc::{boxed::Box, vec::Vec};
+    use std::time::{Duration, Instant};
+
+    use super::{CostModel, InstructionClass};
+    use crate::error::Result;
+    use crate::exec::CallResult;
+    use crate::imports::Imports;
+    use crate::types::instructions::{BlockArgs, ConstInstruction, Instruction, Instruction::*};
+    use crate::types::{
+        value::ValType, Export, ExternalKind, FuncType, Global, GlobalType, MemoryType, Module, WasmFunction,
+    };
+    use crate::Instance;
+
+    /// Loop iterations of every measurement
+    const ITERATIONS: i32 = 1000;
+    /// Copies of the measured sequence in the body of the loop
+    const UNROLL: usize = 16;
+    /// Measurements of every sequence, of which the fastest is used
+    const RUNS: usize = 5;
+
+    // locals of the benchmark function, the first one is the loop counter
+    const I32: u32 = 1;
+    const I64: u32 = 2;
+    const F64: u32 = 3;
+
+    /// Stack neutral sequences measuring each class, in an order where all other classes a sequence
+    /// contains have already been measured
+    fn sequences() -> [(InstructionClass, Vec<Instruction>); 12] {
+        [
+            (InstructionClass::Local, alloc::vec![LocalGet(I32), LocalSet(I32)]),
+            (InstructionClass::Const, alloc::vec![I32Const(7), LocalSet(I32)]),
+            (InstructionClass::Global, alloc::vec![GlobalGet(0), GlobalSet(0)]),
+            (InstructionClass::Control, alloc::vec![Block(BlockArgs::Empty, 1), EndBlockFrame]),
+            (InstructionClass::Call, alloc::vec![Call(1)]),
+            (InstructionClass::IntArith, alloc::vec![LocalGet(I32), I32Const(3), I32Add, LocalSet(I32)]),
+            (InstructionClass::IntDiv, alloc::vec![LocalGet(I32), I32Const(3), I32DivU, LocalSet(I32)]),
+            (InstructionClass::Float, alloc::vec![LocalGet(F64), F64Const(0.5), F64Mul, LocalSet(F64)]),
+            (InstructionClass::Conversion, alloc::vec![LocalGet(I32), I64ExtendI32U, LocalSet(I64)]),
+            (
+                InstructionClass::Load,
+                alloc::vec![I32Const(0), I32Load { offset: 8, mem_addr: 0, align: 2 }, LocalSet(I32)],
+            ),
+            (
+                InstructionClass::Store,
+                alloc::vec![I32Const(0), LocalGet(I32), I32Store { offset: 8, mem_addr: 0, align: 2 }],
+            ),
+            (InstructionClass::Bulk, alloc::vec![I32Const(0), I32Const(64), I32Const(64), MemoryCopy(0, 0)]),
+        ]
+    }
+
+    /// A module exporting a function `bench` that runs `body` `ITERATIONS * UNROLL` times
+    fn module(body: &[Instruction]) -> Module {
+        let mut instrs = alloc::vec![I32Const(ITERATIONS), LocalSet(0), Loop(BlockArgs::Empty, 0)];
+        for _ in 0..UNROLL {
+            instrs.extend_from_slice(body);
+        }
+        instrs.extend([
+            LocalGet(0),
+            I32Const(1),
+            I32Sub,
+            LocalTee(0),
+            BrIf(0, Default::default()),
+            EndBlockFrame,
+            Return,
+        ]);
+        let end = instrs.len() - 2;
+        instrs[2] = Loop(BlockArgs::Empty, (end - 2) as u32);
+
+        let func = |instructions: Vec<Instruction>, locals: &[ValType]| WasmFunction {
+            instructions: instructions.into_boxed_slice(),
+            offsets: Box::new([]),
+            locals: locals.into(),
+            ty: FuncType::default(),
+            max_stack: 0,
+        };
+        Module {
+            funcs: Box::new([
+                func(instrs, &[ValType::I32, ValType::I32, ValType::I64, ValType::F64]),
+                func(alloc::vec![Return], &[]),
+            ]),
+            func_types: Box::new([FuncType::default()]),
+            exports: Box::new([Export { name: "bench".into(), kind: ExternalKind::Func, index: 0 }]),
+            globals: Box::new([Global {
+                ty: GlobalType { mutable: true, ty: ValType::I32 },
+                init: ConstInstruction::I32Const(0),
+            }]),
+            memory_types: Box::new([MemoryType::new_32(1, None)]),
+            ..Default::default()
+        }
+    }
+
+    /// The fastest of several runs of `body`
+    fn measure(body: &[Instruction]) -> Result<Duration> {
+        let mut instance = Instance::instantiate(module(body), Imports::new())?;
+        let mut fastest = Duration::MAX;
+        for _ in 0..RUNS {
+            let func = instance.exported_func_untyped("bench")?;
+            let start = Instant::now();
+            let mut exec = func.call(Vec::new(), None)?;
+            while let CallResult::Incomplete = exec.run(usize::MAX)? {}
+            fastest = fastest.min(start.elapsed());
+            instance = exec.into_instance();
+        }
+        Ok(fastest)
+    }
+
+    pub(super) fn calibrate() -> Result<CostModel> {
+        let overhead = measure(&[])?;
+        let runs = f64::from(ITERATIONS) * UNROLL as f64;
+
+        // nanoseconds per instruction of every class
+        let mut nanos = [None::<f64>; InstructionClass::ALL.len()];
+        for (class, body) in sequences() {
+            let total = measure(&body)?.saturating_sub(overhead).as_secs_f64() * 1e9 / runs;
+            let mut known = 0.0;
+            let mut count = 0;
+            for instr in &body {
+                match InstructionClass::of(instr) {
+                    c if c == class => count += 1,
+                    c => known += nanos[c.index()].expect("sequences are ordered by their dependencies"),
+                }
+            }
+            // calls also execute the return of the callee
+            if class == InstructionClass::Call {
+                known += nanos[InstructionClass::Control.index()].expect("measured before calls");
+            }
+            nanos[class.index()] = Some(((total - known) / f64::from(count)).max(0.0));
+        }
+
+        let nanos = nanos.map(|n| n.expect("every class is measured"));
+        let unit = nanos.iter().copied().filter(|n| *n > 0.0).fold(f64::INFINITY, f64::min);
+        let mut model = CostModel::default();
+        if unit.is_finite() {
+            for class in InstructionClass::ALL {
+                model.set_class_cost(class, ((nanos[class.index()] / unit).round() as u32).max(1));
+            }
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(feature = "std")]
+impl CostModel {
+    /// Measure the instruction classes on the current host
+    ///
+    /// Runs a short benchmark of every [`InstructionClass`] in the interpreter and returns a model where the
+    /// cheapest class costs one unit. This takes a few milliseconds in an optimized build. Timings are noisy, so
+    /// a fleet should calibrate once per host type and distribute the result with [`CostModel::to_bytes`]
+    /// instead of calibrating on every worker, which would make gas usage differ between them.
+    pub fn calibrate() -> Result<Self> {
+        calibrate::calibrate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_model_bytes() {
+        let mut model = CostModel::default();
+        model.set_class_cost(InstructionClass::IntDiv, 7);
+        model.set_class_cost(InstructionClass::Bulk, 40);
+        assert_eq!(CostModel::from_bytes(&model.to_bytes()).unwrap(), model);
+
+        // older models without the later classes
+        let mut bytes = model.to_bytes();
+        bytes[12..16].copy_from_slice(&9u32.to_le_bytes());
+        bytes.truncate(HEADER_LEN + 9 * 4);
+        let old = CostModel::from_bytes(&bytes).unwrap();
+        assert_eq!(old.class_cost(InstructionClass::IntDiv), 7);
+        assert_eq!(old.class_cost(InstructionClass::Bulk), 1);
+
+        assert!(CostModel::from_bytes(&bytes[..20]).is_err());
+        assert!(CostModel::from_bytes(b"REEFSNAP").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_calibrate() {
+        let model = CostModel::calibrate().unwrap();
+        assert!(InstructionClass::ALL.iter().all(|class| model.class_cost(*class) >= 1));
+        assert!(InstructionClass::ALL.iter().any(|class| model.class_cost(*class) == 1));
+        assert_eq!(model.cost(&Instruction::I32DivU), model.class_cost(InstructionClass::IntDiv));
+    }
+}
@@ -0,0 +1,177 @@
+use alloc::vec::Vec;
+
+use super::CostModel;
+use crate::error::Result;
+use crate::types::{
+    instructions::{BlockArgs, BranchHint, ConstInstruction, Instruction},
+    value::ValType,
+    Export, ExternalKind, FuncType, GlobalType, Module,
+};
+
+/// Configuration for [`Module::inject_gas_metering`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasConfig {
+    /// Module name of the imported out-of-gas hook
+    pub hook_module: &'static str,
+    /// Name of the imported out-of-gas hook
+    pub hook_name: &'static str,
+    /// Name under which the gas counter global is exported
+    pub global_name: &'static str,
+    /// Gas charged for every unit of [`cost_model`](Self::cost_model)
+    pub instruction_cost: u32,
+    /// Relative cost of the instructions, the default charges one unit for every instruction
+    pub cost_model: CostModel,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            hook_module: "reef",
+            hook_name: "out_of_gas",
+            global_name: "__reef_gas",
+            instruction_cost: 1,
+            cost_model: CostModel::default(),
+        }
+    }
+}
+
+impl Module {
+    /// Instrument the module to count the gas it uses
+    ///
+    /// Adds a mutable `i64` global holding the remaining gas, exported as [`GasConfig::global_name`].
+    /// At the start of every straight-line sequence of instructions, the cost of the whole sequence is subtracted
+    /// from it. Once the counter drops below zero, the imported hook `() -> ()` is called, which is expected to
+    /// return an error to stop execution. The instrumentation only depends on the module, so the same
+    /// module always consumes the same amount of gas.
+    ///
+    /// The counter starts at zero, so it has to be set through the export before any code runs.
+    pub fn inject_gas_metering(&mut self, config: &GasConfig) -> Result<()> {
+        let hook = self.add_func_import(config.hook_module, config.hook_name, FuncType::default());
+        let gas = self.add_global(GlobalType { mutable: true, ty: ValType::I64 }, ConstInstruction::I64Const(0));
+        self.exports = self
+            .exports
+            .iter()
+            .cloned()
+            .chain([Export { name: config.global_name.into(), kind: ExternalKind::Global, index: gas }])
+            .collect();
+
+        let charge = |cost: u64| {
+            [
+                Instruction::GlobalGet(gas),
+                Instruction::I64Const((cost * u64::from(config.instruction_cost)) as i64),
+                Instruction::I64Sub,
+                Instruction::GlobalSet(gas),
+                Instruction::GlobalGet(gas),
+                Instruction::I64Const(0),
+                Instruction::I64LtS,
+                Instruction::If(BlockArgs::Empty.into(), 0, 0, BranchHint::Unlikely),
+                Instruction::Call(hook),
+                Instruction::EndBlockFrame,
+            ]
+        };
+
+        self.for_each_function_mut(|_, func| {
+            for (start, cost) in metered_blocks(func.instructions(), &config.cost_model) {
+                func.insert_before(start, charge(cost))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Split a function into sequences that are always executed from start to end (or until a trap),
+/// returning the index each one starts at and its cost
+fn metered_blocks(instrs: &[Instruction], model: &CostModel) -> Vec<(usize, u64)> {
+    let cost = |range: &[Instruction]| range.iter().map(|instr| u64::from(model.cost(instr))).sum::<u64>();
+    let mut blocks = Vec::new();
+    let mut start = 0;
+
+    for (idx, instr) in instrs.iter().enumerate() {
+        let ends_block = match instr {
+            // branch targets or the start of a new branch follow these
+            Instruction::Loop(..)
+            | Instruction::If(..)
+            | Instruction::Else(..)
+            | Instruction::EndBlockFrame
+            | Instruction::BrIf(..)
+            | Instruction::I32EqzBrIf(..)
+            | Instruction::I32CmpBrIf(..) => true,
+            // the labels of a br_table are part of it
+            Instruction::BrTable(_, len) => {
+                blocks.push((start, cost(&instrs[start..=idx])));
+                start = idx + 1 + *len as usize;
+                continue;
+            }
+            Instruction::BrLabel(_) => continue,
+            _ => false,
+        };
+
+        if ends_block {
+            blocks.push((start, cost(&instrs[start..=idx])));
+            start = idx + 1;
+        }
+    }
+
+    if start < instrs.len() {
+        blocks.push((start, cost(&instrs[start..])));
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, Result};
+    use crate::exec::CallResult;
+    use crate::imports::{Extern, Imports};
+    use crate::transform::InstructionClass;
+    use crate::types::value::WasmValue;
+    use crate::{parse_bytes, Instance};
+
+    const COUNTDOWN: &str = r#"
+        (module
+            (func (export "countdown") (param i32) (result i32)
+                (loop $l
+                    (br_if $l (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+                (local.get 0)))
+    "#;
+
+    fn run_countdown(gas: i64, iterations: i32) -> Result<CallResult> {
+        let mut module = parse_bytes(&wat::parse_str(COUNTDOWN).unwrap()).unwrap();
+        module.inject_gas_metering(&GasConfig::default()).unwrap();
+
+        let mut imports = Imports::new();
+        imports
+            .define("reef", "out_of_gas", Extern::typed_func(|_, ()| Err::<(), _>(Error::Other("out of gas".into()))))
+            .unwrap();
+
+        let mut instance = Instance::instantiate(module, imports)?;
+        instance.exported_global("__reef_gas")?.set(WasmValue::I64(gas))?;
+        let mut exec =
+            instance.exported_func_untyped("countdown")?.call(alloc::vec![WasmValue::I32(iterations)], None)?;
+        exec.run(usize::MAX)
+    }
+
+    #[test]
+    fn test_gas_metering() {
+        assert!(matches!(run_countdown(1000, 10), Ok(CallResult::Done(res)) if res == [WasmValue::I32(0)]));
+        assert!(matches!(run_countdown(1000, 1000), Err(Error::Other(msg)) if msg == "out of gas"));
+    }
+
+    #[test]
+    fn test_metered_blocks() {
+        let module = parse_bytes(&wat::parse_str(COUNTDOWN).unwrap()).unwrap();
+        let blocks = metered_blocks(&module.funcs[0].instructions, &CostModel::default());
+        assert_eq!(blocks.first().map(|b| b.0), Some(0));
+        assert_eq!(blocks.iter().map(|b| b.1).sum::<u64>(), module.funcs[0].instructions.len() as u64);
+
+        let mut model = CostModel::default();
+        model.set_class_cost(InstructionClass::Local, 3);
+        let locals = module.funcs[0].instructions.iter().filter(|i| InstructionClass::of(i) == InstructionClass::Local);
+        let weighted = metered_blocks(&module.funcs[0].instructions, &model);
+        assert_eq!(
+            weighted.iter().map(|b| b.1).sum::<u64>(),
+            module.funcs[0].instructions.len() as u64 + 2 * locals.count() as u64
+        );
+    }
+}
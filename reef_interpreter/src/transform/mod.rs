@@ -0,0 +1,317 @@
+//! Transformations of parsed modules
+//!
+//! Use [`Module::for_each_function_mut`] to instrument function bodies before a module is instantiated.
+//! Block offsets are fixed up automatically after a function has been rewritten, so instruction sequences
+//! (including whole blocks) can be inserted anywhere without having to care about the internal bytecode layout.
+
+mod cost;
+mod gas;
+mod strip;
+pub use cost::{CostModel, InstructionClass};
+pub use gas::GasConfig;
+pub use strip::StripOptions;
+
+use alloc::{collections::BTreeMap, format, string::ToString, vec::Vec};
+
+use crate::error::{Error, Result};
+use crate::types::{
+    instructions::{ConstInstruction, Instruction},
+    ElementItem, ExternalKind, FuncAddr, FuncType, Global, GlobalAddr, GlobalType, Import, ImportKind, Module,
+    WasmFunction,
+};
+
+/// Rewrites the instructions of a single function
+///
+/// Insertions are collected and only applied once the callback passed to
+/// [`Module::for_each_function_mut`] returns, so indices always refer to the original instructions.
+#[derive(Debug)]
+pub struct FunctionRewriter<'a> {
+    func: &'a mut WasmFunction,
+    before: BTreeMap<usize, Vec<Instruction>>,
+    after: BTreeMap<usize, Vec<Instruction>>,
+}
+
+impl<'a> FunctionRewriter<'a> {
+    fn new(func: &'a mut WasmFunction) -> Self {
+        Self { func, before: BTreeMap::new(), after: BTreeMap::new() }
+    }
+
+    /// The type of the function
+    pub fn ty(&self) -> &FuncType {
+        &self.func.ty
+    }
+
+    /// The original instructions of the function
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.func.instructions
+    }
+
+    /// Indices of all `loop` instructions
+    ///
+    /// Every back-edge jumps to the instruction following the loop,
+    /// so code inserted after one of these runs once per iteration.
+    pub fn loop_headers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.func.instructions.iter().enumerate().filter(|(_, i)| matches!(i, Instruction::Loop(..))).map(|(i, _)| i)
+    }
+
+    /// Insert instructions before the instruction at `idx`
+    ///
+    /// Use an index of `0` to insert code at the start of the function.
+    pub fn insert_before(&mut self, idx: usize, instrs: impl IntoIterator<Item = Instruction>) -> Result<()> {
+        self.check_index(idx)?;
+        self.before.entry(idx).or_default().extend(instrs);
+        Ok(())
+    }
+
+    /// Insert instructions after the instruction at `idx`
+    pub fn insert_after(&mut self, idx: usize, instrs: impl IntoIterator<Item = Instruction>) -> Result<()> {
+        self.check_index(idx)?;
+        if idx + 1 == self.func.instructions.len() {
+            return Err(Error::Other("Can't insert instructions after the end of a function".to_string()));
+        }
+        if let Instruction::BrTable(..) = self.func.instructions[idx] {
+            return Err(Error::Other("Can't insert instructions between a br_table and its labels".to_string()));
+        }
+        self.after.entry(idx).or_default().extend(instrs);
+        Ok(())
+    }
+
+    /// Append locals to the function, returning the index of the first new local
+    pub fn add_locals(&mut self, locals: &[crate::types::value::ValType]) -> u32 {
+        let first = (self.func.ty.params.len() + self.func.locals.len()) as u32;
+        self.func.locals = self.func.locals.iter().chain(locals).copied().collect();
+        first
+    }
+
+    fn check_index(&self, idx: usize) -> Result<()> {
+        match self.func.instructions.get(idx) {
+            None => Err(Error::Other(format!("Instruction index {} is out of bounds", idx))),
+            Some(Instruction::BrLabel(_)) => {
+                Err(Error::Other("Can't insert instructions between a br_table and its labels".to_string()))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if self.before.is_empty() && self.after.is_empty() {
+            return Ok(());
+        }
+
+        let inserted = self.before.values().chain(self.after.values()).map(Vec::len).sum::<usize>();
+        // the stack estimate assumes inserted code pushes at most one value per instruction
+        let longest = self.before.values().chain(self.after.values()).map(Vec::len).max().unwrap_or(0);
+        let mut instructions = Vec::with_capacity(self.func.instructions.len() + inserted);
+        // inserted instructions are attributed to the instruction they were inserted next to
+        let mut offsets = Vec::with_capacity(self.func.offsets.len() + inserted);
+        for (idx, instr) in self.func.instructions.iter().enumerate() {
+            let offset = self.func.offsets.get(idx).copied();
+            if let Some(before) = self.before.remove(&idx) {
+                offsets.extend(offset.iter().cycle().take(before.len()));
+                instructions.extend(before);
+            }
+            instructions.push(instr.clone());
+            offsets.extend(offset);
+            if let Some(after) = self.after.remove(&idx) {
+                offsets.extend(offset.iter().cycle().take(after.len()));
+                instructions.extend(after);
+            }
+        }
+
+        relink(&mut instructions)?;
+        self.func.instructions = instructions.into_boxed_slice();
+        self.func.offsets = offsets.into_boxed_slice();
+        self.func.max_stack += longest as u32;
+        Ok(())
+    }
+}
+
+/// Recompute the offsets stored in block instructions from the structure of the function
+pub(crate) fn relink(instrs: &mut [Instruction]) -> Result<()> {
+    #[cold]
+    fn error(msg: &str) -> Error {
+        Error::Other(format!("Invalid block structure: {}", msg))
+    }
+
+    let mut labels: Vec<usize> = Vec::new();
+    for idx in 0..instrs.len() {
+        match instrs[idx] {
+            Instruction::Block(..) | Instruction::Loop(..) | Instruction::If(..) | Instruction::Else(..) => {
+                labels.push(idx)
+            }
+            Instruction::EndBlockFrame => {
+                let label = labels.pop().ok_or_else(|| error("end without a block"))?;
+                match &mut instrs[label] {
+                    Instruction::Else(end_offset) => {
+                        *end_offset = (idx - label) as u32;
+                        let if_label = labels.pop().ok_or_else(|| error("else without an if"))?;
+                        let Instruction::If(_, else_offset, end_offset, _) = &mut instrs[if_label] else {
+                            return Err(error("else without an if"));
+                        };
+                        *else_offset = (label - if_label) as u32;
+                        *end_offset = (idx - if_label) as u32;
+                    }
+                    Instruction::If(_, else_offset, end_offset, _) => {
+                        *else_offset = 0;
+                        *end_offset = (idx - label) as u32;
+                    }
+                    Instruction::Block(_, end_offset) | Instruction::Loop(_, end_offset) => {
+                        *end_offset = (idx - label) as u32;
+                    }
+                    _ => unreachable!("only block instructions are pushed as labels"),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match labels.is_empty() {
+        true => Ok(()),
+        false => Err(error("block without an end")),
+    }
+}
+
+impl Module {
+    /// Rewrite the body of every function defined in the module
+    ///
+    /// The callback receives the function's address (imported functions come first) and a
+    /// [`FunctionRewriter`] to insert instructions with.
+    pub fn for_each_function_mut<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(FuncAddr, &mut FunctionRewriter<'_>) -> Result<()>,
+    {
+        let imported = self.imported_func_count();
+        for (idx, func) in self.funcs.iter_mut().enumerate() {
+            let mut rewriter = FunctionRewriter::new(func);
+            f(imported + idx as u32, &mut rewriter)?;
+            rewriter.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Add a function import, returning its address
+    ///
+    /// Imported functions come before the functions defined in the module,
+    /// so all references to those are shifted by one.
+    pub fn add_func_import(&mut self, module: &str, name: &str, ty: FuncType) -> FuncAddr {
+        let type_idx = match self.func_types.iter().position(|t| *t == ty) {
+            Some(idx) => idx as u32,
+            None => {
+                self.func_types = self.func_types.iter().cloned().chain([ty]).collect();
+                self.func_types.len() as u32 - 1
+            }
+        };
+
+        let addr = self.imported_func_count();
+        self.remap_funcs(|func| if func >= addr { func + 1 } else { func });
+
+        // the new import is the last function import, regardless of other imports following it
+        let import = Import { module: module.into(), name: name.into(), kind: ImportKind::Function(type_idx) };
+        self.imports = self.imports.iter().cloned().chain([import]).collect();
+        addr
+    }
+
+    /// Add a global to the module, returning its address
+    pub fn add_global(&mut self, ty: GlobalType, init: ConstInstruction) -> GlobalAddr {
+        let imported = self.imports.iter().filter(|i| matches!(i.kind, ImportKind::Global(_))).count();
+        self.globals = self.globals.iter().cloned().chain([Global { ty, init }]).collect();
+        (imported + self.globals.len() - 1) as GlobalAddr
+    }
+
+    pub(crate) fn imported_func_count(&self) -> u32 {
+        self.imports.iter().filter(|i| matches!(i.kind, ImportKind::Function(_))).count() as u32
+    }
+
+    /// Replace every reference to a function address
+    pub(crate) fn remap_funcs(&mut self, f: impl Fn(FuncAddr) -> FuncAddr) {
+        let remap_const = |c: &mut ConstInstruction| {
+            if let ConstInstruction::RefFunc(func) = c {
+                *func = f(*func);
+            }
+        };
+
+        for func in self.funcs.iter_mut() {
+            for instr in func.instructions.iter_mut() {
+                match instr {
+                    Instruction::Call(func) | Instruction::RefFunc(func) => *func = f(*func),
+                    _ => {}
+                }
+            }
+        }
+
+        for global in self.globals.iter_mut() {
+            remap_const(&mut global.init);
+        }
+
+        for element in self.elements.iter_mut() {
+            for item in element.items.iter_mut() {
+                match item {
+                    ElementItem::Func(func) => *func = f(*func),
+                    ElementItem::Expr(expr) => remap_const(expr),
+                }
+            }
+        }
+
+        for (func, _) in self.func_names.iter_mut() {
+            *func = f(*func);
+        }
+
+        for export in self.exports.iter_mut().filter(|e| e.kind == ExternalKind::Func) {
+            export.index = f(export.index);
+        }
+
+        if let Some(start) = self.start_func.as_mut() {
+            *start = f(*start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    #[test]
+    fn test_instrument_loops() {
+        let mut module = parse(
+            r#"
+            (module
+                (func $f (export "f") (param i32) (result i32)
+                    (loop $l
+                        (if (local.get 0) (then (local.set 0 (i32.sub (local.get 0) (i32.const 1))) (br $l))))
+                    (call $f (i32.const 0))))
+            "#,
+        );
+
+        let hook = module.add_func_import("env", "tick", FuncType::default());
+        module
+            .for_each_function_mut(|_, func| {
+                for idx in func.loop_headers().collect::<Vec<_>>() {
+                    func.insert_after(idx, [Instruction::Call(hook)])?;
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let mut expected = parse(
+            r#"
+            (module
+                (import "env" "tick" (func $tick))
+                (func $f (export "f") (param i32) (result i32)
+                    (loop $l
+                        call $tick
+                        (if (local.get 0) (then (local.set 0 (i32.sub (local.get 0) (i32.const 1))) (br $l))))
+                    (call $f (i32.const 0))))
+            "#,
+        );
+        assert_eq!(module.funcs[0].offsets[..2], [module.funcs[0].offsets[0]; 2]);
+        // the estimate makes room for a value pushed by the inserted call
+        assert_eq!(module.funcs[0].max_stack, expected.funcs[0].max_stack + 1);
+        module.funcs.iter_mut().chain(expected.funcs.iter_mut()).for_each(|f| {
+            f.offsets = Default::default();
+            f.max_stack = 0;
+        });
+        assert_eq!(module.funcs, expected.funcs);
+        assert_eq!(module.exports, expected.exports);
+    }
+}
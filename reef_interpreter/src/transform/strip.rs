@@ -0,0 +1,150 @@
+use alloc::{vec, vec::Vec};
+
+use crate::types::{
+    instructions::{BranchHint, ConstInstruction, Instruction},
+    ElementItem, ExternalKind, FuncAddr, Module,
+};
+
+/// Configuration for [`Module::strip`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StripOptions {
+    /// Also remove functions that are defined in the module but can never be called
+    ///
+    /// A function is kept if it's exported, the start function, referenced by an element segment or a `ref.func`,
+    /// or called by another function that is kept. Imports are never removed.
+    pub dead_functions: bool,
+}
+
+impl Module {
+    /// Remove everything that isn't needed to run the module
+    ///
+    /// Drops function names, the instruction offsets used by [`Module::resolve_location`], branch hints and the
+    /// contents of the `producers` and `target_features` sections, which are the only parts of custom sections that
+    /// are kept when parsing. Together with [`Module::encode`], this works like `wasm-strip`.
+    ///
+    /// Without its target features, a stripped module is no longer checked for required features when it's
+    /// instantiated.
+    pub fn strip(&mut self, options: &StripOptions) {
+        self.func_names = Default::default();
+        self.producers = Default::default();
+        self.target_features = Default::default();
+        self.funcs.iter_mut().for_each(|func| func.offsets = Default::default());
+        self.clear_branch_hints();
+
+        if options.dead_functions {
+            self.remove_dead_functions();
+        }
+    }
+
+    /// Forget the branch hints of all instructions
+    pub(crate) fn clear_branch_hints(&mut self) {
+        for instr in self.funcs.iter_mut().flat_map(|func| func.instructions.iter_mut()) {
+            match instr {
+                Instruction::If(.., hint)
+                | Instruction::BrIf(_, hint)
+                | Instruction::I32EqzBrIf(_, hint)
+                | Instruction::I32CmpBrIf(_, _, hint) => *hint = BranchHint::Unknown,
+                _ => {}
+            }
+        }
+    }
+
+    fn remove_dead_functions(&mut self) {
+        let imported = self.imported_func_count();
+        let mut live = vec![false; imported as usize + self.funcs.len()];
+        live[..imported as usize].fill(true);
+
+        let mut roots: Vec<FuncAddr> = self.start_func.into_iter().collect();
+        roots.extend(self.exports.iter().filter(|e| e.kind == ExternalKind::Func).map(|e| e.index));
+        for item in self.elements.iter().flat_map(|element| element.items.iter()) {
+            match item {
+                ElementItem::Func(func) | ElementItem::Expr(ConstInstruction::RefFunc(func)) => roots.push(*func),
+                ElementItem::Expr(_) => {}
+            }
+        }
+        for global in self.globals.iter() {
+            if let ConstInstruction::RefFunc(func) = global.init {
+                roots.push(func);
+            }
+        }
+
+        while let Some(func) = roots.pop() {
+            if core::mem::replace(&mut live[func as usize], true) {
+                continue;
+            }
+
+            let body = &self.funcs[(func - imported) as usize];
+            for instr in body.instructions.iter() {
+                if let Instruction::Call(callee) | Instruction::RefFunc(callee) = instr {
+                    roots.push(*callee);
+                }
+            }
+        }
+
+        if live.iter().all(|live| *live) {
+            return;
+        }
+
+        // new address of every function that is kept
+        let mut addrs = Vec::with_capacity(live.len());
+        let mut next = 0;
+        for live in live.iter() {
+            addrs.push(next);
+            next += *live as FuncAddr;
+        }
+
+        let funcs = core::mem::take(&mut self.funcs).into_vec();
+        self.funcs =
+            funcs.into_iter().zip(&live[imported as usize..]).filter(|(_, live)| **live).map(|(f, _)| f).collect();
+        self.remap_funcs(|func| addrs[func as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::CallResult;
+    use crate::imports::{Extern, Imports};
+    use crate::types::value::WasmValue;
+    use crate::{parse_bytes, Instance};
+
+    #[test]
+    fn test_strip() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "log" (func $log (param i32)))
+                (table 1 funcref)
+                (elem (i32.const 0) $indirect)
+                (global $g (mut i32) (i32.const 0))
+                (func $unused (call $also_unused))
+                (func $also_unused (call $unused))
+                (func $indirect (result i32) (i32.const 40))
+                (func $helper (param i32) (result i32) (i32.add (local.get 0) (call_indirect (result i32) (i32.const 0))))
+                (func $init (global.set $g (i32.const 2)))
+                (func (export "main") (result i32)
+                    (call $log (global.get $g))
+                    (call $helper (global.get $g)))
+                (start $init))
+            "#,
+        )
+        .unwrap();
+
+        let mut module = parse_bytes(&wasm).unwrap();
+        module.strip(&StripOptions::default());
+        assert!(module.func_names.is_empty());
+        assert!(module.funcs.iter().all(|func| func.offsets.is_empty()));
+        assert_eq!(module.funcs.len(), 6);
+
+        module.strip(&StripOptions { dead_functions: true });
+        assert_eq!(module.funcs.len(), 4);
+        assert_eq!(module.start_func, Some(3));
+        assert!(parse_bytes(&module.encode().unwrap()).is_ok());
+
+        let mut imports = Imports::new();
+        imports.define("env", "log", Extern::typed_func(|_, _: i32| Ok(()))).unwrap();
+        let instance = Instance::instantiate_start(module, imports, usize::MAX).unwrap();
+        let mut exec = instance.exported_func_untyped("main").unwrap().call(Vec::new(), None).unwrap();
+        assert!(matches!(exec.run(usize::MAX), Ok(CallResult::Done(res)) if res == [WasmValue::I32(42)]));
+    }
+}
@@ -50,8 +50,91 @@ impl From<BlockArgsPacked> for BlockArgs {
 #[derive(Debug, Copy, Clone, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[archive(check_bytes)]
 pub struct MemoryArg {
-    pub offset: u64,
+    pub offset: u32,
     pub mem_addr: MemAddr,
+    /// The alignment hint as a power of two, the access is expected to be aligned to `1 << align` bytes
+    pub align: u8,
+}
+
+/// An `i32` comparison fused with a following `br_if`, see [`Instruction::I32CmpBrIf`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub enum I32Cmp {
+    Eq,
+    Ne,
+    LtS,
+    LtU,
+    GtS,
+    GtU,
+    LeS,
+    LeU,
+    GeS,
+    GeU,
+}
+
+impl I32Cmp {
+    #[inline(always)]
+    pub(crate) fn eval(self, a: i32, b: i32) -> bool {
+        match self {
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+            Self::LtS => a < b,
+            Self::LtU => (a as u32) < (b as u32),
+            Self::GtS => a > b,
+            Self::GtU => (a as u32) > (b as u32),
+            Self::LeS => a <= b,
+            Self::LeU => (a as u32) <= (b as u32),
+            Self::GeS => a >= b,
+            Self::GeU => (a as u32) >= (b as u32),
+        }
+    }
+
+    /// The instruction this comparison was fused from
+    pub(crate) fn instruction(self) -> Instruction {
+        match self {
+            Self::Eq => Instruction::I32Eq,
+            Self::Ne => Instruction::I32Ne,
+            Self::LtS => Instruction::I32LtS,
+            Self::LtU => Instruction::I32LtU,
+            Self::GtS => Instruction::I32GtS,
+            Self::GtU => Instruction::I32GtU,
+            Self::LeS => Instruction::I32LeS,
+            Self::LeU => Instruction::I32LeU,
+            Self::GeS => Instruction::I32GeS,
+            Self::GeU => Instruction::I32GeU,
+        }
+    }
+
+    pub(crate) fn from_instruction(instr: &Instruction) -> Option<Self> {
+        Some(match instr {
+            Instruction::I32Eq => Self::Eq,
+            Instruction::I32Ne => Self::Ne,
+            Instruction::I32LtS => Self::LtS,
+            Instruction::I32LtU => Self::LtU,
+            Instruction::I32GtS => Self::GtS,
+            Instruction::I32GtU => Self::GtU,
+            Instruction::I32LeS => Self::LeS,
+            Instruction::I32LeU => Self::LeU,
+            Instruction::I32GeS => Self::GeS,
+            Instruction::I32GeU => Self::GeU,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether a conditional branch is expected to be taken, from the `metadata.code.branch_hint` custom section
+///
+/// For an `if`, taking the branch means entering the `then` block. Hints are kept so
+/// [`Module::encode`](crate::Module::encode) can write them back and the disassembly shows them. The interpreter
+/// doesn't use them: passing conditions through `likely`/`unlikely` made no measurable difference in the
+/// `branch_hints` benchmark.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub enum BranchHint {
+    #[default]
+    Unknown,
+    Likely,
+    Unlikely,
 }
 
 type BrTableDefault = u32;
@@ -78,6 +161,7 @@ pub enum ConstInstruction {
 ///
 /// # Differences to the spec
 /// * `br_table` stores the jump labels in the following `br_label` instructions to keep this enum small.
+/// * Memory offsets are stored as `u32`, since only 32-bit memories are supported.
 /// * Lables/Blocks: we store the label end offset in the instruction itself and use `EndBlockFrame` to mark the end of a block.
 ///   This makes it easier to implement the label stack iteratively.
 ///
@@ -96,7 +180,7 @@ pub enum Instruction {
     // LocalGet + I32Const + I32Store => I32LocalGetConstStore + I32Const
     // Also common, helps us skip the stack entirely.
     // Has to be followed by an I32Const instruction
-    I32StoreLocal { local: LocalAddr, const_i32: i32, offset: u32, mem_addr: u8 },
+    I32StoreLocal { local: LocalAddr, const_i32: i32, offset: u32, mem_addr: u8, align: u8 },
     // I64Xor + I64Const + I64RotL
     // Commonly used by a few crypto libraries
     I64XorConstRotl(i64),
@@ -105,6 +189,16 @@ pub enum Instruction {
     LocalGet2(LocalAddr, LocalAddr),
     LocalGet3(LocalAddr, LocalAddr, LocalAddr),
     LocalGetSet(LocalAddr, LocalAddr),
+    // LocalGet + LocalGet + I32Add
+    I32AddLocals(LocalAddr, LocalAddr),
+    // I32Const + I32Add
+    I32AddConst(i32),
+    // I32Eqz + BrIf
+    I32EqzBrIf(LabelAddr, BranchHint),
+    // I32 comparison + BrIf
+    I32CmpBrIf(I32Cmp, LabelAddr, BranchHint),
+    // Replaces floating point instructions if they are denied, see `ParserLimits::deny_floats`
+    FloatDenied,
 
     // > Control Instructions
     // See <https://webassembly.github.io/spec/core/binary/instructions.html#control-instructions>
@@ -112,11 +206,11 @@ pub enum Instruction {
     Nop,
     Block(BlockArgs, EndOffset),
     Loop(BlockArgs, EndOffset),
-    If(BlockArgsPacked, ElseOffset, EndOffset), // If else offset is 0 if there is no else block
+    If(BlockArgsPacked, ElseOffset, EndOffset, BranchHint), // If else offset is 0 if there is no else block
     Else(EndOffset),
     EndBlockFrame,
     Br(LabelAddr),
-    BrIf(LabelAddr),
+    BrIf(LabelAddr, BranchHint),
     BrTable(BrTableDefault, BrTableLen), // has to be followed by multiple BrLabel instructions
     Return,
     Call(FuncAddr),
@@ -136,29 +230,29 @@ pub enum Instruction {
     GlobalSet(GlobalAddr),
 
     // > Memory Instructions
-    I32Load { offset: u64, mem_addr: MemAddr },
-    I64Load { offset: u64, mem_addr: MemAddr },
-    F32Load { offset: u64, mem_addr: MemAddr },
-    F64Load { offset: u64, mem_addr: MemAddr },
-    I32Load8S { offset: u64, mem_addr: MemAddr },
-    I32Load8U { offset: u64, mem_addr: MemAddr },
-    I32Load16S { offset: u64, mem_addr: MemAddr },
-    I32Load16U { offset: u64, mem_addr: MemAddr },
-    I64Load8S { offset: u64, mem_addr: MemAddr },
-    I64Load8U { offset: u64, mem_addr: MemAddr },
-    I64Load16S { offset: u64, mem_addr: MemAddr },
-    I64Load16U { offset: u64, mem_addr: MemAddr },
-    I64Load32S { offset: u64, mem_addr: MemAddr },
-    I64Load32U { offset: u64, mem_addr: MemAddr },
-    I32Store { offset: u64, mem_addr: MemAddr },
-    I64Store { offset: u64, mem_addr: MemAddr },
-    F32Store { offset: u64, mem_addr: MemAddr },
-    F64Store { offset: u64, mem_addr: MemAddr },
-    I32Store8 { offset: u64, mem_addr: MemAddr },
-    I32Store16 { offset: u64, mem_addr: MemAddr },
-    I64Store8 { offset: u64, mem_addr: MemAddr },
-    I64Store16 { offset: u64, mem_addr: MemAddr },
-    I64Store32 { offset: u64, mem_addr: MemAddr },
+    I32Load { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Load { offset: u32, mem_addr: MemAddr, align: u8 },
+    F32Load { offset: u32, mem_addr: MemAddr, align: u8 },
+    F64Load { offset: u32, mem_addr: MemAddr, align: u8 },
+    I32Load8S { offset: u32, mem_addr: MemAddr, align: u8 },
+    I32Load8U { offset: u32, mem_addr: MemAddr, align: u8 },
+    I32Load16S { offset: u32, mem_addr: MemAddr, align: u8 },
+    I32Load16U { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Load8S { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Load8U { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Load16S { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Load16U { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Load32S { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Load32U { offset: u32, mem_addr: MemAddr, align: u8 },
+    I32Store { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Store { offset: u32, mem_addr: MemAddr, align: u8 },
+    F32Store { offset: u32, mem_addr: MemAddr, align: u8 },
+    F64Store { offset: u32, mem_addr: MemAddr, align: u8 },
+    I32Store8 { offset: u32, mem_addr: MemAddr, align: u8 },
+    I32Store16 { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Store8 { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Store16 { offset: u32, mem_addr: MemAddr, align: u8 },
+    I64Store32 { offset: u32, mem_addr: MemAddr, align: u8 },
     MemorySize(MemAddr, u8),
     MemoryGrow(MemAddr, u8),
 
@@ -214,6 +308,35 @@ pub enum Instruction {
     DataDrop(DataAddr),
 }
 
+// Keeps the instruction stream dense, a larger variant should get its payload packed or moved out of line
+const _: () = assert!(core::mem::size_of::<Instruction>() <= 16);
+
+impl Instruction {
+    /// Whether this instruction operates on floating point values
+    #[rustfmt::skip]
+    pub(crate) fn is_float(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            F32Const(_) | F64Const(_)
+                | F32Load { .. } | F64Load { .. } | F32Store { .. } | F64Store { .. }
+                | F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge
+                | F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge
+                | F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt
+                | F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign
+                | F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt
+                | F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign
+                | I32TruncF32S | I32TruncF32U | I32TruncF64S | I32TruncF64U
+                | I64TruncF32S | I64TruncF32U | I64TruncF64S | I64TruncF64U
+                | F32ConvertI32S | F32ConvertI32U | F32ConvertI64S | F32ConvertI64U | F32DemoteF64
+                | F64ConvertI32S | F64ConvertI32U | F64ConvertI64S | F64ConvertI64U | F64PromoteF32
+                | I32ReinterpretF32 | I64ReinterpretF64 | F32ReinterpretI32 | F64ReinterpretI64
+                | I32TruncSatF32S | I32TruncSatF32U | I32TruncSatF64S | I32TruncSatF64U
+                | I64TruncSatF32S | I64TruncSatF32U | I64TruncSatF64S | I64TruncSatF64U
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_blockargs_packed {
     use super::*;
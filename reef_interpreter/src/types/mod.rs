@@ -66,12 +66,171 @@ pub struct Module {
     ///
     /// Corresponds to the `elem` section of the original WebAssembly module.
     pub elements: Box<[Element]>,
+
+    /// Names of functions, sorted by their address
+    ///
+    /// Corresponds to the function names in the `name` custom section of the original WebAssembly module.
+    pub func_names: Box<[(FuncAddr, Box<str>)]>,
+
+    /// Languages and tools the module was built with, see [`Module::producers`]
+    ///
+    /// Corresponds to the `producers` custom section of the original WebAssembly module.
+    pub producers: Box<[ProducersField]>,
+
+    /// Features the module was compiled for, see [`Module::required_features`]
+    ///
+    /// Corresponds to the `target_features` custom section of the original WebAssembly module.
+    pub target_features: Box<[TargetFeature]>,
+}
+
+impl Module {
+    /// Get the name of a function from the `name` section
+    pub fn func_name(&self, addr: FuncAddr) -> Option<&str> {
+        let idx = self.func_names.binary_search_by_key(&addr, |(addr, _)| *addr).ok()?;
+        Some(&self.func_names[idx].1)
+    }
+
+    /// The languages, tools and SDKs the module was built with, from the `producers` custom section
+    pub fn producers(&self) -> &[ProducersField] {
+        &self.producers
+    }
+
+    /// Names of the features the module needs, from the `target_features` custom section
+    ///
+    /// These are the features marked as used (`+`) or required (`=`) by the toolchain, like `simd128` or `atomics`.
+    /// Instantiating a module that requires a feature this runtime doesn't support fails with
+    /// [`Error::UnsupportedFeature`](crate::error::Error::UnsupportedFeature).
+    pub fn required_features(&self) -> impl Iterator<Item = &str> + '_ {
+        self.target_features.iter().filter(|f| f.policy != FeaturePolicy::Disallowed).map(|f| &*f.name)
+    }
+
+    /// Map an instruction of a function to where it came from in the original module binary
+    ///
+    /// `instr_ptr` is an index into the interpreter's bytecode, like the ones in call frames of a serialized
+    /// [`ExecHandle`](crate::exec::ExecHandle) or [`TraceEvent::Instruction`](crate::exec::TraceEvent::Instruction).
+    /// Returns `None` for imported functions, out of bounds indices and functions without recorded offsets.
+    pub fn resolve_location(&self, func_idx: FuncAddr, instr_ptr: usize) -> Option<Location<'_>> {
+        let func = self.funcs.get(func_idx.checked_sub(self.imported_func_count())? as usize)?;
+        let byte_offset = *func.offsets.get(instr_ptr)?;
+        Some(Location { func_name: self.func_name(func_idx), byte_offset })
+    }
+
+    /// Iterate over the imports of the module with their types
+    pub fn imports(&self) -> impl Iterator<Item = ImportType<'_>> + '_ {
+        self.imports.iter().map(|import| ImportType {
+            module: &import.module,
+            name: &import.name,
+            ty: match &import.kind {
+                ImportKind::Function(ty) => ExternType::Func(&self.func_types[*ty as usize]),
+                ImportKind::Table(ty) => ExternType::Table(ty),
+                ImportKind::Memory(ty) => ExternType::Memory(ty),
+                ImportKind::Global(ty) => ExternType::Global(ty),
+            },
+        })
+    }
+
+    /// Iterate over the exports of the module with their types
+    pub fn exports(&self) -> impl Iterator<Item = ExportType<'_>> + '_ {
+        self.exports
+            .iter()
+            .map(|export| ExportType { name: &export.name, ty: self.extern_type(export.kind, export.index) })
+    }
+
+    /// Get the signature of an exported function by name
+    pub fn export_func_type(&self, name: &str) -> Option<&FuncType> {
+        self.exports().find(|export| export.name == name).and_then(|export| match export.ty {
+            ExternType::Func(ty) => Some(ty),
+            _ => None,
+        })
+    }
+
+    /// Get the signature of an imported function by module and name
+    pub fn import_func_type(&self, module: &str, name: &str) -> Option<&FuncType> {
+        self.imports().find(|import| import.module == module && import.name == name).and_then(|import| {
+            match import.ty {
+                ExternType::Func(ty) => Some(ty),
+                _ => None,
+            }
+        })
+    }
+
+    /// Get the type of an item in the index space of `kind`, which starts with the imported items
+    fn extern_type(&self, kind: ExternalKind, index: u32) -> ExternType<'_> {
+        let mut imports = self.imports().map(|import| import.ty).filter(|ty| ty.kind() == kind);
+        if let Some(ty) = imports.nth(index as usize) {
+            return ty;
+        }
+
+        let index = index as usize - self.imports.iter().filter(|i| ExternalKind::from(&i.kind) == kind).count();
+        match kind {
+            ExternalKind::Func => ExternType::Func(&self.funcs[index].ty),
+            ExternalKind::Table => ExternType::Table(&self.table_types[index]),
+            ExternalKind::Memory => ExternType::Memory(&self.memory_types[index]),
+            ExternalKind::Global => ExternType::Global(&self.globals[index].ty),
+        }
+    }
+}
+
+/// The type of an imported or exported item
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExternType<'m> {
+    /// A function with the given signature
+    Func(&'m FuncType),
+    /// A table
+    Table(&'m TableType),
+    /// A memory
+    Memory(&'m MemoryType),
+    /// A global
+    Global(&'m GlobalType),
+}
+
+impl ExternType<'_> {
+    /// The kind of item this is
+    pub fn kind(&self) -> ExternalKind {
+        match self {
+            Self::Func(_) => ExternalKind::Func,
+            Self::Table(_) => ExternalKind::Table,
+            Self::Memory(_) => ExternalKind::Memory,
+            Self::Global(_) => ExternalKind::Global,
+        }
+    }
+}
+
+/// An import of a module, returned by [`Module::imports`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImportType<'m> {
+    /// The module the item is imported from
+    pub module: &'m str,
+    /// The name of the imported item
+    pub name: &'m str,
+    /// The type the imported item has to have
+    pub ty: ExternType<'m>,
+}
+
+/// Where an instruction came from, returned by [`Module::resolve_location`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'m> {
+    /// The name of the function from the `name` section
+    pub func_name: Option<&'m str>,
+    /// Offset of the operator in the module binary
+    ///
+    /// Instructions fused from several operators point to the first one.
+    pub byte_offset: u32,
+}
+
+/// An export of a module, returned by [`Module::exports`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportType<'m> {
+    /// The name of the export
+    pub name: &'m str,
+    /// The type of the exported item
+    pub ty: ExternType<'m>,
 }
 
 /// A WebAssembly External Kind.
 ///
 /// See <https://webassembly.github.io/spec/core/syntax/types.html#external-types>
-#[derive(Debug, Clone, Copy, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[archive(check_bytes)]
 pub enum ExternalKind {
     /// A WebAssembly Function.
@@ -152,8 +311,90 @@ pub struct FuncType {
 #[archive(check_bytes)]
 pub struct WasmFunction {
     pub instructions: Box<[Instruction]>,
+    /// Offset of every instruction in the original module binary, empty if the function wasn't parsed from one
+    pub offsets: Box<[u32]>,
     pub locals: Box<[ValType]>,
     pub ty: FuncType,
+    /// Conservative estimate of how many values the function pushes onto the value stack, on top of its locals
+    ///
+    /// Space for these is reserved when the function is called, so the stack doesn't grow in the middle of it.
+    /// Zero if unknown, as it's only used to reserve space and never enforced.
+    pub max_stack: u32,
+}
+
+impl WasmFunction {
+    /// Number of locals, including the parameters
+    pub fn local_count(&self) -> usize {
+        self.ty.params.len() + self.locals.len()
+    }
+
+    /// Number of instructions in the interpreter's bytecode
+    pub fn instruction_count(&self) -> usize {
+        self.instructions.len()
+    }
+}
+
+/// A field of the `producers` custom section, like `language`, `processed-by` or `sdk`
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct ProducersField {
+    /// The name of the field
+    pub name: Box<str>,
+    /// The languages or tools listed in the field
+    pub values: Box<[ProducerVersion]>,
+}
+
+/// A language or tool listed in the `producers` custom section
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct ProducerVersion {
+    /// The name of the language or tool, like `rustc`
+    pub name: Box<str>,
+    /// Its version, which may be empty
+    pub version: Box<str>,
+}
+
+/// An entry of the `target_features` custom section
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct TargetFeature {
+    /// Whether the module uses the feature
+    pub policy: FeaturePolicy,
+    /// The name of the feature, like `simd128` or `atomics`
+    pub name: Box<str>,
+}
+
+/// How a module depends on a [`TargetFeature`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub enum FeaturePolicy {
+    /// The module uses the feature (`+`)
+    Used,
+    /// The module must not be linked with code that uses the feature (`-`)
+    Disallowed,
+    /// The module requires the feature (`=`), an older form of [`FeaturePolicy::Used`]
+    Required,
+}
+
+impl FeaturePolicy {
+    /// The prefix of the feature in the binary format
+    pub fn prefix(self) -> u8 {
+        match self {
+            Self::Used => b'+',
+            Self::Disallowed => b'-',
+            Self::Required => b'=',
+        }
+    }
+
+    /// The policy for a prefix in the binary format
+    pub fn from_prefix(prefix: u8) -> Option<Self> {
+        match prefix {
+            b'+' => Some(Self::Used),
+            b'-' => Some(Self::Disallowed),
+            b'=' => Some(Self::Required),
+            _ => None,
+        }
+    }
 }
 
 /// A WebAssembly Module Export
@@ -289,3 +530,170 @@ pub enum ElementItem {
     Func(FuncAddr),
     Expr(ConstInstruction),
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::parse_bytes;
+
+    #[test]
+    fn test_import_export_types() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "log" (func $log (param i32)))
+                (import "env" "memory" (memory 1))
+                (import "env" "base" (global $base i32))
+                (global $top (export "top") (mut i64) (i64.const 0))
+                (table (export "table") 2 funcref)
+                (export "log" (func $log))
+                (export "base" (global $base))
+                (export "memory" (memory 0))
+                (func (export "run") (result i32) (global.get $base)))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+
+        let imports: Vec<_> = module.imports().map(|i| (i.module, i.name, i.ty.kind())).collect();
+        assert_eq!(
+            imports,
+            [
+                ("env", "log", ExternalKind::Func),
+                ("env", "memory", ExternalKind::Memory),
+                ("env", "base", ExternalKind::Global)
+            ]
+        );
+
+        let i32_to_unit = FuncType { params: Box::new([ValType::I32]), results: Box::new([]) };
+        let unit_to_i32 = FuncType { params: Box::new([]), results: Box::new([ValType::I32]) };
+        let exports: Vec<_> = module.exports().map(|e| (e.name, e.ty)).collect();
+        assert_eq!(
+            exports,
+            [
+                ("top", ExternType::Global(&GlobalType { mutable: true, ty: ValType::I64 })),
+                ("table", ExternType::Table(&TableType::new(ValType::RefFunc, 2, None))),
+                ("log", ExternType::Func(&i32_to_unit)),
+                ("base", ExternType::Global(&GlobalType { mutable: false, ty: ValType::I32 })),
+                ("memory", ExternType::Memory(&MemoryType::new_32(1, None))),
+                ("run", ExternType::Func(&unit_to_i32)),
+            ]
+        );
+
+        assert_eq!(module.export_func_type("run"), Some(&unit_to_i32));
+        assert_eq!(module.export_func_type("log"), Some(&i32_to_unit));
+        assert_eq!(module.export_func_type("memory"), None);
+        assert_eq!(module.import_func_type("env", "log"), Some(&i32_to_unit));
+        assert_eq!(module.import_func_type("env", "base"), None);
+        assert_eq!(module.import_func_type("wasi", "log"), None);
+    }
+
+    #[test]
+    fn test_producers_and_target_features() {
+        let wat = |features: &str| {
+            alloc::format!(
+                r#"
+                (module
+                    (@producers (language "Rust" "") (processed-by "rustc" "1.80.0"))
+                    (@custom "target_features" "{}")
+                    (func (export "run")))
+                "#,
+                features
+            )
+        };
+        let module = parse_bytes(&wat::parse_str(wat(r"\02+\08sign-ext-\07atomics")).unwrap()).unwrap();
+        assert_eq!(module.producers().len(), 2);
+        assert_eq!(&*module.producers()[1].name, "processed-by");
+        assert_eq!(&*module.producers()[1].values[0].name, "rustc");
+        assert_eq!(&*module.producers()[1].values[0].version, "1.80.0");
+        assert_eq!(module.required_features().collect::<Vec<_>>(), ["sign-ext"]);
+        assert_eq!(parse_bytes(&module.encode().unwrap()).unwrap().target_features, module.target_features);
+        assert!(crate::Instance::instantiate(module, crate::imports::Imports::new()).is_ok());
+
+        let module = parse_bytes(&wat::parse_str(wat(r"\02+\07simd128=\07atomics")).unwrap()).unwrap();
+        assert_eq!(module.required_features().collect::<Vec<_>>(), ["simd128", "atomics"]);
+        let err = crate::Instance::instantiate(module.clone(), crate::imports::Imports::new()).unwrap_err();
+        assert!(matches!(&err, crate::error::Error::UnsupportedFeature(msg) if msg.contains("simd128")), "{}", err);
+
+        let mut stripped = module;
+        stripped.strip(&Default::default());
+        assert!(stripped.producers().is_empty());
+        assert!(crate::Instance::instantiate(stripped, crate::imports::Imports::new()).is_ok());
+
+        // a malformed section is ignored like other broken custom sections
+        let module = parse_bytes(&wat::parse_str(wat(r"\01!\07simd128")).unwrap()).unwrap();
+        assert_eq!(module.required_features().count(), 0);
+    }
+
+    #[test]
+    fn test_function_metadata() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func (param i32) (result i32) (local i64 i64)
+                    (i32.add (i32.add (local.get 0) (i32.const 1)) (i32.add (i32.const 2) (local.get 0)))))
+            "#,
+        )
+        .unwrap();
+        let func = &parse_bytes(&wasm).unwrap().funcs[0];
+        assert_eq!(func.local_count(), 3);
+        assert_eq!(func.instruction_count(), func.instructions.len());
+        assert_eq!(func.max_stack, 3);
+    }
+
+    #[test]
+    fn test_resolve_location() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "log" (func $log (param i32)))
+                (func $main (param i32)
+                    (call $log (i32.mul (local.get 0) (i32.const 3)))
+                    nop
+                    (call $log (i32.add (i32.const 2) (i32.const 5)))))
+            "#,
+        )
+        .unwrap();
+        let module = parse_bytes(&wasm).unwrap();
+        let func = &module.funcs[0];
+        assert_eq!(func.offsets.len(), func.instructions.len());
+
+        let locations: Vec<_> = (0..func.instructions.len()).map(|i| module.resolve_location(1, i).unwrap()).collect();
+        assert!(locations.iter().all(|l| l.func_name == Some("main")));
+        assert!(locations.windows(2).all(|l| l[0].byte_offset < l[1].byte_offset));
+
+        // calls point at their opcode, the folded constant at the first operand
+        let calls: Vec<_> = (0..func.instructions.len())
+            .filter(|i| matches!(func.instructions[*i], Instruction::Call(0)))
+            .map(|i| wasm[locations[i].byte_offset as usize])
+            .collect();
+        assert_eq!(calls, [0x10, 0x10]);
+        let folded = func.instructions.iter().position(|i| *i == Instruction::I32Const(7)).unwrap();
+        assert_eq!(&wasm[locations[folded].byte_offset as usize..][..2], [0x41, 2]);
+
+        assert_eq!(module.resolve_location(0, 0), None);
+        assert_eq!(module.resolve_location(1, func.instructions.len()), None);
+        assert_eq!(module.resolve_location(2, 0), None);
+    }
+
+    #[test]
+    fn test_unsigned_conversions() {
+        use value::WasmValue;
+
+        assert_eq!(WasmValue::from(u32::MAX), WasmValue::I32(-1));
+        assert_eq!(u32::try_from(WasmValue::I32(-1)), Ok(u32::MAX));
+        assert_eq!(u64::try_from(WasmValue::from(u64::MAX - 1)), Ok(u64::MAX - 1));
+        assert_eq!(u32::try_from(WasmValue::I64(1)), Err(()));
+
+        assert_eq!(WasmValue::from(200u8), WasmValue::I32(200));
+        assert_eq!(u8::try_from(WasmValue::I32(255)), Ok(255));
+        assert_eq!(u8::try_from(WasmValue::I32(256)), Err(()));
+        assert_eq!(u8::try_from(WasmValue::I32(-1)), Err(()));
+
+        assert_eq!(WasmValue::from(true), WasmValue::I32(1));
+        assert_eq!(bool::try_from(WasmValue::I32(0)), Ok(false));
+        assert_eq!(bool::try_from(WasmValue::I32(-7)), Ok(true));
+    }
+}
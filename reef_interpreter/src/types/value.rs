@@ -1,5 +1,8 @@
-use core::fmt::Debug;
+use alloc::format;
+use core::fmt::{Debug, Display};
+use core::str::FromStr;
 
+use crate::error::Error;
 use crate::types::{ExternAddr, FuncAddr};
 
 /// A WebAssembly value.
@@ -87,6 +90,74 @@ impl Debug for WasmValue {
     }
 }
 
+/// Formats values as `type:value`, e.g. `i32:42`, `f64:3.14` or `funcref:null`
+///
+/// Floats are printed with as many digits as needed to parse them back to the same value, see [`FromStr`].
+impl Display for WasmValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WasmValue::I32(i) => write!(f, "i32:{}", i),
+            WasmValue::I64(i) => write!(f, "i64:{}", i),
+            WasmValue::F32(i) => write!(f, "f32:{}", i),
+            WasmValue::F64(i) => write!(f, "f64:{}", i),
+            WasmValue::RefExtern(addr) => write!(f, "externref:{}", addr),
+            WasmValue::RefFunc(addr) => write!(f, "funcref:{}", addr),
+            WasmValue::RefNull(ty) => write!(f, "{}:null", ty),
+        }
+    }
+}
+
+/// Parses the format written by [`Display`]
+impl FromStr for WasmValue {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Other(format!("Invalid value: {}", s));
+        let (ty, value) = s.split_once(':').ok_or_else(invalid)?;
+        let ty: ValType = ty.parse()?;
+        if value == "null" && matches!(ty, ValType::RefFunc | ValType::RefExtern) {
+            return Ok(WasmValue::RefNull(ty));
+        }
+
+        Ok(match ty {
+            ValType::I32 => WasmValue::I32(value.parse().map_err(|_| invalid())?),
+            ValType::I64 => WasmValue::I64(value.parse().map_err(|_| invalid())?),
+            ValType::F32 => WasmValue::F32(value.parse().map_err(|_| invalid())?),
+            ValType::F64 => WasmValue::F64(value.parse().map_err(|_| invalid())?),
+            ValType::RefFunc => WasmValue::RefFunc(value.parse().map_err(|_| invalid())?),
+            ValType::RefExtern => WasmValue::RefExtern(value.parse().map_err(|_| invalid())?),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for WasmValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for WasmValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = WasmValue;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.write_str("a value like \"i32:42\"")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
 impl WasmValue {
     /// Get the type of a [`WasmValue`]
     #[inline]
@@ -155,6 +226,36 @@ impl ValType {
     }
 }
 
+/// Formats types like the text format, e.g. `i32` or `funcref`
+impl Display for ValType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F32 => "f32",
+            ValType::F64 => "f64",
+            ValType::RefFunc => "funcref",
+            ValType::RefExtern => "externref",
+        })
+    }
+}
+
+impl FromStr for ValType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "i32" => ValType::I32,
+            "i64" => ValType::I64,
+            "f32" => ValType::F32,
+            "f64" => ValType::F64,
+            "funcref" => ValType::RefFunc,
+            "externref" => ValType::RefExtern,
+            _ => return Err(Error::Other(format!("Invalid value type: {}", s))),
+        })
+    }
+}
+
 macro_rules! impl_conversion_for_wasmvalue {
     ($($t:ty => $variant:ident),*) => {
         $(
@@ -190,3 +291,110 @@ impl_conversion_for_wasmvalue! {
     f32 => F32,
     f64 => F64
 }
+
+macro_rules! impl_unsigned_conversion_for_wasmvalue {
+    ($($t:ty => $variant:ident as $signed:ty),*) => {
+        $(
+            // Reinterprets the bits, so every value round-trips
+            impl From<$t> for WasmValue {
+                #[inline]
+                fn from(i: $t) -> Self {
+                    Self::$variant(i as $signed)
+                }
+            }
+
+            impl TryFrom<WasmValue> for $t {
+                type Error = ();
+
+                #[inline]
+                fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+                    <$signed>::try_from(value).map(|i| i as $t)
+                }
+            }
+        )*
+    }
+}
+
+// `u32` and `u64` are passed as `i32` and `i64` with the same bits, e.g. `u32::MAX` is `-1i32`
+impl_unsigned_conversion_for_wasmvalue! {
+    u32 => I32 as i32,
+    u64 => I64 as i64
+}
+
+/// Zero-extended to `i32`. Converting back fails for values outside of `0..=255`.
+impl From<u8> for WasmValue {
+    #[inline]
+    fn from(i: u8) -> Self {
+        Self::I32(i as i32)
+    }
+}
+
+impl TryFrom<WasmValue> for u8 {
+    type Error = ();
+
+    #[inline]
+    fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+        u8::try_from(i32::try_from(value)?).map_err(|_| cold())
+    }
+}
+
+/// Passed as `i32` `1` or `0`. Like a Wasm condition, every non-zero `i32` converts back to `true`.
+impl From<bool> for WasmValue {
+    #[inline]
+    fn from(b: bool) -> Self {
+        Self::I32(b as i32)
+    }
+}
+
+impl TryFrom<WasmValue> for bool {
+    type Error = ();
+
+    #[inline]
+    fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+        i32::try_from(value).map(|i| i != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn test_display_round_trip() {
+        let values = [
+            WasmValue::I32(-42),
+            WasmValue::I64(i64::MAX),
+            WasmValue::F32(0.1),
+            WasmValue::F64(2.5),
+            WasmValue::F64(f64::NEG_INFINITY),
+            WasmValue::RefFunc(3),
+            WasmValue::RefExtern(7),
+            WasmValue::RefNull(ValType::RefExtern),
+        ];
+        let strings = ["i32:-42", "i64:9223372036854775807", "f32:0.1", "f64:2.5", "f64:-inf", "funcref:3"];
+        for (value, string) in values.iter().zip(strings) {
+            assert_eq!(value.to_string(), string);
+        }
+        assert_eq!(WasmValue::RefNull(ValType::RefFunc).to_string(), "funcref:null");
+
+        for value in values {
+            assert_eq!(value.to_string().parse::<WasmValue>().unwrap(), value);
+        }
+        assert!("f64:NaN".parse::<WasmValue>().unwrap().eq_loose(&WasmValue::F64(f64::NAN)));
+
+        for invalid in ["42", "i32:", "i32:1.5", "u32:1", "i64:null"] {
+            assert!(invalid.parse::<WasmValue>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde::de::{value::StrDeserializer, Deserialize, IntoDeserializer};
+
+        let deserializer: StrDeserializer<'_, serde::de::value::Error> = "f32:1.5".into_deserializer();
+        assert_eq!(WasmValue::deserialize(deserializer).unwrap(), WasmValue::F32(1.5));
+    }
+}
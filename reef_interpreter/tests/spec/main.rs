@@ -0,0 +1,448 @@
+//! Runs the `.wast` tests of this crate and the official WebAssembly spec tests
+//!
+//! The files in `tests/spec/reef` cover the instruction semantics of the interpreter. They always run, every
+//! assertion in them has to pass, and trap messages have to match.
+//!
+//! With the `spec-tests` feature, the official testsuite runs afterwards and pass rates are reported per proposal.
+//! It expects a checkout of <https://github.com/WebAssembly/testsuite> in `tests/spec/testsuite`, or at the path in
+//! the `WASM_TESTSUITE` environment variable:
+//!
+//! ```sh
+//! git clone --depth 1 https://github.com/WebAssembly/testsuite reef_interpreter/tests/spec/testsuite
+//! cargo test -p reef_interpreter --features spec-tests --test spec
+//! ```
+//!
+//! Arguments filter the files by a substring of their path. Set `SPEC_VERBOSE=1` to print every failed assertion.
+//! Failed assertions in the official testsuite don't fail the run, since many proposals aren't supported yet.
+//!
+//! Modules registered with `register` can be imported from. Functions are called in the registered instance,
+//! globals are copied and memories and tables are created anew with the same type, so they aren't shared.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use reef_interpreter::error::{Error, Result};
+use reef_interpreter::exec::CallResult;
+use reef_interpreter::imports::{Extern, Imports};
+use reef_interpreter::types::value::{ValType, WasmValue};
+use reef_interpreter::types::{ExternType, MemoryType, TableType};
+use reef_interpreter::{parse_bytes, Instance, Module};
+use wast::core::{AbstractHeapType, HeapType, NanPattern, WastArgCore, WastRetCore};
+use wast::parser::{self, ParseBuffer};
+use wast::{QuoteWat, Wast, WastArg, WastDirective, WastExecute, WastInvoke, WastRet, Wat};
+
+/// An instance shared between the runner and the host functions of modules importing from it
+///
+/// The instance is taken out while one of its functions runs.
+type Slot = Rc<RefCell<Option<Instance>>>;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl Stats {
+    fn add(&mut self, other: Stats) {
+        self.passed += other.passed;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+    }
+
+    fn rate(&self) -> f64 {
+        let total = self.passed + self.failed;
+        if total == 0 {
+            return 0.0;
+        }
+        self.passed as f64 * 100.0 / total as f64
+    }
+}
+
+enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Default)]
+struct Runner {
+    /// Check trap messages and fail on directives that can't be run
+    strict: bool,
+    current: Option<Slot>,
+    named: HashMap<String, Slot>,
+    registered: HashMap<String, Slot>,
+}
+
+impl Runner {
+    fn run_directive(&mut self, directive: WastDirective<'_>) -> Outcome {
+        match directive {
+            WastDirective::Module(mut wat) => match self.instantiate(&mut wat) {
+                Ok(slot) => {
+                    if let Some(id) = quote_id(&wat) {
+                        self.named.insert(id.to_string(), slot.clone());
+                    }
+                    self.current = Some(slot);
+                    Outcome::Passed
+                }
+                Err(err) => {
+                    self.current = None;
+                    Outcome::Failed(format!("module failed to instantiate: {}", err))
+                }
+            },
+            WastDirective::Register { name, module, .. } => match self.slot(module.map(|id| id.name())) {
+                Some(slot) => {
+                    self.registered.insert(name.to_string(), slot);
+                    Outcome::Passed
+                }
+                None => Outcome::Failed("no module to register".to_string()),
+            },
+            WastDirective::Invoke(invoke) => match self.invoke(invoke) {
+                Ok(_) => Outcome::Passed,
+                Err(err) => Outcome::Failed(format!("invoke failed: {}", err)),
+            },
+            WastDirective::AssertReturn { exec, results, .. } => match self.execute(exec) {
+                Ok(values) => match_results(&values, &results),
+                Err(err) => Outcome::Failed(format!("expected results, got error: {}", err)),
+            },
+            WastDirective::AssertTrap { exec, message, .. } => match self.execute(exec) {
+                Err(Error::Trap(trap)) if !self.strict || trap.to_string().starts_with(message) => Outcome::Passed,
+                Err(err) => Outcome::Failed(format!("expected trap `{}`, got error: {}", message, err)),
+                Ok(values) => Outcome::Failed(format!("expected trap `{}`, got {:?}", message, values)),
+            },
+            WastDirective::AssertExhaustion { call, message, .. } => match self.invoke(call) {
+                Err(Error::Trap(_)) => Outcome::Passed,
+                Err(err) => Outcome::Failed(format!("expected `{}`, got error: {}", message, err)),
+                Ok(values) => Outcome::Failed(format!("expected `{}`, got {:?}", message, values)),
+            },
+            WastDirective::AssertInvalid { mut module, message, .. }
+            | WastDirective::AssertMalformed { mut module, message, .. } => {
+                let Ok(bytes) = module.encode() else {
+                    // The text format itself is malformed, which `wast` already rejected
+                    return Outcome::Passed;
+                };
+                match parse_bytes(&bytes) {
+                    Err(_) => Outcome::Passed,
+                    Ok(_) => Outcome::Failed(format!("expected module to be rejected: {}", message)),
+                }
+            }
+            WastDirective::AssertUnlinkable { module, message, .. } => {
+                match self.instantiate(&mut QuoteWat::Wat(module)) {
+                    Err(_) => Outcome::Passed,
+                    Ok(_) => Outcome::Failed(format!("expected module to be unlinkable: {}", message)),
+                }
+            }
+            _ => Outcome::Skipped,
+        }
+    }
+
+    fn slot(&self, id: Option<&str>) -> Option<Slot> {
+        match id {
+            Some(id) => self.named.get(id).cloned(),
+            None => self.current.clone(),
+        }
+    }
+
+    fn instantiate(&self, wat: &mut QuoteWat<'_>) -> Result<Slot> {
+        let bytes = wat.encode().map_err(|err| Error::Other(err.to_string()))?;
+        let module = parse_bytes(&bytes)?;
+        let imports = self.imports(&module)?;
+        let instance = Instance::instantiate_start(module, imports, usize::MAX)?;
+        Ok(Rc::new(RefCell::new(Some(instance))))
+    }
+
+    fn imports(&self, module: &Module) -> Result<Imports> {
+        let mut imports = Imports::new();
+        for import in module.imports() {
+            let value = match import.module {
+                "spectest" => spectest(import.name),
+                module => self.registered.get(module).and_then(|slot| registered_extern(slot, import.name)),
+            };
+            if let Some(value) = value {
+                imports.define(import.module, import.name, value)?;
+            }
+        }
+        Ok(imports)
+    }
+
+    fn execute(&mut self, exec: WastExecute<'_>) -> Result<Vec<WasmValue>> {
+        match exec {
+            WastExecute::Invoke(invoke) => self.invoke(invoke),
+            WastExecute::Wat(Wat::Module(module)) => {
+                self.instantiate(&mut QuoteWat::Wat(Wat::Module(module)))?;
+                Ok(Vec::new())
+            }
+            WastExecute::Get { module, global, .. } => {
+                let slot = self.slot(module.map(|id| id.name())).ok_or_else(no_module)?;
+                let mut instance = slot.borrow_mut();
+                let instance = instance.as_mut().ok_or_else(no_module)?;
+                Ok(vec![instance.exported_global(global)?.get()])
+            }
+            _ => Err(Error::UnsupportedFeature("component".to_string())),
+        }
+    }
+
+    fn invoke(&mut self, invoke: WastInvoke<'_>) -> Result<Vec<WasmValue>> {
+        let slot = self.slot(invoke.module.map(|id| id.name())).ok_or_else(no_module)?;
+        let args = invoke.args.iter().map(convert_arg).collect::<Result<Vec<_>>>()?;
+        call(&slot, invoke.name, args)
+    }
+}
+
+fn no_module() -> Error {
+    Error::Other("no module instantiated".to_string())
+}
+
+fn quote_id<'a>(wat: &QuoteWat<'a>) -> Option<&'a str> {
+    match wat {
+        QuoteWat::Wat(Wat::Module(module)) => module.id.map(|id| id.name()),
+        _ => None,
+    }
+}
+
+/// Run an exported function of the instance in `slot` to completion
+fn call(slot: &Slot, name: &str, args: Vec<WasmValue>) -> Result<Vec<WasmValue>> {
+    let instance = slot.borrow_mut().take().ok_or_else(no_module)?;
+    if instance.func_type(name).is_none() {
+        *slot.borrow_mut() = Some(instance);
+        return Err(Error::Other(format!("no exported function `{}`", name)));
+    }
+
+    let mut exec = instance.exported_func_untyped(name)?.call(args, None)?;
+    let res = exec.run(usize::MAX);
+    *slot.borrow_mut() = Some(Instance::from(exec));
+
+    match res? {
+        CallResult::Done(values) => Ok(values),
+        _ => Err(Error::Other("execution did not finish".to_string())),
+    }
+}
+
+fn spectest(name: &str) -> Option<Extern> {
+    Some(match name {
+        "print" => Extern::typed_func(|_, ()| Ok(())),
+        "print_i32" => Extern::typed_func(|_, _: i32| Ok(())),
+        "print_i64" => Extern::typed_func(|_, _: i64| Ok(())),
+        "print_f32" => Extern::typed_func(|_, _: f32| Ok(())),
+        "print_f64" => Extern::typed_func(|_, _: f64| Ok(())),
+        "print_i32_f32" => Extern::typed_func(|_, _: (i32, f32)| Ok(())),
+        "print_f64_f64" => Extern::typed_func(|_, _: (f64, f64)| Ok(())),
+        "global_i32" => Extern::global(WasmValue::I32(666), false),
+        "global_i64" => Extern::global(WasmValue::I64(666), false),
+        "global_f32" => Extern::global(WasmValue::F32(666.6), false),
+        "global_f64" => Extern::global(WasmValue::F64(666.6), false),
+        "table" => Extern::table(TableType::new(ValType::RefFunc, 10, Some(20)), WasmValue::RefNull(ValType::RefFunc)),
+        "memory" => Extern::memory(MemoryType::new_32(1, Some(2))),
+        _ => return None,
+    })
+}
+
+fn registered_extern(slot: &Slot, name: &str) -> Option<Extern> {
+    let mut instance = slot.borrow_mut();
+    let instance = instance.as_mut()?;
+    let ty = instance.exports().find(|export| export.name == name)?.ty;
+
+    Some(match ty {
+        ExternType::Func(ty) => {
+            let (slot, name) = (slot.clone(), name.to_string());
+            Extern::func(&ty.clone(), move |_, args| call(&slot, &name, args.to_vec()))
+        }
+        ExternType::Table(ty) => Extern::table(ty.clone(), WasmValue::RefNull(ty.element_type)),
+        ExternType::Memory(ty) => Extern::memory(*ty),
+        ExternType::Global(ty) => {
+            let mutable = ty.mutable;
+            Extern::global(instance.exported_global(name).ok()?.get(), mutable)
+        }
+    })
+}
+
+fn convert_arg(arg: &WastArg<'_>) -> Result<WasmValue> {
+    let WastArg::Core(arg) = arg else {
+        return Err(Error::UnsupportedFeature("component values".to_string()));
+    };
+
+    Ok(match arg {
+        WastArgCore::I32(i) => WasmValue::I32(*i),
+        WastArgCore::I64(i) => WasmValue::I64(*i),
+        WastArgCore::F32(f) => WasmValue::F32(f32::from_bits(f.bits)),
+        WastArgCore::F64(f) => WasmValue::F64(f64::from_bits(f.bits)),
+        WastArgCore::RefNull(HeapType::Abstract { ty: AbstractHeapType::Func, .. }) => {
+            WasmValue::RefNull(ValType::RefFunc)
+        }
+        WastArgCore::RefNull(HeapType::Abstract { ty: AbstractHeapType::Extern, .. }) => {
+            WasmValue::RefNull(ValType::RefExtern)
+        }
+        WastArgCore::RefExtern(i) => WasmValue::RefExtern(*i),
+        arg => return Err(Error::UnsupportedFeature(format!("argument {:?}", arg))),
+    })
+}
+
+fn match_results(values: &[WasmValue], expected: &[WastRet<'_>]) -> Outcome {
+    if values.len() != expected.len() {
+        return Outcome::Failed(format!("expected {} results, got {:?}", expected.len(), values));
+    }
+
+    for (value, expected) in values.iter().zip(expected) {
+        let WastRet::Core(expected) = expected else {
+            return Outcome::Skipped;
+        };
+
+        let matches = match (expected, value) {
+            (WastRetCore::I32(e), WasmValue::I32(v)) => e == v,
+            (WastRetCore::I64(e), WasmValue::I64(v)) => e == v,
+            (WastRetCore::F32(NanPattern::Value(e)), WasmValue::F32(v)) => e.bits == v.to_bits(),
+            (WastRetCore::F32(_), WasmValue::F32(v)) => v.is_nan(),
+            (WastRetCore::F64(NanPattern::Value(e)), WasmValue::F64(v)) => e.bits == v.to_bits(),
+            (WastRetCore::F64(_), WasmValue::F64(v)) => v.is_nan(),
+            (WastRetCore::RefNull(_), WasmValue::RefNull(_)) => true,
+            (WastRetCore::RefExtern(e), WasmValue::RefExtern(v)) => e.is_none_or(|e| e == *v),
+            (WastRetCore::RefFunc(_), WasmValue::RefFunc(_)) => true,
+            (WastRetCore::V128(_), _) => return Outcome::Skipped,
+            _ => false,
+        };
+        if !matches {
+            return Outcome::Failed(format!("expected {:?}, got {:?}", expected, values));
+        }
+    }
+
+    Outcome::Passed
+}
+
+fn run_file(path: &Path, verbose: bool, strict: bool) -> Stats {
+    let mut stats = Stats::default();
+    let Ok(source) = std::fs::read_to_string(path) else {
+        stats.failed += 1;
+        return stats;
+    };
+
+    let buf = match ParseBuffer::new(&source) {
+        Ok(buf) => buf,
+        Err(err) => {
+            println!("  {}: {}", path.display(), err);
+            stats.failed += 1;
+            return stats;
+        }
+    };
+    let wast = match parser::parse::<Wast<'_>>(&buf) {
+        Ok(wast) => wast,
+        Err(err) => {
+            println!("  {}: {}", path.display(), err);
+            stats.failed += 1;
+            return stats;
+        }
+    };
+
+    let mut runner = Runner { strict, ..Runner::default() };
+    for directive in wast.directives {
+        let (line, _) = directive.span().linecol_in(&source);
+        let outcome = catch_unwind(AssertUnwindSafe(|| runner.run_directive(directive)))
+            .unwrap_or_else(|_| Outcome::Failed("panicked".to_string()));
+
+        match outcome {
+            Outcome::Passed => stats.passed += 1,
+            Outcome::Skipped if strict => {
+                stats.failed += 1;
+                println!("  {}:{}: can't be run", path.display(), line + 1);
+            }
+            Outcome::Skipped => stats.skipped += 1,
+            Outcome::Failed(message) => {
+                stats.failed += 1;
+                if verbose {
+                    println!("  {}:{}: {}", path.display(), line + 1, message);
+                }
+            }
+        }
+    }
+    stats
+}
+
+/// Collect the `.wast` files of the testsuite, grouped by proposal
+fn collect(root: &Path) -> BTreeMap<String, Vec<PathBuf>> {
+    fn wast_files(dir: &Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else { return };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.is_dir() {
+                wast_files(&path, files);
+            } else if path.extension().is_some_and(|ext| ext == "wast") {
+                files.push(path);
+            }
+        }
+    }
+
+    let mut groups = BTreeMap::new();
+    let mut core = Vec::new();
+    for path in std::fs::read_dir(root).into_iter().flatten().flatten().map(|entry| entry.path()) {
+        if path.extension().is_some_and(|ext| ext == "wast") {
+            core.push(path);
+        }
+    }
+    groups.insert("core".to_string(), core);
+
+    for path in std::fs::read_dir(root.join("proposals")).into_iter().flatten().flatten().map(|entry| entry.path()) {
+        let mut files = Vec::new();
+        wast_files(&path, &mut files);
+        groups.insert(path.file_name().unwrap().to_string_lossy().into_owned(), files);
+    }
+
+    for files in groups.values_mut() {
+        files.sort();
+    }
+    groups
+}
+
+fn print_stats(name: &str, stats: Stats) {
+    println!("{:<32} {:>8} {:>8} {:>8} {:>6.1}%", name, stats.passed, stats.failed, stats.skipped, stats.rate());
+}
+
+fn main() {
+    let filters: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with('-')).collect();
+    let verbose = std::env::var_os("SPEC_VERBOSE").is_some();
+    std::panic::set_hook(Box::new(|_| {}));
+
+    println!("{:<32} {:>8} {:>8} {:>8} {:>7}", "proposal", "passed", "failed", "skipped", "rate");
+    let local = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/spec/reef");
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&local).into_iter().flatten().flatten().map(|e| e.path()).collect();
+    files.sort();
+    let mut reef = Stats::default();
+    for path in files.iter().filter(|path| path.extension().is_some_and(|ext| ext == "wast")) {
+        reef.add(run_file(path, true, true));
+    }
+    print_stats("reef", reef);
+
+    if cfg!(feature = "spec-tests") {
+        run_testsuite(&filters, verbose);
+    }
+    if reef.failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn run_testsuite(filters: &[String], verbose: bool) {
+    let root = std::env::var_os("WASM_TESTSUITE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/spec/testsuite"));
+    if !root.is_dir() {
+        println!("spec testsuite not found at {}, see tests/spec/main.rs", root.display());
+        return;
+    }
+
+    let mut total = Stats::default();
+    for (proposal, files) in collect(&root) {
+        let mut stats = Stats::default();
+        for path in files {
+            let name = path.strip_prefix(&root).unwrap_or(&path).to_string_lossy().into_owned();
+            if filters.is_empty() || filters.iter().any(|filter| name.contains(filter.as_str())) {
+                stats.add(run_file(&path, verbose, false));
+            }
+        }
+
+        if stats.passed + stats.failed + stats.skipped > 0 {
+            print_stats(&proposal, stats);
+            total.add(stats);
+        }
+    }
+    print_stats("total", total);
+}
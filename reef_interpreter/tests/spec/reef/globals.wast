@@ -0,0 +1,16 @@
+;; Mutable and immutable globals written and read across calls
+
+(module
+  (global $sp (mut i32) (i32.const 1024))
+  (global $base i64 (i64.const 7))
+  (func $frame (param i32) (result i32)
+    (global.set $sp (i32.sub (global.get $sp) (local.get 0)))
+    (global.get $sp))
+  (func (export "run") (result i32 i64)
+    (drop (call $frame (i32.const 16)))
+    (call $frame (i32.const 32))
+    (global.get $base)))
+
+(assert_return (invoke "run") (i32.const 976) (i64.const 7))
+;; the stack pointer keeps going down in the same instance
+(assert_return (invoke "run") (i32.const 928) (i64.const 7))
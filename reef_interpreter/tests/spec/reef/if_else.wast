@@ -0,0 +1,28 @@
+;; Arity and branching of `if` with and without `else`
+
+(module
+  (func (export "select") (param i32) (result i32)
+    (if (result i32) (local.get 0) (then (i32.const 10)) (else (i32.const 20))))
+  (func (export "no_else") (param i32) (result i32)
+    (i32.const 1)
+    (if (param i32) (result i32) (local.get 0) (then (i32.const 2) (i32.add))))
+  (func (export "br_out") (param i32) (result i32)
+    (i32.const 7)
+    (if (param i32) (result i32) (local.get 0)
+      (then (drop) (i32.const 30) (br_if 0 (i32.const 1)) (drop) (i32.const 31))
+      (else (i32.const 40) (br 0))))
+  (func (export "count") (param i32) (result i32) (local i32)
+    (loop $l
+      (if (i32.and (local.get 0) (i32.const 1))
+        (then (local.set 1 (i32.add (local.get 1) (i32.const 1))))
+        (else (nop)))
+      (br_if $l (local.tee 0 (i32.shr_u (local.get 0) (i32.const 1)))))
+    (local.get 1)))
+
+(assert_return (invoke "select" (i32.const 1)) (i32.const 10))
+(assert_return (invoke "select" (i32.const 0)) (i32.const 20))
+(assert_return (invoke "no_else" (i32.const 1)) (i32.const 3))
+(assert_return (invoke "no_else" (i32.const 0)) (i32.const 1))
+(assert_return (invoke "br_out" (i32.const 1)) (i32.const 30))
+(assert_return (invoke "br_out" (i32.const 0)) (i32.const 40))
+(assert_return (invoke "count" (i32.const 0xb6)) (i32.const 5))
@@ -0,0 +1,57 @@
+;; Unsigned comparisons and the traps of integer division
+
+(module
+  (func (export "i32.lt_u") (param i32 i32) (result i32) (i32.lt_u (local.get 0) (local.get 1)))
+  (func (export "i32.lt_s") (param i32 i32) (result i32) (i32.lt_s (local.get 0) (local.get 1)))
+  (func (export "i32.gt_u") (param i32 i32) (result i32) (i32.gt_u (local.get 0) (local.get 1)))
+  (func (export "i32.le_u") (param i32 i32) (result i32) (i32.le_u (local.get 0) (local.get 1)))
+  (func (export "i32.ge_u") (param i32 i32) (result i32) (i32.ge_u (local.get 0) (local.get 1)))
+  (func (export "i32.div_u") (param i32 i32) (result i32) (i32.div_u (local.get 0) (local.get 1)))
+  (func (export "i32.rem_u") (param i32 i32) (result i32) (i32.rem_u (local.get 0) (local.get 1)))
+  (func (export "i32.div_s") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1)))
+  (func (export "i32.rem_s") (param i32 i32) (result i32) (i32.rem_s (local.get 0) (local.get 1)))
+  (func (export "i64.lt_u") (param i64 i64) (result i32) (i64.lt_u (local.get 0) (local.get 1)))
+  (func (export "i64.lt_s") (param i64 i64) (result i32) (i64.lt_s (local.get 0) (local.get 1)))
+  (func (export "i64.gt_u") (param i64 i64) (result i32) (i64.gt_u (local.get 0) (local.get 1)))
+  (func (export "i64.le_u") (param i64 i64) (result i32) (i64.le_u (local.get 0) (local.get 1)))
+  (func (export "i64.ge_u") (param i64 i64) (result i32) (i64.ge_u (local.get 0) (local.get 1)))
+  (func (export "i64.div_u") (param i64 i64) (result i64) (i64.div_u (local.get 0) (local.get 1)))
+  (func (export "i64.rem_u") (param i64 i64) (result i64) (i64.rem_u (local.get 0) (local.get 1)))
+  (func (export "i64.div_s") (param i64 i64) (result i64) (i64.div_s (local.get 0) (local.get 1)))
+  (func (export "i64.rem_s") (param i64 i64) (result i64) (i64.rem_s (local.get 0) (local.get 1)))
+  ;; the compare is fused into the branch here
+  (func (export "br_lt_u") (param i32 i32) (result i32)
+    (block (br_if 0 (i32.lt_u (local.get 0) (local.get 1))) (return (i32.const 0)))
+    (i32.const 1)))
+
+(assert_return (invoke "i32.lt_u" (i32.const -1) (i32.const 1)) (i32.const 0))
+(assert_return (invoke "i32.lt_s" (i32.const -1) (i32.const 1)) (i32.const 1))
+(assert_return (invoke "i32.gt_u" (i32.const -1) (i32.const 1)) (i32.const 1))
+(assert_return (invoke "i32.le_u" (i32.const 0x80000000) (i32.const 0x80000000)) (i32.const 1))
+(assert_return (invoke "i32.ge_u" (i32.const 0) (i32.const 0x80000000)) (i32.const 0))
+(assert_return (invoke "i64.lt_u" (i64.const -1) (i64.const 1)) (i32.const 0))
+(assert_return (invoke "i64.lt_s" (i64.const -1) (i64.const 1)) (i32.const 1))
+(assert_return (invoke "i64.gt_u" (i64.const 0x8000000000000000) (i64.const 0x7fffffffffffffff)) (i32.const 1))
+(assert_return (invoke "i64.le_u" (i64.const 1) (i64.const -1)) (i32.const 1))
+(assert_return (invoke "i64.ge_u" (i64.const 1) (i64.const -1)) (i32.const 0))
+(assert_return (invoke "br_lt_u" (i32.const -1) (i32.const 1)) (i32.const 0))
+(assert_return (invoke "br_lt_u" (i32.const 1) (i32.const -1)) (i32.const 1))
+
+(assert_return (invoke "i32.div_u" (i32.const -1) (i32.const 2)) (i32.const 0x7fffffff))
+(assert_return (invoke "i32.rem_u" (i32.const -1) (i32.const 7)) (i32.const 3))
+(assert_return (invoke "i32.div_u" (i32.const 0x80000000) (i32.const -1)) (i32.const 0))
+(assert_return (invoke "i32.rem_s" (i32.const 0x80000000) (i32.const -1)) (i32.const 0))
+(assert_return (invoke "i64.div_u" (i64.const -1) (i64.const 2)) (i64.const 0x7fffffffffffffff))
+(assert_return (invoke "i64.rem_u" (i64.const -1) (i64.const 7)) (i64.const 1))
+(assert_return (invoke "i64.rem_s" (i64.const 0x8000000000000000) (i64.const -1)) (i64.const 0))
+
+(assert_trap (invoke "i32.div_u" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "i32.rem_u" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "i32.div_s" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "i32.rem_s" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "i64.div_u" (i64.const 1) (i64.const 0)) "integer divide by zero")
+(assert_trap (invoke "i64.rem_u" (i64.const 1) (i64.const 0)) "integer divide by zero")
+(assert_trap (invoke "i64.div_s" (i64.const 1) (i64.const 0)) "integer divide by zero")
+(assert_trap (invoke "i64.rem_s" (i64.const 1) (i64.const 0)) "integer divide by zero")
+(assert_trap (invoke "i32.div_s" (i32.const 0x80000000) (i32.const -1)) "integer overflow")
+(assert_trap (invoke "i64.div_s" (i64.const 0x8000000000000000) (i64.const -1)) "integer overflow")
@@ -0,0 +1,51 @@
+;; Blocks, loops, ifs and calls with several params and results
+
+(module $env
+  (func (export "swap") (param i32 i64) (result i64 i32)
+    (local.get 1) (local.get 0)))
+(register "env" $env)
+
+(module
+  (import "env" "swap" (func $swap (param i32 i64) (result i64 i32)))
+  (func $pair (param i32) (result i32 i64)
+    (local.get 0) (i64.extend_i32_u (i32.mul (local.get 0) (i32.const 2))))
+  (func (export "block") (result i32 i64)
+    (block (result i32 i64) (i32.const 1) (i64.const 2) (br 0)))
+  (func (export "block_params") (result i32 i32)
+    (i32.const 7) (i32.const 3)
+    (block (param i32 i32) (result i32 i32) (i32.add) (i32.const 10)))
+  (func (export "loop") (param i32) (result i32 i32)
+    (i32.const 0) (local.get 0)
+    (loop $l (param i32 i32) (result i32 i32)
+      (local.set 0)
+      (i32.add (local.get 0))
+      (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+      (br_if $l (local.get 0))))
+  (func (export "if") (param i32) (result i32 i32 i32)
+    (i32.const 1) (i32.const 2)
+    (if (param i32 i32) (result i32 i32 i32) (local.get 0)
+      (then (i32.add) (i32.const 3) (i32.const 4))
+      (else (i32.sub) (i32.const 5) (i32.const 6))))
+  (func (export "call") (result i32 i64 i64 i32)
+    (call $pair (i32.const 21)) (call $swap (i32.const 1) (i64.const 2)))
+  (func $early (result i32 i64)
+    (i32.const 1) (i64.const 2)
+    (block (i32.const 3) (i64.const 4) (return)))
+  (func (export "early") (result i32 i32 i64 i32)
+    (i32.const 9) (call $early) (i32.const 10))
+  (func (export "nested") (result i32 i64)
+    (i32.const 99)
+    (block (result i32 i64)
+      (block (result i32 i64) (i32.const 5) (i64.const 6) (br 1))
+      (unreachable))
+    (i64.add (i64.extend_i32_u (call $pair (i32.const 1)) (drop)))
+    (return)))
+
+(assert_return (invoke "block") (i32.const 1) (i64.const 2))
+(assert_return (invoke "block_params") (i32.const 10) (i32.const 10))
+(assert_return (invoke "loop" (i32.const 4)) (i32.const 10) (i32.const 0))
+(assert_return (invoke "if" (i32.const 1)) (i32.const 3) (i32.const 3) (i32.const 4))
+(assert_return (invoke "if" (i32.const 0)) (i32.const -1) (i32.const 5) (i32.const 6))
+(assert_return (invoke "call") (i32.const 21) (i64.const 42) (i64.const 2) (i32.const 1))
+(assert_return (invoke "nested") (i32.const 5) (i64.const 7))
+(assert_return (invoke "early") (i32.const 9) (i32.const 3) (i64.const 4) (i32.const 10))
@@ -0,0 +1,35 @@
+;; `return` from nested blocks and loops, with values left on the stack below the results
+
+(module
+  ;; returns the first i with i * i >= n, from inside a loop nested in blocks
+  (func $isqrt (param $n i32) (result i32) (local $i i32)
+    (block
+      (loop $next
+        (i32.const 99) ;; left on the stack below the result
+        (if (i32.ge_u (i32.mul (local.get $i) (local.get $i)) (local.get $n))
+          (then (return (local.get $i))))
+        (drop)
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (unreachable))
+  (func $pair (result i32 i64)
+    (block (i32.const 1) (i64.const 2) (return))
+    (unreachable))
+  (func (export "main") (param i32) (result i32)
+    (block $outer (result i32)
+      (block
+        (br $outer (i32.add (call $isqrt (local.get 0)) (i32.const 100))))
+      (i32.const -1)))
+  (func (export "pair") (result i32 i64)
+    (call $pair))
+  (func (export "top") (result i32)
+    (i32.const 5)
+    (block (loop (i32.const 7) (return)))
+    (unreachable)))
+
+(assert_return (invoke "main" (i32.const 0)) (i32.const 100))
+(assert_return (invoke "main" (i32.const 1)) (i32.const 101))
+(assert_return (invoke "main" (i32.const 10)) (i32.const 104))
+(assert_return (invoke "main" (i32.const 16)) (i32.const 104))
+(assert_return (invoke "pair") (i32.const 1) (i64.const 2))
+(assert_return (invoke "top") (i32.const 7))
@@ -3,15 +3,9 @@ use std::io;
 use argh::FromArgs;
 // use args::WasmArg;
 use color_eyre::eyre::Result;
-use rkyv::AlignedVec;
-
-use reef_interpreter::{
-    error::Error,
-    exec::CallResultTyped,
-    imports::{Extern, FuncContext, Imports},
-    parse_bytes,
-    reference::MemoryStringExt,
-    Instance, PAGE_SIZE,
+
+use reef::{
+    CallResultTyped, Error, Extern, FuncContext, Imports, Instance, MemoryStringExt, ParserSession, Snapshot, PAGE_SIZE,
 };
 
 /// Test CLI args
@@ -45,13 +39,14 @@ const MAX_CYCLES: usize = 5000;
 const ENTRY_NAME: &str = "reef_main";
 
 fn run(module_bytes: &[u8], arg: i32) -> Result<()> {
-    let mut serialized_state: Option<AlignedVec> = None;
+    let mut serialized_state: Option<Snapshot> = None;
+    let mut session = ParserSession::new();
     let mut cycles = 0;
 
     loop {
         cycles += 1;
 
-        let module = parse_bytes(module_bytes)?;
+        let module = session.parse_bytes(module_bytes)?;
 
         let mut imports = Imports::new();
 
@@ -102,9 +97,13 @@ fn run(module_bytes: &[u8], arg: i32) -> Result<()> {
 
                 break Ok(());
             }
-            CallResultTyped::Incomplete => {
+            CallResultTyped::Exit(code) => {
+                println!("exited with status {code}");
+                break Ok(());
+            }
+            CallResultTyped::Incomplete | CallResultTyped::Breakpoint(_) => {
                 if serialized_state.is_none() {
-                    serialized_state = Some(AlignedVec::with_capacity(PAGE_SIZE * 2));
+                    serialized_state = Some(Snapshot::with_capacity(PAGE_SIZE * 2));
                 }
                 serialized_state = Some(exec_handle.serialize(serialized_state.take().unwrap())?);
                 // println!("serialized {} bytes", serialized_state.as_ref().unwrap().len());